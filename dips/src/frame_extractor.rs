@@ -2,7 +2,7 @@
 use log::*;
 
 // std
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Mutex};
 
 // gstreamer imports
 use gstreamer::{
@@ -13,8 +13,423 @@ use gstreamer::{CoreError, LibraryError, element_error, element_warning, prelude
 use gstreamer_app::{self, AppSink, AppSinkCallbacks, AppSrc};
 
 use crate::gpu::ComputeState;
-use crate::{DiPsProperties, StreamPipelineError};
-use crate::{FrameCallbackNotSpecifiedError, VideoPathNotSpecifiedError};
+use crate::{
+    Backend, BlobTracker, Colormap, CpuBackend, DiPsBackend, DiPsParameters, DiPsProperties,
+    DiffExporter, EventDetector, EventLog, FrameDecimator, FrequencyAnalyzer, MotExporter,
+    RefreshPoint, Retiming, RoiSeriesExporter, SceneCutDetector, StreamPipelineError,
+    draw_blob_boxes,
+};
+use crate::{CallbackFunction, FrameCallbackNotSpecifiedError, VideoPathNotSpecifiedError};
+
+/// Refresh points that haven't been reached yet
+struct RefreshSchedule {
+    // Frames seen so far
+    frame: usize,
+    pending: Vec<RefreshPoint>,
+}
+
+impl RefreshSchedule {
+    fn new(points: &[RefreshPoint]) -> Self {
+        Self {
+            frame: 0,
+            pending: points.to_vec(),
+        }
+    }
+
+    /// Whether the starting texture should be retaken before the next frame, which was
+    /// presented at the given timestamp in seconds. Points passed together only refresh once
+    fn reached(&mut self, timestamp: Option<f64>) -> bool {
+        let frame = self.frame;
+        self.frame += 1;
+
+        let pending = self.pending.len();
+        self.pending.retain(|point| match point {
+            RefreshPoint::Frame(point) => *point > frame,
+            // Frames without a timestamp can't reach a timestamp
+            RefreshPoint::Timestamp(point) => timestamp.is_none_or(|timestamp| timestamp < *point),
+        });
+
+        if self.pending.len() == pending {
+            return false;
+        }
+
+        match timestamp {
+            Some(seconds) => info!("Refreshing the baseline at frame {frame} ({seconds:.3}s)"),
+            None => info!("Refreshing the baseline at frame {frame}"),
+        }
+
+        true
+    }
+}
+
+/// Everything done with the decoded frames on their way from the app sink to the app source,
+/// DiPs itself and the exports, blob detection and refreshes around it
+struct SampleProcessor {
+    compute: Box<dyn DiPsBackend + Send + Sync>,
+    frame_callback: Arc<Mutex<CallbackFunction>>,
+
+    // Raw diff export and region of interest time series, finished when the stream ends
+    exporter: Option<DiffExporter>,
+    roi_series: Option<RoiSeriesExporter>,
+
+    // Summary maps of the whole run, written when the stream ends
+    summary_path: Option<String>,
+
+    // Blobs of the thresholded diffs and their tracks, finished when the stream ends
+    event_detector: EventDetector,
+    event_log: Option<EventLog>,
+    event_overlay: bool,
+    blob_tracker: BlobTracker,
+    mot_exporter: Option<MotExporter>,
+
+    // Temporal spectra of the input, the maps are written when the stream ends
+    frequency_analyzer: FrequencyAnalyzer,
+    frequency_path: Option<String>,
+
+    // Thins out the decoded frames and retimes the output video
+    frame_decimator: FrameDecimator,
+    retime_to_duration: bool,
+    decimation_factor: u64,
+
+    // Retakes the baseline at the refresh points and after scene cuts
+    refresh_schedule: RefreshSchedule,
+    scene_cut_detector: SceneCutDetector,
+}
+
+impl SampleProcessor {
+    fn new(properties: &DiPsProperties) -> Result<Self, Box<dyn std::error::Error>> {
+        let compute: Box<dyn DiPsBackend + Send + Sync> = match properties.backend {
+            Backend::Gpu => Box::new(ComputeState::new(properties)?),
+            Backend::Cpu => Box::new(CpuBackend::new(DiPsParameters::from(properties))?),
+        };
+
+        let exporter = match properties.get_diff_export_path() {
+            Some(path) => Some(DiffExporter::new(path, DiPsParameters::from(properties))?),
+            None => None,
+        };
+
+        let roi_series = match properties.get_roi_series_path() {
+            Some(path) => Some(RoiSeriesExporter::new(path, properties.rois.clone())?),
+            None => None,
+        };
+
+        let summary_path = properties.get_summary_path().cloned();
+        if summary_path.is_some() && !properties.summary.is_on() {
+            warn!("A summary path is set but the summary is off, no summary maps will be written");
+        }
+
+        if (properties.get_event_log_path().is_some() || properties.event_overlay)
+            && !properties.events.is_on()
+        {
+            warn!(
+                "An event log or overlay is set but the events are off, no blobs will be detected"
+            );
+        }
+        let event_log = match properties.get_event_log_path() {
+            Some(path) => Some(EventLog::new(path)?),
+            None => None,
+        };
+
+        if properties.tracking.is_on() && !properties.events.is_on() {
+            warn!("Tracking is on but the events are off, no blobs will be tracked");
+        }
+        let mot_exporter = match properties.get_tracks_path() {
+            Some(path) => Some(MotExporter::new(path)?),
+            None => None,
+        };
+
+        let frequency_path = properties.get_frequency_path().cloned();
+        if frequency_path.is_some() && !properties.frequency.is_on() {
+            warn!(
+                "A frequency path is set but the analysis is off, no frequency maps will be written"
+            );
+        }
+
+        // Frame Callback cloning
+        let frame_callback = match properties.frame_callback.as_ref() {
+            Some(callback) => callback.clone(),
+            None => {
+                return Err(Box::new(FrameCallbackNotSpecifiedError));
+            }
+        };
+
+        Ok(Self {
+            compute,
+            frame_callback,
+            exporter,
+            roi_series,
+            summary_path,
+            event_detector: EventDetector::new(properties.events),
+            event_log,
+            event_overlay: properties.event_overlay,
+            blob_tracker: BlobTracker::new(properties.tracking),
+            mot_exporter,
+            frequency_analyzer: FrequencyAnalyzer::new(
+                properties.frequency.clone(),
+                properties.rois.clone(),
+            ),
+            frequency_path,
+            frame_decimator: FrameDecimator::new(properties.decimation, properties.retiming),
+            retime_to_duration: matches!(properties.retiming, Retiming::Duration(_)),
+            decimation_factor: properties.decimation.factor() as u64,
+            refresh_schedule: RefreshSchedule::new(&properties.refresh_points),
+            scene_cut_detector: SceneCutDetector::new(properties.scene_cut),
+        })
+    }
+
+    /// Runs DiPs on a decoded sample and pushes the output frame into the app source
+    fn process(
+        &mut self,
+        appsink: &AppSink,
+        sample: &gst::Sample,
+        appsrc: &AppSrc,
+    ) -> Result<FlowSuccess, FlowError> {
+        // Retrieve Frame info (width, height) and data bytes
+        let (width, height) = if let Some(caps) = sample.caps() {
+            if let Some(s) = caps.structure(0) {
+                (
+                    s.get::<i32>("width").unwrap_or(0),
+                    s.get::<i32>("height").unwrap_or(0),
+                )
+            } else {
+                (0, 0)
+            }
+        } else {
+            (0, 0)
+        };
+        let (width, height) = (width as u32, height as u32);
+
+        let buffer = sample.buffer().expect("Failed to get buffer");
+        let map = buffer.map_readable().expect("Failed to map buffer");
+
+        let pts = buffer.pts();
+        let duration = buffer.duration();
+
+        let timestamp = pts.map(|pts| pts.nseconds() as f64 / 1_000_000_000.0);
+
+        info!("pts: {:#?}", pts);
+
+        // Variable frame rate streams have a rate of 0/1, their rate is estimated from the
+        // timestamps instead
+        let source_frame_rate = sample
+            .caps()
+            .and_then(|caps| caps.structure(0))
+            .and_then(|s| s.get::<gst::Fraction>("framerate").ok())
+            .filter(|rate| rate.numer() > 0 && rate.denom() > 0)
+            .map(|rate| rate.numer() as f64 / rate.denom() as f64);
+
+        // Frames that don't complete a group go no further
+        if let Some(rate) = source_frame_rate {
+            self.frame_decimator.set_source_frame_rate(rate);
+        }
+        if self.retime_to_duration
+            && self.frame_decimator.frame_count() == 0
+            && let Some(length) = appsink.query_duration::<ClockTime>()
+        {
+            self.frame_decimator
+                .set_source_duration(length.nseconds() as f64 / 1_000_000_000.0);
+        }
+        let Some(decimated) = self
+            .frame_decimator
+            .push(width, height, map.as_slice(), timestamp)
+        else {
+            return Ok(FlowSuccess::Ok);
+        };
+        let decimation_on = self.frame_decimator.is_on();
+
+        let frame_data = &*decimated.data;
+        let timestamp = decimated.timestamp;
+
+        // The output is retimed, the undecimated stream keeps its own
+        let to_clock_time = |seconds: f64| {
+            ClockTime::from_nseconds((seconds.max(0.0) * 1_000_000_000.0).round() as u64)
+        };
+        let (pts, duration) = match decimation_on {
+            true => (
+                decimated.output_timestamp.map(to_clock_time),
+                match decimated.output_duration {
+                    Some(seconds) => Some(to_clock_time(seconds)),
+                    None => duration.map(|duration| {
+                        ClockTime::from_nseconds(duration.nseconds() * self.decimation_factor)
+                    }),
+                },
+            ),
+            false => (pts, duration),
+        };
+
+        // The spectra are of the input, before any compensation
+        if self.frequency_path.is_some() {
+            if let Some(rate) = self.frame_decimator.frame_rate() {
+                self.frequency_analyzer.set_frame_rate(rate);
+            }
+
+            self.frequency_analyzer
+                .push(width, height, frame_data, timestamp);
+        }
+
+        if let Err(err) = self.compute.check_frame_size(width, height) {
+            error!("{err}");
+            return Err(FlowError::Error);
+        }
+
+        if self.refresh_schedule.reached(timestamp) {
+            self.compute.refresh_baseline();
+        }
+
+        // Here is where the callback is called for each frame
+        let Ok(callback) = self.frame_callback.lock() else {
+            return Ok(FlowSuccess::Ok);
+        };
+        let mut callback_data = callback(width, height, frame_data, &mut *self.compute);
+        drop(callback);
+
+        // Frames without a diff are still collecting the baseline
+        if let (Some(exporter), Some(diff)) = (self.exporter.as_mut(), self.compute.raw_diff())
+            && let Err(err) = exporter.write_frame(
+                width,
+                height,
+                diff,
+                timestamp,
+                self.compute.gain(),
+                self.compute.motion(),
+            )
+        {
+            error!("Failed to export the diff: {err}");
+            return Err(FlowError::Error);
+        }
+
+        if let (Some(roi_series), Some(stats)) =
+            (self.roi_series.as_mut(), self.compute.roi_stats())
+            && let Err(err) = roi_series.write_frame(timestamp, stats)
+        {
+            error!("Failed to write the ROI series: {err}");
+            return Err(FlowError::Error);
+        }
+
+        let frame = self.event_detector.frame_count();
+        let blobs = self
+            .event_detector
+            .detect(width, height, self.compute.raw_diff());
+
+        if let Some(event_log) = self.event_log.as_mut()
+            && let Err(err) = event_log.write_frame(frame, timestamp, blobs)
+        {
+            error!("Failed to write the event log: {err}");
+            return Err(FlowError::Error);
+        }
+
+        let tracked = self.blob_tracker.update(frame, blobs);
+        if let Some(mot_exporter) = self.mot_exporter.as_mut()
+            && let Err(err) = mot_exporter.write(tracked)
+        {
+            error!("Failed to write the tracks: {err}");
+            return Err(FlowError::Error);
+        }
+
+        if self.event_overlay {
+            draw_blob_boxes(&mut callback_data, width, height, blobs);
+        }
+
+        // A refresh for a scene cut takes effect from the next frame on
+        if self
+            .scene_cut_detector
+            .check(frame_data, self.compute.raw_diff(), timestamp)
+        {
+            self.compute.refresh_baseline();
+        }
+
+        let mut new_buffer = Buffer::from_slice(callback_data);
+        // Set the PTS and duration of the new buffer
+        // INFO: This might not be needed
+        new_buffer.make_mut().set_pts(pts);
+        new_buffer.make_mut().set_duration(duration);
+
+        // Set the caps of the appsrc to the same as the sample, at the frame rate of the output
+        if let Some(caps) = sample.caps() {
+            let mut caps = caps.copy();
+            if decimation_on
+                && let Some(rate) = self
+                    .frame_decimator
+                    .output_frame_rate()
+                    .and_then(gst::Fraction::approximate_f64)
+            {
+                caps.make_mut().set("framerate", rate);
+            }
+            appsrc.set_caps(Some(&caps));
+        }
+
+        match appsrc.push_buffer(new_buffer) {
+            Ok(_) => info!("Successfully pushed to appsrc"),
+            Err(err) => {
+                error!("Error Pushing buffer: {:#?}", err);
+                return Err(FlowError::Error);
+            }
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+
+    /// Finishes the exports and writes the maps of the whole run once the stream ends
+    fn finish(&mut self) {
+        if self.frame_decimator.is_on() {
+            info!(
+                "DiPs ran on {} decimated frames",
+                self.frame_decimator.frame_count()
+            );
+        }
+
+        if let Some(exporter) = self.exporter.take() {
+            let frames = exporter.frame_count();
+            match exporter.finish() {
+                Ok(_) => info!("Exported the diffs of {frames} frames"),
+                Err(err) => error!("Failed to finish the diff export: {err}"),
+            }
+        }
+
+        if let Some(roi_series) = self.roi_series.take() {
+            let frames = roi_series.frame_count();
+            match roi_series.finish() {
+                Ok(_) => info!("Wrote the ROI series of {frames} frames"),
+                Err(err) => error!("Failed to finish the ROI series: {err}"),
+            }
+        }
+
+        if let Some(event_log) = self.event_log.take() {
+            let frames = event_log.event_count();
+            match event_log.finish() {
+                Ok(_) => info!("Logged blobs in {frames} frames"),
+                Err(err) => error!("Failed to finish the event log: {err}"),
+            }
+        }
+
+        if let Some(mot_exporter) = self.mot_exporter.take() {
+            let tracks = self.blob_tracker.track_count();
+            match mot_exporter.finish() {
+                Ok(_) => info!("Wrote {tracks} tracks"),
+                Err(err) => error!("Failed to finish the tracks: {err}"),
+            }
+        }
+
+        if let Some(path) = self.summary_path.as_ref()
+            && let Some(summary) = self.compute.summary()
+        {
+            match summary.write(path, &Colormap::Viridis) {
+                Ok(_) => info!("Wrote the summary maps of {} frames", summary.frame_count()),
+                Err(err) => error!("Failed to write the summary maps: {err}"),
+            }
+        }
+
+        if let Some(path) = self.frequency_path.as_ref() {
+            match self.frequency_analyzer.window_count() {
+                0 => warn!("Too few frames for a frequency window, no maps written"),
+                windows => match self.frequency_analyzer.write(path, &Colormap::Viridis) {
+                    Ok(_) => info!("Wrote the frequency maps of {windows} windows"),
+                    Err(err) => error!("Failed to write the frequency maps: {err}"),
+                },
+            }
+        }
+    }
+}
 
 pub fn initialize_frame_extractor() {
     gst::init().unwrap();
@@ -72,26 +487,8 @@ pub fn create_video_frame_decoder_pipeline(
 
     let pipeline_weak = frame_decoding_pipeline.downgrade();
 
-    // GPU Compute
-    let compute = Arc::new(RwLock::new(
-        ComputeState::new(
-            properties.colorize,
-            properties.spatial_window_size,
-            properties.sensitivity,
-            properties.filter_type,
-            properties.chroma_filter,
-        )
-        .expect("Could not create Compute State"),
-    ));
-    let compute_closure_clone = compute.clone();
-
-    // Frame Callback cloning
-    let frame_callback_closure_clone = match properties.frame_callback.as_ref() {
-        Some(callback) => callback.clone(),
-        None => {
-            return Err(Box::new(FrameCallbackNotSpecifiedError));
-        }
-    };
+    // Everything done with the decoded frames, shared by the callbacks of the app sink
+    let sample_processor = Arc::new(Mutex::new(SampleProcessor::new(properties)?));
 
     decodebin.connect_pad_added(move |dbin, src_pad| {
         let Some(pipeline) = pipeline_weak.upgrade() else {
@@ -121,8 +518,8 @@ pub fn create_video_frame_decoder_pipeline(
         };
 
         // Creating clones to send into sink closure
-        let compute_clone = compute_closure_clone.clone();
-        let frame_callback_clone = frame_callback_closure_clone.clone();
+        let sample_processor_clone = sample_processor.clone();
+        let eos_sample_processor_clone = sample_processor.clone();
 
         let insert_sink = |is_video| -> Result<(), Box<dyn std::error::Error>> {
             if is_video {
@@ -199,79 +596,29 @@ pub fn create_video_frame_decoder_pipeline(
                     AppSinkCallbacks::builder()
                         // This is needed to pass on the eos signal from the filesrc
                         .eos(move |_appsink| {
+                            if let Ok(mut sample_processor) = eos_sample_processor_clone.lock() {
+                                sample_processor.finish();
+                            }
+
                             if let Ok(appsrc) = eos_app_src_clone.lock() {
                                 appsrc.end_of_stream().expect("Failed to send EOS");
                             }
                         })
-                        .new_sample(move |appsink| {
-                            match appsink.pull_sample() {
-                                Ok(sample) => {
-                                    // Retrieve Frame info (width, height) and data bytes
-                                    let (width, height) = if let Some(caps) = sample.caps() {
-                                        if let Some(s) = caps.structure(0) {
-                                            (
-                                                s.get::<i32>("width").unwrap_or(0),
-                                                s.get::<i32>("height").unwrap_or(0),
-                                            )
-                                        } else {
-                                            (0, 0)
-                                        }
-                                    } else {
-                                        (0, 0)
-                                    };
-
-                                    let buffer = sample.buffer().expect("Failed to get buffer");
-                                    let map = buffer.map_readable().expect("Failed to map buffer");
-
-                                    let frame_data = map.as_slice();
-                                    let pts = buffer.pts();
-                                    let duration = buffer.duration();
-
-                                    info!("pts: {:#?}", pts);
-
-                                    if let Ok(mut compute) = compute_clone.write() {
-                                        // Here is where the callback is called for each frame
-                                        if let Ok(callback) = frame_callback_clone.lock() {
-                                            let callback_data = callback(
-                                                width as u32,
-                                                height as u32,
-                                                frame_data,
-                                                &mut compute,
-                                            );
-
-                                            let mut new_buffer = Buffer::from_slice(callback_data);
-                                            // Set the PTS and duration of the new buffer
-                                            // INFO: This might not be needed
-                                            new_buffer.make_mut().set_pts(pts);
-                                            new_buffer.make_mut().set_duration(duration);
-
-                                            if let Ok(appsrc) = app_src_clone.lock() {
-                                                // Set the caps of the appsrc to the same as the sample
-                                                if let Some(caps) = sample.caps() {
-                                                    appsrc.set_caps(Some(&caps.copy()));
-                                                }
-
-                                                match appsrc.push_buffer(new_buffer) {
-                                                    Ok(_) => {
-                                                        info!("Successfully pushed to appsrc")
-                                                    }
-                                                    Err(err) => {
-                                                        error!("Error Pushing buffer: {:#?}", err);
-                                                        return Err(FlowError::Error);
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-
-                                    Ok(FlowSuccess::Ok)
-                                }
-                                Err(_) => {
-                                    if let Ok(appsrc) = app_src_clone.lock() {
-                                        appsrc.end_of_stream().expect("Failed to send EOS");
-                                    }
-                                    Err(FlowError::Eos)
+                        .new_sample(move |appsink| match appsink.pull_sample() {
+                            Ok(sample) => {
+                                let (Ok(mut sample_processor), Ok(appsrc)) =
+                                    (sample_processor_clone.lock(), app_src_clone.lock())
+                                else {
+                                    return Err(FlowError::Error);
+                                };
+
+                                sample_processor.process(appsink, &sample, &appsrc)
+                            }
+                            Err(_) => {
+                                if let Ok(appsrc) = app_src_clone.lock() {
+                                    appsrc.end_of_stream().expect("Failed to send EOS");
                                 }
+                                Err(FlowError::Eos)
                             }
                         })
                         .build(),
@@ -322,3 +669,44 @@ pub fn run_pipeline(pipeline: Pipeline) -> Result<(), Box<dyn std::error::Error>
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_points_are_reached_once() {
+        let mut schedule = RefreshSchedule::new(&[RefreshPoint::Frame(2), RefreshPoint::Frame(4)]);
+
+        let reached = (0..6).map(|_| schedule.reached(None)).collect::<Vec<_>>();
+        assert_eq!(reached, [false, false, true, false, true, false]);
+    }
+
+    #[test]
+    fn timestamp_points_are_reached_by_the_first_frame_at_or_after_them() {
+        let mut schedule = RefreshSchedule::new(&[RefreshPoint::Timestamp(0.25)]);
+
+        // Frames without a timestamp can't reach it
+        assert!(!schedule.reached(None));
+        assert!(!schedule.reached(Some(0.2)));
+        assert!(schedule.reached(Some(0.3)));
+        assert!(!schedule.reached(Some(0.4)));
+    }
+
+    #[test]
+    fn points_passed_together_refresh_once() {
+        let mut schedule = RefreshSchedule::new(&[
+            RefreshPoint::Timestamp(0.1),
+            RefreshPoint::Frame(1),
+            RefreshPoint::Timestamp(0.15),
+            RefreshPoint::Frame(3),
+        ]);
+
+        // A frame too late for the first three points refreshes for all of them
+        assert!(!schedule.reached(Some(0.0)));
+        assert!(schedule.reached(Some(0.5)));
+        assert!(!schedule.reached(Some(1.0)));
+        assert!(schedule.reached(Some(1.5)));
+        assert!(schedule.pending.is_empty());
+    }
+}
@@ -1,6 +1,9 @@
 use std::{error::Error, fmt::Display, num::NonZeroU32};
 
 use crate::utils::indexing::UCircularIndex;
+use dips_core::{
+    COLORMAP_SIZE, Colormap, HISTOGRAM_BINS, Rois, SensorCalibration, TRANSFER_LUT_SIZE, Transfer,
+};
 
 #[allow(unused_imports)]
 use log::*;
@@ -10,13 +13,10 @@ use wgpu::{
     BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType,
     BufferDescriptor, BufferUsages, Device, Extent3d, PipelineLayout, PipelineLayoutDescriptor,
     Queue, ShaderStages, StorageTextureAccess, TexelCopyBufferLayout, Texture, TextureDescriptor,
-    TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
     util::{BufferInitDescriptor, DeviceExt},
 };
 
-// Constants
-pub const TEMPORAL_BUFFER_SIZE: usize = 4;
-
 // Error Structs
 #[derive(Debug)]
 pub struct BindGroupsAlreadyInitializedError;
@@ -40,15 +40,99 @@ fn padded_bytes_per_row(width: u32) -> usize {
     bytes_per_row + padding
 }
 
+/// Creates the dark frame and flat field textures of the calibration for frames of the given
+/// size, they are 1x1 while the calibration is off since the shaders don't read them then
+fn create_calibration_textures(
+    device: &Device,
+    queue: &Queue,
+    calibration: &SensorCalibration,
+    (width, height): (u32, u32),
+) -> (Texture, Texture) {
+    let maps = match calibration.is_on() {
+        true => calibration.maps(width, height),
+        false => calibration.maps(1, 1),
+    };
+
+    let create_texture = |label, data: &[[f32; 4]]| {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width: maps.width,
+                height: maps.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(data),
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(maps.width * 4 * std::mem::size_of::<f32>() as u32),
+                rows_per_image: Some(maps.height),
+            },
+            texture.size(),
+        );
+
+        texture
+    };
+
+    (
+        create_texture("calibration dark texture", &maps.dark),
+        create_texture("calibration flat texture", &maps.flat),
+    )
+}
+
+// Layout entry of a calibration texture at the binding
+fn calibration_texture_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
 pub enum MainComputeBindGroups {
     Uninitialized(MainComputeBindGroupLayouts),
     Initialized(MainComputeBindGroupsContainer),
 }
 
 impl MainComputeBindGroups {
-    /// Create new uninitialized bind groups
-    pub fn new(device: &Device) -> Self {
-        Self::Uninitialized(MainComputeBindGroupLayouts::new(device))
+    /// Create new uninitialized bind groups for a temporal window of temporal_window textures
+    /// read in input_format, a baseline history of baseline_window frames, the colors of
+    /// colormap, the curve and starting gain of transfer, the regions of interest the diffs are
+    /// summarised over and the calibration of the sensor
+    pub fn new(
+        device: &Device,
+        temporal_window: usize,
+        baseline_window: usize,
+        input_format: TextureFormat,
+        colormap: Colormap,
+        transfer: Transfer,
+        rois: Rois,
+        calibration: SensorCalibration,
+    ) -> Self {
+        Self::Uninitialized(MainComputeBindGroupLayouts::new(
+            device,
+            temporal_window,
+            baseline_window,
+            input_format,
+            colormap,
+            transfer,
+            rois,
+            calibration,
+        ))
     }
 
     /// Initialize the bind groups with a set of textures and a starting texture
@@ -56,7 +140,7 @@ impl MainComputeBindGroups {
         main_compute_bind_groups: &mut Self,
         (device, queue): (&Device, &Queue),
         (width, height): (u32, u32),
-        (starting_texture, temporal_textures): (&[u8], &[Vec<u8>]),
+        (starting_texture, temporal_textures): (&[f32], &[Vec<u8>]),
     ) -> Result<(), BindGroupsAlreadyInitializedError> {
         let new_main_compute_bind_groups: MainComputeBindGroups;
 
@@ -100,24 +184,92 @@ pub struct MainComputeBindGroupLayouts {
     temporal_textures_bind_group_layout: BindGroupLayout,
     output_texture_bind_group_layout: BindGroupLayout,
     pipeline_layout: PipelineLayout,
+    baseline_window: usize,
+    // Format the frames are uploaded in, RGBA8 or the float intensities of the CPU stages
+    input_format: TextureFormat,
+    colormap: Colormap,
+    transfer: Transfer,
+    rois: Rois,
+    calibration: SensorCalibration,
 }
 
 impl MainComputeBindGroupLayouts {
-    pub fn new(device: &Device) -> Self {
-        // Create the layout for the main compute input
+    pub fn new(
+        device: &Device,
+        temporal_window: usize,
+        baseline_window: usize,
+        input_format: TextureFormat,
+        colormap: Colormap,
+        transfer: Transfer,
+        rois: Rois,
+        calibration: SensorCalibration,
+    ) -> Self {
+        // Create the layout for the main compute input and the rolling baseline state
         let start_texture_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("main compute start texture bind group layout"),
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadOnly,
-                        format: TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::ReadOnly,
+                            format: TextureFormat::R32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
 
         // Create the layout for the main compute temporal textures
@@ -129,11 +281,11 @@ impl MainComputeBindGroupLayouts {
                         binding: 0,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
+                            access: StorageTextureAccess::ReadOnly,
+                            format: input_format,
                             view_dimension: wgpu::TextureViewDimension::D2,
                         },
-                        count: NonZeroU32::new(TEMPORAL_BUFFER_SIZE as u32),
+                        count: NonZeroU32::new(temporal_window as u32),
                     },
                     BindGroupLayoutEntry {
                         binding: 1,
@@ -145,23 +297,108 @@ impl MainComputeBindGroupLayouts {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        // Create the layout for the main compute output texture
+        // Create the layout for the main compute output and raw diff textures, the lookup tables,
+        // the auto sensitivity, the regions of interest and the calibration
         let output_texture_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("main compute output texture bind group layout"),
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::WriteOnly,
-                        format: TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::Rgba8Unorm,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::R32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    calibration_texture_layout_entry(8),
+                    calibration_texture_layout_entry(9),
+                ],
             });
 
         // Create the pipeline layout for the main compute stage
@@ -180,6 +417,12 @@ impl MainComputeBindGroupLayouts {
             temporal_textures_bind_group_layout,
             output_texture_bind_group_layout,
             pipeline_layout,
+            baseline_window,
+            input_format,
+            colormap,
+            transfer,
+            rois,
+            calibration,
         }
     }
 }
@@ -197,8 +440,35 @@ pub struct MainComputeBindGroupsContainer {
     pub output_texture: Texture,
     pub output_texture_buffer: Buffer,
 
+    // Signed difference of every pixel before it is filtered and colorized
+    pub diff_texture: Texture,
+    pub diff_texture_buffer: Buffer,
+
+    // Auto sensitivity state
+    pub histogram_buffer: Buffer,
+    pub histogram_read_buffer: Buffer,
+    gain_buffer: Buffer,
+
+    // Region of interest statistics, cleared before every dispatch
+    pub roi_stats_buffer: Buffer,
+    pub roi_stats_read_buffer: Buffer,
+    roi_stats_clear: Vec<u32>,
+    // Number of pixels in every region of interest
+    pub roi_pixels: Vec<usize>,
+
     starting_temporal_index: UCircularIndex,
     pub starting_temporal_index_buffer: Buffer,
+
+    // Rolling baseline state
+    baseline_window: usize,
+    rolling_baseline_buffer: Buffer,
+    baseline_history_buffer: Buffer,
+    baseline_history_index: UCircularIndex,
+    baseline_history_index_buffer: Buffer,
+
+    // Frame to frame difference state
+    previous_frame_buffer: Buffer,
+    accumulated_diff_buffer: Buffer,
 }
 
 impl MainComputeBindGroupsContainer {
@@ -207,7 +477,7 @@ impl MainComputeBindGroupsContainer {
         device: &Device,
         width: u32,
         height: u32,
-        starting_texture: &[u8],
+        starting_texture: &[f32],
         textures: &[Vec<u8>],
         starting_temporal_index: usize,
         queue: &Queue,
@@ -225,14 +495,14 @@ impl MainComputeBindGroupsContainer {
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
+            format: TextureFormat::R32Float,
             usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
         queue.write_texture(
             start_texture.as_image_copy(),
-            starting_texture,
+            bytemuck::cast_slice(starting_texture),
             TexelCopyBufferLayout {
                 offset: 0,
                 bytes_per_row: Some(width * 4),
@@ -242,8 +512,8 @@ impl MainComputeBindGroupsContainer {
         );
 
         // Create the temporal textures
-        let mut temporal_views = Vec::with_capacity(TEMPORAL_BUFFER_SIZE);
-        let mut temporal_textures = Vec::with_capacity(TEMPORAL_BUFFER_SIZE);
+        let mut temporal_views = Vec::with_capacity(textures.len());
+        let mut temporal_textures = Vec::with_capacity(textures.len());
         for frame_data in textures.iter() {
             let temporal_texture = device.create_texture(&TextureDescriptor {
                 label: Some("main compute temporal texture"),
@@ -251,7 +521,7 @@ impl MainComputeBindGroupsContainer {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8Unorm,
+                format: main_bind_group_layouts.input_format,
                 usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST,
                 view_formats: &[],
             });
@@ -296,6 +566,185 @@ impl MainComputeBindGroupsContainer {
             })
         };
 
+        // Create the raw diff texture and its buffer, a float is as wide as an RGBA8 pixel so
+        // the rows are padded the same way
+        let diff_texture = device.create_texture(&TextureDescriptor {
+            label: Some("main compute diff texture"),
+            size: texture_dimensions,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let diff_texture_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute diff buffer"),
+            size: padded_bytes_per_row(width) as u64 * height as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        // Create the colormap lookup texture, only read by the shader so it isn't kept around
+        let colormap_texture = device.create_texture(&TextureDescriptor {
+            label: Some("main compute colormap texture"),
+            size: Extent3d {
+                width: COLORMAP_SIZE as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            colormap_texture.as_image_copy(),
+            bytemuck::cast_slice(&main_bind_group_layouts.colormap.texture_data()),
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(COLORMAP_SIZE as u32 * 4 * std::mem::size_of::<f32>() as u32),
+                rows_per_image: Some(1),
+            },
+            colormap_texture.size(),
+        );
+
+        // Same for the transfer curve lookup texture
+        let transfer_texture = device.create_texture(&TextureDescriptor {
+            label: Some("main compute transfer curve texture"),
+            size: Extent3d {
+                width: TRANSFER_LUT_SIZE as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            transfer_texture.as_image_copy(),
+            bytemuck::cast_slice(&main_bind_group_layouts.transfer.curve.texture_data()),
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(TRANSFER_LUT_SIZE as u32 * std::mem::size_of::<f32>() as u32),
+                rows_per_image: Some(1),
+            },
+            transfer_texture.size(),
+        );
+
+        // Create the rolling baseline buffers, they are filled when the start texture is set
+        let baseline_window = main_bind_group_layouts.baseline_window;
+        let pixel_bytes = (width as u64 * height as u64) * std::mem::size_of::<f32>() as u64;
+
+        let rolling_baseline_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute rolling baseline buffer"),
+            size: pixel_bytes,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let baseline_history_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute baseline history buffer"),
+            size: pixel_bytes * baseline_window as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let baseline_history_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("baseline history index buffer"),
+            contents: bytemuck::cast_slice(&[0u32]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        // Create the frame to frame difference buffers, they are also filled when the start
+        // texture is set
+        let previous_frame_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute previous frame buffer"),
+            size: pixel_bytes,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let accumulated_diff_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute accumulated diff buffer"),
+            size: pixel_bytes,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Every temporal texture is spatially filtered once into its own frame of this buffer
+        let filtered_frames_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute filtered frames buffer"),
+            size: pixel_bytes * textures.len() as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // Diff histogram of the auto sensitivity, cleared before every dispatch that collects it
+        let histogram_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute diff histogram buffer"),
+            size: (HISTOGRAM_BINS * std::mem::size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let histogram_read_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute diff histogram read buffer"),
+            size: (HISTOGRAM_BINS * std::mem::size_of::<u32>()) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let gain_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("main compute gain buffer"),
+            contents: bytemuck::cast_slice(&[main_bind_group_layouts.transfer.gain]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        // Regions of interest every pixel is in, the buffers can't be empty without any
+        let rois = &main_bind_group_layouts.rois;
+        let roi_mask = match rois.is_empty() {
+            true => vec![0],
+            false => rois.mask(width, height),
+        };
+        let roi_pixels = rois.pixel_counts(&roi_mask);
+
+        let roi_mask_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("main compute roi mask buffer"),
+            contents: bytemuck::cast_slice(&roi_mask),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let roi_stats_clear = rois.gpu_stats_clear();
+        let roi_stats_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("main compute roi stats buffer"),
+            contents: bytemuck::cast_slice(&roi_stats_clear),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        });
+
+        let roi_stats_read_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("main compute roi stats read buffer"),
+            size: (roi_stats_clear.len() * std::mem::size_of::<u32>()) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        // Dark frame and flat field of the calibration, only read by the shader
+        let (dark_texture, flat_texture) = create_calibration_textures(
+            device,
+            queue,
+            &main_bind_group_layouts.calibration,
+            (width, height),
+        );
+
         // Create the bind groups
         let (
             start_texture_bind_group,
@@ -306,12 +755,34 @@ impl MainComputeBindGroupsContainer {
             let start_texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
                 label: Some("main compute start texture bind group"),
                 layout: &main_bind_group_layouts.start_texture_bind_group_layout,
-                entries: &[BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(
-                        &start_texture.create_view(&TextureViewDescriptor::default()),
-                    ),
-                }],
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(
+                            &start_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: rolling_baseline_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: baseline_history_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: baseline_history_index_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 4,
+                        resource: previous_frame_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 5,
+                        resource: accumulated_diff_buffer.as_entire_binding(),
+                    },
+                ],
             });
 
             let starting_temporal_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
@@ -333,18 +804,70 @@ impl MainComputeBindGroupsContainer {
                         binding: 1,
                         resource: starting_temporal_index_buffer.as_entire_binding(),
                     },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: filtered_frames_buffer.as_entire_binding(),
+                    },
                 ],
             });
 
             let output_texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
                 label: Some("main compute output texture bind group"),
                 layout: &main_bind_group_layouts.output_texture_bind_group_layout,
-                entries: &[BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(
-                        &output_texture.create_view(&TextureViewDescriptor::default()),
-                    ),
-                }],
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(
+                            &output_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(
+                            &diff_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(
+                            &colormap_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(
+                            &transfer_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 4,
+                        resource: histogram_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 5,
+                        resource: gain_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 6,
+                        resource: roi_mask_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 7,
+                        resource: roi_stats_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 8,
+                        resource: BindingResource::TextureView(
+                            &dark_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 9,
+                        resource: BindingResource::TextureView(
+                            &flat_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                ],
             });
 
             (
@@ -368,15 +891,38 @@ impl MainComputeBindGroupsContainer {
             output_texture,
             output_texture_buffer,
 
-            starting_temporal_index: UCircularIndex::new(0, TEMPORAL_BUFFER_SIZE),
+            diff_texture,
+            diff_texture_buffer,
+
+            histogram_buffer,
+            histogram_read_buffer,
+            gain_buffer,
+
+            roi_stats_buffer,
+            roi_stats_read_buffer,
+            roi_stats_clear,
+            roi_pixels,
+
+            starting_temporal_index: UCircularIndex::new(0, textures.len()),
             starting_temporal_index_buffer,
+
+            baseline_window,
+            rolling_baseline_buffer,
+            baseline_history_buffer,
+            baseline_history_index: UCircularIndex::new(0, baseline_window),
+            baseline_history_index_buffer,
+
+            previous_frame_buffer,
+            accumulated_diff_buffer,
         }
     }
 
-    pub fn set_start_texture(&mut self, input_texture: &[u8], queue: &Queue) {
+    /// Sets the start texture and restarts the rolling baselines and frame to frame
+    /// differences from it
+    pub fn set_start_texture(&mut self, intensities: &[f32], queue: &Queue) {
         queue.write_texture(
             self.start_texture.as_image_copy(),
-            input_texture,
+            bytemuck::cast_slice(intensities),
             TexelCopyBufferLayout {
                 offset: 0,
                 bytes_per_row: Some(self.texture_dimensions.width * 4),
@@ -384,6 +930,57 @@ impl MainComputeBindGroupsContainer {
             },
             self.texture_dimensions,
         );
+
+        queue.write_buffer(
+            &self.rolling_baseline_buffer,
+            0,
+            bytemuck::cast_slice(intensities),
+        );
+
+        for frame in 0..self.baseline_window {
+            queue.write_buffer(
+                &self.baseline_history_buffer,
+                (frame * intensities.len() * std::mem::size_of::<f32>()) as u64,
+                bytemuck::cast_slice(intensities),
+            );
+        }
+
+        // The frame to frame differences start from the start texture with nothing accumulated
+        queue.write_buffer(
+            &self.previous_frame_buffer,
+            0,
+            bytemuck::cast_slice(intensities),
+        );
+        queue.write_buffer(
+            &self.accumulated_diff_buffer,
+            0,
+            bytemuck::cast_slice(&vec![0.0f32; intensities.len()]),
+        );
+    }
+
+    /// Sets the gain the filtered diffs are displayed with from the next dispatch on
+    pub fn set_gain(&self, gain: f32, queue: &Queue) {
+        queue.write_buffer(&self.gain_buffer, 0, bytemuck::cast_slice(&[gain]));
+    }
+
+    /// Clears the region of interest statistics the next dispatch collects
+    pub fn clear_roi_stats(&self, queue: &Queue) {
+        queue.write_buffer(
+            &self.roi_stats_buffer,
+            0,
+            bytemuck::cast_slice(&self.roi_stats_clear),
+        );
+    }
+
+    /// Moves the baseline history on to the slot the next dispatch writes into
+    pub fn advance_baseline_history(&mut self, queue: &Queue) {
+        queue.write_buffer(
+            &self.baseline_history_index_buffer,
+            0,
+            bytemuck::cast_slice(&[*self.baseline_history_index.as_ref() as u32]),
+        );
+
+        self.baseline_history_index += 1;
     }
 
     #[allow(dead_code)]
@@ -435,9 +1032,20 @@ pub enum PreComputeBindGroups {
 }
 
 impl PreComputeBindGroups {
-    /// Create new uninitialized bind groups
-    pub fn new(device: &Device) -> Self {
-        Self::Uninitialized(PreComputeBindGroupLayouts::new(device))
+    /// Create new uninitialized bind groups for a temporal window of temporal_window textures
+    /// read in input_format and the calibration of the sensor
+    pub fn new(
+        device: &Device,
+        temporal_window: usize,
+        input_format: TextureFormat,
+        calibration: SensorCalibration,
+    ) -> Self {
+        Self::Uninitialized(PreComputeBindGroupLayouts::new(
+            device,
+            temporal_window,
+            input_format,
+            calibration,
+        ))
     }
 
     /// Initialized the bind groups with a set of starting textures
@@ -486,10 +1094,17 @@ pub struct PreComputeBindGroupLayouts {
     start_textures_bind_group_layout: BindGroupLayout,
     output_texture_bind_group_layout: BindGroupLayout,
     pipeline_layout: PipelineLayout,
+    input_format: TextureFormat,
+    calibration: SensorCalibration,
 }
 
 impl PreComputeBindGroupLayouts {
-    pub fn new(device: &Device) -> Self {
+    pub fn new(
+        device: &Device,
+        temporal_window: usize,
+        input_format: TextureFormat,
+        calibration: SensorCalibration,
+    ) -> Self {
         // Create the layout for the pre compute input
         let start_textures_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -499,27 +1114,31 @@ impl PreComputeBindGroupLayouts {
                     visibility: ShaderStages::COMPUTE,
                     ty: BindingType::StorageTexture {
                         access: StorageTextureAccess::ReadOnly,
-                        format: TextureFormat::Rgba8Unorm,
+                        format: input_format,
                         view_dimension: wgpu::TextureViewDimension::D2,
                     },
-                    count: NonZeroU32::new(TEMPORAL_BUFFER_SIZE as u32),
+                    count: NonZeroU32::new(temporal_window as u32),
                 }],
             });
 
-        // Create the layout for the pre compute output
+        // Create the layout for the pre compute output and the calibration
         let output_texture_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("Pre compute output texture bind group layout"),
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::WriteOnly,
-                        format: TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::R32Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    calibration_texture_layout_entry(1),
+                    calibration_texture_layout_entry(2),
+                ],
             });
 
         // Create the pipeline layout
@@ -536,6 +1155,8 @@ impl PreComputeBindGroupLayouts {
             start_textures_bind_group_layout,
             output_texture_bind_group_layout,
             pipeline_layout,
+            input_format,
+            calibration,
         }
     }
 }
@@ -545,6 +1166,7 @@ pub struct PreComputeBindGroupsContainer {
 
     // Pre compute start textures
     pub start_textures_bind_group: BindGroup,
+    start_textures: Vec<Texture>,
 
     // Output of the pre compute state
     pub output_texture_bind_group: BindGroup,
@@ -568,7 +1190,8 @@ impl PreComputeBindGroupsContainer {
         };
 
         // Create the array of starting textures
-        let mut start_views = Vec::with_capacity(TEMPORAL_BUFFER_SIZE);
+        let mut start_views = Vec::with_capacity(textures.len());
+        let mut start_textures = Vec::with_capacity(textures.len());
         for frame_data in textures.iter() {
             let start_texture = device.create_texture(&TextureDescriptor {
                 label: Some("pre compute Start Textures"),
@@ -576,7 +1199,7 @@ impl PreComputeBindGroupsContainer {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8Unorm,
+                format: pre_compute_bind_group_layouts.input_format,
                 usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST,
                 view_formats: &[],
             });
@@ -593,6 +1216,7 @@ impl PreComputeBindGroupsContainer {
             );
 
             start_views.push(start_texture.create_view(&TextureViewDescriptor::default()));
+            start_textures.push(start_texture);
         }
 
         // Create the output texture
@@ -602,7 +1226,7 @@ impl PreComputeBindGroupsContainer {
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
+            format: TextureFormat::R32Float,
             usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
             view_formats: &[],
         });
@@ -620,6 +1244,14 @@ impl PreComputeBindGroupsContainer {
             })
         };
 
+        // Dark frame and flat field of the calibration, only read by the shader
+        let (dark_texture, flat_texture) = create_calibration_textures(
+            device,
+            queue,
+            &pre_compute_bind_group_layouts.calibration,
+            (width, height),
+        );
+
         // Create the bind groups with the texture views
         let (start_textures_bind_group, output_texture_bind_group) = {
             let start_view_refs: Vec<_> = start_views.iter().collect();
@@ -635,12 +1267,26 @@ impl PreComputeBindGroupsContainer {
             let output_texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
                 label: Some("Pre compute outptu texture bind group"),
                 layout: &pre_compute_bind_group_layouts.output_texture_bind_group_layout,
-                entries: &[BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(
-                        &output_texture.create_view(&TextureViewDescriptor::default()),
-                    ),
-                }],
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(
+                            &output_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(
+                            &dark_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(
+                            &flat_texture.create_view(&TextureViewDescriptor::default()),
+                        ),
+                    },
+                ],
             });
 
             (start_textures_bind_group, output_texture_bind_group)
@@ -650,10 +1296,27 @@ impl PreComputeBindGroupsContainer {
             texture_dimensions,
 
             start_textures_bind_group,
+            start_textures,
 
             output_texture_bind_group,
             output_texture,
             output_texture_buffer,
         }
     }
+
+    pub fn update_start_textures(&mut self, input_textures: &[Vec<u8>], queue: &Queue) {
+        for (start_texture, input_texture) in self.start_textures.iter().zip(input_textures.iter())
+        {
+            queue.write_texture(
+                start_texture.as_image_copy(),
+                input_texture,
+                TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.texture_dimensions.width * 4),
+                    rows_per_image: Some(self.texture_dimensions.height),
+                },
+                self.texture_dimensions,
+            );
+        }
+    }
 }
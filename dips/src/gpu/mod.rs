@@ -1,4 +1,7 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+};
 
 use bind_groups::{MainComputeBindGroups, PreComputeBindGroups};
 use log::*;
@@ -7,11 +10,18 @@ use wgpu::{
     Backends, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
     ComputePipelineDescriptor, Device, DeviceDescriptor, Features, Instance, InstanceDescriptor,
     Limits, Maintain, MapMode, MemoryHints, Origin3d, PipelineCompilationOptions, PowerPreference,
-    Queue, RequestAdapterOptionsBase, TexelCopyBufferInfo, TexelCopyBufferLayout,
-    TexelCopyTextureInfo, TextureAspect, include_wgsl,
+    Queue, RequestAdapterOptionsBase, ShaderModule, ShaderModuleDescriptor, ShaderSource,
+    TexelCopyBufferInfo, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect, TextureFormat,
+};
+
+use dips_core::{
+    BaselineMode, ChromaFilter, DiPsParameters, FrequencyDenoiser, Motion, MotionCompensator,
+    ROI_STATS_WORDS, RoiStats, SensitivityEstimator, SensorCalibration, SensorCalibrator, Summary,
+    SummaryMaps, WindowError, check_baseline_buffer, check_baseline_window, check_spatial_window,
+    check_temporal_window, frame_intensities,
 };
 
-use crate::{ChromaFilter, DiPsFilter};
+use crate::{DiPsBackend, DiPsProperties, MAX_TEMPORAL_WINDOW};
 
 mod bind_groups;
 
@@ -36,6 +46,28 @@ fn padded_bytes_per_row(width: u32) -> usize {
     bytes_per_row + padding
 }
 
+/// Creates a shader module that reads the frames in the input format, the shaders declare the
+/// InputTexture alias for RGBA8 frames
+fn create_input_shader_module(
+    device: &Device,
+    label: &str,
+    source: &str,
+    input_format: TextureFormat,
+) -> ShaderModule {
+    let source = match input_format {
+        TextureFormat::R32Float => source.replace(
+            "alias InputTexture = texture_storage_2d<rgba8unorm, read>;",
+            "alias InputTexture = texture_storage_2d<r32float, read>;",
+        ),
+        _ => source.to_string(),
+    };
+
+    device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(Cow::from(source)),
+    })
+}
+
 pub struct ComputeState {
     device: Device,
     queue: Queue,
@@ -48,21 +80,68 @@ pub struct ComputeState {
     compute_pipeline: ComputePipeline,
     main_compute_bind_groups: MainComputeBindGroups,
 
+    // Spatially filters each temporal texture once when it arrives
+    filter_pipeline: ComputePipeline,
+
     pixels: Vec<u8>,
 
+    // Signed difference of every pixel of the last dispatch before it was filtered
+    raw_diff: Vec<f32>,
+
+    // Frames of the temporal window as they were uploaded, RGBA8 or the bytes of the float
+    // intensities of the CPU stages
     textures: VecDeque<Vec<u8>>,
 
-    starting_texture: Vec<u8>,
+    // Float intensity of the start texture
+    starting_texture: Vec<f32>,
+
+    // Number of frames the temporal median is taken over
+    temporal_window: usize,
+
+    // The sliding median baseline keeps a whole frame per history slot in a single buffer, so
+    // the frame size it allows depends on the device
+    baseline_mode: BaselineMode,
+    max_baseline_buffer_size: u64,
+
+    // Number of frames left to collect before the starting texture is retaken
+    refresh_countdown: Option<usize>,
+
+    // Picks the gain of the transfer from the diff histograms when the auto sensitivity is on
+    sensitivity_estimator: SensitivityEstimator,
+    // Gain the last dispatch displayed the diffs with
+    gain: f32,
+
+    // Statistics of the raw diffs in every region of interest of the last dispatch
+    roi_stats: Vec<RoiStats>,
+
+    // Statistics of every dispatch of the run when the summary is on
+    summary_mode: Summary,
+    summary: Option<SummaryMaps>,
+
+    // Frames go through the CPU stages below and are uploaded as their float intensities
+    intensity_input: bool,
+    chroma_filter: ChromaFilter,
+
+    // Calibrates the frames on the CPU when they go through the denoiser or the motion
+    // compensation, the shaders calibrate them otherwise
+    calibrator: SensorCalibrator,
+
+    // Removes periodic sensor patterns from every frame before it is registered
+    denoiser: FrequencyDenoiser,
+
+    // Warps every frame back onto the first frame of the starting texture window
+    motion_compensator: MotionCompensator,
 }
 
 impl ComputeState {
-    pub fn new(
-        colorize: bool,
-        spatial_window_size: i32,
-        sensitivity: f32,
-        filter_type: DiPsFilter,
-        chroma_filter: ChromaFilter,
-    ) -> anyhow::Result<Self> {
+    pub fn new(properties: &DiPsProperties) -> anyhow::Result<Self> {
+        let temporal_window = properties.temporal_window;
+        let baseline_mode = properties.baseline_mode;
+
+        check_temporal_window(temporal_window, MAX_TEMPORAL_WINDOW)?;
+        check_baseline_window(baseline_mode)?;
+        check_spatial_window(properties.spatial_window_size.max(1) as u32)?;
+
         let instance = Instance::new(&InstanceDescriptor {
             backends: Backends::all(),
             ..Default::default()
@@ -81,6 +160,15 @@ impl ComputeState {
             error!("Texture Binding Array Not supported");
         }
 
+        // The temporal textures are bound next to the start, output and diff textures
+        let required_storage_textures = temporal_window as u32 + 3;
+        let max_storage_textures = adapter.limits().max_storage_textures_per_shader_stage;
+        if required_storage_textures > max_storage_textures {
+            return Err(anyhow::anyhow!(
+                "A temporal window of {temporal_window} frames needs {required_storage_textures} storage textures per shader stage but the adapter only supports {max_storage_textures}"
+            ));
+        }
+
         let (device, queue) = adapter
             .request_device(
                 &DeviceDescriptor {
@@ -90,30 +178,147 @@ impl ComputeState {
                         | Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING
                         | Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
                         | Features::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
-                    required_limits: Limits::default(),
+                    // The sliding median baseline keeps a whole frame per history slot
+                    // so allow buffers as large as the adapter can handle
+                    required_limits: Limits {
+                        max_storage_textures_per_shader_stage: required_storage_textures
+                            .max(Limits::default().max_storage_textures_per_shader_stage),
+                        max_storage_buffer_binding_size: adapter
+                            .limits()
+                            .max_storage_buffer_binding_size,
+                        max_buffer_size: adapter.limits().max_buffer_size,
+                        // The main compute stage binds its state, histogram and region of
+                        // interest buffers together
+                        max_storage_buffers_per_shader_stage: adapter
+                            .limits()
+                            .max_storage_buffers_per_shader_stage,
+                        ..Default::default()
+                    },
                     memory_hints: MemoryHints::default(),
                 },
                 None,
             )
             .block_on()?;
 
+        let max_baseline_buffer_size = device
+            .limits()
+            .max_buffer_size
+            .min(device.limits().max_storage_buffer_binding_size as u64);
+
+        // The denoiser and the motion compensation run on the CPU and hand the frames over as
+        // float intensities, which are uploaded as they are instead of being quantised to RGBA8
+        let intensity_input = properties.denoise.is_on() || properties.motion_compensation.is_on();
+        let input_format = match intensity_input {
+            true => TextureFormat::R32Float,
+            false => TextureFormat::Rgba8Unorm,
+        };
+
+        // The denoiser and the motion compensation need calibrated frames, so the calibration
+        // moves to the CPU in front of them when either is on instead of the frames being read
+        // back from the GPU after the shader calibrated them
+        let (gpu_calibration, cpu_calibration) = match intensity_input {
+            true => (SensorCalibration::default(), properties.calibration.clone()),
+            false => (properties.calibration.clone(), SensorCalibration::default()),
+        };
+
         // These are the pipeline overrides to use
         let pipeline_compilation_options = {
             let mut hm = HashMap::new();
-            hm.insert(String::from("0"), if colorize { 1.0 } else { 0.0 });
-            hm.insert(String::from("1"), spatial_window_size as f64);
-            hm.insert(String::from("2"), sensitivity as f64);
-            hm.insert(String::from("3"), filter_type.into());
-            hm.insert(String::from("4"), chroma_filter.into());
+            hm.insert(
+                String::from("0"),
+                if properties.colorize { 1.0 } else { 0.0 },
+            );
+            hm.insert(String::from("1"), properties.spatial_window_size as f64);
+            hm.insert(String::from("2"), properties.sensitivity as f64);
+            hm.insert(String::from("3"), properties.filter_type.into());
+            hm.insert(String::from("4"), properties.chroma_filter.into());
+            hm.insert(String::from("5"), temporal_window as f64);
+            hm.insert(
+                String::from("6"),
+                properties.temporal_estimator.shader_id() as f64,
+            );
+            hm.insert(
+                String::from("7"),
+                properties.temporal_estimator.parameter() as f64,
+            );
+            hm.insert(String::from("8"), baseline_mode.shader_id() as f64);
+            hm.insert(String::from("9"), baseline_mode.alpha() as f64);
+            hm.insert(String::from("10"), baseline_mode.window() as f64);
+            hm.insert(
+                String::from("11"),
+                properties.difference_mode.shader_id() as f64,
+            );
+            hm.insert(
+                String::from("12"),
+                properties.difference_mode.decay() as f64,
+            );
+            hm.insert(
+                String::from("13"),
+                properties.spatial_filter.shader_id() as f64,
+            );
+            hm.insert(
+                String::from("14"),
+                properties.spatial_filter.spatial_sigma() as f64,
+            );
+            hm.insert(
+                String::from("15"),
+                properties.spatial_filter.range_sigma() as f64,
+            );
+            hm.insert(
+                String::from("16"),
+                properties.border_mode.shader_id() as f64,
+            );
+            hm.insert(
+                String::from("17"),
+                properties.transfer.curve.shader_id() as f64,
+            );
+            hm.insert(
+                String::from("18"),
+                properties.transfer.curve.parameter() as f64,
+            );
+            hm.insert(String::from("19"), properties.transfer.offset as f64);
+            hm.insert(String::from("20"), properties.transfer.clamp_min as f64);
+            hm.insert(String::from("21"), properties.transfer.clamp_max as f64);
+            hm.insert(
+                String::from("22"),
+                if properties.auto_sensitivity.is_on() {
+                    1.0
+                } else {
+                    0.0
+                },
+            );
+            hm.insert(String::from("23"), properties.rois.len() as f64);
+            hm.insert(
+                String::from("24"),
+                if properties.rois.grey_outside {
+                    1.0
+                } else {
+                    0.0
+                },
+            );
+            hm.insert(
+                String::from("25"),
+                if gpu_calibration.is_on() { 1.0 } else { 0.0 },
+            );
+            hm.insert(String::from("26"), if intensity_input { 1.0 } else { 0.0 });
             hm
         };
 
         // Create the pre compute pipeline
         let (pre_compute_bind_groups, pre_compute_pipeline) = {
-            let shader =
-                device.create_shader_module(include_wgsl!("./shaders/pre_compute_shader.wgsl"));
+            let shader = create_input_shader_module(
+                &device,
+                "Pre compute shader",
+                include_str!("./shaders/pre_compute_shader.wgsl"),
+                input_format,
+            );
 
-            let pre_compute_bind_groups = PreComputeBindGroups::new(&device);
+            let pre_compute_bind_groups = PreComputeBindGroups::new(
+                &device,
+                temporal_window,
+                input_format,
+                gpu_calibration.clone(),
+            );
 
             let pre_compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
                 label: Some("Compute pipeline"),
@@ -131,10 +336,24 @@ impl ComputeState {
         };
 
         // Create the main compute pipeline
-        let (main_compute_bind_groups, compute_pipeline) = {
-            let shader = device.create_shader_module(include_wgsl!("./shaders/dips_shader.wgsl"));
+        let (main_compute_bind_groups, compute_pipeline, filter_pipeline) = {
+            let shader = create_input_shader_module(
+                &device,
+                "DiPs shader",
+                include_str!("./shaders/dips_shader.wgsl"),
+                input_format,
+            );
 
-            let bind_groups_container = MainComputeBindGroups::new(&device);
+            let bind_groups_container = MainComputeBindGroups::new(
+                &device,
+                temporal_window,
+                baseline_mode.window(),
+                input_format,
+                properties.colormap.clone(),
+                properties.transfer.clone(),
+                properties.rois.clone(),
+                gpu_calibration,
+            );
 
             let compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
                 label: Some("Compute Pipeline"),
@@ -148,7 +367,20 @@ impl ComputeState {
                 cache: None,
             });
 
-            (bind_groups_container, compute_pipeline)
+            // The spatial filter stage shares the bind groups of the main compute stage
+            let filter_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("Filter Pipeline"),
+                layout: Some(&bind_groups_container.pipeline_layout().as_ref().unwrap()),
+                module: &shader,
+                entry_point: Some("filter_main"),
+                compilation_options: PipelineCompilationOptions {
+                    constants: &pipeline_compilation_options,
+                    ..Default::default()
+                },
+                cache: None,
+            });
+
+            (bind_groups_container, compute_pipeline, filter_pipeline)
         };
 
         Ok(Self {
@@ -158,23 +390,69 @@ impl ComputeState {
             pre_compute_bind_groups,
             compute_pipeline,
             main_compute_bind_groups,
+            filter_pipeline,
             pixels: Vec::new(),
-            textures: VecDeque::with_capacity(bind_groups::TEMPORAL_BUFFER_SIZE + 1), // NOTE this is done because it only deques once the texture buffer is greater than TEMPORAL_BUFFER_SIZE
+            raw_diff: Vec::new(),
+            textures: VecDeque::with_capacity(temporal_window + 1), // NOTE this is done because it only deques once the texture buffer is greater than temporal_window
             starting_texture: Vec::new(),
+            temporal_window,
+            baseline_mode,
+            max_baseline_buffer_size,
+            refresh_countdown: None,
+            sensitivity_estimator: SensitivityEstimator::new(DiPsParameters::from(properties)),
+            gain: properties.transfer.gain,
+            roi_stats: Vec::new(),
+            summary_mode: properties.summary,
+            summary: None,
+            intensity_input,
+            chroma_filter: properties.chroma_filter.into(),
+            calibrator: SensorCalibrator::new(cpu_calibration, properties.chroma_filter.into()),
+            denoiser: FrequencyDenoiser::new(properties.denoise.clone()),
+            motion_compensator: MotionCompensator::new(properties.motion_compensation),
         })
     }
 
+    /// Runs the CPU stages over the frame when any is on and hands over the bytes of the float
+    /// intensities they make for the R32Float input, the frame is uploaded as it is otherwise
+    fn prepare_frame<'a>(&mut self, width: u32, height: u32, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        if !self.intensity_input {
+            return Cow::Borrowed(frame);
+        }
+
+        let intensities = self
+            .calibrator
+            .calibrate(width, height, frame)
+            .unwrap_or_else(|| frame_intensities(frame, self.chroma_filter));
+        let intensities = self
+            .denoiser
+            .denoise(width, height, &intensities)
+            .unwrap_or(intensities);
+        let intensities = self
+            .motion_compensator
+            .compensate(width, height, &intensities)
+            .unwrap_or(intensities);
+
+        Cow::Owned(bytemuck::cast_slice(&intensities).to_vec())
+    }
+
     /// Add textures to the compute state
-    /// If there are TEMPORAL_BUFFER_SIZE textures added, then create the start texture
+    /// If there are temporal_window textures added, then create the start texture
     /// and create the bind groups for the main compute pipeline
     pub fn add_texture(&mut self, width: u32, height: u32, frame_data: &[u8]) {
         self.textures.push_back(frame_data.to_vec());
 
-        if self.textures.len() > bind_groups::TEMPORAL_BUFFER_SIZE {
+        if self.textures.len() > self.temporal_window {
             self.textures.pop_front();
         }
 
-        if self.textures.len() == bind_groups::TEMPORAL_BUFFER_SIZE {
+        if let Some(countdown) = self.refresh_countdown.as_mut() {
+            *countdown = countdown.saturating_sub(1);
+        }
+
+        // The whole temporal window has been replaced since the refresh was requested
+        let refresh = self.refresh_countdown == Some(0);
+
+        if self.textures.len() == self.temporal_window {
             match PreComputeBindGroups::initialize(
                 &mut self.pre_compute_bind_groups,
                 (&self.device, &self.queue),
@@ -186,7 +464,21 @@ impl ComputeState {
                     // dispatch it, and create the starting texture
                     self.run_precompute_pipeline();
                 }
-                Err(_already_initialized) => {}
+                Err(_already_initialized) => {
+                    // if a refresh was requested then retake the starting texture from the new window
+                    if refresh {
+                        if let PreComputeBindGroups::Initialized(bind_groups) =
+                            &mut self.pre_compute_bind_groups
+                        {
+                            bind_groups.update_start_textures(
+                                self.textures.make_contiguous(),
+                                &self.queue,
+                            );
+                        }
+
+                        self.run_precompute_pipeline();
+                    }
+                }
             }
 
             // FIXME: this api is really bad and should be fixed
@@ -202,6 +494,9 @@ impl ComputeState {
                     {
                         bind_groups.set_start_texture(&self.starting_texture, &self.queue);
                     }
+
+                    // The temporal textures start out with the whole window unfiltered
+                    self.run_filter_pipeline();
                 }
                 Err(_already_initialized) => {
                     // if it has already been initilized then update the textures
@@ -209,10 +504,18 @@ impl ComputeState {
                         &mut self.main_compute_bind_groups
                     {
                         bind_groups.update_temporal_texture(frame_data, &self.queue);
+
+                        if refresh {
+                            bind_groups.set_start_texture(&self.starting_texture, &self.queue);
+                        }
                     }
                 }
             }
         }
+
+        if refresh {
+            self.refresh_countdown = None;
+        }
     }
 
     fn run_precompute_pipeline(&mut self) {
@@ -282,18 +585,21 @@ impl ComputeState {
                 buffer_slice.map_async(MapMode::Read, |_| {});
                 self.device.poll(Maintain::Wait);
 
+                // The output texture holds one float per pixel
                 let padded_data = buffer_slice.get_mapped_range();
                 self.starting_texture = vec![
-                    0;
-                    (unpadded_bytes_per_row * bind_groups.texture_dimensions.height)
+                    0.0;
+                    (bind_groups.texture_dimensions.width * bind_groups.texture_dimensions.height)
                         as usize
                 ];
 
-                for (padded, pixels) in padded_data.chunks_exact(padded_bytes_per_row).zip(
+                for (padded, intensities) in padded_data.chunks_exact(padded_bytes_per_row).zip(
                     self.starting_texture
-                        .chunks_exact_mut(unpadded_bytes_per_row as usize),
+                        .chunks_exact_mut(bind_groups.texture_dimensions.width as usize),
                 ) {
-                    pixels.copy_from_slice(&padded[..unpadded_bytes_per_row as usize]);
+                    intensities.copy_from_slice(bytemuck::cast_slice(
+                        &padded[..unpadded_bytes_per_row as usize],
+                    ));
                 }
 
                 // deinitialize
@@ -303,14 +609,71 @@ impl ComputeState {
         }
     }
 
-    pub fn dispatch(&mut self) -> Option<Vec<u8>> {
+    /// Spatially filters every temporal texture into the filtered frames
+    fn run_filter_pipeline(&self) {
         if let MainComputeBindGroups::Initialized(bind_groups) = &self.main_compute_bind_groups {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("filter command encoder"),
+                });
+
+            {
+                let (dispatch_width, dispatch_height) = compute_work_group_count(
+                    (
+                        bind_groups.texture_dimensions.width,
+                        bind_groups.texture_dimensions.height,
+                    ),
+                    (WORK_GROUP_WIDTH, WORK_GROUP_HEIGHT),
+                );
+
+                let mut filter_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("filter pass"),
+                    timestamp_writes: None,
+                });
+
+                filter_pass.set_pipeline(&self.filter_pipeline);
+
+                filter_pass.set_bind_group(0, &bind_groups.start_texture_bind_group, &[]);
+                filter_pass.set_bind_group(1, &bind_groups.temporal_textures_bind_group, &[]);
+                filter_pass.set_bind_group(2, &bind_groups.output_texture_bind_group, &[]);
+
+                // One layer of work groups per temporal texture
+                filter_pass.dispatch_workgroups(
+                    dispatch_width,
+                    dispatch_height,
+                    self.temporal_window as u32,
+                );
+            }
+
+            self.queue.submit(Some(encoder.finish()));
+        }
+    }
+
+    pub fn dispatch(&mut self) -> Option<Vec<u8>> {
+        if let MainComputeBindGroups::Initialized(bind_groups) = &mut self.main_compute_bind_groups
+        {
+            bind_groups.advance_baseline_history(&self.queue);
+
+            // The gain buffer always holds the estimator's gain when a dispatch starts
+            self.gain = self.sensitivity_estimator.gain();
+            let collect_histogram = self.sensitivity_estimator.is_collecting();
+
             let mut encoder = self
                 .device
                 .create_command_encoder(&CommandEncoderDescriptor {
                     label: Some("main compute command encoder"),
                 });
 
+            if collect_histogram {
+                encoder.clear_buffer(&bind_groups.histogram_buffer, 0, None);
+            }
+
+            let collect_roi_stats = !bind_groups.roi_pixels.is_empty();
+            if collect_roi_stats {
+                bind_groups.clear_roi_stats(&self.queue);
+            }
+
             // Run the pipeline
             {
                 let (dispatch_width, dispatch_height) = compute_work_group_count(
@@ -327,15 +690,17 @@ impl ComputeState {
                     timestamp_writes: None,
                 });
 
-                // Set the pipeilne
-                main_compute_pass.set_pipeline(&self.compute_pipeline);
-
                 // Set the bind groups
                 main_compute_pass.set_bind_group(0, &bind_groups.start_texture_bind_group, &[]);
                 main_compute_pass.set_bind_group(1, &bind_groups.temporal_textures_bind_group, &[]);
                 main_compute_pass.set_bind_group(2, &bind_groups.output_texture_bind_group, &[]);
 
+                // Spatially filter the newest temporal texture before it is read
+                main_compute_pass.set_pipeline(&self.filter_pipeline);
+                main_compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, 1);
+
                 // Dispatch the work groups
+                main_compute_pass.set_pipeline(&self.compute_pipeline);
                 main_compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, 1);
             }
 
@@ -361,6 +726,45 @@ impl ComputeState {
                 bind_groups.texture_dimensions,
             );
 
+            // The diff texture is a float per pixel so its rows are as wide as the output's
+            encoder.copy_texture_to_buffer(
+                TexelCopyTextureInfo {
+                    aspect: TextureAspect::All,
+                    texture: &bind_groups.diff_texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                },
+                TexelCopyBufferInfo {
+                    buffer: &bind_groups.diff_texture_buffer,
+                    layout: TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_bytes_per_row as u32),
+                        rows_per_image: Some(bind_groups.texture_dimensions.height as u32),
+                    },
+                },
+                bind_groups.texture_dimensions,
+            );
+
+            if collect_histogram {
+                encoder.copy_buffer_to_buffer(
+                    &bind_groups.histogram_buffer,
+                    0,
+                    &bind_groups.histogram_read_buffer,
+                    0,
+                    bind_groups.histogram_buffer.size(),
+                );
+            }
+
+            if collect_roi_stats {
+                encoder.copy_buffer_to_buffer(
+                    &bind_groups.roi_stats_buffer,
+                    0,
+                    &bind_groups.roi_stats_read_buffer,
+                    0,
+                    bind_groups.roi_stats_buffer.size(),
+                );
+            }
+
             // Send the command encoder to the gpu
             self.queue.submit(Some(encoder.finish()));
 
@@ -369,6 +773,8 @@ impl ComputeState {
                 // We need to get it out and map it to make it usable as data
                 let buffer_slice = bind_groups.output_texture_buffer.slice(..);
                 buffer_slice.map_async(MapMode::Read, |_| {});
+                let diff_buffer_slice = bind_groups.diff_texture_buffer.slice(..);
+                diff_buffer_slice.map_async(MapMode::Read, |_| {});
                 self.device.poll(Maintain::Wait);
 
                 let padded_data = buffer_slice.get_mapped_range();
@@ -385,9 +791,72 @@ impl ComputeState {
                     pixels.copy_from_slice(&padded[..unpadded_bytes_per_row as usize]);
                 }
 
+                let padded_diffs = diff_buffer_slice.get_mapped_range();
+                self.raw_diff = vec![
+                    0.0;
+                    (bind_groups.texture_dimensions.width * bind_groups.texture_dimensions.height)
+                        as usize
+                ];
+
+                for (padded, diffs) in padded_diffs.chunks_exact(padded_bytes_per_row).zip(
+                    self.raw_diff
+                        .chunks_exact_mut(bind_groups.texture_dimensions.width as usize),
+                ) {
+                    diffs.copy_from_slice(bytemuck::cast_slice(
+                        &padded[..unpadded_bytes_per_row as usize],
+                    ));
+                }
+
                 // deinitialize
                 drop(padded_data);
+                drop(padded_diffs);
                 bind_groups.output_texture_buffer.unmap();
+                bind_groups.diff_texture_buffer.unmap();
+            }
+
+            if let Summary::On { threshold } = self.summary_mode {
+                self.summary
+                    .get_or_insert_with(|| {
+                        SummaryMaps::new(
+                            bind_groups.texture_dimensions.width,
+                            bind_groups.texture_dimensions.height,
+                            threshold,
+                        )
+                    })
+                    .update(&self.raw_diff);
+            }
+
+            // The histogram of this dispatch picks the gain of the next one
+            if collect_histogram {
+                let histogram_slice = bind_groups.histogram_read_buffer.slice(..);
+                histogram_slice.map_async(MapMode::Read, |_| {});
+                self.device.poll(Maintain::Wait);
+
+                let histogram = histogram_slice.get_mapped_range();
+                let gain = self
+                    .sensitivity_estimator
+                    .update(bytemuck::cast_slice(&histogram));
+
+                drop(histogram);
+                bind_groups.histogram_read_buffer.unmap();
+
+                bind_groups.set_gain(gain, &self.queue);
+            }
+
+            if collect_roi_stats {
+                let roi_stats_slice = bind_groups.roi_stats_read_buffer.slice(..);
+                roi_stats_slice.map_async(MapMode::Read, |_| {});
+                self.device.poll(Maintain::Wait);
+
+                let roi_stats = roi_stats_slice.get_mapped_range();
+                self.roi_stats = bytemuck::cast_slice::<u8, u32>(&roi_stats)
+                    .chunks_exact(ROI_STATS_WORDS)
+                    .zip(&bind_groups.roi_pixels)
+                    .map(|(words, pixels)| RoiStats::from_gpu(words, *pixels))
+                    .collect();
+
+                drop(roi_stats);
+                bind_groups.roi_stats_read_buffer.unmap();
             }
 
             Some(self.pixels.clone())
@@ -396,3 +865,241 @@ impl ComputeState {
         }
     }
 }
+
+impl DiPsBackend for ComputeState {
+    fn process_frame(&mut self, width: u32, height: u32, frame: &[u8]) -> Option<Vec<u8>> {
+        let frame = self.prepare_frame(width, height, frame);
+        self.add_texture(width, height, &frame);
+
+        // Don't compare against the old starting texture while the new one is being collected
+        if self.refresh_countdown.is_some() {
+            return None;
+        }
+
+        self.dispatch()
+    }
+
+    fn refresh_baseline(&mut self) {
+        self.refresh_countdown = Some(self.temporal_window);
+        self.motion_compensator.reset();
+
+        self.sensitivity_estimator.reset();
+        if let MainComputeBindGroups::Initialized(bind_groups) = &self.main_compute_bind_groups {
+            bind_groups.set_gain(self.sensitivity_estimator.gain(), &self.queue);
+        }
+    }
+
+    fn raw_diff(&self) -> Option<&[f32]> {
+        // Nothing has been dispatched yet or the baseline is being retaken
+        if self.raw_diff.is_empty() || self.refresh_countdown.is_some() {
+            return None;
+        }
+
+        Some(&self.raw_diff)
+    }
+
+    fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    fn roi_stats(&self) -> Option<&[RoiStats]> {
+        // Like the raw diff there's nothing to summarise while the baseline is being retaken
+        if self.roi_stats.is_empty() || self.raw_diff().is_none() {
+            return None;
+        }
+
+        Some(&self.roi_stats)
+    }
+
+    fn summary(&self) -> Option<&SummaryMaps> {
+        self.summary.as_ref()
+    }
+
+    fn motion(&self) -> Option<Motion> {
+        self.motion_compensator.motion()
+    }
+
+    fn check_frame_size(&self, width: u32, height: u32) -> Result<(), WindowError> {
+        check_baseline_buffer(
+            width,
+            height,
+            self.baseline_mode,
+            self.max_baseline_buffer_size,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dips_core::{
+        BaselineMode, BorderMode, CalibrationFrame, CpuBackend, DifferenceMode, FrequencyDenoise,
+        HotPixelMap, SpatialFilter, TemporalEstimator,
+    };
+
+    use super::*;
+
+    /// Largest difference allowed between the raw diffs of the two backends
+    const TOLERANCE: f32 = 1e-4;
+
+    const WIDTH: u32 = 40;
+    const HEIGHT: u32 = 24;
+
+    /// Bright square moving over a gradient with some noise, in RGBA8
+    fn frames(count: usize) -> Vec<Vec<u8>> {
+        let mut seed = 1u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 24) as i32 % 16
+        };
+
+        (0..count)
+            .map(|index| {
+                let (square_x, square_y) = (4 + 2 * index as u32, 6 + index as u32 % 3);
+
+                (0..HEIGHT)
+                    .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+                    .flat_map(|(x, y)| {
+                        let inside = (square_x..square_x + 8).contains(&x)
+                            && (square_y..square_y + 8).contains(&y);
+                        let level = if inside {
+                            220
+                        } else {
+                            40 + 3 * x as i32 + y as i32
+                        };
+                        let level = (level + noise()).clamp(0, 255) as u8;
+
+                        [level, level / 2, 255 - level, 255]
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Runs both backends on the same frames and checks that they output on the same frames
+    /// with the same raw diffs
+    fn compare_backends(properties: &DiPsProperties) {
+        let parameters = DiPsParameters::from(properties);
+        let mut gpu = ComputeState::new(properties).unwrap();
+        let mut cpu = CpuBackend::new(parameters.clone()).unwrap();
+
+        for (index, frame) in frames(12).iter().enumerate() {
+            let gpu_output = gpu.process_frame(WIDTH, HEIGHT, frame);
+            let cpu_output = cpu.process_frame(WIDTH, HEIGHT, frame);
+            assert_eq!(gpu_output.is_some(), cpu_output.is_some(), "frame {index}");
+
+            let (Some(gpu_diff), Some(cpu_diff)) = (gpu.raw_diff(), cpu.raw_diff()) else {
+                continue;
+            };
+            assert_eq!(gpu_diff.len(), cpu_diff.len());
+            for (pixel, (gpu_diff, cpu_diff)) in gpu_diff.iter().zip(cpu_diff.iter()).enumerate() {
+                assert!(
+                    (gpu_diff - cpu_diff).abs() <= TOLERANCE,
+                    "pixel {pixel} of frame {index} is {gpu_diff} on the GPU and {cpu_diff} on the CPU with {parameters:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn gpu_matches_cpu() {
+        let mut properties = DiPsProperties::new();
+        compare_backends(&properties);
+
+        for (spatial_filter, border_mode) in [
+            (SpatialFilter::Median, BorderMode::Clamp),
+            (SpatialFilter::Box, BorderMode::Mirror),
+            (SpatialFilter::Gaussian(1.5), BorderMode::Clamp),
+            (
+                SpatialFilter::Bilateral {
+                    spatial_sigma: 2.0,
+                    range_sigma: 0.1,
+                },
+                BorderMode::Mirror,
+            ),
+        ] {
+            properties.spatial_window_size = 5;
+            properties.spatial_filter = spatial_filter;
+            properties.border_mode = border_mode;
+            compare_backends(&properties);
+        }
+
+        properties.spatial_window_size = 3;
+        for temporal_estimator in [
+            TemporalEstimator::Mean,
+            TemporalEstimator::TrimmedMean(0.25),
+            TemporalEstimator::Max,
+            TemporalEstimator::Percentile(30.0),
+        ] {
+            properties.temporal_estimator = temporal_estimator;
+            compare_backends(&properties);
+        }
+
+        properties.temporal_estimator = TemporalEstimator::Median;
+        for baseline_mode in [
+            BaselineMode::ExponentialMovingAverage(3.0),
+            BaselineMode::SlidingMedian(4),
+        ] {
+            properties.baseline_mode = baseline_mode;
+            compare_backends(&properties);
+        }
+
+        properties.baseline_mode = BaselineMode::Fixed;
+        for difference_mode in [
+            DifferenceMode::FrameToFrame,
+            DifferenceMode::AccumulatedFrameToFrame(5.0),
+        ] {
+            properties.difference_mode = difference_mode;
+            compare_backends(&properties);
+        }
+
+        // The maps are half the frame size so they are scaled like on a binned sensor
+        properties.difference_mode = DifferenceMode::Baseline;
+        let (width, height) = (WIDTH / 2, HEIGHT / 2);
+        let pixels = (0..width * height)
+            .map(|pixel| (pixel % width, pixel / width))
+            .collect::<Vec<_>>();
+        properties.calibration = SensorCalibration {
+            dark: Some(
+                CalibrationFrame::from_rgb(
+                    width,
+                    height,
+                    pixels
+                        .iter()
+                        .map(|(x, _)| [0.02, 0.04, 0.01].map(|dark| dark * (*x % 3) as f32))
+                        .collect(),
+                )
+                .unwrap(),
+            ),
+            flat: Some(
+                CalibrationFrame::from_rgb(
+                    width,
+                    height,
+                    pixels
+                        .iter()
+                        .map(|(x, y)| {
+                            [0.9 - 0.02 * x.abs_diff(width / 2) as f32 - 0.01 * *y as f32; 3]
+                        })
+                        .collect(),
+                )
+                .unwrap(),
+            ),
+            hot_pixels: Some(
+                HotPixelMap::from_luma(
+                    width,
+                    height,
+                    &pixels
+                        .iter()
+                        .map(|(x, y)| if (x * 7 + y * 3) % 23 == 0 { 255 } else { 0 })
+                        .collect::<Vec<_>>(),
+                )
+                .unwrap(),
+            ),
+        };
+        compare_backends(&properties);
+
+        // The CPU calibrates and denoises the frames and uploads their float intensities
+        properties.denoise = FrequencyDenoise::LowPass { radius: 8.0 };
+        compare_backends(&properties);
+    }
+}
@@ -1,15 +1,78 @@
+// Format the frames are read in, swapped for r32float along with INTENSITY_INPUT
+alias InputTexture = texture_storage_2d<rgba8unorm, read>;
+
+// Intensity of the start texture, kept as a float so the baseline isn't quantised
 @group(0) @binding(0)
-var start_texture: texture_storage_2d<rgba8unorm, read>;
+var start_texture: texture_storage_2d<r32float, read>;
+
+// Rolling baseline state, one intensity per pixel
+@group(0) @binding(1)
+var<storage, read_write> rolling_baseline: array<f32>;
+
+// Previous temporally filtered frames for the sliding median baseline
+@group(0) @binding(2)
+var<storage, read_write> baseline_history: array<f32>;
+
+@group(0) @binding(3)
+var<uniform> baseline_history_index: u32;
+
+// Previous temporally filtered frame for the frame to frame difference modes
+@group(0) @binding(4)
+var<storage, read_write> previous_frame: array<f32>;
+
+// Leaky sum of the frame to frame changes
+@group(0) @binding(5)
+var<storage, read_write> accumulated_diff: array<f32>;
 
 @group(1) @binding(0)
-var temporal_texture_array: binding_array<texture_storage_2d<rgba8unorm, read_write> >;
+var temporal_texture_array: binding_array<InputTexture>;
 
 @group(1) @binding(1)
 var<uniform> starting_index: u32;
 
+// Spatially filtered intensities of every temporal texture, one frame per texture
+@group(1) @binding(2)
+var<storage, read_write> filtered_frames: array<f32>;
+
 @group(2) @binding(0)
 var output_texture: texture_storage_2d<rgba8unorm, write>;
 
+// Signed difference before the sensitivity filter and colorizing
+@group(2) @binding(1)
+var diff_texture: texture_storage_2d<r32float, write>;
+
+// Color of every diff from -1 to 1, one row of COLORMAP_SIZE texels
+@group(2) @binding(2)
+var colormap_lut: texture_2d<f32>;
+
+// Transfer curve of diffs from -1 to 1, one row of TRANSFER_LUT_SIZE texels
+@group(2) @binding(3)
+var transfer_lut: texture_2d<f32>;
+
+// Count of the raw diffs in each of HISTOGRAM_BINS bins from -1 to 1 for the auto sensitivity
+@group(2) @binding(4)
+var<storage, read_write> diff_histogram: array<atomic<u32>, HISTOGRAM_BINS>;
+
+// The filtered diff is displayed as gain * diff + OFFSET, the auto sensitivity keeps moving it
+@group(2) @binding(5)
+var<uniform> gain: f32;
+
+// Bit i of every pixel is set when it is in the region of interest i
+@group(2) @binding(6)
+var<storage, read> roi_mask: array<u32>;
+
+// ROI_STATS_WORDS words for every region of interest, see accumulate_roi_stats
+@group(2) @binding(7)
+var<storage, read_write> roi_stats: array<atomic<u32>>;
+
+// Dark frame of the sensor with the hot pixels in its alpha, see SensorCalibration
+@group(2) @binding(8)
+var dark_texture: texture_2d<f32>;
+
+// Flat field of the sensor normalised to a mean of 1
+@group(2) @binding(9)
+var flat_texture: texture_2d<f32>;
+
 
 // Compiled constants
 @id(0) override COLORIZE: bool = true;
@@ -19,46 +82,163 @@ var output_texture: texture_storage_2d<rgba8unorm, write>;
 // 1 = Inverse Sigmoid
 @id(3) override FILTER_TYPE: u32 = 0;
 @id(4) override CHROMA_FILTER: u32 = 0;
+@id(5) override TEMPORAL_WINDOW: i32 = 4;
+// 0 = Median
+// 1 = Mean
+// 2 = Trimmed Mean
+// 3 = Min
+// 4 = Max
+// 5 = Percentile
+@id(6) override TEMPORAL_ESTIMATOR: u32 = 0;
+// Fraction trimmed from each end for the trimmed mean, percentile for the percentile
+@id(7) override TEMPORAL_ESTIMATOR_PARAMETER: f32 = 0.0;
+// 0 = Fixed
+// 1 = Exponential Moving Average
+// 2 = Sliding Median
+@id(8) override BASELINE_MODE: u32 = 0;
+// Weight of the newest frame in the exponential moving average
+@id(9) override BASELINE_ALPHA: f32 = 0.0;
+// Number of previous frames in the sliding median
+@id(10) override BASELINE_WINDOW: u32 = 1;
+// 0 = Baseline
+// 1 = Frame to Frame
+// 2 = Accumulated Frame to Frame
+@id(11) override DIFFERENCE_MODE: u32 = 0;
+// Fraction of the accumulated changes kept from one frame to the next
+@id(12) override ACCUMULATION_DECAY: f32 = 0.0;
+// 0 = Median
+// 1 = Box
+// 2 = Gaussian
+// 3 = Bilateral
+@id(13) override SPATIAL_FILTER: u32 = 0;
+// Standard deviation in pixels of the Gaussian and bilateral filters
+@id(14) override SPATIAL_SIGMA: f32 = 1.0;
+// Standard deviation in intensity of the bilateral filter
+@id(15) override RANGE_SIGMA: f32 = 1.0;
+// 0 = Clamp
+// 1 = Mirror
+@id(16) override BORDER_MODE: u32 = 0;
+// 0 = Linear
+// 1 = Gamma
+// 2 = Symmetric Log
+// 3 = LUT
+@id(17) override TRANSFER_CURVE: u32 = 0;
+// Gamma for the gamma curve, linear scale for the symmetric log
+@id(18) override TRANSFER_PARAMETER: f32 = 1.0;
+@id(19) override OFFSET: f32 = 0.0;
+// Range the raw diff is clamped to before the transfer curve
+@id(20) override CLAMP_MIN: f32 = -1.0;
+@id(21) override CLAMP_MAX: f32 = 1.0;
+// Collect the diff histogram
+@id(22) override AUTO_SENSITIVITY: bool = false;
+// Number of regions of interest the diffs are summarised over
+@id(23) override ROI_COUNT: u32 = 0;
+// Show the grey frame instead of the diffs outside of every region
+@id(24) override ROI_GREY_OUTSIDE: bool = false;
+// Correct every frame with the dark frame, flat field and hot pixel map
+@id(25) override CALIBRATION: bool = false;
+// The frames are float intensities the CPU stages made instead of RGBA8 colors, see InputTexture
+@id(26) override INTENSITY_INPUT: bool = false;
 
 override WIN_SIZE_SQUARE = WINDOW_SIZE * WINDOW_SIZE;
 
-const SENSITIVITY: f32 = 5.0;
-const MEDIAN_ARRAY_SIZE: i32 = 4;
-const MAX_WIN_SIZE_SQUARE = 11 * 11;
+const COLORMAP_SIZE: u32 = 256;
+const TRANSFER_LUT_SIZE: u32 = 256;
+const HISTOGRAM_BINS: u32 = 1024;
+const ROI_STATS_WORDS: u32 = 4;
+const ROI_SUM_RANGE: f32 = 8.0;
+const ROI_SUM_SCALE: f32 = 65536.0;
+const MAX_TEMPORAL_WINDOW: i32 = 64;
+const WORKGROUP_SIZE: u32 = 16;
+const MAX_WINDOW_SIZE: u32 = 31;
+// The workgroup reads a tile of its own size plus half a window on every side
+const MAX_TILE_SIZE: u32 = WORKGROUP_SIZE + MAX_WINDOW_SIZE - 1;
+override TILE_SIZE: u32 = WORKGROUP_SIZE + u32(WINDOW_SIZE) - 1;
+
+var<workgroup> spatial_tile: array<f32, MAX_TILE_SIZE * MAX_TILE_SIZE>;
 
 // helper funcitons
-fn diff_to_color(diff: f32) -> vec3<f32> {
-    if (diff < 0) {
-        return hsl_to_rgb(0.0, abs(diff), 0.5);
+// Linearly interpolates the colormap lookup table over diffs from -1 to 1
+fn colormap(diff: f32) -> vec3<f32> {
+    let position = clamp(diff * 0.5 + 0.5, 0.0, 1.0) * f32(COLORMAP_SIZE - 1);
+    let lower = min(u32(floor(position)), COLORMAP_SIZE - 2);
+    let fraction = position - f32(lower);
+
+    let start = textureLoad(colormap_lut, vec2<u32>(lower, 0), 0).rgb;
+    let end = textureLoad(colormap_lut, vec2<u32>(lower + 1, 0), 0).rgb;
+
+    return mix(start, end, fraction);
+}
+
+// Shapes a clamped diff with the TRANSFER_CURVE
+fn transfer_curve(diff: f32) -> f32 {
+    switch TRANSFER_CURVE {
+        case 1u: {
+            return sign(diff) * pow(abs(diff), TRANSFER_PARAMETER);
+        }
+        case 2u: {
+            return sign(diff) * log(1.0 + abs(diff) / TRANSFER_PARAMETER) / log(1.0 + 1.0 / TRANSFER_PARAMETER);
+        }
+        case 3u: {
+            let position = clamp(diff * 0.5 + 0.5, 0.0, 1.0) * f32(TRANSFER_LUT_SIZE - 1);
+            let lower = min(u32(floor(position)), TRANSFER_LUT_SIZE - 2);
+            let fraction = position - f32(lower);
+
+            let start = textureLoad(transfer_lut, vec2<u32>(lower, 0), 0).r;
+            let end = textureLoad(transfer_lut, vec2<u32>(lower + 1, 0), 0).r;
+
+            return mix(start, end, fraction);
+        }
+        default: {
+            return diff;
+        }
     }
+}
+
+// Bin of the diff histogram a raw diff falls in
+fn histogram_bin(diff: f32) -> u32 {
+    let position = (diff * 0.5 + 0.5) * f32(HISTOGRAM_BINS);
 
-    return hsl_to_rgb(120.0, diff, 0.5);
+    return min(u32(max(position, 0.0)), HISTOGRAM_BINS - 1);
 }
 
+// Maps a float to an unsigned integer of the same order so that atomics can take the min and max
+fn orderable_bits(value: f32) -> u32 {
+    let bits = bitcast<u32>(value);
 
-// h must be between 0 and 360
-fn hsl_to_rgb(h: f32, s: f32, l: f32) -> vec3<f32> {
-    let chroma = s * (1 - abs(2 * l - 1));
-    let h_prime = h / 60.0;
-    let x = chroma * (1 - abs(h_prime % 2.0 - 1));
+    if ((bits & 0x80000000u) != 0u) {
+        return ~bits;
+    }
 
-    let m = l - chroma / 2.0;
+    return bits | 0x80000000u;
+}
 
-    if (h_prime >= 0 && h_prime < 1) {
-        return vec3<f32>(chroma + m, x + m, 0.0 + m);
-    } else if (h_prime >= 1 && h_prime < 2) {
-        return vec3<f32>(x + m, chroma + m, 0.0 + m);
-    } else if (h_prime >= 2 && h_prime < 3) {
-        return vec3<f32>(0.0 + m, chroma + m, x + m);
-    } else if (h_prime >= 3 && h_prime < 4) {
-        return vec3<f32>(0.0 + m, x + m, chroma + m);
-    } else if (h_prime >= 4 && h_prime < 5) {
-        return vec3<f32>(x + m, 0.0 + m, chroma + m);
-    } else if (h_prime >= 5 && h_prime <= 6) {
-        return vec3<f32>(chroma + m, 0.0 + m, x + m);
-    } else {
-        return vec3<f32>(0.0 + m, 0.0 + m, 0.0 + m);
-    } 
+// Adds the diff of a pixel to the statistics of every region it is in
+// The diffs are summed as positive fixed point numbers in a low and a high word, and the min and
+// max are kept as orderable bits
+fn accumulate_roi_stats(pixel: u32, diff: f32) {
+    let mask = roi_mask[pixel];
+    if (mask == 0u) {
+        return;
+    }
+
+    let term = u32(round((clamp(diff, -ROI_SUM_RANGE, ROI_SUM_RANGE) + ROI_SUM_RANGE) * ROI_SUM_SCALE));
+    let bits = orderable_bits(diff);
+
+    for (var roi: u32 = 0; roi < ROI_COUNT; roi++) {
+        if ((mask & (1u << roi)) == 0u) {
+            continue;
+        }
+
+        let stats = roi * ROI_STATS_WORDS;
+        let low = atomicAdd(&roi_stats[stats], term);
+        // Carry into the high word when the low word wraps around
+        if (low + term < low) {
+            atomicAdd(&roi_stats[stats + 1u], 1u);
+        }
+        atomicMin(&roi_stats[stats + 2u], bits);
+        atomicMax(&roi_stats[stats + 3u], bits);
+    }
 }
 
 fn get_intensity(color: vec4<f32>) -> f32 {
@@ -117,44 +297,275 @@ fn inv_sigmoid(
     return (-log((1.0 / (input + 0.5)) - 1)) / SIGMOID_HORIZONTAL_SCALAR;
 }
 
-/// Takes in the coordinates of the pixel and returns the spatial median filter
-/// color of that pixel with the set WINDOW_SIZE
-fn spatial_median_filter(coords: vec2<u32>, dimensions: vec2<u32>, input_texture: texture_storage_2d<rgba8unorm, read_write>) -> vec4<f32> {
+// Maps a coordinate outside of a texture dimension back inside with the BORDER_MODE
+fn border_coordinate(coordinate: i32, size: i32) -> i32 {
+    var mapped = coordinate;
+    if (BORDER_MODE == 1u) {
+        if (mapped < 0) {
+            mapped = -mapped;
+        }
+        if (mapped >= size) {
+            mapped = 2 * (size - 1) - mapped;
+        }
+    }
+
+    return clamp(mapped, 0, size - 1);
+}
+
+// Weight of a neighbour at the offset with the intensity in the weighted spatial filters
+fn spatial_weight(offset: vec2<i32>, intensity: f32, center: f32) -> f32 {
+    let distance = f32(offset.x * offset.x + offset.y * offset.y);
+
+    switch SPATIAL_FILTER {
+        case 2u: {
+            return exp(-distance / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA));
+        }
+        case 3u: {
+            let range = intensity - center;
+            return exp(-distance / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA) - range * range / (2.0 * RANGE_SIGMA * RANGE_SIGMA));
+        }
+        default: {
+            return 1.0;
+        }
+    }
+}
+
+// Dark frame and flat field corrected color of a pixel of a frame
+fn corrected_color(input_texture: InputTexture, coords: vec2<u32>) -> vec4<f32> {
+    let color = textureLoad(input_texture, coords);
+    let dark = textureLoad(dark_texture, coords, 0).rgb;
+    let flat_field = textureLoad(flat_texture, coords, 0).rgb;
+
+    return vec4<f32>(clamp((color.rgb - dark) / flat_field, vec3<f32>(0.0), vec3<f32>(1.0)), color.a);
+}
+
+// Color of a pixel of a frame with the sensor CALIBRATION applied, hot pixels take the mean of
+// the corrected colors of their good neighbours
+fn calibrated_color(input_texture: InputTexture, coords: vec2<u32>, dimensions: vec2<u32>) -> vec4<f32> {
+    // A grey color of the intensity has that intensity whatever the CHROMA_FILTER
+    if (INTENSITY_INPUT) {
+        return vec4<f32>(textureLoad(input_texture, coords).rrr, 1.0);
+    }
+
+    if (!CALIBRATION) {
+        return textureLoad(input_texture, coords);
+    }
+
+    if (textureLoad(dark_texture, coords, 0).a < 0.5) {
+        return corrected_color(input_texture, coords);
+    }
+
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let neighbour = vec2<i32>(coords) + vec2<i32>(dx, dy);
+            if ((dx == 0 && dy == 0) || any(neighbour < vec2<i32>(0)) || any(neighbour >= vec2<i32>(dimensions))) {
+                continue;
+            }
+
+            if (textureLoad(dark_texture, vec2<u32>(neighbour), 0).a > 0.5) {
+                continue;
+            }
+
+            sum += corrected_color(input_texture, vec2<u32>(neighbour)).rgb;
+            count += 1.0;
+        }
+    }
+
+    if (count == 0.0) {
+        return corrected_color(input_texture, coords);
+    }
+
+    return vec4<f32>(sum / count, 1.0);
+}
+
+/// Takes in the local coordinates of the pixel in its workgroup and returns the SPATIAL_FILTER
+/// color of that pixel with the set WINDOW_SIZE centred on it
+/// Every invocation of the workgroup has to call it since the window is read from the shared tile
+fn spatial_filter(local_id: vec2<u32>, workgroup_id: vec2<u32>, dimensions: vec2<u32>, input_texture: InputTexture) -> vec4<f32> {
     if (WINDOW_SIZE == 1) {
-        let intensity = get_intensity(textureLoad(input_texture, coords.xy));
+        let intensity = get_intensity(calibrated_color(input_texture, workgroup_id * WORKGROUP_SIZE + local_id, dimensions));
         return vec4<f32>(intensity, intensity, intensity, 1.0);
     }
 
-    
-    var median_array: array<f32, MAX_WIN_SIZE_SQUARE>;
+    // The tile is shared with the previous filter so wait for everyone to be done with it
+    workgroupBarrier();
+
+    // Load every pixel the windows of the workgroup cover into the tile once
+    let origin = vec2<i32>(workgroup_id * WORKGROUP_SIZE) - vec2<i32>(WINDOW_SIZE / 2);
+    let local_index = local_id.y * WORKGROUP_SIZE + local_id.x;
+    for (var i = local_index; i < TILE_SIZE * TILE_SIZE; i += WORKGROUP_SIZE * WORKGROUP_SIZE) {
+        let x = border_coordinate(origin.x + i32(i % TILE_SIZE), i32(dimensions.x));
+        let y = border_coordinate(origin.y + i32(i / TILE_SIZE), i32(dimensions.y));
+
+        spatial_tile[i] = get_intensity(calibrated_color(input_texture, vec2<u32>(u32(x), u32(y)), dimensions));
+    }
+
+    workgroupBarrier();
+
+    let tile_center = vec2<i32>(local_id) + vec2<i32>(WINDOW_SIZE / 2);
+    var intensity: f32;
+    if (SPATIAL_FILTER == 0u) {
+        intensity = spatial_median(tile_center);
+    } else {
+        intensity = spatial_weighted_mean(tile_center);
+    }
+
+    return vec4<f32>(intensity, intensity, intensity, 1.0);
+}
+
+fn tile_intensity(position: vec2<i32>) -> f32 {
+    return spatial_tile[u32(position.x) + TILE_SIZE * u32(position.y)];
+}
+
+// Box, Gaussian or bilateral mean of the window centred on the tile position
+fn spatial_weighted_mean(tile_center: vec2<i32>) -> f32 {
+    let center = tile_intensity(tile_center);
     let win_size_2 = WINDOW_SIZE / 2;
 
-    for (var i = -win_size_2; i < win_size_2; i++) {
-        for (var j = -win_size_2; j < win_size_2; j++) {
-            var color: f32;
-            if (i32(coords.x) + i >= i32(dimensions.x) || i32(coords.y) + j >= i32(dimensions.y) || i32(coords.x) + i < 0 || i32(coords.y) + j < 0) {
-                color = 0.0;
-            } else {
-                color = get_intensity(textureLoad(input_texture, vec2<u32>(u32(i32(coords.x) + i), u32(i32(coords.y) + j))));
+    var weighted_sum = 0.0;
+    var weight_sum = 0.0;
+    for (var j = -win_size_2; j <= win_size_2; j++) {
+        for (var i = -win_size_2; i <= win_size_2; i++) {
+            let color = tile_intensity(tile_center + vec2<i32>(i, j));
+            let weight = spatial_weight(vec2<i32>(i, j), color, center);
+
+            weighted_sum += weight * color;
+            weight_sum += weight;
+        }
+    }
+
+    return weighted_sum / weight_sum;
+}
+
+// Key of an intensity whose unsigned order is the order of the floats, like f32::total_cmp
+fn order_key(value: f32) -> u32 {
+    let bits = bitcast<u32>(value);
+    if ((bits & 0x80000000u) != 0u) {
+        return ~bits;
+    }
+    return bits | 0x80000000u;
+}
+
+fn from_order_key(key: u32) -> f32 {
+    if ((key & 0x80000000u) != 0u) {
+        return bitcast<f32>(key & 0x7fffffffu);
+    }
+    return bitcast<f32>(~key);
+}
+
+// Median of the window centred on the tile position, the upper middle value like the CPU backend
+// Bisects the order keys of the intensities between the smallest and largest of the window while
+// counting the window instead of sorting it, so the cost only grows with the window area. The
+// bisection ends on the key of an intensity of the window, so the median is exact whatever the
+// calibration or denoising made of the intensities
+fn spatial_median(tile_center: vec2<i32>) -> f32 {
+    let win_size_2 = WINDOW_SIZE / 2;
+    // Position of the median in the sorted window
+    let rank = u32(WIN_SIZE_SQUARE / 2);
+
+    var low: u32 = 0xffffffffu;
+    var high: u32 = 0u;
+    for (var j = -win_size_2; j <= win_size_2; j++) {
+        for (var i = -win_size_2; i <= win_size_2; i++) {
+            let key = order_key(tile_intensity(tile_center + vec2<i32>(i, j)));
+            low = min(low, key);
+            high = max(high, key);
+        }
+    }
+
+    while (low < high) {
+        let middle = low + (high - low) / 2u;
+
+        var count: u32 = 0;
+        for (var j = -win_size_2; j <= win_size_2; j++) {
+            for (var i = -win_size_2; i <= win_size_2; i++) {
+                if (order_key(tile_intensity(tile_center + vec2<i32>(i, j))) <= middle) {
+                    count++;
+                }
             }
+        }
+
+        if (count > rank) {
+            high = middle;
+        } else {
+            low = middle + 1u;
+        }
+    }
+
+    return from_order_key(low);
+}
+
+// Sorts the temporal window and reduces it with the TEMPORAL_ESTIMATOR
+fn temporal_estimate(values: ptr<function, array<f32, MAX_TEMPORAL_WINDOW>>) -> f32 {
+    for (var i = 0; i < TEMPORAL_WINDOW; i++) {
+        var swapped: bool = false;
+        for (var j = 0; j < TEMPORAL_WINDOW - 1; j++) {
+            if ((*values)[j] > (*values)[j + 1]) {
+                let temp = (*values)[j];
+                (*values)[j] = (*values)[j + 1];
+                (*values)[j + 1] = temp;
 
-            let array_i = i + win_size_2;
-            let array_j = j + win_size_2;
+                swapped = true;
+            }
+        }
 
-            let array_ind = array_i + (WINDOW_SIZE * array_j);
+        if (!swapped) {
+            break;
+        }
+    }
 
-            median_array[array_ind] = color;
+    switch TEMPORAL_ESTIMATOR {
+        case 1u: {
+            return mean(values, 0, TEMPORAL_WINDOW);
+        }
+        case 2u: {
+            let trim = i32(f32(TEMPORAL_WINDOW) * TEMPORAL_ESTIMATOR_PARAMETER);
+            return mean(values, trim, TEMPORAL_WINDOW - trim);
+        }
+        case 3u: {
+            return (*values)[0];
+        }
+        case 4u: {
+            return (*values)[TEMPORAL_WINDOW - 1];
+        }
+        case 5u: {
+            let position = TEMPORAL_ESTIMATOR_PARAMETER / 100.0 * f32(TEMPORAL_WINDOW - 1);
+            let lower = i32(floor(position));
+            let upper = min(lower + 1, TEMPORAL_WINDOW - 1);
+            return mix((*values)[lower], (*values)[upper], fract(position));
         }
+        default: {
+            return (*values)[TEMPORAL_WINDOW / 2];
+        }
+    }
+}
+
+// Mean of the sorted values in [start, end)
+fn mean(values: ptr<function, array<f32, MAX_TEMPORAL_WINDOW>>, start: i32, end: i32) -> f32 {
+    var sum = 0.0;
+    for (var i = start; i < end; i++) {
+        sum += (*values)[i];
     }
 
-    // sort the array
-    for (var i = 0; i < WIN_SIZE_SQUARE; i++) {
+    return sum / f32(end - start);
+}
+
+// Median of the previous frames kept in the baseline history
+fn sliding_median(pixel: u32, pixel_count: u32) -> f32 {
+    var history: array<f32, MAX_TEMPORAL_WINDOW>;
+    for (var i: u32 = 0; i < BASELINE_WINDOW; i++) {
+        history[i] = baseline_history[i * pixel_count + pixel];
+    }
+
+    for (var i: u32 = 0; i < BASELINE_WINDOW; i++) {
         var swapped: bool = false;
-        for (var j = 0; j < WIN_SIZE_SQUARE; j++) {
-            if (median_array[j] > median_array[j + 1]) {
-                let temp = median_array[j];
-                median_array[j] = median_array[j + 1];
-                median_array[j + 1] = temp;
+        for (var j: u32 = 0; j < BASELINE_WINDOW - 1; j++) {
+            if (history[j] > history[j + 1]) {
+                let temp = history[j];
+                history[j] = history[j + 1];
+                history[j + 1] = temp;
 
                 swapped = true;
             }
@@ -165,55 +576,100 @@ fn spatial_median_filter(coords: vec2<u32>, dimensions: vec2<u32>, input_texture
         }
     }
 
-    let intensity = median_array[(WIN_SIZE_SQUARE / 2) + 1];
-    return vec4<f32>(intensity, intensity, intensity, 1.0);
+    return history[BASELINE_WINDOW / 2];
 }
 
-@compute @workgroup_size(16, 16)
-fn compute_main(
+// Spatially filters the newest temporal texture into its slot of the filtered frames
+// Every layer of workgroups filters the next texture on from the starting index so that the
+// whole window can be filtered at once
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn filter_main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
 ) {
     let dimensions = textureDimensions(output_texture);
     let coords = vec2<u32>(global_id.xy);
+    let texture_index = (starting_index + workgroup_id.z) % u32(TEMPORAL_WINDOW);
+
+    // Every invocation helps load the spatial tile so pixels outside of the texture are only
+    // dropped afterwards
+    let filtered_color = spatial_filter(local_id.xy, workgroup_id.xy, dimensions.xy, temporal_texture_array[texture_index]);
 
     if (coords.x >= dimensions.x || coords.y >= dimensions.y) {
         return;
     }
 
+    let pixel = coords.y * dimensions.x + coords.x;
+    let pixel_count = dimensions.x * dimensions.y;
+    filtered_frames[texture_index * pixel_count + pixel] = filtered_color.r;
+}
 
-    var median_array: array<f32, MEDIAN_ARRAY_SIZE>;
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn compute_main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+) {
+    let dimensions = textureDimensions(output_texture);
+    let coords = vec2<u32>(global_id.xy);
 
-    // Apply the spatial filter to the texture that has been changed for future reference
-    textureStore(temporal_texture_array[starting_index], coords.xy, spatial_median_filter(coords.xy, dimensions.xy, temporal_texture_array[starting_index]));
-    // textureStore(temporal_texture_array[starting_index], coords.xy, textureLoad(temporal_texture_array[starting_index], coords.xy));
-   
-    // Fill the median array with the values from all the spatially filtered textures
-    for (var i = 0; i < MEDIAN_ARRAY_SIZE; i++) {
-        median_array[i] = get_intensity(textureLoad(temporal_texture_array[i], coords.xy));
+    if (coords.x >= dimensions.x || coords.y >= dimensions.y) {
+        return;
     }
 
-    // Sort the temporl texture array
-    for (var i = 0; i < MEDIAN_ARRAY_SIZE; i++) {
-        var swapped: bool = false;
-        for (var j = 0; j < MEDIAN_ARRAY_SIZE; j++) {
-            if (median_array[j] > median_array[j + 1]) {
-                let temp = median_array[j];
-                median_array[j] = median_array[j + 1];
-                median_array[j + 1] = temp;
+    let pixel = coords.y * dimensions.x + coords.x;
+    let pixel_count = dimensions.x * dimensions.y;
 
-                swapped = true;
-            }
+    // Fill the median array with the values from all the spatially filtered frames
+    var median_array: array<f32, MAX_TEMPORAL_WINDOW>;
+    for (var i = 0; i < TEMPORAL_WINDOW; i++) {
+        median_array[i] = filtered_frames[u32(i) * pixel_count + pixel];
+    }
+
+    let intensity = temporal_estimate(&median_array);
+
+    // Get the baseline to compare against and move it along for the rolling baselines
+    var original_intensity: f32;
+    switch BASELINE_MODE {
+        case 1u: {
+            original_intensity = rolling_baseline[pixel];
+            rolling_baseline[pixel] = mix(original_intensity, intensity, BASELINE_ALPHA);
         }
+        case 2u: {
+            original_intensity = sliding_median(pixel, pixel_count);
+            baseline_history[baseline_history_index * pixel_count + pixel] = intensity;
+        }
+        default: {
+            original_intensity = textureLoad(start_texture, coords.xy).r;
+        }
+    }
 
-        if (!swapped) {
-            break;
+    var diff: f32;
+    switch DIFFERENCE_MODE {
+        case 1u: {
+            diff = previous_frame[pixel] - intensity;
+            previous_frame[pixel] = intensity;
+        }
+        case 2u: {
+            diff = previous_frame[pixel] - intensity + accumulated_diff[pixel] * ACCUMULATION_DECAY;
+            previous_frame[pixel] = intensity;
+            accumulated_diff[pixel] = diff;
+        }
+        default: {
+            diff = original_intensity - intensity;
         }
     }
-    
-    let original_intensity = textureLoad(start_texture, coords.xy).r;
-    var diff = (original_intensity - median_array[MEDIAN_ARRAY_SIZE / 2]);
 
+    textureStore(diff_texture, coords.xy, vec4<f32>(diff, 0.0, 0.0, 0.0));
+
+    if (AUTO_SENSITIVITY) {
+        atomicAdd(&diff_histogram[histogram_bin(diff)], 1u);
+    }
+
+    if (ROI_COUNT > 0u) {
+        accumulate_roi_stats(pixel, diff);
+    }
 
+    diff = transfer_curve(clamp(diff, CLAMP_MIN, CLAMP_MAX));
     diff = map(diff, -1.0, 1.0, -0.5, 0.5);
 
     switch FILTER_TYPE {
@@ -226,15 +682,19 @@ fn compute_main(
         default: {}
     }
 
-    diff *= SENSITIVITY;
+    diff = diff * gain + OFFSET;
     
     var new_color: vec3<f32>;
 
     if (COLORIZE == true) {
-        new_color = diff_to_color(diff);
+        new_color = colormap(diff);
     } else {
         new_color = vec3<f32>(0.5, 0.5, 0.5) - vec3<f32>(diff, diff, diff);
     }
+
+    if (ROI_GREY_OUTSIDE && ROI_COUNT > 0u && roi_mask[pixel] == 0u) {
+        new_color = vec3<f32>(intensity, intensity, intensity);
+    }
     
     textureStore(output_texture, coords.xy, vec4<f32>(new_color.rgb, 1.0));
 }
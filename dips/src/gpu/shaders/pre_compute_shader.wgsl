@@ -1,20 +1,64 @@
+// Format the frames are read in, swapped for r32float along with INTENSITY_INPUT
+alias InputTexture = texture_storage_2d<rgba8unorm, read>;
+
 @group(0) @binding(0)
-var start_texture_array: binding_array<texture_storage_2d<rgba8unorm, read> >;
+var start_texture_array: binding_array<InputTexture>;
 
+// Temporal estimate of the start textures as a float intensity
 @group(1) @binding(0)
-var output_texture: texture_storage_2d<rgba8unorm, write>;
+var output_texture: texture_storage_2d<r32float, write>;
+
+// Dark frame of the sensor with the hot pixels in its alpha, see SensorCalibration
+@group(1) @binding(1)
+var dark_texture: texture_2d<f32>;
+
+// Flat field of the sensor normalised to a mean of 1
+@group(1) @binding(2)
+var flat_texture: texture_2d<f32>;
 
 
 // Compiled constants
 @id(1) override WINDOW_SIZE: i32 = 3;
 @id(4) override CHROMA_FILTER: u32 = 0;
+@id(5) override TEMPORAL_WINDOW: i32 = 4;
+// 0 = Median
+// 1 = Mean
+// 2 = Trimmed Mean
+// 3 = Min
+// 4 = Max
+// 5 = Percentile
+@id(6) override TEMPORAL_ESTIMATOR: u32 = 0;
+// Fraction trimmed from each end for the trimmed mean, percentile for the percentile
+@id(7) override TEMPORAL_ESTIMATOR_PARAMETER: f32 = 0.0;
+// 0 = Median
+// 1 = Box
+// 2 = Gaussian
+// 3 = Bilateral
+@id(13) override SPATIAL_FILTER: u32 = 0;
+// Standard deviation in pixels of the Gaussian and bilateral filters
+@id(14) override SPATIAL_SIGMA: f32 = 1.0;
+// Standard deviation in intensity of the bilateral filter
+@id(15) override RANGE_SIGMA: f32 = 1.0;
+// 0 = Clamp
+// 1 = Mirror
+@id(16) override BORDER_MODE: u32 = 0;
+// Correct every frame with the dark frame, flat field and hot pixel map
+@id(25) override CALIBRATION: bool = false;
+// The frames are float intensities the CPU stages made instead of RGBA8 colors, see InputTexture
+@id(26) override INTENSITY_INPUT: bool = false;
 
 override WIN_SIZE_SQUARE: i32 = WINDOW_SIZE * WINDOW_SIZE;
 
 const SENSITIVITY: f32 = 2.0;
-const MEDIAN_ARRAY_SIZE: i32 = 4;
+const MAX_TEMPORAL_WINDOW: i32 = 64;
 
-const MAX_WIN_SIZE_SQUARE = 11 * 11;
+const WORKGROUP_SIZE: u32 = 16;
+const MAX_WINDOW_SIZE: u32 = 31;
+// The workgroup reads a tile of its own size plus half a window on every side
+const MAX_TILE_SIZE: u32 = WORKGROUP_SIZE + MAX_WINDOW_SIZE - 1;
+override TILE_SIZE: u32 = WORKGROUP_SIZE + u32(WINDOW_SIZE) - 1;
+
+var<workgroup> spatial_tile: array<f32, MAX_TILE_SIZE * MAX_TILE_SIZE>;
 
 // helper funcitons
 fn get_intensity(color: vec4<f32>) -> f32 {
@@ -37,44 +81,215 @@ fn get_intensity(color: vec4<f32>) -> f32 {
     return luminance;
 }
 
-/// Takes in the coordinates of the pixel and returns the spatial median filter
-/// color of that pixel with the set WINDOW_SIZE
-fn spatial_median_filter(coords: vec2<u32>, dimensions: vec2<u32>, input_texture: texture_storage_2d<rgba8unorm, read>) -> vec4<f32> {
+// Maps a coordinate outside of a texture dimension back inside with the BORDER_MODE
+fn border_coordinate(coordinate: i32, size: i32) -> i32 {
+    var mapped = coordinate;
+    if (BORDER_MODE == 1u) {
+        if (mapped < 0) {
+            mapped = -mapped;
+        }
+        if (mapped >= size) {
+            mapped = 2 * (size - 1) - mapped;
+        }
+    }
+
+    return clamp(mapped, 0, size - 1);
+}
+
+// Weight of a neighbour at the offset with the intensity in the weighted spatial filters
+fn spatial_weight(offset: vec2<i32>, intensity: f32, center: f32) -> f32 {
+    let distance = f32(offset.x * offset.x + offset.y * offset.y);
+
+    switch SPATIAL_FILTER {
+        case 2u: {
+            return exp(-distance / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA));
+        }
+        case 3u: {
+            let range = intensity - center;
+            return exp(-distance / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA) - range * range / (2.0 * RANGE_SIGMA * RANGE_SIGMA));
+        }
+        default: {
+            return 1.0;
+        }
+    }
+}
+
+// Dark frame and flat field corrected color of a pixel of a frame
+fn corrected_color(input_texture: InputTexture, coords: vec2<u32>) -> vec4<f32> {
+    let color = textureLoad(input_texture, coords);
+    let dark = textureLoad(dark_texture, coords, 0).rgb;
+    let flat_field = textureLoad(flat_texture, coords, 0).rgb;
+
+    return vec4<f32>(clamp((color.rgb - dark) / flat_field, vec3<f32>(0.0), vec3<f32>(1.0)), color.a);
+}
+
+// Color of a pixel of a frame with the sensor CALIBRATION applied, hot pixels take the mean of
+// the corrected colors of their good neighbours
+fn calibrated_color(input_texture: InputTexture, coords: vec2<u32>, dimensions: vec2<u32>) -> vec4<f32> {
+    // A grey color of the intensity has that intensity whatever the CHROMA_FILTER
+    if (INTENSITY_INPUT) {
+        return vec4<f32>(textureLoad(input_texture, coords).rrr, 1.0);
+    }
+
+    if (!CALIBRATION) {
+        return textureLoad(input_texture, coords);
+    }
+
+    if (textureLoad(dark_texture, coords, 0).a < 0.5) {
+        return corrected_color(input_texture, coords);
+    }
+
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let neighbour = vec2<i32>(coords) + vec2<i32>(dx, dy);
+            if ((dx == 0 && dy == 0) || any(neighbour < vec2<i32>(0)) || any(neighbour >= vec2<i32>(dimensions))) {
+                continue;
+            }
+
+            if (textureLoad(dark_texture, vec2<u32>(neighbour), 0).a > 0.5) {
+                continue;
+            }
+
+            sum += corrected_color(input_texture, vec2<u32>(neighbour)).rgb;
+            count += 1.0;
+        }
+    }
+
+    if (count == 0.0) {
+        return corrected_color(input_texture, coords);
+    }
+
+    return vec4<f32>(sum / count, 1.0);
+}
+
+/// Takes in the local coordinates of the pixel in its workgroup and returns the SPATIAL_FILTER
+/// color of that pixel with the set WINDOW_SIZE centred on it
+/// Every invocation of the workgroup has to call it since the window is read from the shared tile
+fn spatial_filter(local_id: vec2<u32>, workgroup_id: vec2<u32>, dimensions: vec2<u32>, input_texture: InputTexture) -> vec4<f32> {
     if (WINDOW_SIZE == 1) {
-        let intensity = get_intensity(textureLoad(input_texture, coords.xy));
+        let intensity = get_intensity(calibrated_color(input_texture, workgroup_id * WORKGROUP_SIZE + local_id, dimensions));
         return vec4<f32>(intensity, intensity, intensity, 1.0);
     }
 
-    
-    var median_array: array<f32, MAX_WIN_SIZE_SQUARE>;
+    // The tile is shared with the previous filter so wait for everyone to be done with it
+    workgroupBarrier();
+
+    // Load every pixel the windows of the workgroup cover into the tile once
+    let origin = vec2<i32>(workgroup_id * WORKGROUP_SIZE) - vec2<i32>(WINDOW_SIZE / 2);
+    let local_index = local_id.y * WORKGROUP_SIZE + local_id.x;
+    for (var i = local_index; i < TILE_SIZE * TILE_SIZE; i += WORKGROUP_SIZE * WORKGROUP_SIZE) {
+        let x = border_coordinate(origin.x + i32(i % TILE_SIZE), i32(dimensions.x));
+        let y = border_coordinate(origin.y + i32(i / TILE_SIZE), i32(dimensions.y));
+
+        spatial_tile[i] = get_intensity(calibrated_color(input_texture, vec2<u32>(u32(x), u32(y)), dimensions));
+    }
+
+    workgroupBarrier();
+
+    let tile_center = vec2<i32>(local_id) + vec2<i32>(WINDOW_SIZE / 2);
+    var intensity: f32;
+    if (SPATIAL_FILTER == 0u) {
+        intensity = spatial_median(tile_center);
+    } else {
+        intensity = spatial_weighted_mean(tile_center);
+    }
+
+    return vec4<f32>(intensity, intensity, intensity, 1.0);
+}
+
+fn tile_intensity(position: vec2<i32>) -> f32 {
+    return spatial_tile[u32(position.x) + TILE_SIZE * u32(position.y)];
+}
+
+// Box, Gaussian or bilateral mean of the window centred on the tile position
+fn spatial_weighted_mean(tile_center: vec2<i32>) -> f32 {
+    let center = tile_intensity(tile_center);
+    let win_size_2 = WINDOW_SIZE / 2;
+
+    var weighted_sum = 0.0;
+    var weight_sum = 0.0;
+    for (var j = -win_size_2; j <= win_size_2; j++) {
+        for (var i = -win_size_2; i <= win_size_2; i++) {
+            let color = tile_intensity(tile_center + vec2<i32>(i, j));
+            let weight = spatial_weight(vec2<i32>(i, j), color, center);
+
+            weighted_sum += weight * color;
+            weight_sum += weight;
+        }
+    }
+
+    return weighted_sum / weight_sum;
+}
+
+// Key of an intensity whose unsigned order is the order of the floats, like f32::total_cmp
+fn order_key(value: f32) -> u32 {
+    let bits = bitcast<u32>(value);
+    if ((bits & 0x80000000u) != 0u) {
+        return ~bits;
+    }
+    return bits | 0x80000000u;
+}
+
+fn from_order_key(key: u32) -> f32 {
+    if ((key & 0x80000000u) != 0u) {
+        return bitcast<f32>(key & 0x7fffffffu);
+    }
+    return bitcast<f32>(~key);
+}
+
+// Median of the window centred on the tile position, the upper middle value like the CPU backend
+// Bisects the order keys of the intensities between the smallest and largest of the window while
+// counting the window instead of sorting it, so the cost only grows with the window area. The
+// bisection ends on the key of an intensity of the window, so the median is exact whatever the
+// calibration or denoising made of the intensities
+fn spatial_median(tile_center: vec2<i32>) -> f32 {
     let win_size_2 = WINDOW_SIZE / 2;
+    // Position of the median in the sorted window
+    let rank = u32(WIN_SIZE_SQUARE / 2);
 
-    for (var i = -win_size_2; i < win_size_2; i++) {
-        for (var j = -win_size_2; j < win_size_2; j++) {
-            var color: f32;
-            if (i32(coords.x) + i >= i32(dimensions.x) || i32(coords.y) + j >= i32(dimensions.y) || i32(coords.x) + i < 0 || i32(coords.y) + j < 0) {
-                color = 0.0;
-            } else {
-                color = get_intensity(textureLoad(input_texture, vec2<u32>(u32(i32(coords.x) + i), u32(i32(coords.y) + j))));
-            }
+    var low: u32 = 0xffffffffu;
+    var high: u32 = 0u;
+    for (var j = -win_size_2; j <= win_size_2; j++) {
+        for (var i = -win_size_2; i <= win_size_2; i++) {
+            let key = order_key(tile_intensity(tile_center + vec2<i32>(i, j)));
+            low = min(low, key);
+            high = max(high, key);
+        }
+    }
 
-            let array_i = i + win_size_2;
-            let array_j = j + win_size_2;
+    while (low < high) {
+        let middle = low + (high - low) / 2u;
 
-            let array_ind = array_i + (WINDOW_SIZE * array_j);
+        var count: u32 = 0;
+        for (var j = -win_size_2; j <= win_size_2; j++) {
+            for (var i = -win_size_2; i <= win_size_2; i++) {
+                if (order_key(tile_intensity(tile_center + vec2<i32>(i, j))) <= middle) {
+                    count++;
+                }
+            }
+        }
 
-            median_array[array_ind] = color;
+        if (count > rank) {
+            high = middle;
+        } else {
+            low = middle + 1u;
         }
     }
 
-    // sort the array
-    for (var i = 0; i < WIN_SIZE_SQUARE; i++) {
+    return from_order_key(low);
+}
+
+// Sorts the temporal window and reduces it with the TEMPORAL_ESTIMATOR
+fn temporal_estimate(values: ptr<function, array<f32, MAX_TEMPORAL_WINDOW>>) -> f32 {
+    for (var i = 0; i < TEMPORAL_WINDOW; i++) {
         var swapped: bool = false;
-        for (var j = 0; j < WIN_SIZE_SQUARE; j++) {
-            if (median_array[j] > median_array[j + 1]) {
-                let temp = median_array[j];
-                median_array[j] = median_array[j + 1];
-                median_array[j + 1] = temp;
+        for (var j = 0; j < TEMPORAL_WINDOW - 1; j++) {
+            if ((*values)[j] > (*values)[j + 1]) {
+                let temp = (*values)[j];
+                (*values)[j] = (*values)[j + 1];
+                (*values)[j + 1] = temp;
 
                 swapped = true;
             }
@@ -85,48 +300,64 @@ fn spatial_median_filter(coords: vec2<u32>, dimensions: vec2<u32>, input_texture
         }
     }
 
-    let intensity = median_array[(WIN_SIZE_SQUARE / 2) + 1];
-    return vec4<f32>(intensity, intensity, intensity, 1.0);
+    switch TEMPORAL_ESTIMATOR {
+        case 1u: {
+            return mean(values, 0, TEMPORAL_WINDOW);
+        }
+        case 2u: {
+            let trim = i32(f32(TEMPORAL_WINDOW) * TEMPORAL_ESTIMATOR_PARAMETER);
+            return mean(values, trim, TEMPORAL_WINDOW - trim);
+        }
+        case 3u: {
+            return (*values)[0];
+        }
+        case 4u: {
+            return (*values)[TEMPORAL_WINDOW - 1];
+        }
+        case 5u: {
+            let position = TEMPORAL_ESTIMATOR_PARAMETER / 100.0 * f32(TEMPORAL_WINDOW - 1);
+            let lower = i32(floor(position));
+            let upper = min(lower + 1, TEMPORAL_WINDOW - 1);
+            return mix((*values)[lower], (*values)[upper], fract(position));
+        }
+        default: {
+            return (*values)[TEMPORAL_WINDOW / 2];
+        }
+    }
 }
 
-@compute @workgroup_size(16, 16)
+// Mean of the sorted values in [start, end)
+fn mean(values: ptr<function, array<f32, MAX_TEMPORAL_WINDOW>>, start: i32, end: i32) -> f32 {
+    var sum = 0.0;
+    for (var i = start; i < end; i++) {
+        sum += (*values)[i];
+    }
+
+    return sum / f32(end - start);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
 fn pre_compute_main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
 ) {
     let dimensions = textureDimensions(output_texture);
     let coords = vec2<u32>(global_id.xy);
 
-    if (coords.x >= dimensions.x || coords.y >= dimensions.y) {
-        return;
+    // Find the temporal estimate of the start textures
+    // Every invocation helps load the spatial tiles so pixels outside of the texture are only
+    // dropped afterwards
+    var start_median_array: array<f32, MAX_TEMPORAL_WINDOW>;
+    for (var i = 0; i < TEMPORAL_WINDOW; i++) {
+        start_median_array[i] = get_intensity(spatial_filter(local_id.xy, workgroup_id.xy, dimensions.xy, start_texture_array[i]));
     }
 
-    // Find the temporal median of the start textures
-    var start_median_array: array<f32, MEDIAN_ARRAY_SIZE>;
-    start_median_array[0] = get_intensity(spatial_median_filter(coords.xy, dimensions.xy, start_texture_array[0]));
-    start_median_array[1] = get_intensity(spatial_median_filter(coords.xy, dimensions.xy, start_texture_array[1]));
-    start_median_array[2] = get_intensity(spatial_median_filter(coords.xy, dimensions.xy, start_texture_array[2]));
-    start_median_array[3] = get_intensity(spatial_median_filter(coords.xy, dimensions.xy, start_texture_array[3]));
-
-    // Sort the start median array
-    for (var i = 0; i < MEDIAN_ARRAY_SIZE; i++) {
-        var swapped: bool = false;
-        for (var j = 0; j < MEDIAN_ARRAY_SIZE; j++) {
-            if (start_median_array[j] > start_median_array[j + 1]) {
-                let temp = start_median_array[j];
-                start_median_array[j] = start_median_array[j + 1];
-                start_median_array[j + 1] = temp;
-
-                swapped = true;
-            }
-        }
-
-        if (!swapped) {
-            break;
-        }
+    if (coords.x >= dimensions.x || coords.y >= dimensions.y) {
+        return;
     }
 
-    let intensity = start_median_array[MEDIAN_ARRAY_SIZE / 2];
-    let new_color = vec3<f32>(intensity, intensity, intensity);
+    let intensity = temporal_estimate(&start_median_array);
 
-    textureStore(output_texture, coords.xy, vec4<f32>(new_color.rgb, 1.0));
+    textureStore(output_texture, coords.xy, vec4<f32>(intensity, 0.0, 0.0, 0.0));
 }
@@ -1,10 +1,24 @@
 use std::{
     error::Error,
     fmt::Display,
+    path::Path,
+    str::FromStr,
     sync::{Arc, Mutex},
 };
 
-use gpu::ComputeState;
+use dips_core::DiPsParameters;
+pub use dips_core::{
+    AutoSensitivity, Backend, BaselineMode, Blob, BlobTracker, BorderMode, BoundingBox,
+    COLORMAP_SIZE, CalibrationFrame, Colormap, CpuBackend, DEFAULT_TEMPORAL_WINDOW, DecimatedFrame,
+    Decimation, DiPsBackend, DiffExportFormat, DiffExporter, DifferenceMode, EventDetection,
+    EventDetector, EventLog, FrameDecimator, FrequencyAnalysis, FrequencyAnalyzer, FrequencyBand,
+    FrequencyDenoise, FrequencyDenoiser, FrequencyWindow, HotPixelMap, MAX_ROIS,
+    MAX_SPATIAL_WINDOW, MAX_TEMPORAL_WINDOW, MotExporter, Motion, MotionCompensation,
+    MotionCompensator, Retiming, Roi, RoiSeriesExporter, RoiSeriesFormat, RoiShape, RoiStats, Rois,
+    SceneCutDetection, SceneCutDetector, SensorCalibration, SensorCalibrator, SpatialFilter,
+    SpectrumProbe, Summary, SummaryMaps, TRANSFER_LUT_SIZE, TemporalEstimator, TrackMatching,
+    TrackedBlob, Tracking, Transfer, TransferCurve, draw_blob_boxes, iou, warp_frame,
+};
 // Logging
 #[allow(unused_imports)]
 use log::*;
@@ -20,7 +34,7 @@ use thumbnail_extractor::{
 };
 
 // Type alias for the callback function
-type CallbackFunction = fn(u32, u32, &[u8], &mut ComputeState) -> Vec<u8>;
+type CallbackFunction = fn(u32, u32, &[u8], &mut dyn DiPsBackend) -> Vec<u8>;
 
 #[derive(Copy, Clone, Debug)]
 pub enum DiPsFilter {
@@ -29,6 +43,16 @@ pub enum DiPsFilter {
     InverseSigmoid,
 }
 
+impl From<DiPsFilter> for dips_core::Filter {
+    fn from(filter: DiPsFilter) -> Self {
+        match filter {
+            DiPsFilter::Unfiltered => Self::Unfiltered,
+            DiPsFilter::Sigmoid => Self::Sigmoid,
+            DiPsFilter::InverseSigmoid => Self::InverseSigmoid,
+        }
+    }
+}
+
 impl Into<f64> for DiPsFilter {
     fn into(self) -> f64 {
         use DiPsFilter::*;
@@ -48,6 +72,17 @@ pub enum ChromaFilter {
     Blue,
 }
 
+impl From<ChromaFilter> for dips_core::ChromaFilter {
+    fn from(chroma_filter: ChromaFilter) -> Self {
+        match chroma_filter {
+            ChromaFilter::None => Self::All,
+            ChromaFilter::Red => Self::Red,
+            ChromaFilter::Green => Self::Green,
+            ChromaFilter::Blue => Self::Blue,
+        }
+    }
+}
+
 impl Into<f64> for ChromaFilter {
     fn into(self) -> f64 {
         use ChromaFilter::*;
@@ -60,15 +95,73 @@ impl Into<f64> for ChromaFilter {
     }
 }
 
+/// Point in the video at which the starting texture is retaken from the frames that follow
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RefreshPoint {
+    /// Before the frame with the given number, counting from 0
+    Frame(usize),
+    /// Before the first frame presented at or after the given time in seconds
+    Timestamp(f64),
+}
+
+impl FromStr for RefreshPoint {
+    type Err = RefreshPointParseError;
+
+    /// Parses a frame number like `120` or a timestamp in seconds like `4.5s`
+    fn from_str(point: &str) -> Result<Self, Self::Err> {
+        let point = point.trim();
+
+        match point.strip_suffix('s') {
+            Some(seconds) => seconds
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+                .map(Self::Timestamp),
+            None => point.parse::<usize>().ok().map(Self::Frame),
+        }
+        .ok_or_else(|| RefreshPointParseError(point.to_string()))
+    }
+}
+
 pub struct DiPsProperties {
     video_path: Option<String>,
     frame_callback: Option<Arc<Mutex<CallbackFunction>>>,
     output_path: Option<String>,
+    diff_export_path: Option<String>,
+    roi_series_path: Option<String>,
+    summary_path: Option<String>,
+    event_log_path: Option<String>,
+    tracks_path: Option<String>,
+    frequency_path: Option<String>,
+    pub refresh_points: Vec<RefreshPoint>,
     pub colorize: bool,
+    pub colormap: Colormap,
     pub spatial_window_size: i32,
+    pub spatial_filter: SpatialFilter,
+    pub border_mode: BorderMode,
     pub sensitivity: f32,
     pub filter_type: DiPsFilter,
     pub chroma_filter: ChromaFilter,
+    pub temporal_window: usize,
+    pub temporal_estimator: TemporalEstimator,
+    pub baseline_mode: BaselineMode,
+    pub difference_mode: DifferenceMode,
+    pub transfer: Transfer,
+    pub auto_sensitivity: AutoSensitivity,
+    pub scene_cut: SceneCutDetection,
+    pub rois: Rois,
+    pub summary: Summary,
+    pub motion_compensation: MotionCompensation,
+    pub denoise: FrequencyDenoise,
+    pub calibration: SensorCalibration,
+    pub events: EventDetection,
+    pub event_overlay: bool,
+    pub tracking: Tracking,
+    pub frequency: FrequencyAnalysis,
+    pub decimation: Decimation,
+    pub retiming: Retiming,
+    pub backend: Backend,
 }
 
 impl DiPsProperties {
@@ -77,11 +170,40 @@ impl DiPsProperties {
             video_path: None,
             frame_callback: None,
             output_path: None,
+            diff_export_path: None,
+            roi_series_path: None,
+            summary_path: None,
+            event_log_path: None,
+            tracks_path: None,
+            frequency_path: None,
+            refresh_points: Vec::new(),
             colorize: false,
+            colormap: Colormap::RedGreen,
             spatial_window_size: 1,
+            spatial_filter: SpatialFilter::Median,
+            border_mode: BorderMode::Clamp,
             sensitivity: 5.0,
             filter_type: DiPsFilter::Unfiltered,
             chroma_filter: ChromaFilter::None,
+            temporal_window: DEFAULT_TEMPORAL_WINDOW,
+            temporal_estimator: TemporalEstimator::Median,
+            baseline_mode: BaselineMode::Fixed,
+            difference_mode: DifferenceMode::Baseline,
+            transfer: Transfer::default(),
+            auto_sensitivity: AutoSensitivity::Off,
+            scene_cut: SceneCutDetection::Off,
+            rois: Rois::default(),
+            summary: Summary::Off,
+            motion_compensation: MotionCompensation::Off,
+            denoise: FrequencyDenoise::Off,
+            calibration: SensorCalibration::default(),
+            events: EventDetection::Off,
+            event_overlay: false,
+            tracking: Tracking::Off,
+            frequency: FrequencyAnalysis::Off,
+            decimation: Decimation::Off,
+            retiming: Retiming::Off,
+            backend: Backend::Gpu,
         }
     }
 
@@ -112,6 +234,83 @@ impl DiPsProperties {
         self
     }
 
+    /// Sets where the raw signed differences are exported using the builder structure
+    /// The format follows the extension, `.npy` for a single stack or `.tif`/`.tiff` for one
+    /// float TIFF per frame, with the timestamps and parameters in a `.json` next to it
+    pub fn diff_export_path<S>(&mut self, diff_export_path: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.diff_export_path = Some(String::from(diff_export_path.as_ref()));
+
+        self
+    }
+
+    /// Sets where the mean, min and max diff of every region of interest are written for every
+    /// frame using the builder structure, `.csv` or `.json` by the extension
+    pub fn roi_series_path<S>(&mut self, roi_series_path: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.roi_series_path = Some(String::from(roi_series_path.as_ref()));
+
+        self
+    }
+
+    /// Sets where the summary maps of the whole run are written once the stream ends using the
+    /// builder structure, see [`SummaryMaps::write`]
+    pub fn summary_path<S>(&mut self, summary_path: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.summary_path = Some(String::from(summary_path.as_ref()));
+
+        self
+    }
+
+    /// Sets where the JSON Lines log of the blobs of every frame is written using the builder
+    /// structure, see [`EventLog`]
+    pub fn event_log_path<S>(&mut self, event_log_path: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.event_log_path = Some(String::from(event_log_path.as_ref()));
+
+        self
+    }
+
+    /// Sets where the blob tracks are written as a MOTChallenge CSV using the builder
+    /// structure, see [`MotExporter`]
+    pub fn tracks_path<S>(&mut self, tracks_path: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.tracks_path = Some(String::from(tracks_path.as_ref()));
+
+        self
+    }
+
+    /// Sets where the frequency maps of the input are written once the stream ends using the
+    /// builder structure, see [`FrequencyAnalyzer::write`]
+    pub fn frequency_path<S>(&mut self, frequency_path: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.frequency_path = Some(String::from(frequency_path.as_ref()));
+
+        self
+    }
+
+    /// Sets the points at which the starting texture is retaken, on top of any scene cuts
+    pub fn refresh_points<I>(&mut self, refresh_points: I) -> &mut Self
+    where
+        I: IntoIterator<Item = RefreshPoint>,
+    {
+        self.refresh_points = refresh_points.into_iter().collect();
+
+        self
+    }
+
     /// Sets the colorize parameter of DiPs
     pub fn colorize(&mut self, colorize: bool) -> &mut Self {
         self.colorize = colorize;
@@ -119,6 +318,13 @@ impl DiPsProperties {
         self
     }
 
+    /// Sets the colormap the diffs are colored with when colorize is set
+    pub fn colormap(&mut self, colormap: Colormap) -> &mut Self {
+        self.colormap = colormap;
+
+        self
+    }
+
     /// Sets the spatial window size parameter of DiPs
     pub fn spatial_window_size(&mut self, spatial_window_size: i32) -> &mut Self {
         self.spatial_window_size = spatial_window_size;
@@ -126,6 +332,20 @@ impl DiPsProperties {
         self
     }
 
+    /// Sets which filter is applied over the spatial window
+    pub fn spatial_filter(&mut self, spatial_filter: SpatialFilter) -> &mut Self {
+        self.spatial_filter = spatial_filter;
+
+        self
+    }
+
+    /// Sets how the spatial filter reads pixels outside of the frame
+    pub fn border_mode(&mut self, border_mode: BorderMode) -> &mut Self {
+        self.border_mode = border_mode;
+
+        self
+    }
+
     /// Sets the sensitivity parameter of DiPs
     pub fn sensitivity(&mut self, sensitivity: f32) -> &mut Self {
         self.sensitivity = sensitivity;
@@ -147,6 +367,146 @@ impl DiPsProperties {
         self
     }
 
+    /// Sets the number of frames the temporal median is taken over
+    pub fn temporal_window(&mut self, temporal_window: usize) -> &mut Self {
+        self.temporal_window = temporal_window;
+
+        self
+    }
+
+    /// Sets how the frames of the temporal window are combined for both the
+    /// starting texture and every new frame
+    pub fn temporal_estimator(&mut self, temporal_estimator: TemporalEstimator) -> &mut Self {
+        self.temporal_estimator = temporal_estimator;
+
+        self
+    }
+
+    /// Sets whether the baseline is fixed or rolls along with the recent frames
+    pub fn baseline_mode(&mut self, baseline_mode: BaselineMode) -> &mut Self {
+        self.baseline_mode = baseline_mode;
+
+        self
+    }
+
+    /// Sets whether frames are compared against the baseline or the previous frame
+    pub fn difference_mode(&mut self, difference_mode: DifferenceMode) -> &mut Self {
+        self.difference_mode = difference_mode;
+
+        self
+    }
+
+    /// Sets how diffs are turned into display values, see [`Transfer`]
+    pub fn transfer(&mut self, transfer: Transfer) -> &mut Self {
+        self.transfer = transfer;
+
+        self
+    }
+
+    /// Sets if and how the gain of the transfer is picked from the diff histogram
+    pub fn auto_sensitivity(&mut self, auto_sensitivity: AutoSensitivity) -> &mut Self {
+        self.auto_sensitivity = auto_sensitivity;
+
+        self
+    }
+
+    /// Sets whether the baseline is retaken on its own after scene cuts and large global
+    /// changes
+    pub fn scene_cut(&mut self, scene_cut: SceneCutDetection) -> &mut Self {
+        self.scene_cut = scene_cut;
+
+        self
+    }
+
+    /// Sets the regions of interest the diffs are summarised over and whether the frame is
+    /// shown greyed out around them
+    pub fn rois(&mut self, rois: Rois) -> &mut Self {
+        self.rois = rois;
+
+        self
+    }
+
+    /// Sets whether per pixel statistics of the diffs are kept over the whole run
+    pub fn summary(&mut self, summary: Summary) -> &mut Self {
+        self.summary = summary;
+
+        self
+    }
+
+    /// Sets how every frame is registered against the start of the baseline before it is
+    /// differenced
+    pub fn motion_compensation(&mut self, motion_compensation: MotionCompensation) -> &mut Self {
+        self.motion_compensation = motion_compensation;
+
+        self
+    }
+
+    /// Sets the mask the spectrum of the intensity of every frame is filtered through to remove
+    /// periodic sensor patterns before the temporal stage
+    pub fn denoise(&mut self, denoise: FrequencyDenoise) -> &mut Self {
+        self.denoise = denoise;
+
+        self
+    }
+
+    /// Sets the dark frame, flat field and hot pixel map every frame is corrected with before
+    /// its intensity is taken
+    pub fn calibration(&mut self, calibration: SensorCalibration) -> &mut Self {
+        self.calibration = calibration;
+
+        self
+    }
+
+    /// Sets how the raw diffs are thresholded into blobs
+    pub fn events(&mut self, events: EventDetection) -> &mut Self {
+        self.events = events;
+
+        self
+    }
+
+    /// Sets whether the bounding boxes of the blobs are drawn over the output
+    pub fn event_overlay(&mut self, event_overlay: bool) -> &mut Self {
+        self.event_overlay = event_overlay;
+
+        self
+    }
+
+    /// Sets how the blobs are followed across frames, needs the events to be on
+    pub fn tracking(&mut self, tracking: Tracking) -> &mut Self {
+        self.tracking = tracking;
+
+        self
+    }
+
+    /// Sets how the temporal spectra of the input are taken, the maps are only written with a
+    /// frequency path
+    pub fn frequency(&mut self, frequency: FrequencyAnalysis) -> &mut Self {
+        self.frequency = frequency;
+
+        self
+    }
+
+    /// Sets how the decoded frames are thinned out before DiPs runs on them
+    pub fn decimation(&mut self, decimation: Decimation) -> &mut Self {
+        self.decimation = decimation;
+
+        self
+    }
+
+    /// Sets the frame rate or duration the output video is retimed to
+    pub fn retiming(&mut self, retiming: Retiming) -> &mut Self {
+        self.retiming = retiming;
+
+        self
+    }
+
+    /// Sets which backend DiPs runs on
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = backend;
+
+        self
+    }
+
     pub fn get_video_path(&self) -> Option<&String> {
         self.video_path.as_ref()
     }
@@ -155,20 +515,137 @@ impl DiPsProperties {
         self.output_path.as_ref()
     }
 
+    pub fn get_diff_export_path(&self) -> Option<&String> {
+        self.diff_export_path.as_ref()
+    }
+
+    pub fn get_roi_series_path(&self) -> Option<&String> {
+        self.roi_series_path.as_ref()
+    }
+
+    pub fn get_summary_path(&self) -> Option<&String> {
+        self.summary_path.as_ref()
+    }
+
+    pub fn get_event_log_path(&self) -> Option<&String> {
+        self.event_log_path.as_ref()
+    }
+
+    pub fn get_tracks_path(&self) -> Option<&String> {
+        self.tracks_path.as_ref()
+    }
+
+    pub fn get_frequency_path(&self) -> Option<&String> {
+        self.frequency_path.as_ref()
+    }
+
     pub fn build(&self) -> Self {
         Self {
             video_path: self.video_path.clone(),
             frame_callback: self.frame_callback.clone(),
             output_path: self.output_path.clone(),
+            diff_export_path: self.diff_export_path.clone(),
+            roi_series_path: self.roi_series_path.clone(),
+            summary_path: self.summary_path.clone(),
+            event_log_path: self.event_log_path.clone(),
+            tracks_path: self.tracks_path.clone(),
+            frequency_path: self.frequency_path.clone(),
+            refresh_points: self.refresh_points.clone(),
             colorize: self.colorize.clone(),
+            colormap: self.colormap.clone(),
             spatial_window_size: self.spatial_window_size.clone(),
+            spatial_filter: self.spatial_filter.clone(),
+            border_mode: self.border_mode.clone(),
             sensitivity: self.sensitivity.clone(),
             filter_type: self.filter_type.clone(),
             chroma_filter: self.chroma_filter.clone(),
+            temporal_window: self.temporal_window.clone(),
+            temporal_estimator: self.temporal_estimator.clone(),
+            baseline_mode: self.baseline_mode.clone(),
+            difference_mode: self.difference_mode.clone(),
+            transfer: self.transfer.clone(),
+            auto_sensitivity: self.auto_sensitivity.clone(),
+            scene_cut: self.scene_cut.clone(),
+            rois: self.rois.clone(),
+            summary: self.summary.clone(),
+            motion_compensation: self.motion_compensation.clone(),
+            denoise: self.denoise.clone(),
+            calibration: self.calibration.clone(),
+            events: self.events.clone(),
+            event_overlay: self.event_overlay.clone(),
+            tracking: self.tracking.clone(),
+            frequency: self.frequency.clone(),
+            decimation: self.decimation.clone(),
+            retiming: self.retiming.clone(),
+            backend: self.backend.clone(),
         }
     }
 }
 
+impl From<&DiPsProperties> for DiPsParameters {
+    fn from(properties: &DiPsProperties) -> Self {
+        Self {
+            colorize: properties.colorize,
+            colormap: properties.colormap.clone(),
+            spatial_window_size: properties.spatial_window_size.max(1) as u32,
+            spatial_filter: properties.spatial_filter,
+            border_mode: properties.border_mode,
+            sigmoid_horizontal_scalar: properties.sensitivity,
+            filter_type: properties.filter_type.into(),
+            chroma_filter: properties.chroma_filter.into(),
+            temporal_window: properties.temporal_window,
+            temporal_estimator: properties.temporal_estimator,
+            baseline_mode: properties.baseline_mode,
+            difference_mode: properties.difference_mode,
+            transfer: properties.transfer.clone(),
+            auto_sensitivity: properties.auto_sensitivity,
+            rois: properties.rois.clone(),
+            summary: properties.summary,
+            motion_compensation: properties.motion_compensation,
+            denoise: properties.denoise.clone(),
+            calibration: properties.calibration.clone(),
+        }
+    }
+}
+
+/// Loads a region of interest mask from an image where every non black pixel is inside, the
+/// mask is scaled to the frame when the sizes differ
+pub fn load_roi_mask<P: AsRef<Path>>(path: P) -> anyhow::Result<RoiShape> {
+    let mask = image::open(path.as_ref())?.to_luma8();
+
+    Ok(RoiShape::mask_from_luma(
+        mask.width(),
+        mask.height(),
+        mask.as_raw(),
+    )?)
+}
+
+/// Loads a dark frame or flat field from an image, 16 bit images keep their full precision
+pub fn load_calibration_frame<P: AsRef<Path>>(path: P) -> anyhow::Result<CalibrationFrame> {
+    let frame = image::open(path.as_ref())?.to_rgb32f();
+
+    Ok(CalibrationFrame::from_rgb(
+        frame.width(),
+        frame.height(),
+        frame
+            .as_raw()
+            .chunks_exact(3)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect(),
+    )?)
+}
+
+/// Loads a hot pixel map from an image where every non black pixel is hot or dead
+pub fn load_hot_pixel_map<P: AsRef<Path>>(path: P) -> anyhow::Result<HotPixelMap> {
+    let map = image::open(path.as_ref())?.to_luma8();
+
+    Ok(HotPixelMap::from_luma(
+        map.width(),
+        map.height(),
+        map.as_raw(),
+    )?)
+}
+
 // Custom Error Types
 #[derive(Debug)]
 pub struct VideoPathNotSpecifiedError;
@@ -200,6 +677,25 @@ impl Display for FrameCallbackNotSpecifiedError {
     }
 }
 
+#[derive(Debug)]
+pub struct RefreshPointParseError(String);
+
+impl Error for RefreshPointParseError {
+    fn description(&self) -> &str {
+        "Refresh point is neither a frame number nor a timestamp in seconds"
+    }
+}
+
+impl Display for RefreshPointParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Invalid refresh point \"{}\", expected a frame number like 120 or seconds like 4.5s",
+            self.0
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct StreamNotFoundError;
 
@@ -234,11 +730,9 @@ fn frame_callback(
     width: u32,
     height: u32,
     frame_data: &[u8],
-    compute: &mut ComputeState,
+    compute: &mut dyn DiPsBackend,
 ) -> Vec<u8> {
-    compute.add_texture(width, height, frame_data);
-
-    if let Some(new_frame) = compute.dispatch() {
+    if let Some(new_frame) = compute.process_frame(width, height, frame_data) {
         new_frame
     } else {
         frame_data.to_vec()
@@ -252,8 +746,11 @@ pub fn init_frame_extractor() {
 pub async fn perform_dips(mut properties: DiPsProperties) {
     properties.frame_callback(frame_callback);
 
-    _ = create_video_frame_decoder_pipeline(&properties)
-        .and_then(|pipeline| run_pipeline(pipeline));
+    if let Err(err) =
+        create_video_frame_decoder_pipeline(&properties).and_then(|pipeline| run_pipeline(pipeline))
+    {
+        error!("DiPs failed: {err}");
+    }
 }
 
 pub fn init_thumbnail_extractor() {
@@ -264,3 +761,43 @@ pub fn extract_thumbnail(input_path: &str, output_path: &str) {
     _ = extract_thumbnail_pipeline(input_path, output_path)
         .and_then(|pipeline| run_thumbnail_pipeline(pipeline));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refresh_points_are_parsed() {
+        assert_eq!(
+            "120".parse::<RefreshPoint>().unwrap(),
+            RefreshPoint::Frame(120)
+        );
+        assert_eq!(
+            " 0 ".parse::<RefreshPoint>().unwrap(),
+            RefreshPoint::Frame(0)
+        );
+        assert_eq!(
+            "4.5s".parse::<RefreshPoint>().unwrap(),
+            RefreshPoint::Timestamp(4.5)
+        );
+        assert_eq!(
+            "12 s".parse::<RefreshPoint>().unwrap(),
+            RefreshPoint::Timestamp(12.0)
+        );
+
+        for point in [
+            "",
+            "s",
+            "-3",
+            "1.5",
+            "-1s",
+            "infs",
+            "NaNs",
+            "4.5 seconds",
+            "frame 3",
+        ] {
+            let error = point.parse::<RefreshPoint>().unwrap_err();
+            assert_eq!(error.0, point.trim());
+        }
+    }
+}
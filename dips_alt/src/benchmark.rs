@@ -0,0 +1,85 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use dips_core::{Backend, CpuBackend, DiPsBackend, MAX_SPATIAL_WINDOW};
+
+use crate::{DiPsProperties, dips_compute::DiPsCompute, gpu_controller::GpuController};
+
+// 4K UHD frames
+const BENCHMARK_WIDTH: u32 = 3840;
+const BENCHMARK_HEIGHT: u32 = 2160;
+const BENCHMARK_FRAMES: u32 = 10;
+
+/// Noise frame so that the median has something to select from
+fn noise_frame(width: u32, height: u32) -> Vec<u8> {
+    // xorshift, the exact values don't matter
+    let mut state: u32 = 0x9E37_79B9;
+
+    (0..width * height)
+        .flat_map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+
+            let [r, g, b, _] = state.to_le_bytes();
+            [r, g, b, 255]
+        })
+        .collect()
+}
+
+/// Runs DiPs with every odd spatial window size on 4K noise and prints the throughput of each
+///
+/// Everything but the spatial window size is taken from the properties
+pub fn benchmark_spatial_windows(properties: DiPsProperties) -> Result<()> {
+    let gpu_controller = match properties.backend {
+        Backend::Gpu => Some(GpuController::new()?),
+        Backend::Cpu => None,
+    };
+
+    let frame = noise_frame(BENCHMARK_WIDTH, BENCHMARK_HEIGHT);
+
+    println!(
+        "Spatial filter {:?} on {}x{} frames",
+        properties.spatial_filter, BENCHMARK_WIDTH, BENCHMARK_HEIGHT
+    );
+    println!("{:>8} {:>12} {:>14}", "window", "ms / frame", "Mpixels / s");
+
+    for window_size in (1..=MAX_SPATIAL_WINDOW).step_by(2) {
+        let mut properties = properties.clone();
+        properties.set_window_size(window_size as u8);
+
+        // The compute state takes the frame sizes swapped
+        let mut backend: Box<dyn DiPsBackend> = match gpu_controller.as_ref() {
+            Some(gpu_controller) => Box::new(DiPsCompute::new(
+                BENCHMARK_HEIGHT,
+                BENCHMARK_WIDTH,
+                None,
+                gpu_controller.device.clone(),
+                gpu_controller.queue.clone(),
+                properties.clone(),
+            )?),
+            None => Box::new(CpuBackend::new((&properties).into())?),
+        };
+
+        // Take the baseline before timing so that every timed frame runs the whole pipeline
+        for _ in 0..=properties.temporal_window {
+            backend.process_frame(BENCHMARK_WIDTH, BENCHMARK_HEIGHT, &frame);
+        }
+
+        let start = Instant::now();
+        for _ in 0..BENCHMARK_FRAMES {
+            backend.process_frame(BENCHMARK_WIDTH, BENCHMARK_HEIGHT, &frame);
+        }
+        let frame_time = start.elapsed() / BENCHMARK_FRAMES;
+
+        let megapixels = (BENCHMARK_WIDTH * BENCHMARK_HEIGHT) as f64 / 1_000_000.0;
+        println!(
+            "{:>8} {:>12.2} {:>14.1}",
+            format!("{window_size}x{window_size}"),
+            frame_time.as_secs_f64() * 1000.0,
+            megapixels / frame_time.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
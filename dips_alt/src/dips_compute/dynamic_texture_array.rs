@@ -7,11 +7,22 @@ use wgpu::{
 
 use std::borrow::Cow;
 
+// Bind groups available for the textures, the last bind group holds the snapshot and output
+const TEXTURE_BIND_GROUPS: usize = 4;
+
+/// Binds the textures as separate storage textures of texture_format spread evenly over the
+/// texture bind groups and generates the matching shader code
 pub fn create_dynamic_bindings(
     device: &Device,
     mut bind_group: u32,
+    texture_format: TextureFormat,
     texture_views: Vec<&TextureView>,
 ) -> (Vec<BindGroupLayout>, Vec<BindGroup>, ShaderModule) {
+    let shader_format = match texture_format {
+        TextureFormat::R32Float => "r32float",
+        _ => "rgba8unorm",
+    };
+
     let mut layouts: Vec<BindGroupLayout> = Vec::new();
     let mut bind_groups: Vec<BindGroup> = Vec::new();
 
@@ -19,11 +30,12 @@ pub fn create_dynamic_bindings(
     let mut bind_group_entries: Vec<BindGroupEntry> = Vec::new();
 
     let mut shader_bindings: String = String::new();
-    let mut arraying_texture: String = String::new();
     let mut texture_loading: String = String::new();
 
+    let textures_per_bind_group = texture_views.len().div_ceil(TEXTURE_BIND_GROUPS).max(1);
+
     for (index, texture_view) in texture_views.iter().enumerate() {
-        if index % 4 == 0 && index != 0 {
+        if index % textures_per_bind_group == 0 && index != 0 {
             bind_group += 1;
 
             let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -44,13 +56,13 @@ pub fn create_dynamic_bindings(
             bind_group_entries.clear();
         }
 
-        let binding_number = index % 4;
+        let binding_number = index % textures_per_bind_group;
         layout_entries.push(BindGroupLayoutEntry {
             binding: binding_number as u32,
             visibility: ShaderStages::COMPUTE,
             ty: BindingType::StorageTexture {
                 access: StorageTextureAccess::ReadOnly,
-                format: TextureFormat::Rgba8Unorm,
+                format: texture_format,
                 view_dimension: TextureViewDimension::D2,
             },
             count: None,
@@ -62,11 +74,8 @@ pub fn create_dynamic_bindings(
         });
 
         shader_bindings.push_str(
-            &format!("@group({bind_group}) @binding({binding_number})\nvar texture_{index}: texture_storage_2d<rgba8unorm, read>;\n")
+            &format!("@group({bind_group}) @binding({binding_number})\nvar texture_{index}: texture_storage_2d<{shader_format}, read>;\n")
         );
-        arraying_texture.push_str(&format!(
-            "    median_array[{index}] = spatial_median_filter(coords.xy, dimensions.xy, {index});\n" // "    textures[{index}] = textureLoad(texture_{index}, coords.xy);\n"
-        ));
         texture_loading.push_str(&format!(
             "        case {index}u: {{\n            return textureLoad(texture_{index}, coords.xy);\n        }}\n"
         ));
@@ -92,7 +101,7 @@ pub fn create_dynamic_bindings(
     }
 
     // Create dummy bind groups to fill in the gap to the required bind groups
-    while layouts.len() < 4 {
+    while layouts.len() < TEXTURE_BIND_GROUPS {
         let l = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("Dummy Bind Group Layout"),
             entries: &[],
@@ -110,7 +119,6 @@ pub fn create_dynamic_bindings(
     let mut modified_shader = String::new();
 
     let mut shader = include_str!("shaders/pre_compute_shader.wgsl").to_string();
-    shader = shader.replace("//r3p1Ac3", &arraying_texture);
     shader = shader.replace("//lFtIr3p1Ac3", &texture_loading);
 
     modified_shader.push_str(&shader_bindings);
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{borrow::Cow, collections::HashMap, rc::Rc};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use dynamic_texture_array::create_dynamic_bindings;
 use wgpu::{
     AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
@@ -20,12 +20,23 @@ use wgpu::{
 };
 
 use crate::{DiPsWindow, utils::indexing::UCircularIndex};
+use dips_core::{
+    Amplification, AutoSensitivity, Backend, BaselineMode, BorderMode, COLORMAP_SIZE, Colormap,
+    DiPsBackend, DiPsParameters, DifferenceMode, FrequencyDenoise, FrequencyDenoiser,
+    HISTOGRAM_BINS, MAX_SPATIAL_WINDOW, MAX_TEMPORAL_WINDOW, Motion, MotionCompensation,
+    MotionCompensator, ROI_STATS_WORDS, RoiStats, Rois, SceneCutDetection, SensitivityEstimator,
+    SensorCalibration, SensorCalibrator, SpatialFilter, Summary, SummaryMaps, TRANSFER_LUT_SIZE,
+    TemporalEstimator, Transfer, check_baseline_buffer, check_baseline_window,
+    check_spatial_window, check_temporal_window, frame_intensities, pyramid_level_dimensions,
+};
 
 mod dynamic_texture_array;
 
 const WORK_GROUP_WIDTH: u32 = 16;
 const WORK_GROUP_HEIGHT: u32 = 16;
 
+const DEFAULT_TEMPORAL_WINDOW: usize = 2;
+
 // Helper functions
 fn compute_work_group_count(
     (width, height): (u32, u32),
@@ -164,23 +175,109 @@ pub enum ChromaFilter {
     Blue = 3,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl From<Filter> for dips_core::Filter {
+    fn from(filter: Filter) -> Self {
+        match filter {
+            Filter::Sigmoid => Self::Sigmoid,
+            Filter::InverseSigmoid => Self::InverseSigmoid,
+        }
+    }
+}
+
+impl From<ChromaFilter> for dips_core::ChromaFilter {
+    fn from(chroma_filter: ChromaFilter) -> Self {
+        match chroma_filter {
+            ChromaFilter::All => Self::All,
+            ChromaFilter::Red => Self::Red,
+            ChromaFilter::Green => Self::Green,
+            ChromaFilter::Blue => Self::Blue,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct DiPsProperties {
     pub colorize: bool,
+    pub colormap: Colormap,
     pub window_size: u8,
+    pub spatial_filter: SpatialFilter,
+    pub border_mode: BorderMode,
     pub sigmoid_horizontal_scalar: f32,
     pub filter_type: Filter,
     pub chroma_filter: ChromaFilter,
+    pub temporal_window: usize,
+    pub temporal_estimator: TemporalEstimator,
+    pub baseline_mode: BaselineMode,
+    pub difference_mode: DifferenceMode,
+    pub transfer: Transfer,
+    pub auto_sensitivity: AutoSensitivity,
+    pub scene_cut: SceneCutDetection,
+    pub rois: Rois,
+    pub summary: Summary,
+    pub motion_compensation: MotionCompensation,
+    /// Spectrum mask the intensity of every frame goes through before the temporal stage
+    pub denoise: FrequencyDenoise,
+    /// Dark frame, flat field and hot pixel map every frame is corrected with
+    pub calibration: SensorCalibration,
+    /// Shows the amplified band-pass filtered frames instead of the diffs
+    pub amplification: Amplification,
+    /// Frame rate of the stream the amplification band in Hz is taken at, set by the driver
+    pub frame_rate: f64,
+    pub backend: Backend,
 }
 
 impl Default for DiPsProperties {
     fn default() -> Self {
         Self {
             colorize: true,
+            colormap: Colormap::default(),
             window_size: 1,
+            spatial_filter: SpatialFilter::default(),
+            border_mode: BorderMode::default(),
             sigmoid_horizontal_scalar: 5.0,
             filter_type: Filter::default(),
             chroma_filter: ChromaFilter::default(),
+            temporal_window: DEFAULT_TEMPORAL_WINDOW,
+            temporal_estimator: TemporalEstimator::default(),
+            baseline_mode: BaselineMode::default(),
+            difference_mode: DifferenceMode::default(),
+            transfer: Transfer::default(),
+            auto_sensitivity: AutoSensitivity::default(),
+            scene_cut: SceneCutDetection::default(),
+            rois: Rois::default(),
+            summary: Summary::default(),
+            motion_compensation: MotionCompensation::default(),
+            denoise: FrequencyDenoise::default(),
+            calibration: SensorCalibration::default(),
+            amplification: Amplification::default(),
+            frame_rate: 30.0,
+            backend: Backend::default(),
+        }
+    }
+}
+
+impl From<&DiPsProperties> for DiPsParameters {
+    fn from(properties: &DiPsProperties) -> Self {
+        Self {
+            colorize: properties.colorize,
+            colormap: properties.colormap.clone(),
+            spatial_window_size: properties.window_size as u32,
+            spatial_filter: properties.spatial_filter,
+            border_mode: properties.border_mode,
+            sigmoid_horizontal_scalar: properties.sigmoid_horizontal_scalar,
+            filter_type: properties.filter_type.into(),
+            chroma_filter: properties.chroma_filter.into(),
+            temporal_window: properties.temporal_window,
+            temporal_estimator: properties.temporal_estimator,
+            baseline_mode: properties.baseline_mode,
+            difference_mode: properties.difference_mode,
+            transfer: properties.transfer.clone(),
+            auto_sensitivity: properties.auto_sensitivity,
+            rois: properties.rois.clone(),
+            summary: properties.summary,
+            motion_compensation: properties.motion_compensation,
+            denoise: properties.denoise.clone(),
+            calibration: properties.calibration.clone(),
         }
     }
 }
@@ -194,6 +291,22 @@ impl DiPsProperties {
             if self.colorize { 1.0 } else { 0.0 },
         );
         hm.insert("WINDOW_SIZE".to_string(), self.window_size as f64);
+        hm.insert(
+            "SPATIAL_FILTER".to_string(),
+            self.spatial_filter.shader_id() as f64,
+        );
+        hm.insert(
+            "SPATIAL_SIGMA".to_string(),
+            self.spatial_filter.spatial_sigma() as f64,
+        );
+        hm.insert(
+            "RANGE_SIGMA".to_string(),
+            self.spatial_filter.range_sigma() as f64,
+        );
+        hm.insert(
+            "BORDER_MODE".to_string(),
+            self.border_mode.shader_id() as f64,
+        );
         hm.insert(
             "SIGMOID_HORIZONTAL_SCALAR".to_string(),
             self.sigmoid_horizontal_scalar as f64,
@@ -203,10 +316,104 @@ impl DiPsProperties {
             "CHROMA_FILTER".to_string(),
             self.chroma_filter as u32 as f64,
         );
+        hm.insert("NUM_TEXTURES".to_string(), self.temporal_window as f64);
+        hm.insert(
+            "TEMPORAL_ESTIMATOR".to_string(),
+            self.temporal_estimator.shader_id() as f64,
+        );
+        hm.insert(
+            "TEMPORAL_ESTIMATOR_PARAMETER".to_string(),
+            self.temporal_estimator.parameter() as f64,
+        );
+        hm.insert(
+            "BASELINE_MODE".to_string(),
+            self.baseline_mode.shader_id() as f64,
+        );
+        hm.insert(
+            "BASELINE_ALPHA".to_string(),
+            self.baseline_mode.alpha() as f64,
+        );
+        hm.insert(
+            "BASELINE_WINDOW".to_string(),
+            self.baseline_mode.window() as f64,
+        );
+        hm.insert(
+            "DIFFERENCE_MODE".to_string(),
+            self.difference_mode.shader_id() as f64,
+        );
+        hm.insert(
+            "ACCUMULATION_DECAY".to_string(),
+            self.difference_mode.decay() as f64,
+        );
+        hm.insert(
+            "TRANSFER_CURVE".to_string(),
+            self.transfer.curve.shader_id() as f64,
+        );
+        hm.insert(
+            "TRANSFER_PARAMETER".to_string(),
+            self.transfer.curve.parameter() as f64,
+        );
+        hm.insert("OFFSET".to_string(), self.transfer.offset as f64);
+        hm.insert("CLAMP_MIN".to_string(), self.transfer.clamp_min as f64);
+        hm.insert("CLAMP_MAX".to_string(), self.transfer.clamp_max as f64);
+        hm.insert(
+            "AUTO_SENSITIVITY".to_string(),
+            if self.auto_sensitivity.is_on() {
+                1.0
+            } else {
+                0.0
+            },
+        );
+        hm.insert("ROI_COUNT".to_string(), self.rois.len() as f64);
+        hm.insert(
+            "ROI_GREY_OUTSIDE".to_string(),
+            if self.rois.grey_outside { 1.0 } else { 0.0 },
+        );
+        hm.insert(
+            "AMPLIFICATION_LEVELS".to_string(),
+            self.amplification.levels() as f64,
+        );
+        hm.insert(
+            "CALIBRATION".to_string(),
+            if self.calibration_split().0.is_on() {
+                1.0
+            } else {
+                0.0
+            },
+        );
+        hm.insert(
+            "INTENSITY_INPUT".to_string(),
+            if self.intensity_input() { 1.0 } else { 0.0 },
+        );
 
         hm
     }
 
+    /// Calibration done by the shader and on the CPU, the denoiser and the motion compensation
+    /// run on the CPU before the frames are uploaded and need calibrated frames, so it moves to
+    /// the CPU in front of them when either is on instead of the frames being read back from the
+    /// GPU after the shader calibrated them
+    fn calibration_split(&self) -> (SensorCalibration, SensorCalibration) {
+        match self.intensity_input() {
+            true => (SensorCalibration::default(), self.calibration.clone()),
+            false => (self.calibration.clone(), SensorCalibration::default()),
+        }
+    }
+
+    /// Whether the frames go through the CPU stages and are uploaded as the float intensities
+    /// they make instead of RGBA8 colors
+    fn intensity_input(&self) -> bool {
+        self.denoise.is_on() || self.motion_compensation.is_on()
+    }
+
+    /// Format the frames are uploaded in
+    fn input_format(&self) -> TextureFormat {
+        match self.intensity_input() {
+            true => TextureFormat::R32Float,
+            false => TextureFormat::Rgba8Unorm,
+        }
+    }
+
     pub fn set_filter(&mut self, filter: Filter) {
         self.filter_type = filter;
     }
@@ -221,16 +428,89 @@ impl DiPsProperties {
     }
 
     pub fn set_window_size(&mut self, size: u8) {
-        // TODO: Export into consts
-        self.window_size = size.clamp(1, 7);
+        self.window_size = size.clamp(1, MAX_SPATIAL_WINDOW as u8);
         if self.window_size % 2 == 0 {
             self.window_size -= 1;
         }
     }
 
+    pub fn set_spatial_filter(&mut self, spatial_filter: SpatialFilter) {
+        self.spatial_filter = spatial_filter;
+    }
+
+    pub fn set_border_mode(&mut self, border_mode: BorderMode) {
+        self.border_mode = border_mode;
+    }
+
+    pub fn set_temporal_window(&mut self, size: usize) {
+        self.temporal_window = size;
+    }
+
+    pub fn set_temporal_estimator(&mut self, temporal_estimator: TemporalEstimator) {
+        self.temporal_estimator = temporal_estimator;
+    }
+
+    pub fn set_baseline_mode(&mut self, baseline_mode: BaselineMode) {
+        self.baseline_mode = baseline_mode;
+    }
+
+    pub fn set_difference_mode(&mut self, difference_mode: DifferenceMode) {
+        self.difference_mode = difference_mode;
+    }
+
     pub fn set_colorize(&mut self, colorize: bool) {
         self.colorize = colorize;
     }
+
+    pub fn set_colormap(&mut self, colormap: Colormap) {
+        self.colormap = colormap;
+    }
+
+    pub fn set_transfer(&mut self, transfer: Transfer) {
+        self.transfer = transfer;
+    }
+
+    pub fn set_auto_sensitivity(&mut self, auto_sensitivity: AutoSensitivity) {
+        self.auto_sensitivity = auto_sensitivity;
+    }
+
+    pub fn set_scene_cut(&mut self, scene_cut: SceneCutDetection) {
+        self.scene_cut = scene_cut;
+    }
+
+    pub fn set_rois(&mut self, rois: Rois) {
+        self.rois = rois;
+    }
+
+    pub fn set_summary(&mut self, summary: Summary) {
+        self.summary = summary;
+    }
+
+    pub fn set_motion_compensation(&mut self, motion_compensation: MotionCompensation) {
+        self.motion_compensation = motion_compensation;
+    }
+
+    pub fn set_denoise(&mut self, denoise: FrequencyDenoise) {
+        self.denoise = denoise;
+    }
+
+    pub fn set_calibration(&mut self, calibration: SensorCalibration) {
+        self.calibration = calibration;
+    }
+
+    pub fn set_amplification(&mut self, amplification: Amplification) {
+        self.amplification = amplification;
+    }
+
+    pub fn set_frame_rate(&mut self, frame_rate: f64) {
+        if frame_rate.is_finite() && frame_rate > 0.0 {
+            self.frame_rate = frame_rate;
+        }
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
 }
 
 #[derive(Debug)]
@@ -252,6 +532,12 @@ pub struct DiPsCompute {
     texture_array_bind_groups: Vec<BindGroup>,
     pre_compute_pipeline: ComputePipeline,
 
+    // Pipelines of the amplification that run after the pre compute pipeline, the pyramid one
+    // only with more than one level
+    pyramid_pipeline: Option<ComputePipeline>,
+    amplify_pipeline: Option<ComputePipeline>,
+    amplification_levels: u32,
+
     output_bind_group: BindGroup,
 
     // input_texture: Texture,
@@ -260,15 +546,61 @@ pub struct DiPsCompute {
     snapshot_buffer: Buffer,
     output_buffer: Buffer,
 
+    // Signed difference of every pixel before it is filtered and colorized
+    diff_texture: Texture,
+    diff_buffer: Buffer,
+    raw_diff: Vec<f32>,
+
+    // Auto sensitivity state, the histogram of every frame picks the gain of the next one
+    histogram_buffer: Buffer,
+    histogram_read_buffer: Buffer,
+    gain_buffer: Buffer,
+    sensitivity_estimator: SensitivityEstimator,
+    // Gain the last frame displayed the diffs with
+    gain: f32,
+
+    // Region of interest statistics, cleared before every frame
+    roi_stats_buffer: Buffer,
+    roi_stats_read_buffer: Buffer,
+    roi_stats_clear: Vec<u32>,
+    // Number of pixels in every region of interest
+    roi_pixels: Vec<usize>,
+    roi_stats: Vec<RoiStats>,
+
+    // Statistics of every output frame of the run when the summary is on
+    summary_mode: Summary,
+    summary: Option<SummaryMaps>,
+
+    // Frames go through the CPU stages below and are uploaded as their float intensities
+    intensity_input: bool,
+    chroma_filter: dips_core::ChromaFilter,
+
+    // Calibrates every frame on the CPU when the shader doesn't
+    calibrator: SensorCalibrator,
+
+    // Removes periodic sensor patterns from every frame before it is registered
+    denoiser: FrequencyDenoiser,
+
+    // Warps every frame back onto the first frame of the snapshot window
+    motion_compensator: MotionCompensator,
+
+    // Rolling baseline state
+    baseline_history_index: UCircularIndex,
+    baseline_history_index_buffer: Buffer,
+
     texture_dimensions: Extent3d,
 
     texture_index: UCircularIndex,
+    frame_index_buffer: Buffer,
     // The current texture getting run through the pipeline
+
+    // Frames sent since the last snapshot was requested through the backend interface
+    snapshot_index: usize,
+    num_textures: usize,
 }
 
 impl DiPsCompute {
     pub fn new(
-        num_textures: usize,
         textures_width: u32,
         textures_height: u32,
         dips_window: Option<&DiPsWindow>,
@@ -276,6 +608,38 @@ impl DiPsCompute {
         queue: Rc<Queue>,
         dips_properties: DiPsProperties,
     ) -> Result<Self> {
+        let num_textures = dips_properties.temporal_window;
+        check_temporal_window(num_textures, MAX_TEMPORAL_WINDOW)?;
+        check_baseline_window(dips_properties.baseline_mode)?;
+        check_spatial_window(dips_properties.window_size as u32)?;
+
+        let amplification_levels = dips_properties.amplification.levels();
+        if amplification_levels > 0 && num_textures < 3 {
+            return Err(anyhow!(
+                "The amplification needs a temporal window of at least 3 frames to band-pass filter over"
+            ));
+        }
+
+        // The temporal textures are bound next to the snapshot, output and diff textures
+        let max_storage_textures = device.limits().max_storage_textures_per_shader_stage as usize;
+        if num_textures + 3 > max_storage_textures {
+            return Err(anyhow!(
+                "A temporal window of {num_textures} frames needs {} storage textures per shader stage but the device only supports {max_storage_textures}",
+                num_textures + 3
+            ));
+        }
+
+        // The sliding median baseline keeps a whole frame per history slot in a single buffer
+        check_baseline_buffer(
+            textures_width,
+            textures_height,
+            dips_properties.baseline_mode,
+            device
+                .limits()
+                .max_buffer_size
+                .min(device.limits().max_storage_buffer_binding_size as u64),
+        )?;
+
         let textures = (0..num_textures)
             .map(|i| {
                 let texture = device.create_texture(&TextureDescriptor {
@@ -288,7 +652,7 @@ impl DiPsCompute {
                     mip_level_count: 1,
                     sample_count: 1,
                     dimension: TextureDimension::D2,
-                    format: TextureFormat::Rgba8Unorm,
+                    format: dips_properties.input_format(),
                     usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST,
                     view_formats: &[],
                 });
@@ -304,6 +668,7 @@ impl DiPsCompute {
             create_dynamic_bindings(
                 &device,
                 0,
+                dips_properties.input_format(),
                 textures
                     .iter()
                     .map(|(texture_view, _texture)| texture_view)
@@ -321,7 +686,7 @@ impl DiPsCompute {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8Unorm,
+                format: TextureFormat::R32Float,
                 usage: TextureUsages::STORAGE_BINDING,
                 view_formats: &[],
             });
@@ -335,6 +700,69 @@ impl DiPsCompute {
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
+        // The rolling baselines are restarted by the shader whenever a snapshot is taken
+        let baseline_window = dips_properties.baseline_mode.window();
+        let pixel_bytes =
+            (textures_width as u64 * textures_height as u64) * std::mem::size_of::<f32>() as u64;
+
+        let rolling_baseline_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Rolling baseline buffer"),
+            size: pixel_bytes,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let baseline_history_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Baseline history buffer"),
+            size: pixel_bytes * baseline_window as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let baseline_history_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Baseline history index buffer"),
+            contents: bytemuck::cast_slice(&[0u32]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        // The frame to frame differences are restarted along with the rolling baselines
+        let previous_frame_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Previous frame buffer"),
+            size: pixel_bytes,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let accumulated_diff_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Accumulated diff buffer"),
+            size: pixel_bytes,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // Every frame is spatially filtered once when it arrives and kept for the whole window,
+        // along with the coarser levels of its amplification pyramid
+        let pyramid_bytes = (1..amplification_levels)
+            .map(|level| {
+                let (width, height) =
+                    pyramid_level_dimensions(textures_height, textures_width, level);
+                (width as u64 * height as u64) * std::mem::size_of::<f32>() as u64
+            })
+            .sum::<u64>();
+
+        let filtered_frames_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Filtered frames buffer"),
+            size: (pixel_bytes + pyramid_bytes) * num_textures as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let frame_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Frame index buffer"),
+            contents: bytemuck::cast_slice(&[0u32]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
         let (output_texture_view, output_texture, output_buffer) = {
             let texture = device.create_texture(&TextureDescriptor {
                 label: Some("Output texture"),
@@ -371,6 +799,195 @@ impl DiPsCompute {
             )
         };
 
+        // A float is as wide as an RGBA8 pixel so the diff rows are padded like the output's
+        let (diff_texture_view, diff_texture, diff_buffer) = {
+            let texture = device.create_texture(&TextureDescriptor {
+                label: Some("Diff texture"),
+                size: Extent3d {
+                    width: textures_height,
+                    height: textures_width,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R32Float,
+                usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+
+            let buffer = device.create_buffer(&BufferDescriptor {
+                label: Some("Diff Texture Buffer"),
+                size: padded_bytes_per_row(textures_height) as u64 * textures_width as u64,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+
+            (
+                texture.create_view(&TextureViewDescriptor::default()),
+                texture,
+                buffer,
+            )
+        };
+
+        // Colors the diff is mapped through when colorizing, one RGBA texel per entry
+        let colormap_texture = device.create_texture(&TextureDescriptor {
+            label: Some("Colormap texture"),
+            size: Extent3d {
+                width: COLORMAP_SIZE as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            colormap_texture.as_image_copy(),
+            bytemuck::cast_slice(&dips_properties.colormap.texture_data()),
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(COLORMAP_SIZE as u32 * 4 * std::mem::size_of::<f32>() as u32),
+                rows_per_image: Some(1),
+            },
+            colormap_texture.size(),
+        );
+
+        // Outputs of the transfer curve, only read for LUT curves
+        let transfer_texture = device.create_texture(&TextureDescriptor {
+            label: Some("Transfer curve texture"),
+            size: Extent3d {
+                width: TRANSFER_LUT_SIZE as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            transfer_texture.as_image_copy(),
+            bytemuck::cast_slice(&dips_properties.transfer.curve.texture_data()),
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(TRANSFER_LUT_SIZE as u32 * std::mem::size_of::<f32>() as u32),
+                rows_per_image: Some(1),
+            },
+            transfer_texture.size(),
+        );
+
+        // Dark frame and flat field of the calibration, 1x1 while the shader doesn't calibrate
+        let (dark_texture, flat_texture) = {
+            let calibration = dips_properties.calibration_split().0;
+            let maps = match calibration.is_on() {
+                true => calibration.maps(textures_height, textures_width),
+                false => calibration.maps(1, 1),
+            };
+
+            let create_texture = |label, data: &[[f32; 4]]| {
+                let texture = device.create_texture(&TextureDescriptor {
+                    label: Some(label),
+                    size: Extent3d {
+                        width: maps.width,
+                        height: maps.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: TextureFormat::Rgba32Float,
+                    usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+
+                queue.write_texture(
+                    texture.as_image_copy(),
+                    bytemuck::cast_slice(data),
+                    TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(maps.width * 4 * std::mem::size_of::<f32>() as u32),
+                        rows_per_image: Some(maps.height),
+                    },
+                    texture.size(),
+                );
+
+                texture
+            };
+
+            (
+                create_texture("Calibration dark texture", &maps.dark),
+                create_texture("Calibration flat texture", &maps.flat),
+            )
+        };
+
+        // Diff histogram of the auto sensitivity, cleared before every frame that collects it
+        let histogram_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Diff histogram buffer"),
+            size: (HISTOGRAM_BINS * std::mem::size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let histogram_read_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Diff histogram read buffer"),
+            size: (HISTOGRAM_BINS * std::mem::size_of::<u32>()) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let gain_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Gain buffer"),
+            contents: bytemuck::cast_slice(&[dips_properties.transfer.gain]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        // Band-pass taps and level gains of the amplification, all zero while it's off
+        let amplification_weights_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Amplification weights buffer"),
+            contents: bytemuck::cast_slice(
+                &dips_properties
+                    .amplification
+                    .weights(num_textures, dips_properties.frame_rate),
+            ),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        // Regions of interest every pixel is in, the buffers can't be empty without any
+        let rois = &dips_properties.rois;
+        let roi_mask = match rois.is_empty() {
+            true => vec![0],
+            false => rois.mask(textures_height, textures_width),
+        };
+        let roi_pixels = rois.pixel_counts(&roi_mask);
+
+        let roi_mask_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ROI mask buffer"),
+            contents: bytemuck::cast_slice(&roi_mask),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let roi_stats_clear = rois.gpu_stats_clear();
+        let roi_stats_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ROI stats buffer"),
+            contents: bytemuck::cast_slice(&roi_stats_clear),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        });
+
+        let roi_stats_read_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("ROI stats read buffer"),
+            size: (roi_stats_clear.len() * std::mem::size_of::<u32>()) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
         let output_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("Output Texture Bind Group Layout"),
@@ -392,7 +1009,7 @@ impl DiPsCompute {
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
                             access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
+                            format: TextureFormat::R32Float,
                             view_dimension: TextureViewDimension::D2,
                         },
                         count: None,
@@ -408,6 +1025,193 @@ impl DiPsCompute {
                         },
                         count: None,
                     },
+                    // Rolling baseline
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Baseline history
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Baseline history index
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Previous frame
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Accumulated diff
+                    BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Filtered frames
+                    BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Frame index
+                    BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Diff texture
+                    BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::R32Float,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    // Colormap
+                    BindGroupLayoutEntry {
+                        binding: 11,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // Transfer curve
+                    BindGroupLayoutEntry {
+                        binding: 12,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // Diff histogram
+                    BindGroupLayoutEntry {
+                        binding: 13,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Gain
+                    BindGroupLayoutEntry {
+                        binding: 14,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // ROI mask
+                    BindGroupLayoutEntry {
+                        binding: 15,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // ROI stats
+                    BindGroupLayoutEntry {
+                        binding: 16,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Amplification weights
+                    BindGroupLayoutEntry {
+                        binding: 17,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Calibration dark frame
+                    BindGroupLayoutEntry {
+                        binding: 18,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // Calibration flat field
+                    BindGroupLayoutEntry {
+                        binding: 19,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -427,6 +1231,82 @@ impl DiPsCompute {
                     binding: 2,
                     resource: BindingResource::TextureView(&output_texture_view),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: rolling_baseline_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: baseline_history_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: baseline_history_index_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: previous_frame_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: accumulated_diff_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 8,
+                    resource: filtered_frames_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 9,
+                    resource: frame_index_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 10,
+                    resource: BindingResource::TextureView(&diff_texture_view),
+                },
+                BindGroupEntry {
+                    binding: 11,
+                    resource: BindingResource::TextureView(
+                        &colormap_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 12,
+                    resource: BindingResource::TextureView(
+                        &transfer_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 13,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 14,
+                    resource: gain_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 15,
+                    resource: roi_mask_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 16,
+                    resource: roi_stats_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 17,
+                    resource: amplification_weights_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 18,
+                    resource: BindingResource::TextureView(
+                        &dark_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 19,
+                    resource: BindingResource::TextureView(
+                        &flat_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
             ],
         });
 
@@ -441,21 +1321,26 @@ impl DiPsCompute {
                 push_constant_ranges: &[],
             });
 
-        let pre_compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: Some("Pre Compute Pipeline"),
-            cache: None,
-            layout: Some(&pre_compute_pipeline_layout),
-            entry_point: Some("pre_compute_main"),
-            module: &modified_shader_module,
-            compilation_options: PipelineCompilationOptions {
-                constants: &{
-                    let mut hm = dips_properties.get_properties_hash_map();
-                    hm.insert(String::from("NUM_TEXTURES"), num_textures as f64);
-                    hm
+        let constants = dips_properties.get_properties_hash_map();
+        let create_pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(label),
+                cache: None,
+                layout: Some(&pre_compute_pipeline_layout),
+                entry_point: Some(entry_point),
+                module: &modified_shader_module,
+                compilation_options: PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
                 },
-                ..Default::default()
-            },
-        });
+            })
+        };
+
+        let pre_compute_pipeline = create_pipeline("Pre Compute Pipeline", "pre_compute_main");
+        let pyramid_pipeline = (amplification_levels > 1)
+            .then(|| create_pipeline("Amplification Pyramid Pipeline", "pyramid_main"));
+        let amplify_pipeline = (amplification_levels > 0)
+            .then(|| create_pipeline("Amplification Pipeline", "amplify_main"));
 
         let renderer = if let Some(dip_window) = dips_window {
             let (pipeline, bind_group) = construct_render_pipeline(
@@ -478,6 +1363,9 @@ impl DiPsCompute {
             renderer,
             texture_array_bind_groups,
             pre_compute_pipeline,
+            pyramid_pipeline,
+            amplify_pipeline,
+            amplification_levels,
             output_bind_group,
             input_textures: textures
                 .into_iter()
@@ -486,20 +1374,96 @@ impl DiPsCompute {
             snapshot_buffer,
             output_texture,
             output_buffer,
+            diff_texture,
+            diff_buffer,
+            raw_diff: Vec::new(),
+            histogram_buffer,
+            histogram_read_buffer,
+            gain_buffer,
+            sensitivity_estimator: SensitivityEstimator::new(DiPsParameters::from(
+                &dips_properties,
+            )),
+            gain: dips_properties.transfer.gain,
+            roi_stats_buffer,
+            roi_stats_read_buffer,
+            roi_stats_clear,
+            roi_pixels,
+            roi_stats: Vec::new(),
+            summary_mode: dips_properties.summary,
+            summary: None,
+            intensity_input: dips_properties.intensity_input(),
+            chroma_filter: dips_properties.chroma_filter.into(),
+            calibrator: SensorCalibrator::new(
+                dips_properties.calibration_split().1,
+                dips_properties.chroma_filter.into(),
+            ),
+            denoiser: FrequencyDenoiser::new(dips_properties.denoise.clone()),
+            motion_compensator: MotionCompensator::new(dips_properties.motion_compensation),
+            baseline_history_index: UCircularIndex::new(0, baseline_window),
+            baseline_history_index_buffer,
             texture_dimensions: Extent3d {
                 width: textures_height,
                 height: textures_width,
                 depth_or_array_layers: 1,
             },
             texture_index: UCircularIndex::new(0, num_textures),
+            frame_index_buffer,
+            snapshot_index: 0,
+            num_textures,
         })
     }
 
+    /// Number of frames the temporal median is taken over
+    pub fn temporal_window(&self) -> usize {
+        self.num_textures
+    }
+
     pub fn send_frame(
         &mut self,
         frame: &[u8],
         snapshot: Option<()>,
         surface_texture: Option<&SurfaceTexture>,
+    ) -> Vec<u8> {
+        let frame = self.prepare_frame(
+            self.texture_dimensions.width,
+            self.texture_dimensions.height,
+            frame,
+        );
+
+        self.send_prepared_frame(&frame, snapshot, surface_texture)
+    }
+
+    /// Calibrates, denoises and motion compensates a frame on the CPU, whichever of them are on,
+    /// and hands over the bytes of its float intensities, the frame is borrowed as is when the
+    /// frames aren't uploaded as intensities
+    fn prepare_frame<'a>(&mut self, width: u32, height: u32, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        if !self.intensity_input {
+            return Cow::Borrowed(frame);
+        }
+
+        let intensities = self
+            .calibrator
+            .calibrate(width, height, frame)
+            .unwrap_or_else(|| frame_intensities(frame, self.chroma_filter));
+        let intensities = self
+            .denoiser
+            .denoise(width, height, &intensities)
+            .unwrap_or(intensities);
+        let intensities = self
+            .motion_compensator
+            .compensate(width, height, &intensities)
+            .unwrap_or(intensities);
+
+        Cow::Owned(bytemuck::cast_slice(&intensities).to_vec())
+    }
+
+    /// Sends a frame that went through prepare_frame through the pipeline, the shader calibrates
+    /// it when the CPU didn't
+    fn send_prepared_frame(
+        &mut self,
+        frame: &[u8],
+        snapshot: Option<()>,
+        surface_texture: Option<&SurfaceTexture>,
     ) -> Vec<u8> {
         let mut encoder = self
             .device
@@ -520,11 +1484,45 @@ impl DiPsCompute {
             self.texture_dimensions,
         );
 
+        // Only the texture that was just written gets spatially filtered
+        self.queue.write_buffer(
+            &self.frame_index_buffer,
+            0,
+            bytemuck::cast_slice(&[*self.texture_index.as_ref() as u32]),
+        );
+
         self.texture_index += 1;
 
+        self.queue.write_buffer(
+            &self.baseline_history_index_buffer,
+            0,
+            bytemuck::cast_slice(&[*self.baseline_history_index.as_ref() as u32]),
+        );
+        self.baseline_history_index += 1;
+
         if let Some(_) = snapshot {
             self.queue
                 .write_buffer(&self.snapshot_buffer, 0, bytemuck::cast_slice(&[1u32]));
+
+            // The gain is picked over again for the diffs against the new snapshot
+            self.sensitivity_estimator.reset();
+            self.set_gain(self.sensitivity_estimator.gain());
+        }
+
+        // The snapshot frame has no diffs to collect
+        self.gain = self.sensitivity_estimator.gain();
+        let collect_histogram = snapshot.is_none() && self.sensitivity_estimator.is_collecting();
+        if collect_histogram {
+            encoder.clear_buffer(&self.histogram_buffer, 0, None);
+        }
+
+        let collect_roi_stats = snapshot.is_none() && !self.roi_pixels.is_empty();
+        if collect_roi_stats {
+            self.queue.write_buffer(
+                &self.roi_stats_buffer,
+                0,
+                bytemuck::cast_slice(&self.roi_stats_clear),
+            );
         }
 
         {
@@ -554,12 +1552,77 @@ impl DiPsCompute {
 
             // Dispatch the work groups
             compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, 1);
+
+            // The pyramid of the newest frame is built from its spatially filtered intensities
+            // before the whole window is band-pass filtered, the bind groups carry over
+            if let Some(pyramid_pipeline) = self.pyramid_pipeline.as_ref() {
+                let (level_width, level_height) = pyramid_level_dimensions(
+                    self.texture_dimensions.width,
+                    self.texture_dimensions.height,
+                    1,
+                );
+                let (dispatch_width, dispatch_height) = compute_work_group_count(
+                    (level_width, level_height),
+                    (WORK_GROUP_WIDTH, WORK_GROUP_HEIGHT),
+                );
+
+                compute_pass.set_pipeline(pyramid_pipeline);
+                compute_pass.dispatch_workgroups(
+                    dispatch_width,
+                    dispatch_height,
+                    self.amplification_levels - 1,
+                );
+            }
+
+            if let Some(amplify_pipeline) = self.amplify_pipeline.as_ref() {
+                compute_pass.set_pipeline(amplify_pipeline);
+                compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, 1);
+            }
         }
 
         let padded_bytes_per_row = padded_bytes_per_row(self.texture_dimensions.width);
         let unpadded_bytes_per_row =
             self.texture_dimensions.width * std::mem::size_of::<f32>() as u32;
 
+        // The raw diff is read back whether or not the output is rendered
+        encoder.copy_texture_to_buffer(
+            TexelCopyTextureInfo {
+                aspect: TextureAspect::All,
+                texture: &self.diff_texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            TexelCopyBufferInfo {
+                buffer: &self.diff_buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row as u32),
+                    rows_per_image: Some(self.texture_dimensions.height as u32),
+                },
+            },
+            self.texture_dimensions,
+        );
+
+        if collect_histogram {
+            encoder.copy_buffer_to_buffer(
+                &self.histogram_buffer,
+                0,
+                &self.histogram_read_buffer,
+                0,
+                self.histogram_buffer.size(),
+            );
+        }
+
+        if collect_roi_stats {
+            encoder.copy_buffer_to_buffer(
+                &self.roi_stats_buffer,
+                0,
+                &self.roi_stats_read_buffer,
+                0,
+                self.roi_stats_buffer.size(),
+            );
+        }
+
         // If we have a renderer attached then render to the screen
         // otherwise just copy to the output buffer
         if let Some(renderer) = self.renderer.as_ref() {
@@ -642,6 +1705,135 @@ impl DiPsCompute {
             output_texture
         };
 
+        {
+            let buffer_slice = self.diff_buffer.slice(..);
+            buffer_slice.map_async(MapMode::Read, |_| {});
+            self.device.poll(Maintain::Wait);
+
+            let padded_data = buffer_slice.get_mapped_range();
+            self.raw_diff = vec![
+                0.0;
+                (self.texture_dimensions.width * self.texture_dimensions.height)
+                    as usize
+            ];
+
+            for (padded, diffs) in padded_data.chunks_exact(padded_bytes_per_row).zip(
+                self.raw_diff
+                    .chunks_exact_mut(self.texture_dimensions.width as usize),
+            ) {
+                diffs.copy_from_slice(bytemuck::cast_slice(
+                    &padded[..unpadded_bytes_per_row as usize],
+                ));
+            }
+
+            drop(padded_data);
+            self.diff_buffer.unmap();
+        }
+
+        // The histogram of this frame picks the gain of the next one
+        if collect_histogram {
+            let buffer_slice = self.histogram_read_buffer.slice(..);
+            buffer_slice.map_async(MapMode::Read, |_| {});
+            self.device.poll(Maintain::Wait);
+
+            let histogram = buffer_slice.get_mapped_range();
+            let gain = self
+                .sensitivity_estimator
+                .update(bytemuck::cast_slice(&histogram));
+
+            drop(histogram);
+            self.histogram_read_buffer.unmap();
+
+            self.set_gain(gain);
+        }
+
+        if collect_roi_stats {
+            let buffer_slice = self.roi_stats_read_buffer.slice(..);
+            buffer_slice.map_async(MapMode::Read, |_| {});
+            self.device.poll(Maintain::Wait);
+
+            let roi_stats = buffer_slice.get_mapped_range();
+            self.roi_stats = bytemuck::cast_slice::<u8, u32>(&roi_stats)
+                .chunks_exact(ROI_STATS_WORDS)
+                .zip(&self.roi_pixels)
+                .map(|(words, pixels)| RoiStats::from_gpu(words, *pixels))
+                .collect();
+
+            drop(roi_stats);
+            self.roi_stats_read_buffer.unmap();
+        }
+
         out
     }
+
+    /// Sets the gain the filtered diffs are displayed with from the next frame on
+    fn set_gain(&self, gain: f32) {
+        self.queue
+            .write_buffer(&self.gain_buffer, 0, bytemuck::cast_slice(&[gain]));
+    }
+}
+
+impl DiPsBackend for DiPsCompute {
+    fn process_frame(&mut self, width: u32, height: u32, frame: &[u8]) -> Option<Vec<u8>> {
+        let frame = self.prepare_frame(width, height, frame);
+
+        // The snapshot is taken once the textures have been filled with new frames
+        let snapshot = self.snapshot_index == self.num_textures;
+        let output = self.send_prepared_frame(&frame, snapshot.then_some(()), None);
+
+        if self.snapshot_index <= self.num_textures {
+            self.snapshot_index += 1;
+            self.raw_diff.clear();
+
+            return None;
+        }
+
+        if let Summary::On { threshold } = self.summary_mode {
+            let (width, height) = (
+                self.texture_dimensions.width,
+                self.texture_dimensions.height,
+            );
+
+            self.summary
+                .get_or_insert_with(|| SummaryMaps::new(width, height, threshold))
+                .update(&self.raw_diff);
+        }
+
+        Some(output)
+    }
+
+    fn refresh_baseline(&mut self) {
+        self.snapshot_index = 0;
+        self.motion_compensator.reset();
+    }
+
+    fn raw_diff(&self) -> Option<&[f32]> {
+        // The diff is cleared while the snapshot is being taken
+        if self.raw_diff.is_empty() || self.snapshot_index <= self.num_textures {
+            return None;
+        }
+
+        Some(&self.raw_diff)
+    }
+
+    fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    fn roi_stats(&self) -> Option<&[RoiStats]> {
+        // Like the raw diff there's nothing to summarise while the snapshot is being taken
+        if self.roi_stats.is_empty() || self.raw_diff().is_none() {
+            return None;
+        }
+
+        Some(&self.roi_stats)
+    }
+
+    fn summary(&self) -> Option<&SummaryMaps> {
+        self.summary.as_ref()
+    }
+
+    fn motion(&self) -> Option<Motion> {
+        self.motion_compensator.motion()
+    }
 }
@@ -2,14 +2,83 @@
 @group(4) @binding(0)
 var<uniform> snapshot: u32;
 
+// Intensity of the snapshot, kept as a float so the baseline isn't quantised
 @group(4) @binding(1)
-var snapshot_texture: texture_storage_2d<rgba8unorm, read_write>;
+var snapshot_texture: texture_storage_2d<r32float, read_write>;
 
 @group(4) @binding(2)
 var output_texture: texture_storage_2d<rgba8unorm, write>;
 
+// Rolling baseline state, one intensity per pixel
+@group(4) @binding(3)
+var<storage, read_write> rolling_baseline: array<f32>;
+
+// Previous temporally filtered frames for the sliding median baseline
+@group(4) @binding(4)
+var<storage, read_write> baseline_history: array<f32>;
+
+@group(4) @binding(5)
+var<uniform> baseline_history_index: u32;
+
+// Previous temporally filtered frame and the accumulated frame to frame differences
+@group(4) @binding(6)
+var<storage, read_write> previous_frame: array<f32>;
+
+@group(4) @binding(7)
+var<storage, read_write> accumulated_diff: array<f32>;
+
+// Spatially filtered intensities of every frame in the temporal window, one frame per texture,
+// followed by the coarser levels of the amplification pyramid of every frame
+@group(4) @binding(8)
+var<storage, read_write> filtered_frames: array<f32>;
+
+// Texture the newest frame was written to
+@group(4) @binding(9)
+var<uniform> frame_index: u32;
+
+// Signed difference before the sensitivity filter and colorizing
+@group(4) @binding(10)
+var diff_texture: texture_storage_2d<r32float, write>;
+
+// Color of every diff from -1 to 1, one row of COLORMAP_SIZE texels
+@group(4) @binding(11)
+var colormap_lut: texture_2d<f32>;
+
+// Transfer curve of diffs from -1 to 1, one row of TRANSFER_LUT_SIZE texels
+@group(4) @binding(12)
+var transfer_lut: texture_2d<f32>;
+
+// Count of the raw diffs in each of HISTOGRAM_BINS bins from -1 to 1 for the auto sensitivity
+@group(4) @binding(13)
+var<storage, read_write> diff_histogram: array<atomic<u32>, HISTOGRAM_BINS>;
+
+// The filtered diff is displayed as gain * diff + OFFSET, the auto sensitivity keeps moving it
+@group(4) @binding(14)
+var<uniform> gain: f32;
+
+// Bit i of every pixel is set when it is in the region of interest i
+@group(4) @binding(15)
+var<storage, read> roi_mask: array<u32>;
+
+// ROI_STATS_WORDS words for every region of interest, see accumulate_roi_stats
+@group(4) @binding(16)
+var<storage, read_write> roi_stats: array<atomic<u32>>;
+
+// Band-pass taps by frame age, newest first, followed by the gain of every pyramid level from
+// MAX_TEMPORAL_ARRAY_SIZE on, see amplification_weight
+@group(4) @binding(17)
+var<uniform> amplification_weights: array<vec4<f32>, AMPLIFICATION_WEIGHT_VECTORS>;
+
+// Dark frame of the sensor with the hot pixels in its alpha, see SensorCalibration
+@group(4) @binding(18)
+var dark_texture: texture_2d<f32>;
+
+// Flat field of the sensor normalised to a mean of 1
+@group(4) @binding(19)
+var flat_texture: texture_2d<f32>;
+
 override NUM_TEXTURES: u32 = 1;
-const MAX_TEMPORAL_ARRAY_SIZE: u32 = 16;
+const MAX_TEMPORAL_ARRAY_SIZE: u32 = 64;
 
 
 // Compiled Constants
@@ -20,47 +89,167 @@ override SIGMOID_HORIZONTAL_SCALAR: f32 = 5.0;
 // 1 = Inverse Sigmoid
 override FILTER_TYPE: u32 = 0;
 override CHROMA_FILTER: u32 = 0;
+// 0 = Median
+// 1 = Mean
+// 2 = Trimmed Mean
+// 3 = Min
+// 4 = Max
+// 5 = Percentile
+override TEMPORAL_ESTIMATOR: u32 = 0;
+// Fraction trimmed from each end for the trimmed mean, percentile for the percentile
+override TEMPORAL_ESTIMATOR_PARAMETER: f32 = 0.0;
+// 0 = Fixed
+// 1 = Exponential Moving Average
+// 2 = Sliding Median
+override BASELINE_MODE: u32 = 0;
+// Weight of the newest frame in the exponential moving average
+override BASELINE_ALPHA: f32 = 0.0;
+// Number of previous frames in the sliding median
+override BASELINE_WINDOW: u32 = 1;
+// 0 = Baseline
+// 1 = Frame to Frame
+// 2 = Accumulated Frame to Frame
+override DIFFERENCE_MODE: u32 = 0;
+// Fraction of the accumulated differences kept from one frame to the next
+override ACCUMULATION_DECAY: f32 = 0.0;
+// 0 = Median
+// 1 = Box
+// 2 = Gaussian
+// 3 = Bilateral
+override SPATIAL_FILTER: u32 = 0;
+// Standard deviation in pixels of the Gaussian and bilateral filters
+override SPATIAL_SIGMA: f32 = 1.0;
+// Standard deviation in intensity of the bilateral filter
+override RANGE_SIGMA: f32 = 1.0;
+// 0 = Clamp
+// 1 = Mirror
+override BORDER_MODE: u32 = 0;
+// 0 = Linear
+// 1 = Gamma
+// 2 = Symmetric Log
+// 3 = LUT
+override TRANSFER_CURVE: u32 = 0;
+// Gamma for the gamma curve, linear scale for the symmetric log
+override TRANSFER_PARAMETER: f32 = 1.0;
+override OFFSET: f32 = 0.0;
+// Range the raw diff is clamped to before the transfer curve
+override CLAMP_MIN: f32 = -1.0;
+override CLAMP_MAX: f32 = 1.0;
+// Collect the diff histogram
+override AUTO_SENSITIVITY: bool = false;
+// Number of regions of interest the diffs are summarised over
+override ROI_COUNT: u32 = 0;
+// Show the grey frame instead of the diffs outside of every region
+override ROI_GREY_OUTSIDE: bool = false;
+// Number of spatial pyramid levels of the amplification, 0 shows the diffs instead
+override AMPLIFICATION_LEVELS: u32 = 0;
+// Correct every frame with the dark frame, flat field and hot pixel map
+override CALIBRATION: bool = false;
+// The frames are float intensities the CPU stages made instead of RGBA8 colors, the textures are
+// r32float then
+override INTENSITY_INPUT: bool = false;
 
 override WIN_SIZE_SQUARE = WINDOW_SIZE * WINDOW_SIZE;
 
-const DIFF_SCALE: f32 = 5.0;
+const TRANSFER_LUT_SIZE: u32 = 256;
+const HISTOGRAM_BINS: u32 = 1024;
+const ROI_STATS_WORDS: u32 = 4;
+const ROI_SUM_RANGE: f32 = 8.0;
+const ROI_SUM_SCALE: f32 = 65536.0;
 const MEDIAN_ARRAY_SIZE: i32 = 4;
-const MAX_WIN_SIZE_SQUARE = 11 * 11;
+const WORKGROUP_SIZE: u32 = 16;
+const MAX_WINDOW_SIZE: u32 = 31;
+// The workgroup reads a tile of its own size plus half a window on every side
+const MAX_TILE_SIZE: u32 = WORKGROUP_SIZE + MAX_WINDOW_SIZE - 1;
+override TILE_SIZE: u32 = WORKGROUP_SIZE + u32(WINDOW_SIZE) - 1;
 
-const NEGATIVE_COLOR: f32 = 0.0;
-const POSITIVE_COLOR: f32 = 120.0;
+var<workgroup> spatial_tile: array<f32, MAX_TILE_SIZE * MAX_TILE_SIZE>;
+
+const COLORMAP_SIZE: u32 = 256;
+const MAX_AMPLIFICATION_LEVELS: u32 = 8;
+const AMPLIFICATION_WEIGHT_VECTORS: u32 = (MAX_TEMPORAL_ARRAY_SIZE + MAX_AMPLIFICATION_LEVELS) / 4;
 
 // Helper Functions
-fn diff_to_color(diff: f32) -> vec3<f32> {
-    if (diff < 0) {
-        return hsl_to_rgb(NEGATIVE_COLOR, abs(diff), 0.5);
-    }
-
-    return hsl_to_rgb(POSITIVE_COLOR, diff, 0.5);
-}
-
-// h must be between 0 and 360
-fn hsl_to_rgb(h: f32, s: f32, l: f32) -> vec3<f32> {
-    let chroma = s * (1 - abs(2 * l - 1));
-    let h_prime = h / 60.0;
-    let x = chroma * (1 - abs(h_prime % 2.0 - 1));
-
-    let m = l - chroma / 2.0;
-
-    if (h_prime >= 0 && h_prime < 1) {
-        return vec3<f32>(chroma + m, x + m, 0.0 + m);
-    } else if (h_prime >= 1 && h_prime < 2) {
-        return vec3<f32>(x + m, chroma + m, 0.0 + m);
-    } else if (h_prime >= 2 && h_prime < 3) {
-        return vec3<f32>(0.0 + m, chroma + m, x + m);
-    } else if (h_prime >= 3 && h_prime < 4) {
-        return vec3<f32>(0.0 + m, x + m, chroma + m);
-    } else if (h_prime >= 4 && h_prime < 5) {
-        return vec3<f32>(x + m, 0.0 + m, chroma + m);
-    } else if (h_prime >= 5 && h_prime <= 6) {
-        return vec3<f32>(chroma + m, 0.0 + m, x + m);
-    } else {
-        return vec3<f32>(0.0 + m, 0.0 + m, 0.0 + m);
+// Linearly interpolates the colormap lookup table over diffs from -1 to 1
+fn colormap(diff: f32) -> vec3<f32> {
+    let position = clamp(diff * 0.5 + 0.5, 0.0, 1.0) * f32(COLORMAP_SIZE - 1);
+    let lower = min(u32(floor(position)), COLORMAP_SIZE - 2);
+    let fraction = position - f32(lower);
+
+    let start = textureLoad(colormap_lut, vec2<u32>(lower, 0), 0).rgb;
+    let end = textureLoad(colormap_lut, vec2<u32>(lower + 1, 0), 0).rgb;
+
+    return mix(start, end, fraction);
+}
+
+// Shapes a clamped diff with the TRANSFER_CURVE
+fn transfer_curve(diff: f32) -> f32 {
+    switch TRANSFER_CURVE {
+        case 1u: {
+            return sign(diff) * pow(abs(diff), TRANSFER_PARAMETER);
+        }
+        case 2u: {
+            return sign(diff) * log(1.0 + abs(diff) / TRANSFER_PARAMETER) / log(1.0 + 1.0 / TRANSFER_PARAMETER);
+        }
+        case 3u: {
+            let position = clamp(diff * 0.5 + 0.5, 0.0, 1.0) * f32(TRANSFER_LUT_SIZE - 1);
+            let lower = min(u32(floor(position)), TRANSFER_LUT_SIZE - 2);
+            let fraction = position - f32(lower);
+
+            let start = textureLoad(transfer_lut, vec2<u32>(lower, 0), 0).r;
+            let end = textureLoad(transfer_lut, vec2<u32>(lower + 1, 0), 0).r;
+
+            return mix(start, end, fraction);
+        }
+        default: {
+            return diff;
+        }
+    }
+}
+
+// Bin of the diff histogram a raw diff falls in
+fn histogram_bin(diff: f32) -> u32 {
+    let position = (diff * 0.5 + 0.5) * f32(HISTOGRAM_BINS);
+
+    return min(u32(max(position, 0.0)), HISTOGRAM_BINS - 1);
+}
+
+// Maps a float to an unsigned integer of the same order so that atomics can take the min and max
+fn orderable_bits(value: f32) -> u32 {
+    let bits = bitcast<u32>(value);
+
+    if ((bits & 0x80000000u) != 0u) {
+        return ~bits;
+    }
+
+    return bits | 0x80000000u;
+}
+
+// Adds the diff of a pixel to the statistics of every region it is in
+// The diffs are summed as positive fixed point numbers in a low and a high word, and the min and
+// max are kept as orderable bits
+fn accumulate_roi_stats(pixel: u32, diff: f32) {
+    let mask = roi_mask[pixel];
+    if (mask == 0u) {
+        return;
+    }
+
+    let term = u32(round((clamp(diff, -ROI_SUM_RANGE, ROI_SUM_RANGE) + ROI_SUM_RANGE) * ROI_SUM_SCALE));
+    let bits = orderable_bits(diff);
+
+    for (var roi: u32 = 0; roi < ROI_COUNT; roi++) {
+        if ((mask & (1u << roi)) == 0u) {
+            continue;
+        }
+
+        let stats = roi * ROI_STATS_WORDS;
+        let low = atomicAdd(&roi_stats[stats], term);
+        // Carry into the high word when the low word wraps around
+        if (low + term < low) {
+            atomicAdd(&roi_stats[stats + 1u], 1u);
+        }
+        atomicMin(&roi_stats[stats + 2u], bits);
+        atomicMax(&roi_stats[stats + 3u], bits);
     }
 }
 
@@ -129,46 +318,275 @@ fn load_from_texture_id(texture_id: u32, coords: vec2<u32>) -> vec4<f32> {
     }
 }
 
-/// Takes in the coordinates of the pixel and returns the spatial median filter
-/// color of that pixel with the set WINDOW_SIZE
-fn spatial_median_filter(coords: vec2<u32>, dimensions: vec2<u32>, input_texture_id: u32) -> f32 {
+// Maps a coordinate outside of a texture dimension back inside with the BORDER_MODE
+fn border_coordinate(coordinate: i32, size: i32) -> i32 {
+    var mapped = coordinate;
+    if (BORDER_MODE == 1u) {
+        if (mapped < 0) {
+            mapped = -mapped;
+        }
+        if (mapped >= size) {
+            mapped = 2 * (size - 1) - mapped;
+        }
+    }
+
+    return clamp(mapped, 0, size - 1);
+}
+
+// Weight of a neighbour at the offset with the intensity in the weighted spatial filters
+fn spatial_weight(offset: vec2<i32>, intensity: f32, center: f32) -> f32 {
+    let distance = f32(offset.x * offset.x + offset.y * offset.y);
+
+    switch SPATIAL_FILTER {
+        case 2u: {
+            return exp(-distance / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA));
+        }
+        case 3u: {
+            let range = intensity - center;
+            return exp(-distance / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA) - range * range / (2.0 * RANGE_SIGMA * RANGE_SIGMA));
+        }
+        default: {
+            return 1.0;
+        }
+    }
+}
+
+// Dark frame and flat field corrected color of a pixel of a frame
+fn corrected_color(texture_id: u32, coords: vec2<u32>) -> vec4<f32> {
+    let color = load_from_texture_id(texture_id, coords);
+    let dark = textureLoad(dark_texture, coords, 0).rgb;
+    let flat_field = textureLoad(flat_texture, coords, 0).rgb;
+
+    return vec4<f32>(clamp((color.rgb - dark) / flat_field, vec3<f32>(0.0), vec3<f32>(1.0)), color.a);
+}
+
+// Color of a pixel of a frame with the sensor CALIBRATION applied, hot pixels take the mean of
+// the corrected colors of their good neighbours
+fn calibrated_color(texture_id: u32, coords: vec2<u32>, dimensions: vec2<u32>) -> vec4<f32> {
+    // A grey color of the intensity has that intensity whatever the CHROMA_FILTER
+    if (INTENSITY_INPUT) {
+        return vec4<f32>(load_from_texture_id(texture_id, coords).rrr, 1.0);
+    }
+
+    if (!CALIBRATION) {
+        return load_from_texture_id(texture_id, coords);
+    }
+
+    if (textureLoad(dark_texture, coords, 0).a < 0.5) {
+        return corrected_color(texture_id, coords);
+    }
+
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let neighbour = vec2<i32>(coords) + vec2<i32>(dx, dy);
+            if ((dx == 0 && dy == 0) || any(neighbour < vec2<i32>(0)) || any(neighbour >= vec2<i32>(dimensions))) {
+                continue;
+            }
+
+            if (textureLoad(dark_texture, vec2<u32>(neighbour), 0).a > 0.5) {
+                continue;
+            }
+
+            sum += corrected_color(texture_id, vec2<u32>(neighbour)).rgb;
+            count += 1.0;
+        }
+    }
+
+    if (count == 0.0) {
+        return corrected_color(texture_id, coords);
+    }
+
+    return vec4<f32>(sum / count, 1.0);
+}
+
+/// Takes in the local coordinates of the pixel in its workgroup and returns the SPATIAL_FILTER
+/// color of that pixel with the set WINDOW_SIZE centred on it
+/// Every invocation of the workgroup has to call it since the window is read from the shared tile
+fn spatial_filter(local_id: vec2<u32>, workgroup_id: vec2<u32>, dimensions: vec2<u32>, input_texture_id: u32) -> f32 {
     if (WINDOW_SIZE == 1) {
-        let intensity = get_intensity(load_from_texture_id(input_texture_id, coords.xy));
-        // return vec4<f32>(intensity, intensity, intensity, 1.0);
+        let intensity = get_intensity(calibrated_color(input_texture_id, workgroup_id * WORKGROUP_SIZE + local_id, dimensions));
         return intensity;
     }
 
+    // The tile is shared with the previous filter so wait for everyone to be done with it
+    workgroupBarrier();
+
+    // Load every pixel the windows of the workgroup cover into the tile once
+    let origin = vec2<i32>(workgroup_id * WORKGROUP_SIZE) - vec2<i32>(WINDOW_SIZE / 2);
+    let local_index = local_id.y * WORKGROUP_SIZE + local_id.x;
+    for (var i = local_index; i < TILE_SIZE * TILE_SIZE; i += WORKGROUP_SIZE * WORKGROUP_SIZE) {
+        let x = border_coordinate(origin.x + i32(i % TILE_SIZE), i32(dimensions.x));
+        let y = border_coordinate(origin.y + i32(i / TILE_SIZE), i32(dimensions.y));
+
+        spatial_tile[i] = get_intensity(calibrated_color(input_texture_id, vec2<u32>(u32(x), u32(y)), dimensions));
+    }
+
+    workgroupBarrier();
 
-    var spatial_median_array: array<f32, MAX_WIN_SIZE_SQUARE>;
+    let tile_center = vec2<i32>(local_id) + vec2<i32>(WINDOW_SIZE / 2);
+    var intensity: f32;
+    if (SPATIAL_FILTER == 0u) {
+        intensity = spatial_median(tile_center);
+    } else {
+        intensity = spatial_weighted_mean(tile_center);
+    }
+
+    return intensity;
+}
+
+fn tile_intensity(position: vec2<i32>) -> f32 {
+    return spatial_tile[u32(position.x) + TILE_SIZE * u32(position.y)];
+}
+
+// Box, Gaussian or bilateral mean of the window centred on the tile position
+fn spatial_weighted_mean(tile_center: vec2<i32>) -> f32 {
+    let center = tile_intensity(tile_center);
+    let win_size_2 = WINDOW_SIZE / 2;
+
+    var weighted_sum = 0.0;
+    var weight_sum = 0.0;
+    for (var j = -win_size_2; j <= win_size_2; j++) {
+        for (var i = -win_size_2; i <= win_size_2; i++) {
+            let color = tile_intensity(tile_center + vec2<i32>(i, j));
+            let weight = spatial_weight(vec2<i32>(i, j), color, center);
+
+            weighted_sum += weight * color;
+            weight_sum += weight;
+        }
+    }
+
+    return weighted_sum / weight_sum;
+}
+
+// Key of an intensity whose unsigned order is the order of the floats, like f32::total_cmp
+fn order_key(value: f32) -> u32 {
+    let bits = bitcast<u32>(value);
+    if ((bits & 0x80000000u) != 0u) {
+        return ~bits;
+    }
+    return bits | 0x80000000u;
+}
+
+fn from_order_key(key: u32) -> f32 {
+    if ((key & 0x80000000u) != 0u) {
+        return bitcast<f32>(key & 0x7fffffffu);
+    }
+    return bitcast<f32>(~key);
+}
+
+// Median of the window centred on the tile position, the upper middle value like the CPU backend
+// Bisects the order keys of the intensities between the smallest and largest of the window while
+// counting the window instead of sorting it, so the cost only grows with the window area. The
+// bisection ends on the key of an intensity of the window, so the median is exact whatever the
+// calibration or denoising made of the intensities
+fn spatial_median(tile_center: vec2<i32>) -> f32 {
     let win_size_2 = WINDOW_SIZE / 2;
+    // Position of the median in the sorted window
+    let rank = u32(WIN_SIZE_SQUARE / 2);
+
+    var low: u32 = 0xffffffffu;
+    var high: u32 = 0u;
+    for (var j = -win_size_2; j <= win_size_2; j++) {
+        for (var i = -win_size_2; i <= win_size_2; i++) {
+            let key = order_key(tile_intensity(tile_center + vec2<i32>(i, j)));
+            low = min(low, key);
+            high = max(high, key);
+        }
+    }
+
+    while (low < high) {
+        let middle = low + (high - low) / 2u;
 
-    for (var i = -win_size_2; i < win_size_2; i++) {
-        for (var j = -win_size_2; j < win_size_2; j++) {
-            var color: f32;
-            if (i32(coords.x) + i >= i32(dimensions.x) || i32(coords.y) + j >= i32(dimensions.y) || i32(coords.x) + i < 0 || i32(coords.y) + j < 0) {
-                color = 0.0;
-            } else {
-                // color = get_intensity(textureLoad(input_texture, vec2<u32>(u32(i32(coords.x) + i), u32(i32(coords.y) + j))));
-                color = get_intensity(load_from_texture_id(input_texture_id, vec2<u32>(u32(i32(coords.x) + i), u32(i32(coords.y) + j))));
+        var count: u32 = 0;
+        for (var j = -win_size_2; j <= win_size_2; j++) {
+            for (var i = -win_size_2; i <= win_size_2; i++) {
+                if (order_key(tile_intensity(tile_center + vec2<i32>(i, j))) <= middle) {
+                    count++;
+                }
             }
+        }
 
-            let array_i = i + win_size_2;
-            let array_j = j + win_size_2;
+        if (count > rank) {
+            high = middle;
+        } else {
+            low = middle + 1u;
+        }
+    }
+
+    return from_order_key(low);
+}
 
-            let array_ind = array_i + (WINDOW_SIZE * array_j);
+// Sorts the temporal window and reduces it with the TEMPORAL_ESTIMATOR
+fn temporal_estimate(values: ptr<function, array<f32, MAX_TEMPORAL_ARRAY_SIZE>>) -> f32 {
+    for (var i: u32 = 0; i < NUM_TEXTURES; i++) {
+        var swapped: bool = false;
+        for (var j: u32 = 0; j < NUM_TEXTURES - 1; j++) {
+            if ((*values)[j] > (*values)[j + 1]) {
+                let temp = (*values)[j];
+                (*values)[j] = (*values)[j + 1];
+                (*values)[j + 1] = temp;
 
-            spatial_median_array[array_ind] = color;
+                swapped = true;
+            }
+        }
+
+        if (!swapped) {
+            break;
         }
     }
 
-    // sort the array
-    for (var i = 0; i < WIN_SIZE_SQUARE - 1; i++) {
+    switch TEMPORAL_ESTIMATOR {
+        case 1u: {
+            return mean(values, 0, NUM_TEXTURES);
+        }
+        case 2u: {
+            let trim = u32(f32(NUM_TEXTURES) * TEMPORAL_ESTIMATOR_PARAMETER);
+            return mean(values, trim, NUM_TEXTURES - trim);
+        }
+        case 3u: {
+            return (*values)[0];
+        }
+        case 4u: {
+            return (*values)[NUM_TEXTURES - 1];
+        }
+        case 5u: {
+            let position = TEMPORAL_ESTIMATOR_PARAMETER / 100.0 * f32(NUM_TEXTURES - 1);
+            let lower = u32(floor(position));
+            let upper = min(lower + 1, NUM_TEXTURES - 1);
+            return mix((*values)[lower], (*values)[upper], fract(position));
+        }
+        default: {
+            return (*values)[NUM_TEXTURES / 2];
+        }
+    }
+}
+
+// Mean of the sorted values in [start, end)
+fn mean(values: ptr<function, array<f32, MAX_TEMPORAL_ARRAY_SIZE>>, start: u32, end: u32) -> f32 {
+    var sum = 0.0;
+    for (var i = start; i < end; i++) {
+        sum += (*values)[i];
+    }
+
+    return sum / f32(end - start);
+}
+
+// Median of the previous frames kept in the baseline history
+fn sliding_median(pixel: u32, pixel_count: u32) -> f32 {
+    var history: array<f32, MAX_TEMPORAL_ARRAY_SIZE>;
+    for (var i: u32 = 0; i < BASELINE_WINDOW; i++) {
+        history[i] = baseline_history[i * pixel_count + pixel];
+    }
+
+    for (var i: u32 = 0; i < BASELINE_WINDOW; i++) {
         var swapped: bool = false;
-        for (var j = 0; j < WIN_SIZE_SQUARE - 1; j++) {
-            if (spatial_median_array[j] > spatial_median_array[j + 1]) {
-                let temp = spatial_median_array[j];
-                spatial_median_array[j] = spatial_median_array[j + 1];
-                spatial_median_array[j + 1] = temp;
+        for (var j: u32 = 0; j < BASELINE_WINDOW - 1; j++) {
+            if (history[j] > history[j + 1]) {
+                let temp = history[j];
+                history[j] = history[j + 1];
+                history[j + 1] = temp;
 
                 swapped = true;
             }
@@ -179,64 +597,178 @@ fn spatial_median_filter(coords: vec2<u32>, dimensions: vec2<u32>, input_texture
         }
     }
 
-    let intensity = spatial_median_array[(WIN_SIZE_SQUARE / 2) + 1];
-    // return vec4<f32>(intensity, intensity, intensity, 1.0);
-    return intensity;
-    // return 0.0;
+    return history[BASELINE_WINDOW / 2];
 }
 
-@compute @workgroup_size(16, 16)
+fn amplification_weight(index: u32) -> f32 {
+    return amplification_weights[index / 4u][index % 4u];
+}
+
+// Texture the frame of the given age in the temporal window was written to
+fn texture_of_age(age: u32) -> u32 {
+    return (frame_index + NUM_TEXTURES - age) % NUM_TEXTURES;
+}
+
+fn pyramid_level_dimensions(dimensions: vec2<u32>, level: u32) -> vec2<u32> {
+    return (dimensions + vec2<u32>((1u << level) - 1u)) >> vec2<u32>(level);
+}
+
+// Index of the first intensity of a level of the pyramid of the frame in the texture, the
+// levels of every frame come after the spatially filtered frames of the whole window
+fn pyramid_level_start(texture_id: u32, level: u32, dimensions: vec2<u32>) -> u32 {
+    var frame_size: u32 = 0;
+    var level_start: u32 = 0;
+    for (var i: u32 = 1; i < AMPLIFICATION_LEVELS; i++) {
+        let size = pyramid_level_dimensions(dimensions, i);
+        if (i < level) {
+            level_start += size.x * size.y;
+        }
+        frame_size += size.x * size.y;
+    }
+
+    let pixel_count = dimensions.x * dimensions.y;
+    return NUM_TEXTURES * pixel_count + texture_id * frame_size + level_start;
+}
+
+// Intensity of a pixel of a level of the pyramid, level 0 being the spatially filtered frame
+fn pyramid_intensity(texture_id: u32, level: u32, coords: vec2<u32>, dimensions: vec2<u32>) -> f32 {
+    if (level == 0u) {
+        return filtered_frames[texture_id * dimensions.x * dimensions.y + coords.y * dimensions.x + coords.x];
+    }
+
+    let size = pyramid_level_dimensions(dimensions, level);
+    return filtered_frames[pyramid_level_start(texture_id, level, dimensions) + coords.y * size.x + coords.x];
+}
+
+// Band-pass filtered intensity of a level of the pyramid sampled up to the pixel of the frame
+// The filter is linear so it's taken over the level before it is bilinearly interpolated
+fn filtered_level(level: u32, coords: vec2<u32>, dimensions: vec2<u32>) -> f32 {
+    let size = pyramid_level_dimensions(dimensions, level);
+    let position = clamp(
+        (vec2<f32>(coords) + 0.5) / f32(1u << level) - 0.5,
+        vec2<f32>(0.0),
+        vec2<f32>(size - vec2<u32>(1u)),
+    );
+    let lower = vec2<u32>(floor(position));
+    let upper = min(lower + vec2<u32>(1u), size - vec2<u32>(1u));
+    let fraction = position - vec2<f32>(lower);
+
+    var corners = vec4<f32>(0.0);
+    for (var age: u32 = 0; age < NUM_TEXTURES; age++) {
+        let texture_id = texture_of_age(age);
+        let tap = amplification_weight(age);
+
+        corners += tap * vec4<f32>(
+            pyramid_intensity(texture_id, level, lower, dimensions),
+            pyramid_intensity(texture_id, level, vec2<u32>(upper.x, lower.y), dimensions),
+            pyramid_intensity(texture_id, level, vec2<u32>(lower.x, upper.y), dimensions),
+            pyramid_intensity(texture_id, level, upper, dimensions),
+        );
+    }
+
+    return mix(
+        mix(corners.x, corners.y, fraction.x),
+        mix(corners.z, corners.w, fraction.x),
+        fraction.y,
+    );
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
 fn pre_compute_main(
-    @builtin(global_invocation_id) global_id: vec3<u32>
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
 ) {
     let dimensions = textureDimensions(output_texture);
     let coords = vec2<u32>(global_id.xy);
 
+    // Only the newest frame is spatially filtered, the rest of the window was filtered when it
+    // arrived
+    // Every invocation helps load the spatial tile so pixels outside of the texture are only
+    // dropped afterwards
+    let filtered_intensity = spatial_filter(local_id.xy, workgroup_id.xy, dimensions.xy, frame_index);
+
     if (coords.x >= dimensions.x || coords.y >= dimensions.y) {
         return;
     }
 
-    // var textures: array<vec4<f32>, MAX_TEMPORAL_ARRAY_SIZE>;
-    var median_array: array<f32, MAX_TEMPORAL_ARRAY_SIZE>;
-    // =========== array generator ===========
-//r3p1Ac3
-    // =========== array generator ===========
+    let pixel = coords.y * dimensions.x + coords.x;
+    let pixel_count = dimensions.x * dimensions.y;
 
+    filtered_frames[frame_index * pixel_count + pixel] = filtered_intensity;
 
-    // Fill the median array with the values from the spatially filtered textures
-    // for (var i: u32 = 0; i < NUM_TEXTURES; i++) {
-    //     median_array[i] = get_intensity(textures[i]);
-    // }
+    // The amplification passes take over from here
+    if (AMPLIFICATION_LEVELS > 0u) {
+        return;
+    }
 
-    // Sort the temporal texture array
+    // Fill the median array with the values from the spatially filtered frames
+    var median_array: array<f32, MAX_TEMPORAL_ARRAY_SIZE>;
     for (var i: u32 = 0; i < NUM_TEXTURES; i++) {
-        var swapped: bool = false;
-        for (var j: u32 = 0; j < NUM_TEXTURES; j++) {
-            if (median_array[j] > median_array[j + 1]) {
-                let temp = median_array[j];
-                median_array[j] = median_array[j + 1];
-                median_array[j + 1] = temp;
+        median_array[i] = filtered_frames[i * pixel_count + pixel];
+    }
 
-                swapped = true;
+    let intensity = temporal_estimate(&median_array);
+
+    if (snapshot == 1) {
+        let new_color = vec3<f32>(intensity, intensity, intensity);
+        textureStore(snapshot_texture, coords.xy, vec4<f32>(intensity, 0.0, 0.0, 0.0));
+        textureStore(output_texture, coords.xy, vec4<f32>(new_color.rgb, 1.0));
+        textureStore(diff_texture, coords.xy, vec4<f32>(0.0, 0.0, 0.0, 0.0));
+
+        // Restart the rolling baselines from the snapshot
+        rolling_baseline[pixel] = intensity;
+        for (var i: u32 = 0; i < BASELINE_WINDOW; i++) {
+            baseline_history[i * pixel_count + pixel] = intensity;
+        }
+
+        // Restart the frame to frame differences from the snapshot
+        previous_frame[pixel] = intensity;
+        accumulated_diff[pixel] = 0.0;
+    } else {
+        // Get the baseline to compare against and move it along for the rolling baselines
+        var original_intensity: f32;
+        switch BASELINE_MODE {
+            case 1u: {
+                original_intensity = rolling_baseline[pixel];
+                rolling_baseline[pixel] = mix(original_intensity, intensity, BASELINE_ALPHA);
+            }
+            case 2u: {
+                original_intensity = sliding_median(pixel, pixel_count);
+                baseline_history[baseline_history_index * pixel_count + pixel] = intensity;
+            }
+            default: {
+                original_intensity = textureLoad(snapshot_texture, coords.xy).r;
             }
         }
 
-        if (!swapped) {
-            break;
+        var diff: f32;
+        switch DIFFERENCE_MODE {
+            case 1u: {
+                diff = previous_frame[pixel] - intensity;
+                previous_frame[pixel] = intensity;
+            }
+            case 2u: {
+                diff = previous_frame[pixel] - intensity + accumulated_diff[pixel] * ACCUMULATION_DECAY;
+                previous_frame[pixel] = intensity;
+                accumulated_diff[pixel] = diff;
+            }
+            default: {
+                diff = original_intensity - intensity;
+            }
         }
-    }
 
+        textureStore(diff_texture, coords.xy, vec4<f32>(diff, 0.0, 0.0, 0.0));
 
+        if (AUTO_SENSITIVITY) {
+            atomicAdd(&diff_histogram[histogram_bin(diff)], 1u);
+        }
 
-    if (snapshot == 1) {
-        let intensity = median_array[NUM_TEXTURES / 2];
-        let new_color = vec3<f32>(intensity, intensity, intensity);
-        textureStore(snapshot_texture, coords.xy, vec4<f32>(new_color.rgb, 1.0));
-        textureStore(output_texture, coords.xy, vec4<f32>(new_color.rgb, 1.0));
-    } else {
-        let original_intensity = textureLoad(snapshot_texture, coords.xy).r;
-        var diff = (original_intensity - median_array[NUM_TEXTURES / 2]);
+        if (ROI_COUNT > 0u) {
+            accumulate_roi_stats(pixel, diff);
+        }
 
+        diff = transfer_curve(clamp(diff, CLAMP_MIN, CLAMP_MAX));
         diff = map(diff, -1.0, 1.0, -0.5, 0.5);
 
         switch FILTER_TYPE {
@@ -249,15 +781,91 @@ fn pre_compute_main(
             default: {}
         }
 
-        diff *= DIFF_SCALE;
+        diff = diff * gain + OFFSET;
         var new_color: vec3<f32>;
 
         if (COLORIZE == true) {
-            new_color = diff_to_color(diff);
+            new_color = colormap(diff);
         } else {
             new_color = vec3<f32>(0.5, 0.5, 0.5) - vec3<f32>(diff, diff, diff);
         }
 
+        if (ROI_GREY_OUTSIDE && ROI_COUNT > 0u && roi_mask[pixel] == 0u) {
+            new_color = vec3<f32>(intensity, intensity, intensity);
+        }
+
         textureStore(output_texture, coords.xy, vec4<f32>(new_color.rgb, 1.0));
     }
 }
+
+// Averages the blocks of the newest spatially filtered frame that make up every coarser level of
+// its amplification pyramid, the z of the invocation is the level above the first
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, 1)
+fn pyramid_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dimensions = textureDimensions(output_texture);
+    let level = global_id.z + 1u;
+    let size = pyramid_level_dimensions(dimensions, level);
+    let coords = global_id.xy;
+
+    if (level >= AMPLIFICATION_LEVELS || coords.x >= size.x || coords.y >= size.y) {
+        return;
+    }
+
+    let start = coords << vec2<u32>(level);
+    let end = min(start + vec2<u32>(1u << level), dimensions);
+
+    var sum = 0.0;
+    for (var y = start.y; y < end.y; y++) {
+        for (var x = start.x; x < end.x; x++) {
+            sum += pyramid_intensity(frame_index, 0u, vec2<u32>(x, y), dimensions);
+        }
+    }
+
+    let count = (end.x - start.x) * (end.y - start.y);
+    filtered_frames[pyramid_level_start(frame_index, level, dimensions) + coords.y * size.x + coords.x] = sum / f32(count);
+}
+
+// Adds the amplified band-pass filtered intensity onto the frame in the middle of the temporal
+// window, which is what the filter output lines up with
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn amplify_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dimensions = textureDimensions(output_texture);
+    let coords = vec2<u32>(global_id.xy);
+
+    if (coords.x >= dimensions.x || coords.y >= dimensions.y) {
+        return;
+    }
+
+    let pixel = coords.y * dimensions.x + coords.x;
+
+    // The amplified bands telescope into every level weighted by its gain over the finer level
+    var amplified = 0.0;
+    for (var level: u32 = 0; level < AMPLIFICATION_LEVELS; level++) {
+        let level_gain = amplification_weight(MAX_TEMPORAL_ARRAY_SIZE + level);
+        if (level_gain != 0.0) {
+            amplified += level_gain * filtered_level(level, coords, dimensions);
+        }
+    }
+
+    // Like the other difference modes the output gets darker as the diff grows
+    let diff = -amplified;
+    textureStore(diff_texture, coords.xy, vec4<f32>(diff, 0.0, 0.0, 0.0));
+
+    if (AUTO_SENSITIVITY) {
+        atomicAdd(&diff_histogram[histogram_bin(diff)], 1u);
+    }
+
+    if (ROI_COUNT > 0u) {
+        accumulate_roi_stats(pixel, diff);
+    }
+
+    let color = calibrated_color(texture_of_age((NUM_TEXTURES - 1u) / 2u), coords, dimensions).rgb;
+    var new_color = clamp(color - vec3<f32>(diff), vec3<f32>(0.0), vec3<f32>(1.0));
+
+    // Only the regions are amplified when the rest is greyed out
+    if (ROI_GREY_OUTSIDE && ROI_COUNT > 0u && roi_mask[pixel] == 0u) {
+        new_color = color;
+    }
+
+    textureStore(output_texture, coords.xy, vec4<f32>(new_color.rgb, 1.0));
+}
@@ -38,8 +38,22 @@ impl GpuController {
                     label: Some("Device and Queue"),
                     required_features: Features::TEXTURE_BINDING_ARRAY
                         | Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                    // Request every storage texture the adapter has so that large temporal
+                    // windows can be bound, the filtered frames of the window also need
+                    // buffers as large as the adapter can handle and the state, histogram and
+                    // region of interest buffers are all bound to the same stage
                     required_limits: Limits {
                         max_bind_groups: 5,
+                        max_storage_textures_per_shader_stage: adapter
+                            .limits()
+                            .max_storage_textures_per_shader_stage,
+                        max_storage_buffers_per_shader_stage: adapter
+                            .limits()
+                            .max_storage_buffers_per_shader_stage,
+                        max_storage_buffer_binding_size: adapter
+                            .limits()
+                            .max_storage_buffer_binding_size,
+                        max_buffer_size: adapter.limits().max_buffer_size,
                         ..Default::default()
                     },
                     memory_hints: MemoryHints::default(),
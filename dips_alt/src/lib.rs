@@ -1,15 +1,33 @@
-use std::{fs, path::Path, rc::Rc, sync::Arc};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
 
 use anyhow::{Result, anyhow};
+pub use benchmark::benchmark_spatial_windows;
 use dips_compute::DiPsCompute;
 pub use dips_compute::{ChromaFilter, DiPsProperties, Filter};
+pub use dips_core::{
+    Amplification, AutoSensitivity, Backend, BaselineMode, Blob, BlobTracker, BorderMode,
+    BoundingBox, COLORMAP_SIZE, CalibrationFrame, Colormap, CpuBackend, DecimatedFrame, Decimation,
+    DiPsBackend, DiffExportFormat, DiffExporter, DifferenceMode, EventDetection, EventDetector,
+    EventLog, FrameDecimator, FrequencyAnalysis, FrequencyAnalyzer, FrequencyBand,
+    FrequencyDenoise, FrequencyDenoiser, FrequencyWindow, HotPixelMap, MAX_ROIS,
+    MAX_SPATIAL_WINDOW, MAX_TEMPORAL_WINDOW, MotExporter, Motion, MotionCompensation,
+    MotionCompensator, Retiming, Roi, RoiSeriesExporter, RoiSeriesFormat, RoiShape, RoiStats, Rois,
+    SceneCutDetection, SceneCutDetector, SensorCalibration, SensorCalibrator, SpatialFilter,
+    SpectrumProbe, Summary, SummaryMaps, TRANSFER_LUT_SIZE, TemporalEstimator, TrackMatching,
+    TrackedBlob, Tracking, Transfer, TransferCurve, draw_blob_boxes, iou, warp_frame,
+};
 use egui_wgpu::ScreenDescriptor;
 use gpu_controller::GpuController;
 use gui::EguiRenderer;
 use log::*;
 use opencv::{
     core::{AlgorithmHint, VecN},
-    highgui, imgproc,
+    highgui, imgcodecs, imgproc,
     prelude::*,
     videoio::{self, VideoCaptureTraitConst},
 };
@@ -28,13 +46,12 @@ use winit::{
     window::Window,
 };
 
+mod benchmark;
 mod dips_compute;
 mod gpu_controller;
 mod gui;
 mod utils;
 
-const FRAME_COUNT: usize = 2;
-
 #[derive(Debug)]
 pub enum Encoding {
     Uncompressed,
@@ -127,10 +144,12 @@ pub struct DiPsApp {
 
     // GUI variables
     colorize: bool,
+    colormap: Colormap,
     filter_type: Filter,
     chroma_filter: ChromaFilter,
     filter_sense: f32,
     spatial_window_size: u8,
+    temporal_window: usize,
 }
 
 impl DiPsApp {
@@ -153,10 +172,12 @@ impl DiPsApp {
             index: 0,
             surface_texture: None,
             colorize: true,
+            colormap: Colormap::default(),
             filter_type: Filter::default(),
             chroma_filter: ChromaFilter::default(),
             filter_sense: 5.0,
             spatial_window_size: 1,
+            temporal_window: DiPsProperties::default().temporal_window,
         })
     }
 
@@ -193,7 +214,6 @@ impl DiPsApp {
 
         if self.compute.is_none() {
             self.compute = Some(DiPsCompute::new(
-                FRAME_COUNT,
                 width as u32,
                 height as u32,
                 self.dips_window.as_ref(),
@@ -215,19 +235,18 @@ impl DiPsApp {
 
         let bytes = rgba_frame.data_bytes()?;
 
+        let temporal_window = unsafe { self.compute.as_ref().unwrap_unchecked() }.temporal_window();
+
         // Render the DiPs Frame
         _ = unsafe {
             self.compute.as_mut().unwrap_unchecked().send_frame(
                 &bytes,
-                match self.index {
-                    FRAME_COUNT => Some(()),
-                    _ => None,
-                },
+                (self.index == temporal_window).then_some(()),
                 self.surface_texture.as_ref(),
             )
         };
 
-        if self.index <= FRAME_COUNT {
+        if self.index <= temporal_window {
             self.index += 1;
         }
 
@@ -265,12 +284,13 @@ impl DiPsApp {
                 .default_open(false)
                 .show(renderer.context(), |ui| {
                     let redip = |color: bool,
+                                 colormap: Colormap,
                                  filter: Filter,
                                  chroma: ChromaFilter,
                                  filter_sense: f32,
-                                 spatial_window_size: u8| {
+                                 spatial_window_size: u8,
+                                 temporal_window: usize| {
                         DiPsCompute::new(
-                            FRAME_COUNT,
                             self.dips_window
                                 .as_ref()
                                 .unwrap()
@@ -283,10 +303,13 @@ impl DiPsApp {
                             self.gpu_controller.queue.clone(),
                             DiPsProperties {
                                 colorize: color,
+                                colormap,
                                 filter_type: filter,
                                 chroma_filter: chroma,
                                 sigmoid_horizontal_scalar: filter_sense,
                                 window_size: spatial_window_size,
+                                temporal_window,
+                                ..Default::default()
                             },
                         )
                         .expect("Failed to redip")
@@ -295,6 +318,11 @@ impl DiPsApp {
                     // This is the button to take a snapshot and reset the initial frame
                     if ui.button("SnapShot").clicked() {
                         self.index = 0;
+
+                        // The motion compensation registers onto the first frame of the snapshot
+                        if let Some(compute) = self.compute.as_mut() {
+                            compute.refresh_baseline();
+                        }
                     }
 
                     // This is the checkbox for Colorizing the output
@@ -302,13 +330,44 @@ impl DiPsApp {
                         self.index = 0;
                         self.compute = Some(redip(
                             self.colorize,
+                            self.colormap.clone(),
                             self.filter_type,
                             self.chroma_filter,
                             self.filter_sense,
                             self.spatial_window_size,
+                            self.temporal_window,
                         ));
                     }
 
+                    // This is the combo box to select the colors the diffs are colorized with
+                    egui::ComboBox::from_label("Colormap")
+                        .selected_text(format!("{:?}", self.colormap))
+                        .show_ui(ui, |ui| {
+                            for (colormap, name) in [
+                                (Colormap::RedGreen, "Red / Green"),
+                                (Colormap::Coolwarm, "Coolwarm"),
+                                (Colormap::RdBu, "RdBu"),
+                                (Colormap::Viridis, "Viridis"),
+                                (Colormap::Magma, "Magma"),
+                            ] {
+                                if ui
+                                    .selectable_value(&mut self.colormap, colormap, name)
+                                    .clicked()
+                                {
+                                    self.index = 0;
+                                    self.compute = Some(redip(
+                                        self.colorize,
+                                        self.colormap.clone(),
+                                        self.filter_type,
+                                        self.chroma_filter,
+                                        self.filter_sense,
+                                        self.spatial_window_size,
+                                        self.temporal_window,
+                                    ));
+                                }
+                            }
+                        });
+
                     // This is the combo box to select the sensitivity filter type to be used during the DiPs
                     // Sigmoid
                     // Inverse Sigmoid
@@ -325,10 +384,12 @@ impl DiPsApp {
                                 self.index = 0;
                                 self.compute = Some(redip(
                                     self.colorize,
+                                    self.colormap.clone(),
                                     self.filter_type,
                                     self.chroma_filter,
                                     self.filter_sense,
                                     self.spatial_window_size,
+                                    self.temporal_window,
                                 ));
                             };
 
@@ -343,10 +404,12 @@ impl DiPsApp {
                                 self.index = 0;
                                 self.compute = Some(redip(
                                     self.colorize,
+                                    self.colormap.clone(),
                                     self.filter_type,
                                     self.chroma_filter,
                                     self.filter_sense,
                                     self.spatial_window_size,
+                                    self.temporal_window,
                                 ));
                             };
                         });
@@ -362,10 +425,12 @@ impl DiPsApp {
                         self.index = 0;
                         self.compute = Some(redip(
                             self.colorize,
+                            self.colormap.clone(),
                             self.filter_type,
                             self.chroma_filter,
                             self.filter_sense,
                             self.spatial_window_size,
+                            self.temporal_window,
                         ));
                     };
 
@@ -386,10 +451,12 @@ impl DiPsApp {
                                 self.index = 0;
                                 self.compute = Some(redip(
                                     self.colorize,
+                                    self.colormap.clone(),
                                     self.filter_type,
                                     self.chroma_filter,
                                     self.filter_sense,
                                     self.spatial_window_size,
+                                    self.temporal_window,
                                 ));
                             }
 
@@ -400,10 +467,12 @@ impl DiPsApp {
                                 self.index = 0;
                                 self.compute = Some(redip(
                                     self.colorize,
+                                    self.colormap.clone(),
                                     self.filter_type,
                                     self.chroma_filter,
                                     self.filter_sense,
                                     self.spatial_window_size,
+                                    self.temporal_window,
                                 ));
                             }
 
@@ -418,10 +487,12 @@ impl DiPsApp {
                                 self.index = 0;
                                 self.compute = Some(redip(
                                     self.colorize,
+                                    self.colormap.clone(),
                                     self.filter_type,
                                     self.chroma_filter,
                                     self.filter_sense,
                                     self.spatial_window_size,
+                                    self.temporal_window,
                                 ));
                             }
 
@@ -436,10 +507,12 @@ impl DiPsApp {
                                 self.index = 0;
                                 self.compute = Some(redip(
                                     self.colorize,
+                                    self.colormap.clone(),
                                     self.filter_type,
                                     self.chroma_filter,
                                     self.filter_sense,
                                     self.spatial_window_size,
+                                    self.temporal_window,
                                 ));
                             }
                         });
@@ -447,19 +520,44 @@ impl DiPsApp {
                     // This is the slider to choose a spatial window size
                     if ui
                         .add(
-                            egui::Slider::new(&mut self.spatial_window_size, 1..=7)
-                                .text("Spatial Window Filtering")
-                                .step_by(2.0),
+                            egui::Slider::new(
+                                &mut self.spatial_window_size,
+                                1..=MAX_SPATIAL_WINDOW as u8,
+                            )
+                            .text("Spatial Window Filtering")
+                            .step_by(2.0),
                         )
                         .drag_stopped()
                     {
                         self.index = 0;
                         self.compute = Some(redip(
                             self.colorize,
+                            self.colormap.clone(),
                             self.filter_type,
                             self.chroma_filter,
                             self.filter_sense,
                             self.spatial_window_size,
+                            self.temporal_window,
+                        ));
+                    };
+
+                    // This is the slider to choose how many frames the temporal median is taken over
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut self.temporal_window, 1..=MAX_TEMPORAL_WINDOW)
+                                .text("Temporal Window"),
+                        )
+                        .drag_stopped()
+                    {
+                        self.index = 0;
+                        self.compute = Some(redip(
+                            self.colorize,
+                            self.colormap.clone(),
+                            self.filter_type,
+                            self.chroma_filter,
+                            self.filter_sense,
+                            self.spatial_window_size,
+                            self.temporal_window,
                         ));
                     };
                 });
@@ -551,20 +649,60 @@ pub fn run_dips_app() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Files run_dips_on_file writes alongside the output video
+#[derive(Debug, Default, Clone)]
+pub struct FileOutputs {
+    /// Raw signed diffs of every frame
+    pub diff_export: Option<PathBuf>,
+    /// Statistics of every region of interest per frame
+    pub roi_series: Option<PathBuf>,
+    /// Summary maps of the whole recording
+    pub summary: Option<PathBuf>,
+    /// JSON Lines log of the blobs of every frame
+    pub event_log: Option<PathBuf>,
+    /// MOTChallenge CSV of the blob tracks
+    pub tracks: Option<PathBuf>,
+    /// Dominant frequency and band power maps of the input
+    pub frequency: Option<PathBuf>,
+}
+
+/// Settings of the stages run_dips_on_file runs around the backend, on the raw diffs or on the
+/// frames before they reach it, which the backend never sees
+#[derive(Debug, Default, Clone)]
+pub struct DriverOptions {
+    /// Blob detection on the raw diffs
+    pub events: EventDetection,
+    /// Draws the bounding boxes of the blobs over the output
+    pub event_overlay: bool,
+    /// Follows the blobs across frames
+    pub tracking: Tracking,
+    /// Temporal spectra of the input intensities
+    pub frequency: FrequencyAnalysis,
+    /// Thins out the frames read from the file before DiPs runs on them
+    pub decimation: Decimation,
+    /// Frame rate or duration the output video is retimed to
+    pub retiming: Retiming,
+}
+
 pub fn run_dips_on_file<P>(
     path: P,
     output: P,
     encoding: Encoding,
-    properites: DiPsProperties,
+    mut properites: DiPsProperties,
+    driver_options: DriverOptions,
     refresh_markers: Vec<usize>,
+    outputs: FileOutputs,
 ) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    let gpu_controller = GpuController::new()?;
+    // The GPU is only needed when running on the GPU backend
+    let gpu_controller = match properites.backend {
+        Backend::Gpu => Some(GpuController::new()?),
+        Backend::Cpu => None,
+    };
 
     let mut overall_frame: usize = 0;
-    let mut index: usize = 0;
 
     highgui::named_window("DiPs", highgui::WINDOW_NORMAL)?;
 
@@ -575,6 +713,14 @@ where
 
     let fps = file_stream.get(videoio::CAP_PROP_FPS)?;
 
+    // The temporal stages run at the frame rate of the decimated frames
+    let mut frame_decimator =
+        FrameDecimator::new(driver_options.decimation, driver_options.retiming);
+    frame_decimator.set_source_frame_rate(fps);
+    frame_decimator.set_source_duration(file_stream.get(videoio::CAP_PROP_FRAME_COUNT)? / fps);
+    let frame_rate = frame_decimator.frame_rate().unwrap_or(fps);
+    properites.set_frame_rate(frame_rate);
+
     let fourcc = encoding.as_fourcc();
     let mut output_stream = None;
 
@@ -583,7 +729,31 @@ where
     }
 
     let mut frame = Mat::default();
-    let mut compute_state: Option<DiPsCompute> = None;
+    let mut compute_state: Option<Box<dyn DiPsBackend>> = None;
+
+    let mut diff_exporter = outputs
+        .diff_export
+        .as_ref()
+        .map(|path| DiffExporter::new(path, (&properites).into()))
+        .transpose()?;
+
+    let mut roi_series_exporter = outputs
+        .roi_series
+        .as_ref()
+        .map(|path| RoiSeriesExporter::new(path, properites.rois.clone()))
+        .transpose()?;
+
+    let mut event_detector = EventDetector::new(driver_options.events);
+    let mut event_log = outputs.event_log.as_ref().map(EventLog::new).transpose()?;
+
+    let mut frequency_analyzer =
+        FrequencyAnalyzer::new(driver_options.frequency.clone(), properites.rois.clone());
+    frequency_analyzer.set_frame_rate(frame_rate);
+
+    let mut blob_tracker = BlobTracker::new(driver_options.tracking);
+    let mut mot_exporter = outputs.tracks.as_ref().map(MotExporter::new).transpose()?;
+
+    let mut scene_cut_detector = SceneCutDetector::new(properites.scene_cut);
 
     loop {
         if !file_stream.read(&mut frame)? {
@@ -592,27 +762,30 @@ where
 
         let pts = file_stream.get(videoio::CAP_PROP_PTS)?;
         let dts = file_stream.get(videoio::CAP_PROP_DTS_DELAY)?;
+        let timestamp = file_stream.get(videoio::CAP_PROP_POS_MSEC)? / 1000.0;
 
         let width = frame.rows();
         let height = frame.cols();
 
         if compute_state.is_none() {
-            compute_state = Some(DiPsCompute::new(
-                FRAME_COUNT,
-                width as u32,
-                height as u32,
-                None,
-                gpu_controller.device.clone(),
-                gpu_controller.queue.clone(),
-                properites,
-            )?);
+            compute_state = Some(match gpu_controller.as_ref() {
+                Some(gpu_controller) => Box::new(DiPsCompute::new(
+                    width as u32,
+                    height as u32,
+                    None,
+                    gpu_controller.device.clone(),
+                    gpu_controller.queue.clone(),
+                    properites.clone(),
+                )?),
+                None => Box::new(CpuBackend::new((&properites).into())?),
+            });
         }
 
         if output_stream.is_none() {
             output_stream = Some(videoio::VideoWriter::new(
                 output.as_ref().as_os_str().to_str().unwrap(),
                 fourcc,
-                fps,
+                frame_decimator.output_frame_rate().unwrap_or(fps),
                 opencv::core::Size::new(height, width),
                 true,
             )?);
@@ -628,18 +801,74 @@ where
             AlgorithmHint::ALGO_HINT_DEFAULT,
         )?;
 
-        let bytes = rgba_frame.data_bytes()?;
+        // Frames that don't complete a group go no further
+        let Some(decimated) = frame_decimator.push(
+            height as u32,
+            width as u32,
+            rgba_frame.data_bytes()?,
+            Some(timestamp),
+        ) else {
+            continue;
+        };
+        let bytes = &*decimated.data;
+        let timestamp = decimated.timestamp.unwrap_or(timestamp);
 
-        let new_frame_data = unsafe {
-            compute_state.as_mut().unwrap_unchecked().send_frame(
+        if outputs.frequency.is_some() {
+            frequency_analyzer.push(height as u32, width as u32, bytes, Some(timestamp));
+        }
+
+        // The frame sizes are swapped in the compute state
+        // Pass the original frame through until the baseline has been taken
+        let mut new_frame_data = unsafe {
+            compute_state.as_mut().unwrap_unchecked().process_frame(
+                height as u32,
+                width as u32,
                 &bytes,
-                match index {
-                    FRAME_COUNT => Some(()),
-                    _ => None,
-                },
-                None,
             )
-        };
+        }
+        .unwrap_or_else(|| bytes.to_vec());
+
+        // Frames without a diff are still collecting the baseline
+        if let Some(exporter) = diff_exporter.as_mut()
+            && let Some(diff) = unsafe { compute_state.as_ref().unwrap_unchecked() }.raw_diff()
+        {
+            let compute = unsafe { compute_state.as_ref().unwrap_unchecked() };
+            exporter.write_frame(
+                height as u32,
+                width as u32,
+                diff,
+                Some(timestamp),
+                compute.gain(),
+                compute.motion(),
+            )?;
+        }
+
+        if let Some(exporter) = roi_series_exporter.as_mut()
+            && let Some(stats) = unsafe { compute_state.as_ref().unwrap_unchecked() }.roi_stats()
+        {
+            exporter.write_frame(Some(timestamp), stats)?;
+        }
+
+        if driver_options.events.is_on() {
+            let blobs = event_detector.detect(
+                height as u32,
+                width as u32,
+                unsafe { compute_state.as_ref().unwrap_unchecked() }.raw_diff(),
+            );
+
+            if let Some(log) = event_log.as_mut() {
+                log.write_frame(overall_frame, Some(timestamp), blobs)?;
+            }
+
+            let tracked = blob_tracker.update(overall_frame, blobs);
+            if let Some(exporter) = mot_exporter.as_mut() {
+                exporter.write(tracked)?;
+            }
+
+            if driver_options.event_overlay {
+                draw_blob_boxes(&mut new_frame_data, height as u32, width as u32, blobs);
+            }
+        }
 
         let new_frame =
             match Mat::new_rows_cols_with_bytes::<VecN<u8, 4>>(width, height, &new_frame_data) {
@@ -659,20 +888,26 @@ where
             AlgorithmHint::ALGO_HINT_DEFAULT,
         )?;
 
-        if index <= FRAME_COUNT {
-            index += 1;
-        }
+        // A refresh for a scene cut takes effect from the next frame on
+        let scene_cut = scene_cut_detector.check(
+            &bytes,
+            unsafe { compute_state.as_ref().unwrap_unchecked() }.raw_diff(),
+            Some(timestamp),
+        );
 
         overall_frame += 1;
 
-        if refresh_markers.contains(&overall_frame) {
-            index = 0;
+        if scene_cut || refresh_markers.contains(&overall_frame) {
+            unsafe { compute_state.as_mut().unwrap_unchecked() }.refresh_baseline();
         }
 
         if let Some(stream) = output_stream.as_mut() {
             print!("\rFrame: {}", overall_frame);
-            stream.set(videoio::VIDEOWRITER_PROP_PTS, pts)?;
-            stream.set(videoio::VIDEOWRITER_PROP_DTS_DELAY, dts)?;
+            // Decimated or retimed frames are written evenly spaced at the output frame rate
+            if !frame_decimator.is_on() {
+                stream.set(videoio::VIDEOWRITER_PROP_PTS, pts)?;
+                stream.set(videoio::VIDEOWRITER_PROP_DTS_DELAY, dts)?;
+            }
             stream.write(&output_frame)?;
         }
 
@@ -686,9 +921,179 @@ where
         writer.release()?;
     }
 
+    if frame_decimator.is_on() {
+        println!();
+        println!(
+            "DiPs ran on {} decimated frames, written at {:.3} fps",
+            frame_decimator.frame_count(),
+            frame_decimator.output_frame_rate().unwrap_or(fps)
+        );
+    }
+
+    if properites.auto_sensitivity.is_on()
+        && let Some(compute_state) = compute_state.as_ref()
+    {
+        println!();
+        println!(
+            "Auto sensitivity ended on a gain of {}",
+            compute_state.gain()
+        );
+    }
+
+    if properites.scene_cut.is_on() {
+        println!();
+        println!(
+            "Refreshed the baseline after {} scene cuts",
+            scene_cut_detector.refreshes().len()
+        );
+        for (frame, timestamp) in scene_cut_detector.refreshes() {
+            println!("    frame {frame} at {:.3}s", timestamp.unwrap_or_default());
+        }
+    }
+
+    if let Some(exporter) = diff_exporter.take() {
+        println!();
+        println!("Exported the diffs of {} frames", exporter.frame_count());
+        exporter.finish()?;
+    }
+
+    if let Some(path) = outputs.frequency.as_ref() {
+        println!();
+        match frequency_analyzer.window_count() {
+            0 => println!("Too few frames for a single frequency window, no maps written"),
+            windows => {
+                println!("Writing the frequency maps of {windows} windows");
+                frequency_analyzer.write(path, &Colormap::Viridis)?;
+            }
+        }
+    }
+
+    if let Some(path) = outputs.summary.as_ref()
+        && let Some(summary) = compute_state.as_ref().and_then(|state| state.summary())
+    {
+        println!();
+        println!(
+            "Writing the summary maps of {} frames",
+            summary.frame_count()
+        );
+        summary.write(path, &Colormap::Viridis)?;
+    }
+
+    if let Some(log) = event_log.take() {
+        println!();
+        println!("Logged blobs in {} frames", log.event_count());
+        log.finish()?;
+    }
+
+    if let Some(exporter) = mot_exporter.take() {
+        println!();
+        println!("Wrote {} tracks", blob_tracker.track_count());
+        exporter.finish()?;
+    }
+
+    if let Some(exporter) = roi_series_exporter.take() {
+        println!();
+        println!("Wrote the ROI series of {} frames", exporter.frame_count());
+        exporter.finish()?;
+    }
+
     Ok(())
 }
 
+/// Loads a region of interest mask from an image where every non black pixel is inside, the
+/// mask is scaled to the frame when the sizes differ
+pub fn load_roi_mask<P>(path: P) -> Result<RoiShape>
+where
+    P: AsRef<Path>,
+{
+    let mask = imgcodecs::imread(
+        path.as_ref().as_os_str().to_str().unwrap(),
+        imgcodecs::IMREAD_GRAYSCALE,
+    )?;
+
+    if mask.empty() {
+        return Err(anyhow!("Failed to read the ROI mask {:?}", path.as_ref()));
+    }
+
+    Ok(RoiShape::mask_from_luma(
+        mask.cols() as u32,
+        mask.rows() as u32,
+        mask.data_bytes()?,
+    )?)
+}
+
+/// Loads a dark frame or flat field from an image, 16 bit and float images keep their full
+/// precision
+pub fn load_calibration_frame<P>(path: P) -> Result<CalibrationFrame>
+where
+    P: AsRef<Path>,
+{
+    let frame = imgcodecs::imread(
+        path.as_ref().as_os_str().to_str().unwrap(),
+        imgcodecs::IMREAD_ANYDEPTH | imgcodecs::IMREAD_COLOR,
+    )?;
+
+    if frame.empty() {
+        return Err(anyhow!(
+            "Failed to read the calibration frame {:?}",
+            path.as_ref()
+        ));
+    }
+
+    // Every depth ends up from 0 to 1
+    let scale = match frame.depth() {
+        opencv::core::CV_16U => 1.0 / u16::MAX as f64,
+        opencv::core::CV_32F | opencv::core::CV_64F => 1.0,
+        _ => 1.0 / u8::MAX as f64,
+    };
+
+    let mut rgb_frame = Mat::default();
+    imgproc::cvt_color(
+        &frame,
+        &mut rgb_frame,
+        imgproc::COLOR_BGR2RGB,
+        0,
+        AlgorithmHint::ALGO_HINT_DEFAULT,
+    )?;
+
+    let mut float_frame = Mat::default();
+    rgb_frame.convert_to(&mut float_frame, opencv::core::CV_32FC3, scale, 0.0)?;
+
+    Ok(CalibrationFrame::from_rgb(
+        float_frame.cols() as u32,
+        float_frame.rows() as u32,
+        float_frame
+            .data_typed::<VecN<f32, 3>>()?
+            .iter()
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect(),
+    )?)
+}
+
+/// Loads a hot pixel map from an image where every non black pixel is hot or dead
+pub fn load_hot_pixel_map<P>(path: P) -> Result<HotPixelMap>
+where
+    P: AsRef<Path>,
+{
+    let map = imgcodecs::imread(
+        path.as_ref().as_os_str().to_str().unwrap(),
+        imgcodecs::IMREAD_GRAYSCALE,
+    )?;
+
+    if map.empty() {
+        return Err(anyhow!(
+            "Failed to read the hot pixel map {:?}",
+            path.as_ref()
+        ));
+    }
+
+    Ok(HotPixelMap::from_luma(
+        map.cols() as u32,
+        map.rows() as u32,
+        map.data_bytes()?,
+    )?)
+}
+
 #[allow(unused_variables)]
 pub fn custom_dips_on_files<P>(config_path: P, data_dir: P, output: P) -> Result<()>
 where
@@ -717,6 +1122,13 @@ where
                     | Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
                 required_limits: Limits {
                     max_bind_groups: 5,
+                    max_storage_textures_per_shader_stage: adapter
+                        .limits()
+                        .max_storage_textures_per_shader_stage,
+                    max_storage_buffer_binding_size: adapter
+                        .limits()
+                        .max_storage_buffer_binding_size,
+                    max_buffer_size: adapter.limits().max_buffer_size,
                     ..Default::default()
                 },
                 memory_hints: MemoryHints::default(),
@@ -799,7 +1211,6 @@ where
 
         if compute_state.is_none() {
             compute_state = Some(DiPsCompute::new(
-                FRAME_COUNT,
                 width as u32,
                 height as u32,
                 None,
@@ -833,13 +1244,13 @@ where
         let bytes = frame.data_bytes()?;
         // println!("len: {}", bytes.len());
 
+        let temporal_window =
+            unsafe { compute_state.as_ref().unwrap_unchecked() }.temporal_window();
+
         let new_frame_data = unsafe {
             compute_state.as_mut().unwrap_unchecked().send_frame(
                 &bytes,
-                match index {
-                    FRAME_COUNT => Some(()),
-                    _ => None,
-                },
+                (index == temporal_window).then_some(()),
                 None,
             )
         };
@@ -862,7 +1273,7 @@ where
             AlgorithmHint::ALGO_HINT_DEFAULT,
         )?;
 
-        if index <= FRAME_COUNT {
+        if index <= temporal_window {
             index += 1;
         }
 
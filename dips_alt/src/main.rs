@@ -7,9 +7,26 @@ fn main() -> Result<()> {
 
     let mut input_path = String::new();
     let mut output_path = String::new();
+    let mut diff_export_path = None;
+    let mut roi_series_path = None;
+    let mut rois = Vec::new();
+    let mut roi_grey_outside = false;
+    let mut calibration = SensorCalibration::default();
+    let mut summary_path = None;
+    let mut summary_threshold = 0.05;
+    let mut event_log_path = None;
+    let mut tracks_path = None;
+    let mut frequency_path = None;
+    let mut frequency_window = 64;
+    let mut frequency_mode = FrequencyWindow::Whole;
+    let mut frequency_binning = 4;
+    let mut frequency_bands = Vec::new();
+    let mut spectrum_probe = None;
     let mut encoding = Encoding::Uncompressed;
     let mut dips_props = DiPsProperties::default();
+    let mut driver_options = DriverOptions::default();
     let mut refresh_markers: Vec<usize> = Vec::new();
+    let mut benchmark = false;
 
     for arg in args[1..].iter() {
         match arg.as_str() {
@@ -24,12 +41,145 @@ fn main() -> Result<()> {
         let split = arg.split('=').collect::<Vec<_>>();
 
         match split[0] {
+            "--benchmark" => {
+                benchmark = true;
+            }
             "--input" => {
                 input_path = split[1].to_string();
             }
             "--output" => {
                 output_path = split[1].to_string();
             }
+            "--export_diff" => {
+                diff_export_path = Some(split[1].to_string());
+            }
+            "--roi" => match Roi::load(split[1]) {
+                Result::Ok(loaded) => rois.extend(loaded),
+                Err(err) => return Err(anyhow!(err)),
+            },
+            "--roi_mask" => {
+                // the mask is named after its file
+                let name = std::path::Path::new(split[1])
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_else(|| format!("mask{}", rois.len()));
+
+                rois.push(Roi::new(name, load_roi_mask(split[1])?));
+            }
+            "--roi_grey_outside" => {
+                roi_grey_outside = true;
+            }
+            "--roi_series" => {
+                roi_series_path = Some(split[1].to_string());
+            }
+            "--summary" => {
+                summary_path = Some(split[1].to_string());
+            }
+            "--events" => {
+                // <high>[:<low>[:<min_area>]], low defaults to high for a plain threshold
+                let events = split[1].split(':').collect::<Vec<_>>();
+                let high = match events[0].parse::<f32>() {
+                    Result::Ok(val) => val,
+                    Err(err) => return Err(anyhow!(err)),
+                };
+                let low = match events.get(1).map(|val| val.parse::<f32>()) {
+                    Some(Result::Ok(val)) => val,
+                    Some(Err(err)) => return Err(anyhow!(err)),
+                    None => high,
+                };
+                let min_area = match events.get(2).map(|val| val.parse::<usize>()) {
+                    Some(Result::Ok(val)) => val,
+                    Some(Err(err)) => return Err(anyhow!(err)),
+                    None => 1,
+                };
+
+                driver_options.events = EventDetection::Threshold {
+                    high,
+                    low,
+                    min_area,
+                };
+            }
+            "--event_log" => {
+                event_log_path = Some(split[1].to_string());
+            }
+            "--track" => {
+                // <matching>:<threshold>[:<min_hits>[:<max_missed>]], e.g. iou:0.3:3:5
+                let track = split[1].split(':').collect::<Vec<_>>();
+                let mut values = Vec::new();
+                for value in track.iter().skip(1) {
+                    values.push(match value.parse::<f32>() {
+                        Result::Ok(val) => val,
+                        Err(err) => return Err(anyhow!(err)),
+                    });
+                }
+
+                let matching = match (track[0], values.first()) {
+                    ("centroid", Some(max_distance)) => TrackMatching::Centroid {
+                        max_distance: *max_distance,
+                    },
+                    ("iou", Some(min_iou)) => TrackMatching::Iou { min_iou: *min_iou },
+                    _ => return Err(anyhow!("Invalid Track Matching")),
+                };
+
+                driver_options.tracking = Tracking::On {
+                    matching,
+                    min_hits: values.get(1).map_or(3, |val| *val as usize),
+                    max_missed: values.get(2).map_or(5, |val| *val as usize),
+                };
+            }
+            "--tracks" => {
+                tracks_path = Some(split[1].to_string());
+            }
+            "--frequency" => {
+                frequency_path = Some(split[1].to_string());
+            }
+            "--frequency_window" => {
+                // <frames>[:<hop>], a hop gives maps per window instead of the whole recording
+                let window = split[1].split(':').collect::<Vec<_>>();
+                frequency_window = match window[0].parse::<usize>() {
+                    Result::Ok(val) if val >= 4 => val,
+                    Result::Ok(_) => return Err(anyhow!("A frequency window needs 4 frames")),
+                    Err(err) => return Err(anyhow!(err)),
+                };
+                frequency_mode = match window.get(1).map(|val| val.parse::<usize>()) {
+                    Some(Result::Ok(hop)) if hop > 0 => FrequencyWindow::Sliding { hop },
+                    Some(Result::Ok(_)) => return Err(anyhow!("Invalid Frequency Hop")),
+                    Some(Err(err)) => return Err(anyhow!(err)),
+                    None => FrequencyWindow::Whole,
+                };
+            }
+            "--frequency_binning" => {
+                frequency_binning = match split[1].parse::<u32>() {
+                    Result::Ok(val) if val > 0 => val,
+                    Result::Ok(_) => return Err(anyhow!("Invalid Frequency Binning")),
+                    Err(err) => return Err(anyhow!(err)),
+                };
+            }
+            "--band" => {
+                // <low>:<high> in Hz
+                let band = split[1].split(':').collect::<Vec<_>>();
+                let (Some(low), Some(high)) = (band.first(), band.get(1)) else {
+                    return Err(anyhow!("Invalid Frequency Band"));
+                };
+                let (low, high) = match (low.parse::<f32>(), high.parse::<f32>()) {
+                    (Result::Ok(low), Result::Ok(high)) if low <= high => (low, high),
+                    _ => return Err(anyhow!("Invalid Frequency Band")),
+                };
+
+                frequency_bands.push(FrequencyBand { low, high });
+            }
+            "--spectrum" => {
+                spectrum_probe = Some(split[1].to_string());
+            }
+            "--event_overlay" => {
+                driver_options.event_overlay = true;
+            }
+            "--summary_threshold" => {
+                summary_threshold = match split[1].parse::<f32>() {
+                    Result::Ok(val) => val,
+                    Err(err) => return Err(anyhow!(err)),
+                };
+            }
             "--encoding" => {
                 encoding = match split[1] {
                     "RGBA" => Encoding::Uncompressed,
@@ -65,12 +215,350 @@ fn main() -> Result<()> {
                     Err(err) => return Err(anyhow!(err)),
                 });
             }
-            "--colorize" => {
-                dips_props.set_colorize(match split[1] {
-                    "false" => false,
-                    _ => true,
+            "--spatial_filter" => {
+                // weighted filters take their standard deviations after colons, e.g. gaussian:1.5
+                let filter = split[1].split(':').collect::<Vec<_>>();
+                let mut sigmas = Vec::new();
+                for sigma in filter.iter().skip(1) {
+                    sigmas.push(match sigma.parse::<f32>() {
+                        Result::Ok(val) => val,
+                        Err(err) => return Err(anyhow!(err)),
+                    });
+                }
+
+                dips_props.set_spatial_filter(match (filter[0], sigmas.as_slice()) {
+                    ("median", _) => SpatialFilter::Median,
+                    ("box", _) => SpatialFilter::Box,
+                    ("gaussian", [sigma]) => SpatialFilter::Gaussian(*sigma),
+                    ("bilateral", [spatial_sigma, range_sigma]) => SpatialFilter::Bilateral {
+                        spatial_sigma: *spatial_sigma,
+                        range_sigma: *range_sigma,
+                    },
+                    _ => return Err(anyhow!("Invalid Spatial Filter")),
+                });
+            }
+            "--border" => {
+                dips_props.set_border_mode(match split[1] {
+                    "clamp" => BorderMode::Clamp,
+                    "mirror" => BorderMode::Mirror,
+                    _ => return Err(anyhow!("Invalid Border Mode")),
+                });
+            }
+            "--temp_win" => {
+                dips_props.set_temporal_window(match split[1].parse::<usize>() {
+                    Result::Ok(val) => val,
+                    Err(err) => return Err(anyhow!(err)),
+                });
+            }
+            "--estimator" => {
+                // estimators with a parameter take it after a colon, e.g. percentile:10
+                let estimator = split[1].split(':').collect::<Vec<_>>();
+                let parameter = match estimator.get(1).map(|val| val.parse::<f32>()) {
+                    Some(Result::Ok(val)) => Some(val),
+                    Some(Err(err)) => return Err(anyhow!(err)),
+                    None => None,
+                };
+
+                dips_props.set_temporal_estimator(match (estimator[0], parameter) {
+                    ("median", _) => TemporalEstimator::Median,
+                    ("mean", _) => TemporalEstimator::Mean,
+                    ("trimmed_mean", fraction) => {
+                        TemporalEstimator::TrimmedMean(fraction.unwrap_or(0.25))
+                    }
+                    ("min", _) => TemporalEstimator::Min,
+                    ("max", _) => TemporalEstimator::Max,
+                    ("percentile", Some(percentile)) => TemporalEstimator::Percentile(percentile),
+                    _ => return Err(anyhow!("Invalid Temporal Estimator")),
+                });
+            }
+            "--baseline" => {
+                // rolling baselines take their time constant in frames after a colon, e.g. ema:30
+                let baseline = split[1].split(':').collect::<Vec<_>>();
+
+                dips_props.set_baseline_mode(match (baseline[0], baseline.get(1)) {
+                    ("fixed", _) => BaselineMode::Fixed,
+                    ("ema", Some(time_constant)) => {
+                        BaselineMode::ExponentialMovingAverage(match time_constant.parse::<f32>() {
+                            Result::Ok(val) => val,
+                            Err(err) => return Err(anyhow!(err)),
+                        })
+                    }
+                    ("sliding_median", Some(frames)) => {
+                        BaselineMode::SlidingMedian(match frames.parse::<usize>() {
+                            Result::Ok(val) => val,
+                            Err(err) => return Err(anyhow!(err)),
+                        })
+                    }
+                    _ => return Err(anyhow!("Invalid Baseline Mode")),
+                });
+            }
+            "--difference" => {
+                // the accumulated mode takes its time constant in frames after a colon, e.g. accumulated:30
+                let difference = split[1].split(':').collect::<Vec<_>>();
+
+                dips_props.set_difference_mode(match (difference[0], difference.get(1)) {
+                    ("baseline", _) => DifferenceMode::Baseline,
+                    ("frame", _) => DifferenceMode::FrameToFrame,
+                    ("accumulated", Some(time_constant)) => {
+                        DifferenceMode::AccumulatedFrameToFrame(
+                            match time_constant.parse::<f32>() {
+                                Result::Ok(val) => val,
+                                Err(err) => return Err(anyhow!(err)),
+                            },
+                        )
+                    }
+                    _ => return Err(anyhow!("Invalid Difference Mode")),
+                });
+            }
+            "--amplify" => {
+                // <low>:<high>:<alpha>[:<levels>[:<cutoff>]], the band in Hz
+                let amplify = split[1].split(':').collect::<Vec<_>>();
+                let mut values = Vec::new();
+                for value in amplify.iter() {
+                    values.push(match value.parse::<f32>() {
+                        Result::Ok(val) => val,
+                        Err(err) => return Err(anyhow!(err)),
+                    });
+                }
+
+                let [low, high, alpha, ..] = values[..] else {
+                    return Err(anyhow!("Invalid Amplification"));
+                };
+                if low < 0.0 || high <= low {
+                    return Err(anyhow!("Invalid Amplification Band"));
+                }
+
+                dips_props.set_amplification(Amplification::On {
+                    band: FrequencyBand { low, high },
+                    alpha,
+                    levels: values.get(3).map_or(1, |val| *val as u32),
+                    cutoff_wavelength: values.get(4).copied().unwrap_or(0.0),
+                });
+            }
+            "--curve" => {
+                // curves with a parameter take it after a colon, e.g. gamma:0.5
+                let curve = match split[1].strip_prefix("lut:") {
+                    Some(lut_path) => match TransferCurve::load_lut(lut_path) {
+                        Result::Ok(curve) => curve,
+                        Err(err) => return Err(anyhow!(err)),
+                    },
+                    None => {
+                        let curve = split[1].split(':').collect::<Vec<_>>();
+                        let parameter = match curve.get(1).map(|val| val.parse::<f32>()) {
+                            Some(Result::Ok(val)) => Some(val),
+                            Some(Err(err)) => return Err(anyhow!(err)),
+                            None => None,
+                        };
+
+                        match (curve[0], parameter) {
+                            ("linear", _) => TransferCurve::Linear,
+                            ("gamma", Some(gamma)) => TransferCurve::Gamma(gamma),
+                            ("symlog", scale) => TransferCurve::SymmetricLog(scale.unwrap_or(0.1)),
+                            _ => return Err(anyhow!("Invalid Transfer Curve")),
+                        }
+                    }
+                };
+
+                dips_props.set_transfer(Transfer {
+                    curve,
+                    ..dips_props.transfer
+                });
+            }
+            "--gain" => {
+                dips_props.set_transfer(Transfer {
+                    gain: match split[1].parse::<f32>() {
+                        Result::Ok(val) => val,
+                        Err(err) => return Err(anyhow!(err)),
+                    },
+                    ..dips_props.transfer.clone()
+                });
+            }
+            "--offset" => {
+                dips_props.set_transfer(Transfer {
+                    offset: match split[1].parse::<f32>() {
+                        Result::Ok(val) => val,
+                        Err(err) => return Err(anyhow!(err)),
+                    },
+                    ..dips_props.transfer.clone()
+                });
+            }
+            "--clamp" => {
+                // the range is given as min:max, e.g. -0.2:0.2
+                let (clamp_min, clamp_max) = match split[1]
+                    .split_once(':')
+                    .map(|(min, max)| (min.parse::<f32>(), max.parse::<f32>()))
+                {
+                    Some((Result::Ok(min), Result::Ok(max))) if min < max => (min, max),
+                    Some((Err(err), _) | (_, Err(err))) => return Err(anyhow!(err)),
+                    _ => return Err(anyhow!("Invalid Clamp Range")),
+                };
+
+                dips_props.set_transfer(Transfer {
+                    clamp_min,
+                    clamp_max,
+                    ..dips_props.transfer.clone()
+                });
+            }
+            "--auto_sensitivity" => {
+                // e.g. first:30:99 or continuous:99:60
+                let auto_sensitivity = split[1].split(':').collect::<Vec<_>>();
+                let parse =
+                    |index: usize| auto_sensitivity.get(index).map(|val| val.parse::<f32>());
+
+                dips_props.set_auto_sensitivity(match (auto_sensitivity[0], parse(1), parse(2)) {
+                    ("off", _, _) => AutoSensitivity::Off,
+                    ("first", Some(Result::Ok(frames)), Some(Result::Ok(percentile))) => {
+                        AutoSensitivity::FirstFrames {
+                            frames: frames as usize,
+                            percentile,
+                        }
+                    }
+                    (
+                        "continuous",
+                        Some(Result::Ok(percentile)),
+                        Some(Result::Ok(time_constant)),
+                    ) => AutoSensitivity::Continuous {
+                        percentile,
+                        time_constant,
+                    },
+                    (_, Some(Err(err)), _) | (_, _, Some(Err(err))) => return Err(anyhow!(err)),
+                    _ => return Err(anyhow!("Invalid Auto Sensitivity")),
+                });
+            }
+            "--scene_cut" => {
+                // the detector takes its threshold after a colon, e.g. histogram:0.3
+                let scene_cut = split[1].split(':').collect::<Vec<_>>();
+                let threshold = match scene_cut.get(1).map(|val| val.parse::<f32>()) {
+                    Some(Result::Ok(val)) => Some(val),
+                    Some(Err(err)) => return Err(anyhow!(err)),
+                    None => None,
+                };
+
+                dips_props.set_scene_cut(match (scene_cut[0], threshold) {
+                    ("off", _) => SceneCutDetection::Off,
+                    ("mean_diff", Some(threshold)) => {
+                        SceneCutDetection::MeanAbsoluteDiff(threshold)
+                    }
+                    ("histogram", Some(threshold)) => {
+                        SceneCutDetection::HistogramDistance(threshold)
+                    }
+                    _ => return Err(anyhow!("Invalid Scene Cut Detection")),
+                });
+            }
+            "--decimate" => {
+                // the group size after a colon, e.g. bin:4
+                let decimate = split[1].split(':').collect::<Vec<_>>();
+                let factor = match decimate.get(1).map(|val| val.parse::<usize>()) {
+                    Some(Result::Ok(val)) if val > 0 => Some(val),
+                    Some(Result::Ok(_)) => return Err(anyhow!("Invalid Decimation Factor")),
+                    Some(Err(err)) => return Err(anyhow!(err)),
+                    None => None,
+                };
+
+                driver_options.decimation = match (decimate[0], factor) {
+                    ("off", _) => Decimation::Off,
+                    ("keep", Some(factor)) => Decimation::KeepEvery(factor),
+                    ("bin", Some(factor)) => Decimation::Bin(factor),
+                    _ => return Err(anyhow!("Invalid Decimation")),
+                };
+            }
+            "--retime" => {
+                let retime = split[1].split(':').collect::<Vec<_>>();
+                let value = match retime.get(1).map(|val| val.parse::<f64>()) {
+                    Some(Result::Ok(val)) if val > 0.0 => Some(val),
+                    Some(Result::Ok(_)) => return Err(anyhow!("Invalid Retiming")),
+                    Some(Err(err)) => return Err(anyhow!(err)),
+                    None => None,
+                };
+
+                driver_options.retiming = match (retime[0], value) {
+                    ("off", _) => Retiming::Off,
+                    ("fps", Some(frame_rate)) => Retiming::FrameRate(frame_rate),
+                    ("duration", Some(duration)) => Retiming::Duration(duration),
+                    _ => return Err(anyhow!("Invalid Retiming")),
+                };
+            }
+            "--motion" => {
+                dips_props.set_motion_compensation(match split[1] {
+                    "off" => MotionCompensation::Off,
+                    "translation" => MotionCompensation::Translation,
+                    "similarity" => MotionCompensation::Similarity,
+                    _ => return Err(anyhow!("Invalid Motion Compensation")),
                 });
             }
+            "--denoise" => {
+                // the mode and its radii in frequency bins, notch points are <fx>,<fy>
+                let denoise = split[1].split(':').collect::<Vec<_>>();
+                let parse = |value: &str| value.parse::<f32>().map_err(|err| anyhow!(err));
+
+                dips_props.set_denoise(match (denoise[0], &denoise[1..]) {
+                    ("off", []) => FrequencyDenoise::Off,
+                    ("lowpass", [radius]) => FrequencyDenoise::LowPass {
+                        radius: parse(radius)?,
+                    },
+                    ("bandpass", [inner, outer]) => FrequencyDenoise::BandPass {
+                        inner: parse(inner)?,
+                        outer: parse(outer)?,
+                    },
+                    ("notch", [radius, points @ ..]) if !points.is_empty() => {
+                        let mut notch_points = Vec::new();
+                        for point in points {
+                            let Some((x, y)) = point.split_once(',') else {
+                                return Err(anyhow!("Invalid Notch Point"));
+                            };
+                            notch_points.push((parse(x)?, parse(y)?));
+                        }
+
+                        FrequencyDenoise::Notch {
+                            points: notch_points.into(),
+                            radius: parse(radius)?,
+                        }
+                    }
+                    ("square", [half_size]) => FrequencyDenoise::Square {
+                        half_size: match half_size.parse::<u32>() {
+                            Result::Ok(val) => val,
+                            Err(err) => return Err(anyhow!(err)),
+                        },
+                    },
+                    _ => return Err(anyhow!("Invalid Denoise Mode")),
+                });
+            }
+            "--dark" => {
+                calibration.dark = Some(load_calibration_frame(split[1])?);
+            }
+            "--flat" => {
+                calibration.flat = Some(load_calibration_frame(split[1])?);
+            }
+            "--hot_pixels" => {
+                calibration.hot_pixels = Some(load_hot_pixel_map(split[1])?);
+            }
+            "--backend" => {
+                dips_props.set_backend(match split[1] {
+                    "gpu" => Backend::Gpu,
+                    "cpu" => Backend::Cpu,
+                    _ => return Err(anyhow!("Invalid Backend")),
+                });
+            }
+            "--colorize" => {
+                // anything but false colorizes, a colormap name or lut:<path> also picks the colors
+                match split[1] {
+                    "false" => dips_props.set_colorize(false),
+                    "true" => dips_props.set_colorize(true),
+                    colormap => {
+                        dips_props.set_colorize(true);
+                        dips_props.set_colormap(match colormap.strip_prefix("lut:") {
+                            Some(lut_path) => match Colormap::load_lut(lut_path) {
+                                Result::Ok(lut) => lut,
+                                Err(err) => return Err(anyhow!(err)),
+                            },
+                            None => match Colormap::from_name(colormap) {
+                                Some(colormap) => colormap,
+                                None => return Err(anyhow!("Invalid Colormap")),
+                            },
+                        });
+                    }
+                }
+            }
             _ => match split[0].parse::<usize>() {
                 Result::Ok(parsed) => refresh_markers.push(parsed),
                 Err(err) => {
@@ -80,6 +568,74 @@ fn main() -> Result<()> {
         }
     }
 
+    // a ROI probe is looked up by name once every region is known
+    let spectrum_probe = match spectrum_probe.as_deref().map(|probe| probe.split_once(':')) {
+        None => None,
+        Some(Some(("roi", name))) => match rois.iter().position(|roi| roi.name == name) {
+            Some(index) => Some(SpectrumProbe::Roi(index)),
+            None => return Err(anyhow!("No region of interest named {name}")),
+        },
+        Some(_) => {
+            let pixel = spectrum_probe.as_deref().unwrap_or_default().split(',');
+            match pixel.map(|val| val.parse::<u32>()).collect::<Vec<_>>()[..] {
+                [Result::Ok(x), Result::Ok(y)] => Some(SpectrumProbe::Pixel { x, y }),
+                _ => return Err(anyhow!("Invalid Spectrum Probe")),
+            }
+        }
+    };
+
+    if frequency_path.is_some() {
+        driver_options.frequency = FrequencyAnalysis::On {
+            window: frequency_window,
+            mode: frequency_mode,
+            binning: frequency_binning,
+            bands: frequency_bands.into(),
+            probe: spectrum_probe,
+        };
+    } else if spectrum_probe.is_some() {
+        return Err(anyhow!("A spectrum probe needs --frequency"));
+    }
+
+    if !rois.is_empty() {
+        dips_props.set_rois(match Rois::new(rois, roi_grey_outside) {
+            Result::Ok(rois) => rois,
+            Err(err) => return Err(anyhow!(err)),
+        });
+    } else if roi_series_path.is_some() {
+        return Err(anyhow!(
+            "A ROI series needs at least one --roi or --roi_mask"
+        ));
+    }
+
+    dips_props.set_calibration(calibration);
+
+    if summary_path.is_some() {
+        dips_props.set_summary(Summary::On {
+            threshold: summary_threshold,
+        });
+    }
+
+    if !driver_options.events.is_on() && (event_log_path.is_some() || driver_options.event_overlay)
+    {
+        return Err(anyhow!("An event log or overlay needs --events"));
+    }
+
+    if dips_props.amplification.is_on() && dips_props.backend == Backend::Cpu {
+        return Err(anyhow!("Amplification needs the GPU backend"));
+    }
+
+    if driver_options.tracking.is_on() && !driver_options.events.is_on() {
+        return Err(anyhow!("Tracking needs --events"));
+    }
+
+    if tracks_path.is_some() && !driver_options.tracking.is_on() {
+        return Err(anyhow!("A tracks file needs --track"));
+    }
+
+    if benchmark {
+        return benchmark_spatial_windows(dips_props);
+    }
+
     if input_path.is_empty() {
         return Err(anyhow!("Input file not specified"));
     }
@@ -92,8 +648,15 @@ fn main() -> Result<()> {
     println!("===================================");
     println!("input path: {}", input_path);
     println!("output path: {}", output_path);
+    println!("diff export path: {:#?}", diff_export_path);
+    println!("ROI series path: {:#?}", roi_series_path);
+    println!("summary path: {:#?}", summary_path);
+    println!("event log path: {:#?}", event_log_path);
+    println!("tracks path: {:#?}", tracks_path);
+    println!("frequency path: {:#?}", frequency_path);
     println!("Encoding: {:#?}", encoding);
     println!("Properties: {:#?}", dips_props);
+    println!("Driver options: {:#?}", driver_options);
     println!("Refresh Markers: {:#?}", refresh_markers);
     println!();
 
@@ -102,6 +665,15 @@ fn main() -> Result<()> {
         output_path,
         encoding,
         dips_props,
+        driver_options,
         refresh_markers,
+        FileOutputs {
+            diff_export: diff_export_path.map(std::path::PathBuf::from),
+            roi_series: roi_series_path.map(std::path::PathBuf::from),
+            summary: summary_path.map(std::path::PathBuf::from),
+            event_log: event_log_path.map(std::path::PathBuf::from),
+            tracks: tracks_path.map(std::path::PathBuf::from),
+            frequency: frequency_path.map(std::path::PathBuf::from),
+        },
     )
 }
@@ -0,0 +1,226 @@
+use std::f64::consts::PI;
+
+use crate::{FrequencyBand, MAX_TEMPORAL_WINDOW};
+
+/// Most levels the spatial pyramid of the amplification can have
+pub const MAX_AMPLIFICATION_LEVELS: u32 = 8;
+/// Floats in the uniform of the amplification weights, the taps of the largest temporal window
+/// followed by the gain of every level
+pub const AMPLIFICATION_WEIGHTS: usize = MAX_TEMPORAL_WINDOW + MAX_AMPLIFICATION_LEVELS as usize;
+
+/// Eulerian style magnification of the periodic changes of every pixel
+///
+/// The intensities of the temporal window are band-pass filtered over the band in Hz and the
+/// filtered change, multiplied by alpha, is added back onto the frame in the middle of the
+/// window instead of showing the difference from the baseline
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Amplification {
+    #[default]
+    Off,
+    /// With more than one level the frame is split into a pyramid of spatial bands, each half
+    /// the size of the previous one, and the bands with a wavelength in pixels under the cutoff
+    /// are amplified proportionally less so fine noise isn't blown up. A cutoff of 0 amplifies
+    /// every band by alpha
+    On {
+        band: FrequencyBand,
+        alpha: f32,
+        levels: u32,
+        cutoff_wavelength: f32,
+    },
+}
+
+impl Amplification {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+
+    /// Number of pyramid levels, 0 while the amplification is off
+    pub fn levels(&self) -> u32 {
+        match self {
+            Self::Off => 0,
+            Self::On { levels, .. } => (*levels).clamp(1, MAX_AMPLIFICATION_LEVELS),
+        }
+    }
+
+    /// Taps of the band-pass filter over a temporal window of the given number of frames at the
+    /// frame rate, by the age of the frame with the newest frame first
+    ///
+    /// A Hann windowed sinc band-pass without a response to constant intensities, scaled to a
+    /// gain of 1 in the middle of the band. The filter is symmetric so its output lines up with
+    /// the frame in the middle of the window
+    pub fn taps(&self, window: usize, frame_rate: f64) -> Vec<f32> {
+        let Self::On { band, .. } = self else {
+            return vec![0.0; window];
+        };
+
+        // Band edges in cycles per frame, at most the Nyquist frequency
+        let frame_rate = if frame_rate.is_finite() && frame_rate > 0.0 {
+            frame_rate
+        } else {
+            1.0
+        };
+        let low = (band.low.max(0.0) as f64 / frame_rate).min(0.5);
+        let high = (band.high.max(0.0) as f64 / frame_rate).clamp(low, 0.5);
+
+        let center = (window as f64 - 1.0) / 2.0;
+        let sinc = |x: f64| match x == 0.0 {
+            true => 1.0,
+            false => (PI * x).sin() / (PI * x),
+        };
+
+        let mut taps = (0..window)
+            .map(|age| {
+                let offset = age as f64 - center;
+                let ideal =
+                    2.0 * high * sinc(2.0 * high * offset) - 2.0 * low * sinc(2.0 * low * offset);
+                let hann =
+                    0.5 - 0.5 * (2.0 * PI * (age as f64 + 1.0) / (window as f64 + 1.0)).cos();
+
+                ideal * hann
+            })
+            .collect::<Vec<_>>();
+
+        // Constant intensities shouldn't leak through the window
+        let mean = taps.iter().sum::<f64>() / window.max(1) as f64;
+        taps.iter_mut().for_each(|tap| *tap -= mean);
+
+        let middle = (low + high) / 2.0;
+        let response = taps
+            .iter()
+            .enumerate()
+            .map(|(age, tap)| tap * (2.0 * PI * middle * (age as f64 - center)).cos())
+            .sum::<f64>()
+            .abs();
+
+        taps.into_iter()
+            .map(|tap| match response > f64::EPSILON {
+                true => (tap / response) as f32,
+                false => 0.0,
+            })
+            .collect()
+    }
+
+    /// Amplification of every pyramid level, finest first
+    ///
+    /// The band of level l has a wavelength of 2^(l + 1) pixels
+    pub fn level_alphas(&self) -> Vec<f32> {
+        let Self::On {
+            alpha,
+            cutoff_wavelength,
+            ..
+        } = *self
+        else {
+            return Vec::new();
+        };
+
+        (0..self.levels())
+            .map(|level| {
+                let wavelength = (2u32 << level) as f32;
+                match cutoff_wavelength > 0.0 {
+                    true => alpha * (wavelength / cutoff_wavelength).min(1.0),
+                    false => alpha,
+                }
+            })
+            .collect()
+    }
+
+    /// Contents of the uniform of the amplification weights, see [`AMPLIFICATION_WEIGHTS`]
+    ///
+    /// Every level is sampled up to the full frame so the sum of the amplified bands telescopes
+    /// into the filtered levels weighted by the difference of their alpha and the alpha of the
+    /// next finer level
+    pub fn weights(&self, window: usize, frame_rate: f64) -> Vec<f32> {
+        let mut weights = vec![0.0; AMPLIFICATION_WEIGHTS];
+
+        for (weight, tap) in weights.iter_mut().zip(self.taps(window, frame_rate)) {
+            *weight = tap;
+        }
+
+        let mut finer_alpha = 0.0;
+        for (level, alpha) in self.level_alphas().into_iter().enumerate() {
+            weights[MAX_TEMPORAL_WINDOW + level] = alpha - finer_alpha;
+            finer_alpha = alpha;
+        }
+
+        weights
+    }
+}
+
+/// Size of a level of the spatial pyramid of a frame, level 0 being the frame itself
+pub fn pyramid_level_dimensions(width: u32, height: u32, level: u32) -> (u32, u32) {
+    (width.div_ceil(1 << level), height.div_ceil(1 << level))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gain of the taps for a sinusoid of the frequency in Hz
+    fn response(taps: &[f32], frequency: f64, frame_rate: f64) -> f64 {
+        let (re, im) = taps
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (age, tap)| {
+                let phase = 2.0 * PI * frequency * age as f64 / frame_rate;
+                (
+                    re + *tap as f64 * phase.cos(),
+                    im + *tap as f64 * phase.sin(),
+                )
+            });
+
+        re.hypot(im)
+    }
+
+    fn amplification(low: f32, high: f32) -> Amplification {
+        Amplification::On {
+            band: FrequencyBand { low, high },
+            alpha: 10.0,
+            levels: 1,
+            cutoff_wavelength: 0.0,
+        }
+    }
+
+    #[test]
+    fn taps_pass_the_band_and_reject_constants() {
+        let taps = amplification(4.0, 6.0).taps(31, 30.0);
+        assert_eq!(taps.len(), 31);
+
+        // Symmetric around the middle frame
+        for (newest, oldest) in taps.iter().zip(taps.iter().rev()) {
+            assert!((newest - oldest).abs() < 1e-6);
+        }
+
+        assert!(taps.iter().sum::<f32>().abs() < 1e-5);
+        assert!((response(&taps, 5.0, 30.0) - 1.0).abs() < 1e-4);
+        for frequency in [0.5, 12.0, 14.0] {
+            let gain = response(&taps, frequency, 30.0);
+            assert!(gain < 0.05, "{frequency} Hz passed with {gain}");
+        }
+
+        // The filtered frame in the middle of the window follows a sinusoid in the band
+        let signal = (0..31)
+            .map(|age| 0.5 + 0.2 * (2.0 * PI * 5.0 * age as f64 / 30.0 + 0.3).sin())
+            .collect::<Vec<_>>();
+        let filtered = taps
+            .iter()
+            .zip(&signal)
+            .map(|(tap, intensity)| *tap as f64 * intensity)
+            .sum::<f64>();
+        assert!((filtered - (signal[15] - 0.5)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn taps_are_zero_while_off() {
+        assert_eq!(Amplification::Off.taps(5, 30.0), [0.0; 5]);
+        assert!(Amplification::Off.level_alphas().is_empty());
+    }
+
+    #[test]
+    fn pyramid_levels_halve_and_round_up() {
+        assert_eq!(pyramid_level_dimensions(640, 480, 0), (640, 480));
+        assert_eq!(pyramid_level_dimensions(640, 480, 1), (320, 240));
+        assert_eq!(pyramid_level_dimensions(101, 51, 1), (51, 26));
+        assert_eq!(pyramid_level_dimensions(101, 51, 3), (13, 7));
+        assert_eq!(pyramid_level_dimensions(5, 3, 7), (1, 1));
+    }
+}
@@ -0,0 +1,462 @@
+use std::{
+    fmt::Debug,
+    io::{self, ErrorKind},
+    sync::Arc,
+};
+
+use crate::{ChromaFilter, cpu::color_intensity};
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+/// Smallest value of the normalised flat field, pixels the flat field barely lit would
+/// otherwise be blown up to full brightness
+pub const MIN_FLAT_FIELD: f32 = 0.01;
+
+/// Calibration image of the sensor with red, green and blue from 0 to 1 for every pixel
+///
+/// Scaled to the frame with the nearest pixel when the sizes differ, like ROI masks
+#[derive(Clone, PartialEq)]
+pub struct CalibrationFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Arc<[[f32; 3]]>,
+}
+
+impl Debug for CalibrationFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CalibrationFrame({}x{})", self.width, self.height)
+    }
+}
+
+impl CalibrationFrame {
+    /// Frame from row major red, green and blue values from 0 to 1
+    pub fn from_rgb(width: u32, height: u32, pixels: Vec<[f32; 3]>) -> io::Result<Self> {
+        if pixels.len() != (width * height) as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "A {width}x{height} calibration frame needs {} pixels but got {}",
+                    width * height,
+                    pixels.len()
+                ),
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels: pixels.into(),
+        })
+    }
+
+    /// Color of the pixel at (x, y) of a frame of the given size
+    fn sample(&self, (x, y): (u32, u32), (frame_width, frame_height): (u32, u32)) -> [f32; 3] {
+        let cx = (x as u64 * self.width as u64 / frame_width.max(1) as u64) as usize;
+        let cy = (y as u64 * self.height as u64 / frame_height.max(1) as u64) as usize;
+
+        self.pixels
+            .get(cy * self.width as usize + cx)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Hot or dead pixels of the sensor, replaced by the mean of their good neighbours
+///
+/// Scaled like a [`CalibrationFrame`]
+#[derive(Clone, PartialEq)]
+pub struct HotPixelMap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Arc<[bool]>,
+}
+
+impl Debug for HotPixelMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "HotPixelMap({}x{}, {} pixels)",
+            self.width,
+            self.height,
+            self.pixels.iter().filter(|hot| **hot).count()
+        )
+    }
+}
+
+impl HotPixelMap {
+    /// Map from an 8 bit greyscale image where every non zero pixel is hot or dead
+    pub fn from_luma(width: u32, height: u32, luma: &[u8]) -> io::Result<Self> {
+        if luma.len() != (width * height) as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "A {width}x{height} hot pixel map needs {} pixels but got {}",
+                    width * height,
+                    luma.len()
+                ),
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels: luma.iter().map(|value| *value != 0).collect(),
+        })
+    }
+
+    /// Whether the pixel at (x, y) of a frame of the given size is hot or dead
+    fn contains(&self, (x, y): (u32, u32), (frame_width, frame_height): (u32, u32)) -> bool {
+        let mx = (x as u64 * self.width as u64 / frame_width.max(1) as u64) as usize;
+        let my = (y as u64 * self.height as u64 / frame_height.max(1) as u64) as usize;
+
+        self.pixels
+            .get(my * self.width as usize + mx)
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
+/// Correction of the fixed pattern noise and vignetting of a scientific camera, applied to every
+/// frame before its intensity is taken
+///
+/// Every color channel becomes (raw - dark) / (flat - dark), with the flat field normalised to a
+/// mean of 1 so the frame keeps its brightness, and the hot pixels are interpolated from their
+/// neighbours afterwards. Any of the three can be left out
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SensorCalibration {
+    pub dark: Option<CalibrationFrame>,
+    pub flat: Option<CalibrationFrame>,
+    pub hot_pixels: Option<HotPixelMap>,
+}
+
+impl SensorCalibration {
+    pub fn is_on(&self) -> bool {
+        self.dark.is_some() || self.flat.is_some() || self.hot_pixels.is_some()
+    }
+
+    /// Calibration of a frame of the given size
+    pub fn maps(&self, width: u32, height: u32) -> CalibrationMaps {
+        let dimensions = (width, height);
+        let coordinates = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)));
+
+        let dark = coordinates
+            .clone()
+            .map(|coords| {
+                let [r, g, b] = self
+                    .dark
+                    .as_ref()
+                    .map_or([0.0; 3], |dark| dark.sample(coords, dimensions));
+                let hot = self
+                    .hot_pixels
+                    .as_ref()
+                    .is_some_and(|hot_pixels| hot_pixels.contains(coords, dimensions));
+
+                [r, g, b, if hot { 1.0 } else { 0.0 }]
+            })
+            .collect::<Vec<_>>();
+
+        let mut flat = coordinates
+            .map(|coords| match &self.flat {
+                Some(flat) => {
+                    let [r, g, b] = flat.sample(coords, dimensions);
+                    let [dark_r, dark_g, dark_b, _] = dark[(coords.1 * width + coords.0) as usize];
+                    [r - dark_r, g - dark_g, b - dark_b, 1.0]
+                }
+                None => [1.0; 4],
+            })
+            .collect::<Vec<_>>();
+
+        // Normalise every channel by its mean over the good pixels
+        if self.flat.is_some() {
+            for channel in 0..3 {
+                let (sum, count) = flat
+                    .iter()
+                    .zip(dark.iter())
+                    .filter(|(_, dark)| dark[3] == 0.0)
+                    .fold((0.0f64, 0usize), |(sum, count), (flat, _)| {
+                        (sum + flat[channel].max(0.0) as f64, count + 1)
+                    });
+                let mean = match sum > 0.0 {
+                    true => (sum / count as f64) as f32,
+                    false => 1.0,
+                };
+
+                for value in flat.iter_mut() {
+                    value[channel] = (value[channel] / mean).max(MIN_FLAT_FIELD);
+                }
+            }
+        }
+
+        CalibrationMaps {
+            width,
+            height,
+            dark,
+            flat,
+        }
+    }
+}
+
+/// Dark frame and normalised flat field of a frame size, uploaded to the GPU as two RGBA float
+/// textures
+///
+/// The alpha of the dark frame is 1 for the hot pixels, the alpha of the flat field is unused
+#[derive(Debug, Clone)]
+pub struct CalibrationMaps {
+    pub width: u32,
+    pub height: u32,
+    pub dark: Vec<[f32; 4]>,
+    pub flat: Vec<[f32; 4]>,
+}
+
+impl CalibrationMaps {
+    /// Dark and flat corrected color of the pixel
+    fn corrected(&self, frame: &[u8], pixel: usize) -> [f32; 3] {
+        let (dark, flat) = (self.dark[pixel], self.flat[pixel]);
+
+        [0, 1, 2].map(|channel| {
+            let raw = frame[pixel * 4 + channel] as f32 / 255.0;
+            ((raw - dark[channel]) / flat[channel]).clamp(0.0, 1.0)
+        })
+    }
+
+    /// Intensities of the calibrated colors of an RGBA8 frame of the size of the maps, kept as
+    /// floats so the correction isn't quantised again
+    pub fn correct(&self, frame: &[u8], chroma_filter: ChromaFilter) -> Vec<f32> {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let mut output = Vec::with_capacity((self.width * self.height) as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = (y * width + x) as usize;
+
+                let color = match self.dark[pixel][3] > 0.5 {
+                    false => self.corrected(frame, pixel),
+                    true => {
+                        // Mean of the good pixels around it
+                        let mut sum = [0.0; 3];
+                        let mut count = 0;
+                        for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                            let (nx, ny) = (x + dx, y + dy);
+                            if (dx, dy) == (0, 0)
+                                || !(0..width).contains(&nx)
+                                || !(0..height).contains(&ny)
+                            {
+                                continue;
+                            }
+
+                            let neighbour = (ny * width + nx) as usize;
+                            if self.dark[neighbour][3] > 0.5 {
+                                continue;
+                            }
+
+                            let color = self.corrected(frame, neighbour);
+                            for channel in 0..3 {
+                                sum[channel] += color[channel];
+                            }
+                            count += 1;
+                        }
+
+                        match count {
+                            0 => self.corrected(frame, pixel),
+                            count => sum.map(|sum| sum / count as f32),
+                        }
+                    }
+                };
+
+                output.push(color_intensity(color, chroma_filter));
+            }
+        }
+
+        output
+    }
+}
+
+/// Calibrates every frame on the CPU, for the CPU backend and for the GPU backends when the
+/// frames go through a CPU stage that needs calibrated frames before they are uploaded
+#[derive(Debug, Clone)]
+pub struct SensorCalibrator {
+    calibration: SensorCalibration,
+    chroma_filter: ChromaFilter,
+
+    // Maps of the frame size they were made for
+    maps: Option<CalibrationMaps>,
+}
+
+impl SensorCalibrator {
+    pub fn new(calibration: SensorCalibration, chroma_filter: ChromaFilter) -> Self {
+        Self {
+            calibration,
+            chroma_filter,
+            maps: None,
+        }
+    }
+
+    /// Float intensities of the calibrated RGBA8 frame, None while the calibration is off
+    pub fn calibrate(&mut self, width: u32, height: u32, frame: &[u8]) -> Option<Vec<f32>> {
+        if !self.calibration.is_on() || frame.len() != (width * height * 4) as usize {
+            return None;
+        }
+
+        let maps = match self.maps.take() {
+            Some(maps) if (maps.width, maps.height) == (width, height) => maps,
+            _ => self.calibration.maps(width, height),
+        };
+        let output = maps.correct(frame, self.chroma_filter);
+        self.maps = Some(maps);
+
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grey RGBA8 frame of the levels
+    fn grey_frame(levels: &[u8]) -> Vec<u8> {
+        levels
+            .iter()
+            .flat_map(|level| [*level, *level, *level, 255])
+            .collect()
+    }
+
+    fn grey(width: u32, height: u32, values: &[f32]) -> CalibrationFrame {
+        CalibrationFrame::from_rgb(
+            width,
+            height,
+            values.iter().map(|value| [*value; 3]).collect(),
+        )
+        .unwrap()
+    }
+
+    fn hot_pixels(width: u32, height: u32, hot: &[usize]) -> HotPixelMap {
+        let luma = (0..(width * height) as usize)
+            .map(|pixel| if hot.contains(&pixel) { 255 } else { 0 })
+            .collect::<Vec<_>>();
+
+        HotPixelMap::from_luma(width, height, &luma).unwrap()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (pixel, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (actual - expected).abs() < 1e-6,
+                "pixel {pixel} is {actual} instead of {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn frames_are_dark_and_flat_corrected() {
+        // The flat field minus the dark frame is 0.5 and 0.9 with a mean of 0.7
+        let calibration = SensorCalibration {
+            dark: Some(grey(4, 1, &[0.1; 4])),
+            flat: Some(grey(4, 1, &[0.6, 0.6, 1.0, 1.0])),
+            hot_pixels: None,
+        };
+        let maps = calibration.maps(4, 1);
+        let flat = [0.5 / 0.7, 0.5 / 0.7, 0.9 / 0.7, 0.9 / 0.7];
+        assert_close(
+            &maps.flat.iter().map(|flat| flat[0]).collect::<Vec<_>>(),
+            &flat,
+        );
+
+        let raw = 100.0 / 255.0;
+        let intensities = SensorCalibrator::new(calibration, ChromaFilter::All)
+            .calibrate(4, 1, &grey_frame(&[100; 4]))
+            .unwrap();
+        assert_close(&intensities, &flat.map(|flat| (raw - 0.1) / flat));
+    }
+
+    #[test]
+    fn intensities_follow_the_chroma_filter() {
+        let calibration = SensorCalibration {
+            dark: Some(CalibrationFrame::from_rgb(1, 1, vec![[0.1, 0.2, 0.3]]).unwrap()),
+            ..Default::default()
+        };
+        let frame = [255, 204, 153, 255];
+
+        for (chroma_filter, expected) in [
+            (ChromaFilter::Red, 0.9),
+            (ChromaFilter::Green, 0.6),
+            (ChromaFilter::Blue, 0.3),
+            (ChromaFilter::All, 0.6),
+        ] {
+            let intensities = SensorCalibrator::new(calibration.clone(), chroma_filter)
+                .calibrate(1, 1, &frame)
+                .unwrap();
+            assert_close(&intensities, &[expected]);
+        }
+    }
+
+    #[test]
+    fn dim_flat_fields_are_limited() {
+        // The first pixel wasn't lit at all, so its flat field would be 0
+        let calibration = SensorCalibration {
+            flat: Some(grey(2, 1, &[0.0, 1.0])),
+            ..Default::default()
+        };
+        let maps = calibration.maps(2, 1);
+        assert_eq!(maps.flat[0][0], MIN_FLAT_FIELD);
+        assert_eq!(maps.flat[1][0], 2.0);
+
+        assert_close(
+            &maps.correct(&grey_frame(&[1, 200]), ChromaFilter::All),
+            &[1.0 / 255.0 / MIN_FLAT_FIELD, 100.0 / 255.0],
+        );
+    }
+
+    #[test]
+    fn hot_pixels_take_the_mean_of_their_good_neighbours() {
+        let levels = [10, 20, 30, 40, 250, 60, 70, 80, 90];
+        let level = |pixel: usize| levels[pixel] as f32 / 255.0;
+        let calibrate = |hot: &[usize]| {
+            SensorCalibration {
+                hot_pixels: Some(hot_pixels(3, 3, hot)),
+                ..Default::default()
+            }
+            .maps(3, 3)
+            .correct(&grey_frame(&levels), ChromaFilter::All)
+        };
+
+        // In the middle all eight neighbours are good
+        let intensities = calibrate(&[4]);
+        let mean = [0, 1, 2, 3, 5, 6, 7, 8].map(level).iter().sum::<f32>() / 8.0;
+        assert_close(&intensities[4..5], &[mean]);
+        assert_close(&intensities[..4], &[0, 1, 2, 3].map(level));
+
+        // In the corner only the neighbours inside the frame that aren't hot count
+        let intensities = calibrate(&[0, 1]);
+        assert_close(
+            &intensities[..2],
+            &[
+                (level(3) + level(4)) / 2.0,
+                (level(2) + level(3) + level(4) + level(5)) / 4.0,
+            ],
+        );
+    }
+
+    #[test]
+    fn hot_pixels_without_good_neighbours_are_kept() {
+        let calibration = SensorCalibration {
+            hot_pixels: Some(hot_pixels(2, 1, &[0, 1])),
+            ..Default::default()
+        };
+
+        assert_close(
+            &calibration
+                .maps(2, 1)
+                .correct(&grey_frame(&[51, 102]), ChromaFilter::All),
+            &[0.2, 0.4],
+        );
+    }
+
+    #[test]
+    fn calibrator_is_off_without_maps() {
+        let mut calibrator = SensorCalibrator::new(SensorCalibration::default(), ChromaFilter::All);
+        assert!(calibrator.calibrate(1, 1, &grey_frame(&[10])).is_none());
+    }
+}
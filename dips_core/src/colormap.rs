@@ -0,0 +1,294 @@
+use std::{
+    fmt::Debug,
+    io::{self, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::cpu::diff_to_color;
+
+/// Number of entries in every colormap lookup table
+pub const COLORMAP_SIZE: usize = 256;
+
+// Control points of the built in maps, evenly spaced from the most negative to the most
+// positive diff and linearly interpolated in between
+const COOLWARM: [[u8; 3]; 9] = [
+    [59, 76, 192],
+    [98, 130, 234],
+    [141, 176, 254],
+    [184, 208, 249],
+    [221, 221, 221],
+    [245, 196, 173],
+    [244, 154, 123],
+    [222, 96, 77],
+    [180, 4, 38],
+];
+
+const RDBU: [[u8; 3]; 11] = [
+    [0x67, 0x00, 0x1F],
+    [0xB2, 0x18, 0x2B],
+    [0xD6, 0x60, 0x4D],
+    [0xF4, 0xA5, 0x82],
+    [0xFD, 0xDB, 0xC7],
+    [0xF7, 0xF7, 0xF7],
+    [0xD1, 0xE5, 0xF0],
+    [0x92, 0xC5, 0xDE],
+    [0x43, 0x93, 0xC3],
+    [0x21, 0x66, 0xAC],
+    [0x05, 0x30, 0x61],
+];
+
+const VIRIDIS: [[u8; 3]; 9] = [
+    [0x44, 0x01, 0x54],
+    [0x47, 0x2D, 0x7B],
+    [0x3B, 0x52, 0x8B],
+    [0x2C, 0x72, 0x8E],
+    [0x21, 0x91, 0x8C],
+    [0x28, 0xAE, 0x80],
+    [0x5E, 0xC9, 0x62],
+    [0xAD, 0xDC, 0x30],
+    [0xFD, 0xE7, 0x25],
+];
+
+const MAGMA: [[u8; 3]; 9] = [
+    [0x00, 0x00, 0x04],
+    [0x18, 0x0F, 0x3D],
+    [0x44, 0x0F, 0x76],
+    [0x72, 0x1F, 0x81],
+    [0x9E, 0x2F, 0x7F],
+    [0xCD, 0x40, 0x71],
+    [0xF1, 0x60, 0x5D],
+    [0xFD, 0x96, 0x68],
+    [0xFC, 0xFD, 0xBF],
+];
+
+/// Colors the filtered diff is mapped through when colorizing
+///
+/// Diffs from -1 to 1 are spread over the table, anything further out gets the end colors
+#[derive(Default, Clone, PartialEq)]
+pub enum Colormap {
+    /// Red for negative and green for positive diffs, saturated by the size of the diff
+    #[default]
+    RedGreen,
+    /// Diverging blue to grey to red, colorblind safe
+    Coolwarm,
+    /// Diverging red to white to blue from ColorBrewer, colorblind safe
+    RdBu,
+    /// Perceptually uniform dark purple to yellow
+    Viridis,
+    /// Perceptually uniform black to pale yellow
+    Magma,
+    /// User supplied table, see [`Colormap::load_lut`]
+    Lut(Arc<[[f32; 3]; COLORMAP_SIZE]>),
+}
+
+impl Debug for Colormap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RedGreen => write!(f, "RedGreen"),
+            Self::Coolwarm => write!(f, "Coolwarm"),
+            Self::RdBu => write!(f, "RdBu"),
+            Self::Viridis => write!(f, "Viridis"),
+            Self::Magma => write!(f, "Magma"),
+            Self::Lut(_) => write!(f, "Lut"),
+        }
+    }
+}
+
+impl Colormap {
+    /// Built in colormap with the given name, as used on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "red_green" => Some(Self::RedGreen),
+            "coolwarm" => Some(Self::Coolwarm),
+            "rdbu" => Some(Self::RdBu),
+            "viridis" => Some(Self::Viridis),
+            "magma" => Some(Self::Magma),
+            _ => None,
+        }
+    }
+
+    /// Loads a text LUT of 256 lines of red, green and blue separated by spaces or commas
+    ///
+    /// Values are read as 0 to 255 if any of them is above 1 and as 0 to 1 otherwise, `#`
+    /// starts a comment
+    pub fn load_lut<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+
+        let mut entries = Vec::with_capacity(COLORMAP_SIZE);
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let values = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Line {} of the LUT: {err}", number + 1),
+                    )
+                })?;
+
+            match values.as_slice() {
+                [] => continue,
+                [red, green, blue] => entries.push([*red, *green, *blue]),
+                _ => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Line {} of the LUT has {} values instead of red, green and blue",
+                            number + 1,
+                            values.len()
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let table: [[f32; 3]; COLORMAP_SIZE] = entries.try_into().map_err(|entries: Vec<_>| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The LUT has {} entries instead of {COLORMAP_SIZE}",
+                    entries.len()
+                ),
+            )
+        })?;
+
+        let scale = if table.iter().flatten().any(|value| *value > 1.0) {
+            255.0
+        } else {
+            1.0
+        };
+
+        Ok(Self::Lut(Arc::new(table.map(|color| {
+            color.map(|value| (value / scale).clamp(0.0, 1.0))
+        }))))
+    }
+
+    /// Lookup table with the color of every entry from 0 to 1
+    pub fn table(&self) -> [[f32; 3]; COLORMAP_SIZE] {
+        let control_points: &[[u8; 3]] = match self {
+            Self::RedGreen => {
+                return std::array::from_fn(|entry| {
+                    let diff = entry as f32 / (COLORMAP_SIZE - 1) as f32 * 2.0 - 1.0;
+                    diff_to_color(diff).map(|value| value.clamp(0.0, 1.0))
+                });
+            }
+            Self::Lut(table) => return **table,
+            Self::Coolwarm => &COOLWARM,
+            Self::RdBu => &RDBU,
+            Self::Viridis => &VIRIDIS,
+            Self::Magma => &MAGMA,
+        };
+
+        let segments = (control_points.len() - 1) as f32;
+        std::array::from_fn(|entry| {
+            let position = entry as f32 / (COLORMAP_SIZE - 1) as f32 * segments;
+            let lower = (position.floor() as usize).min(control_points.len() - 2);
+            let fraction = position - lower as f32;
+
+            std::array::from_fn(|channel| {
+                let start = control_points[lower][channel] as f32 / 255.0;
+                let end = control_points[lower + 1][channel] as f32 / 255.0;
+
+                start + (end - start) * fraction
+            })
+        })
+    }
+
+    /// Row of RGBA float texels for the lookup texture the shaders sample
+    pub fn texture_data(&self) -> Vec<f32> {
+        self.table()
+            .iter()
+            .flat_map(|[red, green, blue]| [*red, *green, *blue, 1.0])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the contents to a LUT file of its own in the temporary directory
+    fn lut_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("dips_colormap_{}_{name}.txt", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn assert_color(color: [f32; 3], expected: [f32; 3]) {
+        for (value, expected) in color.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-5, "{color:?} != {expected:?}");
+        }
+    }
+
+    fn control_color(control_point: [u8; 3]) -> [f32; 3] {
+        control_point.map(|value| value as f32 / 255.0)
+    }
+
+    #[test]
+    fn control_points_are_interpolated() {
+        // The 10 segments of RdBu start every 25.5 entries, so every other one is on an entry
+        let table = Colormap::RdBu.table();
+        for point in (0..RDBU.len()).step_by(2) {
+            assert_color(table[point * 51 / 2], control_color(RDBU[point]));
+        }
+
+        // Entry 64 is a fraction of 0.5098 into the segment from the third to the fourth point
+        let fraction = 64.0 * 10.0 / 255.0 - 2.0;
+        let [start, end] = [RDBU[2], RDBU[3]].map(control_color);
+        assert_color(
+            table[64],
+            std::array::from_fn(|channel| {
+                start[channel] + (end[channel] - start[channel]) * fraction
+            }),
+        );
+    }
+
+    #[test]
+    fn luts_are_scaled_to_their_range() {
+        let lines = (0..COLORMAP_SIZE)
+            .map(|entry| format!("{entry}, {} {}", 255 - entry, entry / 2))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let path = lut_file("bytes", &format!("# red green blue\n\n{lines}\n"));
+
+        let colormap = Colormap::load_lut(&path).unwrap();
+        let table = colormap.table();
+        assert_color(table[0], [0.0, 1.0, 0.0]);
+        assert_color(table[255], [1.0, 0.0, 127.0 / 255.0]);
+
+        // Tables from 0 to 1 are read as they are
+        let lines = (0..COLORMAP_SIZE)
+            .map(|_| "0.25 0.5 1 # grey blue")
+            .collect::<Vec<_>>()
+            .join("\n");
+        let path = lut_file("unit", &lines);
+        assert_color(
+            Colormap::load_lut(&path).unwrap().table()[17],
+            [0.25, 0.5, 1.0],
+        );
+    }
+
+    #[test]
+    fn malformed_luts_are_rejected() {
+        for (name, contents) in [
+            ("short", "0 0 0\n".repeat(COLORMAP_SIZE - 1)),
+            ("long", "0 0 0\n".repeat(COLORMAP_SIZE + 1)),
+            (
+                "channels",
+                format!("0 0\n{}", "0 0 0\n".repeat(COLORMAP_SIZE - 1)),
+            ),
+            (
+                "number",
+                format!("0 zero 0\n{}", "0 0 0\n".repeat(COLORMAP_SIZE - 1)),
+            ),
+        ] {
+            let error = Colormap::load_lut(lut_file(name, &contents)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{name}");
+        }
+    }
+}
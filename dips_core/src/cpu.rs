@@ -0,0 +1,955 @@
+use std::collections::VecDeque;
+
+use crate::{
+    BaselineMode, BorderMode, COLORMAP_SIZE, ChromaFilter, DiPsBackend, DiPsParameters,
+    DifferenceMode, Filter, FrequencyDenoiser, HISTOGRAM_BINS, MAX_TEMPORAL_WINDOW, Motion,
+    MotionCompensator, RoiStats, SensitivityEstimator, SensorCalibrator, SpatialFilter, Summary,
+    SummaryMaps, TemporalEstimator, WindowError, check_baseline_window, check_spatial_window,
+    check_temporal_window, histogram_bin,
+};
+
+// Helper functions
+// These mirror the functions of the same name in the WGSL shaders
+
+/// h must be between 0 and 360
+pub fn hsl_to_rgb(h: f32, s: f32, l: f32) -> [f32; 3] {
+    let chroma = s * (1.0 - (2.0 * l - 1.0).abs());
+    let h_prime = h / 60.0;
+    let x = chroma * (1.0 - (h_prime % 2.0 - 1.0).abs());
+
+    let m = l - chroma / 2.0;
+
+    if (0.0..1.0).contains(&h_prime) {
+        [chroma + m, x + m, m]
+    } else if (1.0..2.0).contains(&h_prime) {
+        [x + m, chroma + m, m]
+    } else if (2.0..3.0).contains(&h_prime) {
+        [m, chroma + m, x + m]
+    } else if (3.0..4.0).contains(&h_prime) {
+        [m, x + m, chroma + m]
+    } else if (4.0..5.0).contains(&h_prime) {
+        [x + m, m, chroma + m]
+    } else if (5.0..=6.0).contains(&h_prime) {
+        [chroma + m, m, x + m]
+    } else {
+        [m, m, m]
+    }
+}
+
+pub fn diff_to_color(diff: f32) -> [f32; 3] {
+    if diff < 0.0 {
+        return hsl_to_rgb(0.0, diff.abs(), 0.5);
+    }
+
+    hsl_to_rgb(120.0, diff, 0.5)
+}
+
+pub fn get_intensity(pixel: &[u8], chroma_filter: ChromaFilter) -> f32 {
+    color_intensity(
+        [pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / 255.0),
+        chroma_filter,
+    )
+}
+
+/// Intensity of a color with channels from 0 to 1, like get_intensity
+pub(crate) fn color_intensity([r, g, b]: [f32; 3], chroma_filter: ChromaFilter) -> f32 {
+    match chroma_filter {
+        ChromaFilter::Red => r,
+        ChromaFilter::Green => g,
+        ChromaFilter::Blue => b,
+        ChromaFilter::All => (r.max(g).max(b) + r.min(g).min(b)) / 2.0,
+    }
+}
+
+/// Intensity of every pixel of an RGBA8 frame, the form the CPU stages in front of the
+/// spatial filter hand frames on in
+pub fn frame_intensities(frame: &[u8], chroma_filter: ChromaFilter) -> Vec<f32> {
+    frame
+        .chunks_exact(4)
+        .map(|pixel| get_intensity(pixel, chroma_filter))
+        .collect()
+}
+
+pub fn sigmoid(input: f32, sigmoid_horizontal_scalar: f32) -> f32 {
+    1.0 / (1.0 + (-sigmoid_horizontal_scalar * input).exp()) - 0.5
+}
+
+pub fn inv_sigmoid(input: f32, sigmoid_horizontal_scalar: f32) -> f32 {
+    -((1.0 / (input + 0.5)) - 1.0).ln() / sigmoid_horizontal_scalar
+}
+
+/// Returns the median of the values, taking the upper middle value for even lengths
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    values[values.len() / 2]
+}
+
+/// Weight of a neighbour at the offset with the intensity in the weighted spatial filters
+pub fn spatial_weight(
+    filter: SpatialFilter,
+    (i, j): (i64, i64),
+    intensity: f32,
+    center: f32,
+) -> f32 {
+    let distance = (i * i + j * j) as f32;
+    let spatial_sigma = filter.spatial_sigma();
+    let range_sigma = filter.range_sigma();
+
+    match filter {
+        SpatialFilter::Gaussian(_) => (-distance / (2.0 * spatial_sigma * spatial_sigma)).exp(),
+        SpatialFilter::Bilateral { .. } => {
+            let range = intensity - center;
+
+            (-distance / (2.0 * spatial_sigma * spatial_sigma)
+                - range * range / (2.0 * range_sigma * range_sigma))
+                .exp()
+        }
+        _ => 1.0,
+    }
+}
+
+/// Spatial filter of a single channel image with a centred square window
+pub fn spatial_filter(
+    intensities: &[f32],
+    (width, height): (u32, u32),
+    window_size: u32,
+    filter: SpatialFilter,
+    border_mode: BorderMode,
+) -> Vec<f32> {
+    if window_size <= 1 {
+        return intensities.to_vec();
+    }
+
+    let half = (window_size / 2) as i64;
+    let (width, height) = (width as i64, height as i64);
+    let mut window = Vec::with_capacity((window_size * window_size) as usize);
+    let mut output = Vec::with_capacity(intensities.len());
+
+    for y in 0..height {
+        for x in 0..width {
+            let center = intensities[(x + y * width) as usize];
+            let (mut weighted_sum, mut weight_sum) = (0.0, 0.0);
+            window.clear();
+
+            for j in -half..=half {
+                for i in -half..=half {
+                    let sx = border_mode.map(x + i, width);
+                    let sy = border_mode.map(y + j, height);
+                    let intensity = intensities[(sx + sy * width) as usize];
+                    let weight = spatial_weight(filter, (i, j), intensity, center);
+
+                    window.push(intensity);
+                    weighted_sum += weight * intensity;
+                    weight_sum += weight;
+                }
+            }
+
+            output.push(match filter {
+                SpatialFilter::Median => median(&mut window),
+                _ => weighted_sum / weight_sum,
+            });
+        }
+    }
+
+    output
+}
+
+/// Reduces the values of a temporal window with the estimator
+pub fn temporal_estimate(values: &mut [f32], estimator: TemporalEstimator) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let count = values.len();
+
+    match estimator {
+        TemporalEstimator::Median => values[count / 2],
+        TemporalEstimator::Mean => values.iter().sum::<f32>() / count as f32,
+        TemporalEstimator::TrimmedMean(_) => {
+            let trim = (count as f32 * estimator.parameter()) as usize;
+            let kept = &values[trim..count - trim];
+
+            kept.iter().sum::<f32>() / kept.len() as f32
+        }
+        TemporalEstimator::Min => values[0],
+        TemporalEstimator::Max => values[count - 1],
+        TemporalEstimator::Percentile(_) => {
+            let position = estimator.parameter() / 100.0 * (count - 1) as f32;
+            let lower = position.floor() as usize;
+            let upper = (lower + 1).min(count - 1);
+
+            values[lower] + (values[upper] - values[lower]) * position.fract()
+        }
+    }
+}
+
+/// Per pixel temporal estimate of a set of single channel images
+pub fn temporal_filter<'a, I>(
+    frames: I,
+    pixel_count: usize,
+    estimator: TemporalEstimator,
+) -> Vec<f32>
+where
+    I: IntoIterator<Item = &'a Vec<f32>> + Clone,
+{
+    let mut window = Vec::new();
+
+    (0..pixel_count)
+        .map(|pixel| {
+            window.clear();
+            window.extend(frames.clone().into_iter().map(|frame| frame[pixel]));
+            temporal_estimate(&mut window, estimator)
+        })
+        .collect()
+}
+
+/// Maps the difference between the baseline and the current intensity through the
+/// transfer and the sensitivity filter
+pub fn filter_diff(diff: f32, parameters: &DiPsParameters) -> f32 {
+    shape_diff(diff, parameters) * parameters.transfer.gain + parameters.transfer.offset
+}
+
+/// Clamps, curves and filters a diff, everything in the transfer but the gain and offset
+pub fn shape_diff(diff: f32, parameters: &DiPsParameters) -> f32 {
+    let transfer = &parameters.transfer;
+    let diff = transfer.curve.apply(transfer.clamp(diff));
+
+    // map from [-1, 1] to [-0.5, 0.5]
+    let diff = diff * 0.5;
+
+    match parameters.filter_type {
+        Filter::Sigmoid => sigmoid(diff, parameters.sigmoid_horizontal_scalar),
+        Filter::InverseSigmoid => inv_sigmoid(diff, parameters.sigmoid_horizontal_scalar),
+        Filter::Unfiltered => diff,
+    }
+}
+
+/// Color of a filtered diff in a colormap table, interpolated between the entries
+pub fn colormap(table: &[[f32; 3]; COLORMAP_SIZE], diff: f32) -> [f32; 3] {
+    let position = (diff * 0.5 + 0.5).clamp(0.0, 1.0) * (COLORMAP_SIZE - 1) as f32;
+    let lower = (position.floor() as usize).min(COLORMAP_SIZE - 2);
+    let fraction = position - lower as f32;
+
+    std::array::from_fn(|channel| {
+        table[lower][channel] + (table[lower + 1][channel] - table[lower][channel]) * fraction
+    })
+}
+
+/// Converts a filtered diff into the output color, grey when there is no colormap table
+pub fn colorize_diff(diff: f32, colormap_table: Option<&[[f32; 3]; COLORMAP_SIZE]>) -> [f32; 3] {
+    match colormap_table {
+        Some(table) => colormap(table, diff),
+        None => [0.5 - diff; 3],
+    }
+}
+
+fn to_unorm(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Pure Rust implementation of DiPs
+///
+/// Runs the same stages as the GPU pipelines and is used as the reference
+/// the shaders are checked against
+pub struct CpuBackend {
+    parameters: DiPsParameters,
+
+    texture_dimensions: (u32, u32),
+
+    // Lookup table of the colormap, none when the output is grey
+    colormap_table: Option<[[f32; 3]; COLORMAP_SIZE]>,
+
+    // Spatially filtered intensities of the most recent frames
+    frames: VecDeque<Vec<f32>>,
+    baseline: Option<Vec<f32>>,
+
+    // Temporally filtered intensities of the previous frames for the sliding median baseline
+    baseline_history: VecDeque<Vec<f32>>,
+
+    // State of the frame to frame difference modes
+    previous_frame: Option<Vec<f32>>,
+    accumulated_diff: Vec<f32>,
+
+    // Unfiltered difference of the last output frame
+    raw_diff: Option<Vec<f32>>,
+
+    // Picks the gain of the transfer when the auto sensitivity is on
+    sensitivity_estimator: SensitivityEstimator,
+    // Gain the last output frame was displayed with
+    gain: f32,
+
+    // Regions every pixel is in and the statistics of the last output frame
+    roi_mask: Vec<u32>,
+    roi_stats: Option<Vec<RoiStats>>,
+
+    // Statistics of every output frame of the run when the summary is on
+    summary: Option<SummaryMaps>,
+
+    // Corrects the fixed pattern noise, vignetting and hot pixels of the sensor
+    calibrator: SensorCalibrator,
+
+    // Removes periodic sensor patterns from every frame before it is registered
+    denoiser: FrequencyDenoiser,
+
+    // Warps every frame back onto the first frame of the baseline
+    motion_compensator: MotionCompensator,
+}
+
+impl CpuBackend {
+    pub fn new(parameters: DiPsParameters) -> Result<Self, WindowError> {
+        check_temporal_window(parameters.temporal_window, MAX_TEMPORAL_WINDOW)?;
+        check_baseline_window(parameters.baseline_mode)?;
+        check_spatial_window(parameters.spatial_window_size)?;
+
+        Ok(Self {
+            texture_dimensions: (0, 0),
+            colormap_table: parameters.colorize.then(|| parameters.colormap.table()),
+            frames: VecDeque::with_capacity(parameters.temporal_window + 1),
+            baseline: None,
+            baseline_history: VecDeque::with_capacity(parameters.baseline_mode.window() + 1),
+            previous_frame: None,
+            accumulated_diff: Vec::new(),
+            raw_diff: None,
+            sensitivity_estimator: SensitivityEstimator::new(parameters.clone()),
+            gain: parameters.transfer.gain,
+            roi_mask: Vec::new(),
+            roi_stats: None,
+            summary: None,
+            calibrator: SensorCalibrator::new(
+                parameters.calibration.clone(),
+                parameters.chroma_filter,
+            ),
+            denoiser: FrequencyDenoiser::new(parameters.denoise.clone()),
+            motion_compensator: MotionCompensator::new(parameters.motion_compensation),
+            parameters,
+        })
+    }
+
+    /// Spatially filtered intensities of a frame
+    pub fn filter_frame(&self, width: u32, height: u32, intensities: &[f32]) -> Vec<f32> {
+        spatial_filter(
+            intensities,
+            (width, height),
+            self.parameters.spatial_window_size,
+            self.parameters.spatial_filter,
+            self.parameters.border_mode,
+        )
+    }
+
+    pub fn baseline(&self) -> Option<&[f32]> {
+        self.baseline.as_deref()
+    }
+
+    /// Signed difference of every pixel with the difference mode
+    fn difference(&mut self, baseline: &[f32], current: &[f32]) -> Vec<f32> {
+        let difference_mode = self.parameters.difference_mode;
+
+        if difference_mode == DifferenceMode::Baseline {
+            return baseline
+                .iter()
+                .zip(current.iter())
+                .map(|(original_intensity, intensity)| original_intensity - intensity)
+                .collect();
+        }
+
+        // Start comparing from the frame the baseline was taken on
+        let previous_frame = self.previous_frame.get_or_insert_with(|| current.to_vec());
+        if self.accumulated_diff.len() != current.len() {
+            self.accumulated_diff = vec![0.0; current.len()];
+        }
+
+        let mut diffs = Vec::with_capacity(current.len());
+        for ((previous, accumulated), intensity) in previous_frame
+            .iter_mut()
+            .zip(self.accumulated_diff.iter_mut())
+            .zip(current.iter())
+        {
+            let mut diff = *previous - intensity;
+            *previous = *intensity;
+
+            if let DifferenceMode::AccumulatedFrameToFrame(_) = difference_mode {
+                diff += *accumulated * difference_mode.decay();
+                *accumulated = diff;
+            }
+
+            diffs.push(diff);
+        }
+
+        diffs
+    }
+
+    /// Returns the baseline the current frame is compared against and moves the
+    /// baseline along with the current frame for the rolling baseline modes
+    fn advance_baseline(&mut self, current: &[f32]) -> Vec<f32> {
+        let Some(baseline) = self.baseline.as_mut() else {
+            // Take the baseline from the first full temporal window
+            self.baseline_history = (0..self.parameters.baseline_mode.window())
+                .map(|_| current.to_vec())
+                .collect();
+            self.baseline = Some(current.to_vec());
+
+            return current.to_vec();
+        };
+
+        match self.parameters.baseline_mode {
+            BaselineMode::Fixed => baseline.clone(),
+            BaselineMode::ExponentialMovingAverage(_) => {
+                let original = baseline.clone();
+                let alpha = self.parameters.baseline_mode.alpha();
+
+                for (baseline, intensity) in baseline.iter_mut().zip(current.iter()) {
+                    *baseline += alpha * (intensity - *baseline);
+                }
+
+                original
+            }
+            BaselineMode::SlidingMedian(_) => {
+                *baseline = temporal_filter(
+                    &self.baseline_history,
+                    current.len(),
+                    TemporalEstimator::Median,
+                );
+
+                self.baseline_history.pop_front();
+                self.baseline_history.push_back(current.to_vec());
+
+                baseline.clone()
+            }
+        }
+    }
+}
+
+impl DiPsBackend for CpuBackend {
+    fn process_frame(&mut self, width: u32, height: u32, frame: &[u8]) -> Option<Vec<u8>> {
+        // Start over if the stream changes size
+        if self.texture_dimensions != (width, height) {
+            self.texture_dimensions = (width, height);
+            self.roi_mask = self.parameters.rois.mask(width, height);
+            self.refresh_baseline();
+        }
+
+        let pixel_count = (width * height) as usize;
+
+        let intensities = self
+            .calibrator
+            .calibrate(width, height, frame)
+            .unwrap_or_else(|| frame_intensities(frame, self.parameters.chroma_filter));
+        let intensities = self
+            .denoiser
+            .denoise(width, height, &intensities)
+            .unwrap_or(intensities);
+        let intensities = self
+            .motion_compensator
+            .compensate(width, height, &intensities)
+            .unwrap_or(intensities);
+
+        self.frames
+            .push_back(self.filter_frame(width, height, &intensities));
+
+        if self.frames.len() > self.parameters.temporal_window {
+            self.frames.pop_front();
+        }
+
+        if self.frames.len() < self.parameters.temporal_window {
+            return None;
+        }
+
+        let current = temporal_filter(
+            &self.frames,
+            pixel_count,
+            self.parameters.temporal_estimator,
+        );
+        let baseline = self.advance_baseline(&current);
+        let diffs = self.difference(&baseline, &current);
+
+        let rois = self.parameters.rois.clone();
+        let mut output = Vec::with_capacity(pixel_count * 4);
+        for (pixel, &diff) in diffs.iter().enumerate() {
+            // Outside of every region the frame itself is shown in grey
+            let [r, g, b] = if rois.grey_outside && !rois.is_empty() && self.roi_mask[pixel] == 0 {
+                [current[pixel]; 3]
+            } else {
+                colorize_diff(
+                    filter_diff(diff, &self.parameters),
+                    self.colormap_table.as_ref(),
+                )
+            };
+
+            output.extend_from_slice(&[to_unorm(r), to_unorm(g), to_unorm(b), 255]);
+        }
+
+        self.roi_stats = (!rois.is_empty()).then(|| rois.stats(&self.roi_mask, &diffs));
+
+        // Like on the GPU the histogram of this frame picks the gain of the next one
+        self.gain = self.parameters.transfer.gain;
+        if self.sensitivity_estimator.is_collecting() {
+            let mut histogram = vec![0; HISTOGRAM_BINS];
+            for &diff in diffs.iter() {
+                histogram[histogram_bin(diff)] += 1;
+            }
+
+            self.parameters.transfer.gain = self.sensitivity_estimator.update(&histogram);
+        }
+
+        if let Summary::On { threshold } = self.parameters.summary {
+            self.summary
+                .get_or_insert_with(|| SummaryMaps::new(width, height, threshold))
+                .update(&diffs);
+        }
+
+        self.raw_diff = Some(diffs);
+
+        Some(output)
+    }
+
+    fn refresh_baseline(&mut self) {
+        self.frames.clear();
+        self.baseline = None;
+        self.baseline_history.clear();
+        self.previous_frame = None;
+        self.accumulated_diff.clear();
+        self.raw_diff = None;
+        self.roi_stats = None;
+        self.motion_compensator.reset();
+        self.sensitivity_estimator.reset();
+        self.parameters.transfer.gain = self.sensitivity_estimator.gain();
+    }
+
+    fn raw_diff(&self) -> Option<&[f32]> {
+        self.raw_diff.as_deref()
+    }
+
+    fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    fn roi_stats(&self) -> Option<&[RoiStats]> {
+        self.roi_stats.as_deref()
+    }
+
+    fn summary(&self) -> Option<&SummaryMaps> {
+        self.summary.as_ref()
+    }
+
+    fn motion(&self) -> Option<Motion> {
+        self.motion_compensator.motion()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AutoSensitivity, Colormap, MAX_SPATIAL_WINDOW, WindowError, check_baseline_buffer,
+    };
+
+    const TOLERANCE: f32 = 1e-5;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (index, (actual, expected)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!(
+                (actual - expected).abs() < TOLERANCE,
+                "value {index} is {actual} instead of {expected}"
+            );
+        }
+    }
+
+    /// RGBA8 frame with every pixel set to the grey level
+    fn grey_frame(levels: &[u8]) -> Vec<u8> {
+        levels
+            .iter()
+            .flat_map(|level| [*level, *level, *level, 255])
+            .collect()
+    }
+
+    /// Raw diffs of a single pixel stream, one per output frame
+    fn pixel_diffs(parameters: DiPsParameters, levels: &[u8]) -> Vec<f32> {
+        let mut backend = CpuBackend::new(parameters).unwrap();
+
+        levels
+            .iter()
+            .filter_map(|level| {
+                backend.process_frame(1, 1, &grey_frame(&[*level]))?;
+                Some(backend.raw_diff().unwrap()[0])
+            })
+            .collect()
+    }
+
+    fn level(level: u8) -> f32 {
+        level as f32 / 255.0
+    }
+
+    #[test]
+    fn temporal_median_takes_the_middle_of_the_window() {
+        let frames = [vec![0.1, 0.9], vec![0.5, 0.2], vec![0.3, 0.4]];
+        assert_close(
+            &temporal_filter(&frames, 2, TemporalEstimator::Median),
+            &[0.3, 0.4],
+        );
+
+        // Even windows take the upper middle value like the shaders
+        let frames = [vec![0.1], vec![0.4], vec![0.3], vec![0.2]];
+        assert_close(
+            &temporal_filter(&frames, 1, TemporalEstimator::Median),
+            &[0.3],
+        );
+    }
+
+    #[test]
+    fn temporal_median_of_the_window_is_centred_on_the_current_frames() {
+        // The baseline is the median of the first window and every frame after it is the
+        // median of the window that ends on it
+        let parameters = DiPsParameters {
+            temporal_window: 3,
+            ..Default::default()
+        };
+        let diffs = pixel_diffs(parameters, &[10, 50, 30, 200, 200, 40]);
+
+        let baseline = level(30);
+        assert_close(
+            &diffs,
+            &[
+                0.0,
+                baseline - level(50),
+                baseline - level(200),
+                baseline - level(200),
+            ],
+        );
+    }
+
+    #[test]
+    fn sliding_median_baseline_trails_the_frames() {
+        // The baseline of every frame is the median of the previous three, not including it
+        let parameters = DiPsParameters {
+            temporal_window: 1,
+            baseline_mode: BaselineMode::SlidingMedian(3),
+            ..Default::default()
+        };
+        let diffs = pixel_diffs(parameters, &[10, 20, 30, 40, 50]);
+
+        assert_close(
+            &diffs,
+            &[
+                0.0,
+                level(10) - level(20),
+                level(10) - level(30),
+                level(20) - level(40),
+                level(30) - level(50),
+            ],
+        );
+    }
+
+    #[test]
+    fn border_modes_at_the_edges() {
+        assert_eq!(BorderMode::Clamp.map(-2, 5), 0);
+        assert_eq!(BorderMode::Clamp.map(6, 5), 4);
+        assert_eq!(BorderMode::Mirror.map(-2, 5), 2);
+        assert_eq!(BorderMode::Mirror.map(6, 5), 2);
+        assert_eq!(BorderMode::Mirror.map(3, 5), 3);
+
+        // A single row, so every row of the window reads the same pixels
+        let row = [0.0, 0.5, 1.0];
+        let clamped = spatial_filter(&row, (3, 1), 3, SpatialFilter::Box, BorderMode::Clamp);
+        assert_close(&clamped, &[0.5 / 3.0, 0.5, 2.5 / 3.0]);
+
+        let mirrored = spatial_filter(&row, (3, 1), 3, SpatialFilter::Box, BorderMode::Mirror);
+        assert_close(&mirrored, &[1.0 / 3.0, 0.5, 2.0 / 3.0]);
+    }
+
+    #[test]
+    fn spatial_median_is_centred() {
+        // The single bright pixel goes and the two pixel wide band stays where it is, a window
+        // that isn't centred would move the band over by a pixel
+        #[rustfmt::skip]
+        let image = [
+            0.0, 1.0, 1.0, 0.0, 0.0,
+            0.0, 1.0, 1.0, 0.0, 1.0,
+            0.0, 1.0, 1.0, 0.0, 0.0,
+        ];
+        let filtered = spatial_filter(&image, (5, 3), 3, SpatialFilter::Median, BorderMode::Clamp);
+
+        #[rustfmt::skip]
+        let expected = [
+            0.0, 1.0, 1.0, 0.0, 0.0,
+            0.0, 1.0, 1.0, 0.0, 0.0,
+            0.0, 1.0, 1.0, 0.0, 0.0,
+        ];
+        assert_close(&filtered, &expected);
+    }
+
+    #[test]
+    fn gaussian_of_a_single_bright_pixel() {
+        #[rustfmt::skip]
+        let image = [
+            0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0,
+        ];
+        let filtered = spatial_filter(
+            &image,
+            (3, 3),
+            3,
+            SpatialFilter::Gaussian(1.0),
+            BorderMode::Mirror,
+        );
+
+        // Weights are exp(-d² / 2) for the squared distances 0, 1 and 2
+        let (edge, corner) = ((-0.5f32).exp(), (-1.0f32).exp());
+        let total = 1.0 + 4.0 * edge + 4.0 * corner;
+        assert_close(&filtered[4..5], &[1.0 / total]);
+
+        // The top middle pixel mirrors the bright pixel above and below itself
+        assert_close(&filtered[1..2], &[2.0 * edge / total]);
+    }
+
+    #[test]
+    fn bilateral_keeps_edges_and_smooths_like_a_gaussian_otherwise() {
+        #[rustfmt::skip]
+        let image = [
+            0.0, 0.0, 1.0,
+            0.0, 0.0, 1.0,
+            0.0, 0.0, 1.0,
+        ];
+        let filter = |range_sigma| {
+            spatial_filter(
+                &image,
+                (3, 3),
+                3,
+                SpatialFilter::Bilateral {
+                    spatial_sigma: 1.0,
+                    range_sigma,
+                },
+                BorderMode::Clamp,
+            )
+        };
+
+        // Neighbours across the edge weigh exp(-1 / (2 * 0.05²)) which is nothing
+        assert_close(&filter(0.05), &image);
+
+        // A huge range sigma leaves only the spatial weights
+        let gaussian = spatial_filter(
+            &image,
+            (3, 3),
+            3,
+            SpatialFilter::Gaussian(1.0),
+            BorderMode::Clamp,
+        );
+        assert_close(&filter(1000.0), &gaussian);
+
+        // The centre column sees the edge on one side only
+        let (edge, corner) = ((-0.5f32).exp(), (-1.0f32).exp());
+        let total = 1.0 + 4.0 * edge + 4.0 * corner;
+        assert_close(&gaussian[4..5], &[(edge + 2.0 * corner) / total]);
+    }
+
+    #[test]
+    fn window_checks() {
+        assert!(check_temporal_window(1, 64).is_ok());
+        assert!(check_temporal_window(64, 64).is_ok());
+        assert!(matches!(
+            check_temporal_window(0, 64),
+            Err(WindowError::Temporal { window: 0, max: 64 })
+        ));
+        assert!(matches!(
+            check_temporal_window(65, 64),
+            Err(WindowError::Temporal { window: 65, .. })
+        ));
+
+        assert!(check_spatial_window(1).is_ok());
+        assert!(check_spatial_window(MAX_SPATIAL_WINDOW).is_ok());
+        assert!(matches!(
+            check_spatial_window(4),
+            Err(WindowError::Spatial { window: 4, .. })
+        ));
+        assert!(matches!(
+            check_spatial_window(MAX_SPATIAL_WINDOW + 2),
+            Err(WindowError::Spatial { .. })
+        ));
+
+        // Four 100x100 float frames take 160000 bytes
+        let baseline_mode = BaselineMode::SlidingMedian(4);
+        assert!(check_baseline_window(baseline_mode).is_ok());
+        assert!(check_baseline_buffer(100, 100, baseline_mode, 160_000).is_ok());
+        assert!(matches!(
+            check_baseline_buffer(100, 100, baseline_mode, 159_999),
+            Err(WindowError::BaselineBuffer { window: 4, max: 3 })
+        ));
+
+        // The CPU backend refuses them too
+        assert!(
+            CpuBackend::new(DiPsParameters {
+                spatial_window_size: 2,
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn intensity_of_every_chroma_filter() {
+        let pixel = [255, 51, 102, 255];
+
+        assert_close(&[get_intensity(&pixel, ChromaFilter::Red)], &[1.0]);
+        assert_close(&[get_intensity(&pixel, ChromaFilter::Green)], &[0.2]);
+        assert_close(&[get_intensity(&pixel, ChromaFilter::Blue)], &[0.4]);
+
+        // Lightness, the mean of the brightest and darkest channel
+        assert_close(&[get_intensity(&pixel, ChromaFilter::All)], &[0.6]);
+    }
+
+    #[test]
+    fn temporal_estimators() {
+        let estimate = |estimator| temporal_estimate(&mut [0.9, 0.1, 0.4, 0.2, 0.3], estimator);
+
+        assert_close(&[estimate(TemporalEstimator::Median)], &[0.3]);
+        assert_close(&[estimate(TemporalEstimator::Mean)], &[0.38]);
+        assert_close(&[estimate(TemporalEstimator::TrimmedMean(0.2))], &[0.3]);
+        assert_close(&[estimate(TemporalEstimator::Min)], &[0.1]);
+        assert_close(&[estimate(TemporalEstimator::Max)], &[0.9]);
+        assert_close(&[estimate(TemporalEstimator::Percentile(25.0))], &[0.2]);
+        assert_close(&[estimate(TemporalEstimator::Percentile(62.5))], &[0.35]);
+    }
+
+    #[test]
+    fn exponential_moving_average_baseline_follows_the_frames() {
+        let parameters = DiPsParameters {
+            temporal_window: 1,
+            baseline_mode: BaselineMode::ExponentialMovingAverage(2.0),
+            ..Default::default()
+        };
+        let diffs = pixel_diffs(parameters, &[100, 200, 200]);
+
+        // Every frame is compared against the average before it is added
+        let alpha = 1.0 - (-0.5f32).exp();
+        let second_baseline = level(100) + alpha * (level(200) - level(100));
+        assert_close(
+            &diffs,
+            &[0.0, level(100) - level(200), second_baseline - level(200)],
+        );
+    }
+
+    #[test]
+    fn frame_to_frame_differences() {
+        let levels = [10, 20, 40, 40];
+        let parameters = DiPsParameters {
+            temporal_window: 1,
+            difference_mode: DifferenceMode::FrameToFrame,
+            ..Default::default()
+        };
+        assert_close(
+            &pixel_diffs(parameters.clone(), &levels),
+            &[0.0, level(10) - level(20), level(20) - level(40), 0.0],
+        );
+
+        // The accumulated changes leak away once the frames stop changing
+        let difference_mode = DifferenceMode::AccumulatedFrameToFrame(4.0);
+        let decay = (-0.25f32).exp();
+        let first = level(10) - level(20);
+        let second = level(20) - level(40) + first * decay;
+        assert_close(
+            &pixel_diffs(
+                DiPsParameters {
+                    difference_mode,
+                    ..parameters
+                },
+                &levels,
+            ),
+            &[0.0, first, second, second * decay],
+        );
+    }
+
+    #[test]
+    fn transfer_and_sensitivity_filter() {
+        let unfiltered = DiPsParameters {
+            filter_type: Filter::Unfiltered,
+            ..Default::default()
+        };
+
+        // Halved to [-0.5, 0.5], then the default gain of 5
+        assert_close(&[filter_diff(0.1, &unfiltered)], &[0.25]);
+        assert_close(&[filter_diff(-3.0, &unfiltered)], &[-2.5]);
+
+        let mut transfer = unfiltered.transfer;
+        transfer.offset = 0.1;
+        transfer.clamp_max = 0.05;
+        let parameters = DiPsParameters {
+            transfer,
+            ..unfiltered
+        };
+        assert_close(&[filter_diff(0.1, &parameters)], &[0.225]);
+
+        // The sigmoid is odd and the display value can be traced back to the diff
+        let sigmoid = DiPsParameters::default();
+        assert_close(&[shape_diff(0.0, &sigmoid)], &[0.0]);
+        assert_close(&[shape_diff(-0.3, &sigmoid)], &[-shape_diff(0.3, &sigmoid)]);
+        for diff in [-0.8, -0.1, 0.05, 0.6] {
+            assert_close(
+                &[sigmoid.diff_from_display(filter_diff(diff, &sigmoid))],
+                &[diff],
+            );
+        }
+    }
+
+    #[test]
+    fn auto_sensitivity_leaves_room_for_the_offset() {
+        let mut parameters = DiPsParameters {
+            filter_type: Filter::Unfiltered,
+            auto_sensitivity: AutoSensitivity::FirstFrames {
+                frames: 1,
+                percentile: 100.0,
+            },
+            ..Default::default()
+        };
+        parameters.transfer.offset = -0.2;
+
+        // Every diff is -0.5, the negative side reaches the full color after the offset
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        histogram[histogram_bin(-0.5)] = 100;
+        let magnitude = 0.5;
+
+        parameters.transfer.gain = SensitivityEstimator::new(parameters.clone()).update(&histogram);
+        assert_close(&[filter_diff(-magnitude, &parameters)], &[-1.0]);
+        assert_close(&[filter_diff(magnitude, &parameters)], &[0.6]);
+    }
+
+    #[test]
+    fn colorized_and_grey_output() {
+        assert_close(&colorize_diff(0.25, None), &[0.25; 3]);
+        assert_close(&colorize_diff(-0.25, None), &[0.75; 3]);
+
+        // The ends of the table, and anything past them, get the first and last colors
+        let table = Colormap::Coolwarm.table();
+        let [first, last] = [[59, 76, 192], [180, 4, 38]].map(|color| color.map(level));
+        assert_close(&colorize_diff(-1.0, Some(&table)), &first);
+        assert_close(&colorize_diff(-4.0, Some(&table)), &first);
+        assert_close(&colorize_diff(1.0, Some(&table)), &last);
+
+        // Halfway between two entries is halfway between their colors
+        let position = 0.5 / (COLORMAP_SIZE - 1) as f32 * 2.0 - 1.0;
+        let halfway =
+            std::array::from_fn::<_, 3, _>(|channel| (table[0][channel] + table[1][channel]) / 2.0);
+        assert_close(&colorize_diff(position, Some(&table)), &halfway);
+    }
+
+    #[test]
+    fn grey_output_frame() {
+        let mut backend = CpuBackend::new(DiPsParameters {
+            colorize: false,
+            filter_type: Filter::Unfiltered,
+            temporal_window: 1,
+            ..Default::default()
+        })
+        .unwrap();
+
+        // The first frame is the baseline and shows as mid grey
+        let baseline = backend
+            .process_frame(2, 1, &grey_frame(&[100, 100]))
+            .unwrap();
+        assert_eq!(baseline, [128, 128, 128, 255, 128, 128, 128, 255]);
+
+        // A pixel that got brighter by 25 levels shows as 0.5 + 25 / 255 * 0.5 * 5
+        let output = backend
+            .process_frame(2, 1, &grey_frame(&[125, 100]))
+            .unwrap();
+        assert_eq!(output, [190, 190, 190, 255, 128, 128, 128, 255]);
+        assert_close(backend.raw_diff().unwrap(), &[level(100) - level(125), 0.0]);
+    }
+}
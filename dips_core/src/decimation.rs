@@ -0,0 +1,360 @@
+use std::borrow::Cow;
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+/// How the input frames are thinned out before they reach DiPs, like
+/// `test_scripts/video_subsampler/vid_sub_sampler.py` without the re-encode
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Decimation {
+    /// Every frame goes through
+    #[default]
+    Off,
+    /// Keeps the first of every group of the given number of frames
+    KeepEvery(usize),
+    /// Averages every group of the given number of frames into one, which trades temporal
+    /// resolution for a better signal to noise ratio. A group left incomplete by the end of the
+    /// stream is dropped
+    Bin(usize),
+}
+
+impl Decimation {
+    pub fn is_on(&self) -> bool {
+        self.factor() > 1
+    }
+
+    /// Number of input frames that make up a frame of DiPs
+    pub fn factor(&self) -> usize {
+        match *self {
+            Self::Off => 1,
+            Self::KeepEvery(factor) | Self::Bin(factor) => factor.max(1),
+        }
+    }
+}
+
+/// Timing of the output video, the diffs, exports and refresh points keep the time of the input
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Retiming {
+    /// The output frames keep the timestamps of the input, so a decimated video plays at the
+    /// original speed with fewer frames
+    #[default]
+    Off,
+    /// Plays the output frames back at the given frame rate
+    FrameRate(f64),
+    /// Stretches or squeezes the output to last the given number of seconds
+    Duration(f64),
+}
+
+impl Retiming {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+}
+
+/// Frame of DiPs made out of one or more input frames
+#[derive(Debug, Clone)]
+pub struct DecimatedFrame<'a> {
+    /// RGBA8 data, borrowed from the input unless frames were averaged
+    pub data: Cow<'a, [u8]>,
+    /// Timestamp in seconds on the input's clock, the mean of the group for binned frames
+    pub timestamp: Option<f64>,
+    /// Timestamp in seconds the frame is shown at in the output video
+    pub output_timestamp: Option<f64>,
+    /// Time in seconds the frame is shown for in the output video, None when it should keep
+    /// the duration of the input frames
+    pub output_duration: Option<f64>,
+}
+
+/// Turns the input frames into the frames DiPs runs on and retimes them for the output
+#[derive(Debug, Clone)]
+pub struct FrameDecimator {
+    decimation: Decimation,
+    retiming: Retiming,
+
+    // Of the input, the duration is only needed to retime to a duration
+    source_frame_rate: Option<f64>,
+    source_duration: Option<f64>,
+
+    // Input frames in the current group and the frames handed out so far
+    group_frames: usize,
+    output_frames: usize,
+    // Sum of every channel of the binned frames of the group
+    sums: Vec<u32>,
+    // Sum and count of the timestamps of the group
+    timestamp_sum: f64,
+    timestamps: usize,
+}
+
+impl FrameDecimator {
+    pub fn new(decimation: Decimation, retiming: Retiming) -> Self {
+        Self {
+            decimation,
+            retiming,
+            source_frame_rate: None,
+            source_duration: None,
+            group_frames: 0,
+            output_frames: 0,
+            sums: Vec::new(),
+            timestamp_sum: 0.0,
+            timestamps: 0,
+        }
+    }
+
+    /// Whether the frames or their timing are changed at all
+    pub fn is_on(&self) -> bool {
+        self.decimation.is_on() || self.retiming.is_on()
+    }
+
+    /// Sets the frame rate of the input, frame rates that aren't positive are ignored
+    pub fn set_source_frame_rate(&mut self, frame_rate: f64) {
+        if frame_rate.is_finite() && frame_rate > 0.0 {
+            self.source_frame_rate = Some(frame_rate);
+        }
+    }
+
+    /// Sets the length of the input in seconds, needed to retime to a duration
+    pub fn set_source_duration(&mut self, duration: f64) {
+        if duration.is_finite() && duration > 0.0 {
+            self.source_duration = Some(duration);
+        }
+    }
+
+    /// Frame rate of the frames DiPs runs on, what the frequencies of the temporal stages are
+    /// in Hz of
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.source_frame_rate
+            .map(|frame_rate| frame_rate / self.decimation.factor() as f64)
+    }
+
+    /// Frame rate of the output video
+    pub fn output_frame_rate(&self) -> Option<f64> {
+        match self.retiming {
+            Retiming::Off => self.frame_rate(),
+            Retiming::FrameRate(frame_rate) => {
+                Some(frame_rate).filter(|frame_rate| frame_rate.is_finite() && *frame_rate > 0.0)
+            }
+            Retiming::Duration(duration) => {
+                let speed = self.source_duration? / duration;
+                self.frame_rate()
+                    .map(|frame_rate| frame_rate * speed)
+                    .filter(|frame_rate| frame_rate.is_finite() && *frame_rate > 0.0)
+            }
+        }
+    }
+
+    /// Number of frames handed out so far
+    pub fn frame_count(&self) -> usize {
+        self.output_frames
+    }
+
+    /// Adds an RGBA8 input frame, returns the frame DiPs should run on once a group is complete
+    pub fn push<'a>(
+        &mut self,
+        width: u32,
+        height: u32,
+        frame: &'a [u8],
+        timestamp: Option<f64>,
+    ) -> Option<DecimatedFrame<'a>> {
+        let factor = self.decimation.factor();
+        let first = self.group_frames == 0;
+
+        // A change of size mid group starts a new one
+        if let Decimation::Bin(_) = self.decimation
+            && !first
+            && self.sums.len() != (width * height * 4) as usize
+        {
+            self.group_frames = 0;
+            return self.push(width, height, frame, timestamp);
+        }
+
+        self.group_frames += 1;
+        let complete = self.group_frames == factor;
+        if complete {
+            self.group_frames = 0;
+        }
+
+        if first {
+            self.timestamp_sum = 0.0;
+            self.timestamps = 0;
+        }
+        if let Some(timestamp) = timestamp {
+            self.timestamp_sum += timestamp;
+            self.timestamps += 1;
+        }
+
+        let data = match self.decimation {
+            Decimation::Bin(_) if factor > 1 => {
+                if first {
+                    self.sums.clear();
+                    self.sums.extend(frame.iter().map(|value| *value as u32));
+                } else {
+                    for (sum, value) in self.sums.iter_mut().zip(frame.iter()) {
+                        *sum += *value as u32;
+                    }
+                }
+
+                if !complete {
+                    return None;
+                }
+
+                let half = factor as u32 / 2;
+                Cow::Owned(
+                    self.sums
+                        .iter()
+                        .map(|sum| ((sum + half) / factor as u32) as u8)
+                        .collect(),
+                )
+            }
+            _ if first => Cow::Borrowed(frame),
+            _ => return None,
+        };
+
+        let timestamp = match self.decimation {
+            Decimation::Bin(_) => {
+                (self.timestamps > 0).then(|| self.timestamp_sum / self.timestamps as f64)
+            }
+            _ => timestamp,
+        };
+
+        let index = self.output_frames;
+        self.output_frames += 1;
+
+        // Retimed frames are evenly spaced, whatever the spacing of the input
+        let (output_timestamp, output_duration) = match self.retiming {
+            Retiming::Off => (timestamp, None),
+            _ => match self.output_frame_rate() {
+                Some(frame_rate) => (Some(index as f64 / frame_rate), Some(1.0 / frame_rate)),
+                None => (timestamp, None),
+            },
+        };
+
+        Some(DecimatedFrame {
+            data,
+            timestamp,
+            output_timestamp,
+            output_duration,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Single pixel RGBA8 frame of the grey level
+    fn frame(level: u8) -> [u8; 4] {
+        [level, level, level, 255]
+    }
+
+    /// Grey level, timestamp, output timestamp and output duration of a frame handed out
+    type Decimated = (u8, Option<f64>, Option<f64>, Option<f64>);
+
+    fn decimate(decimator: &mut FrameDecimator, levels: &[u8]) -> Vec<Decimated> {
+        levels
+            .iter()
+            .enumerate()
+            .filter_map(|(index, level)| {
+                let frame = frame(*level);
+                let decimated = decimator.push(1, 1, &frame, Some(index as f64 * 0.1))?;
+
+                Some((
+                    decimated.data[0],
+                    decimated.timestamp,
+                    decimated.output_timestamp,
+                    decimated.output_duration,
+                ))
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn first_of_every_group_is_kept() {
+        let mut decimator = FrameDecimator::new(Decimation::KeepEvery(3), Retiming::Off);
+        decimator.set_source_frame_rate(30.0);
+        assert_eq!(decimator.frame_rate(), Some(10.0));
+        assert_eq!(decimator.output_frame_rate(), Some(10.0));
+
+        let frames = decimate(&mut decimator, &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(
+            frames.iter().map(|frame| frame.0).collect::<Vec<_>>(),
+            [0, 3, 6]
+        );
+        for ((_, timestamp, output_timestamp, output_duration), expected) in
+            frames.into_iter().zip([0.0, 0.3, 0.6])
+        {
+            assert_close(timestamp, expected);
+            assert_eq!(output_timestamp, timestamp);
+            assert_eq!(output_duration, None);
+        }
+        assert_eq!(decimator.frame_count(), 3);
+
+        // Kept frames are handed on without a copy
+        let frame = frame(9);
+        let mut decimator = FrameDecimator::new(Decimation::KeepEvery(2), Retiming::Off);
+        let decimated = decimator.push(1, 1, &frame, None).unwrap();
+        assert!(matches!(decimated.data, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn bins_are_averaged() {
+        let mut decimator = FrameDecimator::new(Decimation::Bin(2), Retiming::Off);
+
+        // The incomplete group at the end is dropped
+        let frames = decimate(&mut decimator, &[10, 21, 30, 40, 200]);
+        assert_eq!(
+            frames.iter().map(|frame| frame.0).collect::<Vec<_>>(),
+            [16, 35]
+        );
+        assert_close(frames[0].1, 0.05);
+        assert_close(frames[1].1, 0.25);
+
+        // A frame of another size starts a new group
+        let mut decimator = FrameDecimator::new(Decimation::Bin(2), Retiming::Off);
+        assert!(decimator.push(1, 1, &frame(10), None).is_none());
+        assert!(decimator.push(2, 1, &[20; 8], None).is_none());
+        let binned = decimator.push(2, 1, &[40; 8], None).unwrap();
+        assert_eq!(*binned.data, [30; 8]);
+        assert_eq!(binned.timestamp, None);
+    }
+
+    #[test]
+    fn frames_are_retimed_to_a_frame_rate() {
+        let mut decimator = FrameDecimator::new(Decimation::Off, Retiming::FrameRate(25.0));
+        assert_eq!(decimator.output_frame_rate(), Some(25.0));
+
+        for (index, (_, timestamp, output_timestamp, output_duration)) in
+            decimate(&mut decimator, &[0, 1, 2]).into_iter().enumerate()
+        {
+            // The diffs keep the time of the input
+            assert_close(timestamp, index as f64 * 0.1);
+            assert_close(output_timestamp, index as f64 / 25.0);
+            assert_close(output_duration, 1.0 / 25.0);
+        }
+    }
+
+    #[test]
+    fn frames_are_retimed_to_a_duration() {
+        let mut decimator = FrameDecimator::new(Decimation::Bin(2), Retiming::Duration(5.0));
+        decimator.set_source_frame_rate(30.0);
+
+        // Without the length of the input the frames keep their timestamps
+        assert_eq!(decimator.output_frame_rate(), None);
+        let frames = decimate(&mut decimator, &[0, 0]);
+        assert_eq!(frames[0].2, frames[0].1);
+        assert_eq!(frames[0].3, None);
+
+        // Ten seconds squeezed into five play the 15 binned frames a second at 30
+        decimator.set_source_duration(10.0);
+        assert_eq!(decimator.output_frame_rate(), Some(30.0));
+        let frames = decimate(&mut decimator, &[0, 0, 0, 0]);
+        assert_close(frames[0].2, 1.0 / 30.0);
+        assert_close(frames[1].2, 2.0 / 30.0);
+        assert_close(frames[1].3, 1.0 / 30.0);
+    }
+}
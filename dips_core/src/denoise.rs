@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use crate::fft::{Complex, fft_2d};
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+/// Mask the spectrum of the intensity of every frame is multiplied by before it goes through
+/// the spatial and temporal filters
+///
+/// Frequencies are offsets in bins from the zero frequency of the spectrum, like the centre of
+/// the shifted spectrum of `test_scripts/image_denoise.py`, and the zero frequency is always
+/// kept so the mean brightness of the frame stays the same
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum FrequencyDenoise {
+    #[default]
+    Off,
+    /// Keeps the frequencies within the radius
+    LowPass { radius: f32 },
+    /// Keeps the frequencies from the inner up to the outer radius
+    BandPass { inner: f32, outer: f32 },
+    /// Removes the frequencies within the radius of every point and of its mirror through the
+    /// zero frequency, for periodic patterns like the banding of a sensor
+    Notch {
+        points: Arc<[(f32, f32)]>,
+        radius: f32,
+    },
+    /// Keeps the offsets from -half_size up to half_size - 1 on both axes, the square mask of
+    /// the prototype
+    Square { half_size: u32 },
+}
+
+impl FrequencyDenoise {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+
+    /// Whether the frequency with the given offsets from the zero frequency is kept
+    pub fn keeps(&self, (x, y): (i64, i64)) -> bool {
+        let distance = ((x * x + y * y) as f32).sqrt();
+
+        match self {
+            _ if x == 0 && y == 0 => true,
+            Self::Off => true,
+            Self::LowPass { radius } => distance <= *radius,
+            Self::BandPass { inner, outer } => distance >= *inner && distance <= *outer,
+            Self::Notch { points, radius } => points.iter().all(|(point_x, point_y)| {
+                let (x, y) = (x as f32, y as f32);
+                (x - point_x).hypot(y - point_y) > *radius
+                    && (x + point_x).hypot(y + point_y) > *radius
+            }),
+            Self::Square { half_size } => {
+                let half_size = *half_size as i64;
+                (-half_size..half_size).contains(&x) && (-half_size..half_size).contains(&y)
+            }
+        }
+    }
+
+    /// Mask of the unshifted spectrum of a frame of the given size, 1 for the kept frequencies
+    pub fn mask(&self, width: u32, height: u32) -> Vec<f32> {
+        // Offset of the unshifted bin from the zero frequency, as fftshift lays them out
+        let offset = |bin: u32, size: u32| match bin < size.div_ceil(2) {
+            true => bin as i64,
+            false => bin as i64 - size as i64,
+        };
+
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(
+                |(x, y)| match self.keeps((offset(x, width), offset(y, height))) {
+                    true => 1.0,
+                    false => 0.0,
+                },
+            )
+            .collect()
+    }
+}
+
+/// Filters the intensity of every frame in the frequency domain
+///
+/// The float intensities go through a 2D FFT, the spectrum is masked and the magnitude of the
+/// inverse FFT is handed on as the filtered intensities. Runs before the motion compensation,
+/// which would otherwise lock on to a fixed sensor pattern
+///
+/// Unlike the rest of the pipeline it runs on the CPU for every backend. The motion
+/// compensation that needs its output is a CPU stage in front of the upload, so a compute pass
+/// would mean reading every frame back from the GPU before it is uploaded again, and the
+/// pipelines have no FFT of their own to run it with. The GPU backends upload its output as the
+/// float intensities of an R32Float input so nothing is lost on the way
+#[derive(Debug, Clone)]
+pub struct FrequencyDenoiser {
+    mode: FrequencyDenoise,
+
+    // Mask of the frame size it was made for
+    mask: Vec<f32>,
+    dimensions: (u32, u32),
+}
+
+impl FrequencyDenoiser {
+    pub fn new(mode: FrequencyDenoise) -> Self {
+        Self {
+            mode,
+            mask: Vec::new(),
+            dimensions: (0, 0),
+        }
+    }
+
+    /// Float intensities of a frame filtered through the mask, None while the denoising is off
+    ///
+    /// The magnitude of the inverse FFT is kept like the prototype does, the square mask isn't
+    /// symmetric around the zero frequency so the filtered intensity isn't purely real
+    pub fn denoise(&mut self, width: u32, height: u32, intensities: &[f32]) -> Option<Vec<f32>> {
+        if !self.mode.is_on() || intensities.len() != (width * height) as usize {
+            return None;
+        }
+
+        if self.dimensions != (width, height) {
+            self.mask = self.mode.mask(width, height);
+            self.dimensions = (width, height);
+        }
+
+        let spectrum = fft_2d(
+            intensities.iter().copied().map(Complex::real).collect(),
+            width as usize,
+            height as usize,
+            false,
+        )
+        .into_iter()
+        .zip(self.mask.iter())
+        .map(|(value, mask)| value.scale(*mask))
+        .collect();
+
+        Some(
+            fft_2d(spectrum, width as usize, height as usize, true)
+                .into_iter()
+                .map(|value| value.norm())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame and reconstructions of test_scripts/image_denoise.py --fixture
+    const FIXTURE: &str = include_str!("../tests/fixtures/image_denoise.txt");
+
+    /// Most an intensity may differ from the prototype, in grey levels
+    const TOLERANCE: f32 = 0.01;
+
+    /// Rows of the section of the fixture with the given name
+    fn section(name: &str, height: usize) -> Vec<f32> {
+        FIXTURE
+            .lines()
+            .skip_while(|line| *line != name)
+            .skip(1)
+            .take(height)
+            .flat_map(|line| line.split_whitespace())
+            .map(|value| value.parse().expect("Fixture values are numbers"))
+            .collect()
+    }
+
+    #[test]
+    fn masks_match_the_prototype() {
+        let size = FIXTURE
+            .lines()
+            .find_map(|line| line.strip_prefix("size "))
+            .expect("Fixture has a size");
+        let [width, height] = [0, 1].map(|index| {
+            size.split_whitespace()
+                .nth(index)
+                .unwrap()
+                .parse::<u32>()
+                .unwrap()
+        });
+
+        let intensities = section("input", height as usize)
+            .into_iter()
+            .map(|grey| grey / 255.0)
+            .collect::<Vec<_>>();
+
+        let masks = [
+            ("low_pass", FrequencyDenoise::LowPass { radius: 3.0 }),
+            (
+                "band_pass",
+                FrequencyDenoise::BandPass {
+                    inner: 1.5,
+                    outer: 4.0,
+                },
+            ),
+            (
+                "notch",
+                FrequencyDenoise::Notch {
+                    points: [(3.0, 0.0), (1.0, 2.0)].into(),
+                    radius: 1.0,
+                },
+            ),
+            ("square", FrequencyDenoise::Square { half_size: 2 }),
+        ];
+
+        for (name, mode) in masks {
+            let expected = section(name, height as usize);
+            assert_eq!(expected.len(), (width * height) as usize, "{name}");
+
+            let filtered = FrequencyDenoiser::new(mode)
+                .denoise(width, height, &intensities)
+                .unwrap();
+
+            for (pixel, (filtered, expected)) in filtered.iter().zip(expected).enumerate() {
+                assert!(
+                    (filtered * 255.0 - expected).abs() <= TOLERANCE,
+                    "{name} pixel {pixel}: {} != {expected}",
+                    filtered * 255.0
+                );
+            }
+        }
+    }
+}
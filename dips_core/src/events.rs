@@ -0,0 +1,440 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+use crate::export::json_number;
+
+/// Color the bounding boxes of the blobs are drawn over the output in
+pub const OVERLAY_COLOR: [u8; 4] = [255, 255, 0, 255];
+
+/// Turns the raw diffs into a binary change mask and the mask into blobs
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum EventDetection {
+    #[default]
+    Off,
+    /// A pixel turns on once its diff magnitude goes over high and stays on until it drops
+    /// under low, a low equal to high is a plain threshold. Blobs of less than min_area pixels
+    /// are dropped
+    Threshold {
+        high: f32,
+        low: f32,
+        min_area: usize,
+    },
+}
+
+impl EventDetection {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+}
+
+/// Pixel rectangle around a blob
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Connected region of changed pixels of a single frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Blob {
+    /// Number of pixels
+    pub area: usize,
+    /// Mean (x, y) of the pixels
+    pub centroid: (f32, f32),
+    pub bounding_box: BoundingBox,
+    /// Mean signed diff of the pixels, positive where the frame got darker than the baseline
+    pub mean_diff: f32,
+}
+
+/// Thresholds the raw diff of every frame and labels the connected regions of the mask
+///
+/// Frames the backend has no diff for are counted but have no mask, the hysteresis starts over
+/// after them
+#[derive(Debug, Clone)]
+pub struct EventDetector {
+    detection: EventDetection,
+
+    // Frames seen so far
+    frame: usize,
+    dimensions: (u32, u32),
+    mask: Vec<bool>,
+    blobs: Vec<Blob>,
+}
+
+impl EventDetector {
+    pub fn new(detection: EventDetection) -> Self {
+        Self {
+            detection,
+            frame: 0,
+            dimensions: (0, 0),
+            mask: Vec::new(),
+            blobs: Vec::new(),
+        }
+    }
+
+    /// Frames checked so far
+    pub fn frame_count(&self) -> usize {
+        self.frame
+    }
+
+    /// Change mask of the last frame in row major order, empty without a diff
+    pub fn mask(&self) -> &[bool] {
+        &self.mask
+    }
+
+    /// Blobs of the last frame
+    pub fn blobs(&self) -> &[Blob] {
+        &self.blobs
+    }
+
+    /// Thresholds the raw diff of a frame and returns its blobs, largest first
+    pub fn detect(&mut self, width: u32, height: u32, diff: Option<&[f32]>) -> &[Blob] {
+        self.frame += 1;
+        self.blobs.clear();
+
+        let EventDetection::Threshold {
+            high,
+            low,
+            min_area,
+        } = self.detection
+        else {
+            return &self.blobs;
+        };
+
+        let Some(diff) = diff.filter(|diff| diff.len() == (width * height) as usize) else {
+            self.mask.clear();
+            return &self.blobs;
+        };
+
+        // The hysteresis only carries over between frames of the same size
+        if self.dimensions != (width, height) || self.mask.len() != diff.len() {
+            self.dimensions = (width, height);
+            self.mask = vec![false; diff.len()];
+        }
+
+        for (on, diff) in self.mask.iter_mut().zip(diff) {
+            let magnitude = diff.abs();
+            *on = magnitude > high || (*on && magnitude > low.min(high));
+        }
+
+        self.blobs = label_blobs(width, height, &self.mask, diff)
+            .into_iter()
+            .filter(|blob| blob.area >= min_area)
+            .collect();
+        self.blobs.sort_by_key(|blob| std::cmp::Reverse(blob.area));
+
+        &self.blobs
+    }
+}
+
+/// Blobs of the 8 connected regions of a mask
+pub fn label_blobs(width: u32, height: u32, mask: &[bool], diff: &[f32]) -> Vec<Blob> {
+    let (width, height) = (width as usize, height as usize);
+
+    let mut visited = vec![false; mask.len()];
+    let mut stack = Vec::new();
+    let mut blobs = Vec::new();
+
+    for start in 0..mask.len() {
+        if !mask[start] || visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        stack.push(start);
+
+        let (mut area, mut sum_x, mut sum_y, mut sum_diff) = (0usize, 0.0f64, 0.0f64, 0.0f64);
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+
+        while let Some(pixel) = stack.pop() {
+            let (x, y) = (pixel % width, pixel / width);
+
+            area += 1;
+            sum_x += x as f64;
+            sum_y += y as f64;
+            sum_diff += diff[pixel] as f64;
+            (min_x, min_y) = (min_x.min(x), min_y.min(y));
+            (max_x, max_y) = (max_x.max(x), max_y.max(y));
+
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let neighbour = ny * width + nx;
+
+                    if mask[neighbour] && !visited[neighbour] {
+                        visited[neighbour] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+
+        blobs.push(Blob {
+            area,
+            centroid: ((sum_x / area as f64) as f32, (sum_y / area as f64) as f32),
+            bounding_box: BoundingBox {
+                x: min_x as u32,
+                y: min_y as u32,
+                width: (max_x - min_x + 1) as u32,
+                height: (max_y - min_y + 1) as u32,
+            },
+            mean_diff: (sum_diff / area as f64) as f32,
+        });
+    }
+
+    blobs
+}
+
+/// Draws the bounding box of every blob over an RGBA8 frame
+pub fn draw_blob_boxes(frame: &mut [u8], width: u32, height: u32, blobs: &[Blob]) {
+    let (width, height) = (width as usize, height as usize);
+    if frame.len() < width * height * 4 {
+        return;
+    }
+
+    let mut set = |x: usize, y: usize| {
+        if x < width && y < height {
+            let pixel = (y * width + x) * 4;
+            frame[pixel..pixel + 4].copy_from_slice(&OVERLAY_COLOR);
+        }
+    };
+
+    for blob in blobs {
+        let BoundingBox {
+            x,
+            y,
+            width: box_width,
+            height: box_height,
+        } = blob.bounding_box;
+        let (left, top) = (x as usize, y as usize);
+        let (right, bottom) = (
+            left + box_width.max(1) as usize - 1,
+            top + box_height.max(1) as usize - 1,
+        );
+
+        for x in left..=right {
+            set(x, top);
+            set(x, bottom);
+        }
+        for y in top..=bottom {
+            set(left, y);
+            set(right, y);
+        }
+    }
+}
+
+/// Writes a JSON Lines log with a line for every frame that has blobs
+pub struct EventLog {
+    file: BufWriter<File>,
+    events: usize,
+}
+
+impl EventLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path.as_ref())?),
+            events: 0,
+        })
+    }
+
+    /// Number of frames logged so far
+    pub fn event_count(&self) -> usize {
+        self.events
+    }
+
+    /// Logs the blobs of a frame, frames without blobs are skipped
+    pub fn write_frame(
+        &mut self,
+        frame: usize,
+        timestamp: Option<f64>,
+        blobs: &[Blob],
+    ) -> io::Result<()> {
+        if blobs.is_empty() {
+            return Ok(());
+        }
+
+        let blobs = blobs
+            .iter()
+            .map(|blob| {
+                let BoundingBox {
+                    x,
+                    y,
+                    width,
+                    height,
+                } = blob.bounding_box;
+
+                format!(
+                    r#"{{"area": {}, "centroid": [{}, {}], "bbox": [{x}, {y}, {width}, {height}], "mean_diff": {}}}"#,
+                    blob.area,
+                    json_number(blob.centroid.0),
+                    json_number(blob.centroid.1),
+                    json_number(blob.mean_diff),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        writeln!(
+            self.file,
+            r#"{{"frame": {frame}, "pts": {}, "blobs": [{blobs}]}}"#,
+            json_number(timestamp.unwrap_or(f64::NAN))
+        )?;
+        self.events += 1;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mask of rows of `#` and `.`
+    fn mask(rows: &[&str]) -> Vec<bool> {
+        rows.iter()
+            .flat_map(|row| row.chars().map(|pixel| pixel == '#'))
+            .collect()
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-6, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn diagonal_neighbours_are_one_blob() {
+        let mask = mask(&["##...", "..#.#", "....#", "#...."]);
+        let diff = (0..mask.len())
+            .map(|pixel| pixel as f32 * 0.1)
+            .collect::<Vec<_>>();
+
+        let blobs = label_blobs(5, 4, &mask, &diff);
+        assert_eq!(blobs.len(), 3);
+
+        let [first, second, third] = [blobs[0], blobs[1], blobs[2]];
+        assert_eq!(first.area, 3);
+        assert_close(first.centroid.0, 1.0);
+        assert_close(first.centroid.1, 1.0 / 3.0);
+        assert_eq!(
+            first.bounding_box,
+            BoundingBox {
+                x: 0,
+                y: 0,
+                width: 3,
+                height: 2,
+            }
+        );
+        assert_close(first.mean_diff, (0.0 + 0.1 + 0.7) / 3.0);
+
+        assert_eq!(second.area, 2);
+        assert_eq!(second.centroid, (4.0, 1.5));
+        assert_eq!(
+            second.bounding_box,
+            BoundingBox {
+                x: 4,
+                y: 1,
+                width: 1,
+                height: 2,
+            }
+        );
+
+        assert_eq!(third.area, 1);
+        assert_eq!(third.centroid, (0.0, 3.0));
+    }
+
+    #[test]
+    fn pixels_stay_on_until_they_drop_under_low() {
+        let mut detector = EventDetector::new(EventDetection::Threshold {
+            high: 0.5,
+            low: 0.2,
+            min_area: 1,
+        });
+        let mut on = |diff: Option<f32>| {
+            let diff = diff.map(|diff| [diff]);
+            !detector
+                .detect(1, 1, diff.as_ref().map(|diff| &diff[..]))
+                .is_empty()
+        };
+
+        assert!(!on(Some(0.3)));
+        assert!(on(Some(-0.6)));
+        assert!(on(Some(0.3)));
+        assert!(!on(Some(0.1)));
+        assert!(!on(Some(0.3)));
+
+        // Frames without a diff start the hysteresis over
+        assert!(on(Some(0.6)));
+        assert!(!on(None));
+        assert!(!on(Some(0.3)));
+        assert_eq!(detector.frame_count(), 8);
+    }
+
+    #[test]
+    fn small_blobs_are_dropped() {
+        let detection = EventDetection::Threshold {
+            high: 0.5,
+            low: 0.5,
+            min_area: 2,
+        };
+        let diff = [1.0, 1.0, 0.0, -1.0, 0.0, 1.0, 1.0, 1.0];
+
+        let mut detector = EventDetector::new(detection);
+        let areas = detector
+            .detect(8, 1, Some(&diff))
+            .iter()
+            .map(|blob| blob.area)
+            .collect::<Vec<_>>();
+        assert_eq!(areas, [3, 2]);
+        assert!(detector.mask()[3]);
+
+        let mut detector = EventDetector::new(EventDetection::Off);
+        assert!(detector.detect(8, 1, Some(&diff)).is_empty());
+    }
+
+    #[test]
+    fn event_log_has_a_line_for_every_frame_with_blobs() {
+        let path =
+            std::env::temp_dir().join(format!("dips_events_{}_log.jsonl", std::process::id()));
+        let blob = Blob {
+            area: 2,
+            centroid: (1.5, 0.0),
+            bounding_box: BoundingBox {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 1,
+            },
+            mean_diff: -0.25,
+        };
+
+        let mut log = EventLog::new(&path).unwrap();
+        log.write_frame(1, Some(0.0), &[]).unwrap();
+        log.write_frame(2, Some(0.5), &[blob]).unwrap();
+        log.write_frame(3, None, &[blob, blob]).unwrap();
+        assert_eq!(log.event_count(), 2);
+        log.finish().unwrap();
+
+        let blob_json =
+            r#"{"area": 2, "centroid": [1.5, 0], "bbox": [1, 0, 2, 1], "mean_diff": -0.25}"#;
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!(
+                "{{\"frame\": 2, \"pts\": 0.5, \"blobs\": [{blob_json}]}}\n\
+                 {{\"frame\": 3, \"pts\": null, \"blobs\": [{blob_json}, {blob_json}]}}\n"
+            )
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
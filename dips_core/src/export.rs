@@ -0,0 +1,830 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{Colormap, DiPsParameters, Motion, RoiStats, Rois};
+
+// The npy header is rewritten with the final frame count so it gets a fixed size
+const NPY_HEADER_SIZE: usize = 128;
+
+// Baseline TIFF tags for a single strip of 32 bit float samples
+const TIFF_TAG_COUNT: u16 = 10;
+const TIFF_IFD_OFFSET: u32 = 8;
+const TIFF_DATA_OFFSET: u32 = TIFF_IFD_OFFSET + 2 + TIFF_TAG_COUNT as u32 * 12 + 4;
+
+/// File format the raw signed differences are exported in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DiffExportFormat {
+    /// A single `(frames, height, width)` little endian float32 stack
+    Npy,
+    /// One single channel float32 TIFF per frame
+    Tiff,
+}
+
+impl DiffExportFormat {
+    /// Picks the format from the extension of the export path
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "npy" => Some(Self::Npy),
+            "tif" | "tiff" => Some(Self::Tiff),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the raw signed difference of every output frame for quantitative analysis
+///
+/// An `.npy` path gets a single stack, a `.tif` or `.tiff` path gets a sequence of frames
+/// numbered after its stem. Either way a `.json` sidecar next to the path records the frame
+/// timestamps, the global motion of every frame and the DiPs parameters once the export is
+/// finished
+pub struct DiffExporter {
+    path: PathBuf,
+    format: DiffExportFormat,
+    parameters: DiPsParameters,
+
+    dimensions: Option<(u32, u32)>,
+    npy_file: Option<BufWriter<File>>,
+    tiff_files: Vec<PathBuf>,
+
+    // Presentation time of every exported frame in seconds, if the source had one
+    timestamps: Vec<Option<f64>>,
+    // Gain of the transfer every exported frame was displayed with
+    gains: Vec<f32>,
+    // Global motion every exported frame was warped back by, if the compensation was on
+    motions: Vec<Option<Motion>>,
+}
+
+impl DiffExporter {
+    pub fn new<P: AsRef<Path>>(path: P, parameters: DiPsParameters) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let format = DiffExportFormat::from_path(&path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Can't export diffs to {}, the path must end in .npy, .tif or .tiff",
+                    path.display()
+                ),
+            )
+        })?;
+
+        Ok(Self {
+            path,
+            format,
+            parameters,
+            dimensions: None,
+            npy_file: None,
+            tiff_files: Vec::new(),
+            timestamps: Vec::new(),
+            gains: Vec::new(),
+            motions: Vec::new(),
+        })
+    }
+
+    pub fn format(&self) -> DiffExportFormat {
+        self.format
+    }
+
+    /// Number of frames exported so far
+    pub fn frame_count(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Appends the raw diff of a frame, the gain it was displayed with and the motion it was
+    /// compensated for, every frame of an export must be the same size
+    pub fn write_frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        diff: &[f32],
+        timestamp: Option<f64>,
+        gain: f32,
+        motion: Option<Motion>,
+    ) -> io::Result<()> {
+        if diff.len() != (width * height) as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "A {width}x{height} frame needs {} diffs but got {}",
+                    width * height,
+                    diff.len()
+                ),
+            ));
+        }
+
+        match self.dimensions {
+            None => self.dimensions = Some((width, height)),
+            Some(dimensions) if dimensions != (width, height) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Can't export a {width}x{height} frame after {}x{} frames",
+                        dimensions.0, dimensions.1
+                    ),
+                ));
+            }
+            Some(_) => {}
+        }
+
+        match self.format {
+            DiffExportFormat::Npy => {
+                if self.npy_file.is_none() {
+                    let mut file = BufWriter::new(File::create(&self.path)?);
+                    // Reserve the header, the frame count is only known once the export finishes
+                    file.write_all(&npy_header(&[0, height as usize, width as usize]))?;
+                    self.npy_file = Some(file);
+                }
+
+                if let Some(file) = self.npy_file.as_mut() {
+                    write_floats(file, diff)?;
+                }
+            }
+            DiffExportFormat::Tiff => {
+                let frame_path = self.tiff_frame_path(self.timestamps.len());
+                let mut file = BufWriter::new(File::create(&frame_path)?);
+                write_tiff(&mut file, width, height, diff)?;
+                file.flush()?;
+
+                self.tiff_files.push(frame_path);
+            }
+        }
+
+        self.timestamps.push(timestamp);
+        self.gains.push(gain);
+        self.motions.push(motion);
+
+        Ok(())
+    }
+
+    /// Fills in the frame count of the npy stack and writes the sidecar
+    pub fn finish(mut self) -> io::Result<()> {
+        let (width, height) = self.dimensions.unwrap_or((0, 0));
+
+        if let Some(mut file) = self.npy_file.take() {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&npy_header(&[
+                self.timestamps.len(),
+                height as usize,
+                width as usize,
+            ]))?;
+            file.flush()?;
+        }
+
+        std::fs::write(
+            self.path.with_extension("json"),
+            self.sidecar(width, height),
+        )
+    }
+
+    fn tiff_frame_path(&self, frame: usize) -> PathBuf {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = self
+            .path
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        self.path
+            .with_file_name(format!("{stem}_{frame:06}.{extension}"))
+    }
+
+    /// JSON description of the export
+    fn sidecar(&self, width: u32, height: u32) -> String {
+        let parameters = &self.parameters;
+
+        let timestamps = self
+            .timestamps
+            .iter()
+            .map(|timestamp| match timestamp {
+                Some(seconds) if seconds.is_finite() => format!("{seconds}"),
+                _ => String::from("null"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let gains = self
+            .gains
+            .iter()
+            .map(|gain| match gain {
+                gain if gain.is_finite() => format!("{gain}"),
+                _ => String::from("null"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let motions = self
+            .motions
+            .iter()
+            .map(|motion| match motion {
+                Some(motion) => format!(
+                    r#"{{"dx": {}, "dy": {}, "rotation": {}, "scale": {}, "response": {}}}"#,
+                    json_number(motion.dx),
+                    json_number(motion.dy),
+                    json_number(motion.rotation),
+                    json_number(motion.scale),
+                    json_number(motion.response),
+                ),
+                None => String::from("null"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let files = self
+            .tiff_files
+            .iter()
+            .map(|path| {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+
+                json_string(&name)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!(
+            r#"{{
+  "format": {format},
+  "dtype": "float32",
+  "width": {width},
+  "height": {height},
+  "frames": {frames},
+  "timestamps": [{timestamps}],
+  "gains": [{gains}],
+  "motion": [{motions}],
+  "files": [{files}],
+  "parameters": {{
+    "spatial_window_size": {spatial_window_size},
+    "spatial_filter": {spatial_filter},
+    "border_mode": {border_mode},
+    "chroma_filter": {chroma_filter},
+    "temporal_window": {temporal_window},
+    "temporal_estimator": {temporal_estimator},
+    "baseline_mode": {baseline_mode},
+    "difference_mode": {difference_mode},
+    "transfer_curve": {transfer_curve},
+    "offset": {offset},
+    "clamp_min": {clamp_min},
+    "clamp_max": {clamp_max},
+    "auto_sensitivity": {auto_sensitivity},
+    "motion_compensation": {motion_compensation},
+    "denoise": {denoise},
+    "calibration": {calibration}
+  }}
+}}
+"#,
+            format = json_string(&format!("{:?}", self.format).to_lowercase()),
+            frames = self.timestamps.len(),
+            spatial_window_size = parameters.spatial_window_size,
+            spatial_filter = json_string(&format!("{:?}", parameters.spatial_filter)),
+            border_mode = json_string(&format!("{:?}", parameters.border_mode)),
+            chroma_filter = json_string(&format!("{:?}", parameters.chroma_filter)),
+            temporal_window = parameters.temporal_window,
+            temporal_estimator = json_string(&format!("{:?}", parameters.temporal_estimator)),
+            baseline_mode = json_string(&format!("{:?}", parameters.baseline_mode)),
+            difference_mode = json_string(&format!("{:?}", parameters.difference_mode)),
+            transfer_curve = json_string(&format!("{:?}", parameters.transfer.curve)),
+            offset = parameters.transfer.offset,
+            clamp_min = parameters.transfer.clamp_min,
+            clamp_max = parameters.transfer.clamp_max,
+            auto_sensitivity = json_string(&format!("{:?}", parameters.auto_sensitivity)),
+            motion_compensation = json_string(&format!("{:?}", parameters.motion_compensation)),
+            denoise = json_string(&format!("{:?}", parameters.denoise)),
+            calibration = json_string(&format!("{:?}", parameters.calibration)),
+        )
+    }
+}
+
+/// File format the ROI time series is written in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RoiSeriesFormat {
+    /// One row per frame and region
+    Csv,
+    /// The regions and one entry per frame with the statistics of every region
+    Json,
+}
+
+impl RoiSeriesFormat {
+    /// Picks the format from the extension of the series path
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the mean, min and max signed diff inside every region of interest of every output
+/// frame as a time series
+///
+/// CSV rows are written as the frames come in, JSON is written once the series is finished
+pub struct RoiSeriesExporter {
+    path: PathBuf,
+    format: RoiSeriesFormat,
+    rois: Rois,
+
+    csv_file: Option<BufWriter<File>>,
+    // Timestamp and statistics of every frame for the JSON series
+    frames: Vec<(Option<f64>, Vec<RoiStats>)>,
+    frame_count: usize,
+}
+
+impl RoiSeriesExporter {
+    pub fn new<P: AsRef<Path>>(path: P, rois: Rois) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let format = RoiSeriesFormat::from_path(&path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Can't write the ROI series to {}, the path must end in .csv or .json",
+                    path.display()
+                ),
+            )
+        })?;
+
+        let csv_file = match format {
+            RoiSeriesFormat::Csv => {
+                let mut file = BufWriter::new(File::create(&path)?);
+                writeln!(file, "frame,timestamp,roi,pixels,mean,min,max")?;
+                Some(file)
+            }
+            RoiSeriesFormat::Json => None,
+        };
+
+        Ok(Self {
+            path,
+            format,
+            rois,
+            csv_file,
+            frames: Vec::new(),
+            frame_count: 0,
+        })
+    }
+
+    pub fn format(&self) -> RoiSeriesFormat {
+        self.format
+    }
+
+    /// Number of frames written so far
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Appends the statistics of every region for a frame
+    pub fn write_frame(&mut self, timestamp: Option<f64>, stats: &[RoiStats]) -> io::Result<()> {
+        if stats.len() != self.rois.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Got the statistics of {} regions for {} regions of interest",
+                    stats.len(),
+                    self.rois.len()
+                ),
+            ));
+        }
+
+        if let Some(file) = self.csv_file.as_mut() {
+            let timestamp = timestamp.map(|seconds| seconds.to_string());
+            for (roi, stats) in self.rois.regions.iter().zip(stats) {
+                writeln!(
+                    file,
+                    "{},{},{},{},{},{},{}",
+                    self.frame_count,
+                    timestamp.as_deref().unwrap_or_default(),
+                    csv_field(&roi.name),
+                    stats.pixels,
+                    stats.mean,
+                    stats.min,
+                    stats.max
+                )?;
+            }
+        } else {
+            self.frames.push((timestamp, stats.to_vec()));
+        }
+
+        self.frame_count += 1;
+
+        Ok(())
+    }
+
+    /// Flushes the CSV rows or writes the JSON series
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(mut file) = self.csv_file.take() {
+            return file.flush();
+        }
+
+        // Every frame has the same regions so the first one gives their sizes
+        let pixels = self
+            .frames
+            .first()
+            .map(|(_, stats)| stats.iter().map(|stats| stats.pixels).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let rois = self
+            .rois
+            .regions
+            .iter()
+            .enumerate()
+            .map(|(index, roi)| {
+                format!(
+                    r#"    {{ "name": {name}, "pixels": {pixels} }}"#,
+                    name = json_string(&roi.name),
+                    pixels = pixels
+                        .get(index)
+                        .map(|pixels| pixels.to_string())
+                        .unwrap_or_else(|| String::from("null")),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",\n");
+
+        let frames = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(frame, (timestamp, stats))| {
+                let values = |value: fn(&RoiStats) -> f32| {
+                    stats
+                        .iter()
+                        .map(|stats| json_number(value(stats)))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+
+                format!(
+                    r#"    {{ "frame": {frame}, "timestamp": {timestamp}, "mean": [{mean}], "min": [{min}], "max": [{max}] }}"#,
+                    timestamp = json_number(timestamp.unwrap_or(f64::NAN)),
+                    mean = values(|stats| stats.mean),
+                    min = values(|stats| stats.min),
+                    max = values(|stats| stats.max),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",\n");
+
+        std::fs::write(
+            &self.path,
+            format!(
+                r#"{{
+  "rois": [
+{rois}
+  ],
+  "frames": [
+{frames}
+  ]
+}}
+"#
+            ),
+        )
+    }
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Number or null for the values JSON can't hold
+pub(crate) fn json_number<T: Into<f64> + Display + Copy>(value: T) -> String {
+    if value.into().is_finite() {
+        format!("{value}")
+    } else {
+        String::from("null")
+    }
+}
+
+/// Quotes a CSV field if it has to be
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
+
+fn write_floats<W: Write>(writer: &mut W, values: &[f32]) -> io::Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Version 1.0 npy header of a float32 array of the given shape padded with spaces to
+/// NPY_HEADER_SIZE bytes
+fn npy_header(shape: &[usize]) -> Vec<u8> {
+    let shape = shape
+        .iter()
+        .map(|dimension| format!("{dimension}, "))
+        .collect::<String>();
+    let dictionary = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}), }}",
+        shape.trim_end()
+    );
+
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&((NPY_HEADER_SIZE - 10) as u16).to_le_bytes());
+    header.extend_from_slice(dictionary.as_bytes());
+    header.resize(NPY_HEADER_SIZE - 1, b' ');
+    header.push(b'\n');
+
+    header
+}
+
+/// Little endian TIFF with a single uncompressed strip of float32 samples
+fn write_tiff<W: Write>(writer: &mut W, width: u32, height: u32, values: &[f32]) -> io::Result<()> {
+    // (tag, type, value) where type 3 is a SHORT and 4 is a LONG
+    let tags: [(u16, u16, u32); TIFF_TAG_COUNT as usize] = [
+        (256, 4, width),              // ImageWidth
+        (257, 4, height),             // ImageLength
+        (258, 3, 32),                 // BitsPerSample
+        (259, 3, 1),                  // Compression: none
+        (262, 3, 1),                  // PhotometricInterpretation: black is zero
+        (273, 4, TIFF_DATA_OFFSET),   // StripOffsets
+        (277, 3, 1),                  // SamplesPerPixel
+        (278, 4, height),             // RowsPerStrip
+        (279, 4, width * height * 4), // StripByteCounts
+        (339, 3, 3),                  // SampleFormat: IEEE float
+    ];
+
+    writer.write_all(b"II")?;
+    writer.write_all(&42u16.to_le_bytes())?;
+    writer.write_all(&TIFF_IFD_OFFSET.to_le_bytes())?;
+
+    writer.write_all(&TIFF_TAG_COUNT.to_le_bytes())?;
+    for (tag, field_type, value) in tags {
+        writer.write_all(&tag.to_le_bytes())?;
+        writer.write_all(&field_type.to_le_bytes())?;
+        writer.write_all(&1u32.to_le_bytes())?;
+
+        // SHORT values are left justified in the value field
+        match field_type {
+            3 => {
+                writer.write_all(&(value as u16).to_le_bytes())?;
+                writer.write_all(&0u16.to_le_bytes())?;
+            }
+            _ => writer.write_all(&value.to_le_bytes())?,
+        }
+    }
+    // No further directories
+    writer.write_all(&0u32.to_le_bytes())?;
+
+    write_floats(writer, values)
+}
+
+/// Writes a little endian float32 `.npy` of the given shape
+pub(crate) fn write_npy(path: &Path, shape: &[usize], values: &[f32]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&npy_header(shape))?;
+    write_floats(&mut file, values)?;
+    file.flush()
+}
+
+/// Writes a `(height, width)` map as a heatmap `.png` running from 0 to the largest finite
+/// magnitude of the map through the colormap, and returns that magnitude
+pub(crate) fn write_heatmap(
+    path: &Path,
+    width: u32,
+    height: u32,
+    values: &[f32],
+    colormap: &Colormap,
+) -> io::Result<f32> {
+    let table = colormap.table();
+
+    let range = values
+        .iter()
+        .map(|value| value.abs())
+        .filter(|value| value.is_finite())
+        .fold(0.0, f32::max);
+
+    let pixels = values
+        .iter()
+        .flat_map(|value| {
+            let position = match range > 0.0 {
+                true => (value.abs() / range).clamp(0.0, 1.0),
+                false => 0.0,
+            };
+            let entry = (position * (table.len() - 1) as f32).round() as usize;
+
+            table[entry].map(|channel| (channel * 255.0).round() as u8)
+        })
+        .collect::<Vec<_>>();
+
+    let mut file = BufWriter::new(File::create(path)?);
+    write_png(&mut file, width, height, &pixels)?;
+    file.flush()?;
+
+    Ok(range)
+}
+
+/// 8 bit RGB PNG with the image data in uncompressed deflate blocks
+fn write_png<W: Write>(writer: &mut W, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    // Bit depth 8, color type 2 (RGB), default compression, filter and no interlacing
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_png_chunk(writer, b"IHDR", &header)?;
+
+    // Every row starts with filter type 0
+    let row_bytes = width as usize * 3;
+    let mut scanlines = Vec::with_capacity((row_bytes + 1) * height as usize);
+    for row in rgb.chunks_exact(row_bytes.max(1)).take(height as usize) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    // zlib stream of stored blocks, which hold at most 65535 bytes each
+    let mut data = vec![0x78, 0x01];
+    let mut blocks = scanlines.chunks(u16::MAX as usize).collect::<Vec<_>>();
+    if blocks.is_empty() {
+        // An empty image still needs a final block
+        blocks.push(&[]);
+    }
+    for (index, block) in blocks.iter().enumerate() {
+        data.push((index + 1 == blocks.len()) as u8);
+        data.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(&scanlines).to_be_bytes());
+    write_png_chunk(writer, b"IDAT", &data)?;
+
+    write_png_chunk(writer, b"IEND", &[])
+}
+
+fn write_png_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let crc = crc32(kind.iter().chain(data));
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// CRC-32 of the PNG chunks
+fn crc32<'a, I: IntoIterator<Item = &'a u8>>(bytes: I) -> u32 {
+    let crc = bytes.into_iter().fold(u32::MAX, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| match crc & 1 {
+            1 => 0xEDB8_8320 ^ (crc >> 1),
+            _ => crc >> 1,
+        })
+    });
+
+    !crc
+}
+
+/// Adler-32 checksum of the zlib stream
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path of its own in the temporary directory
+    fn export_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dips_export_{}_{name}", std::process::id()))
+    }
+
+    /// Raw diffs of a 3x2 frame
+    fn diffs(frame: usize) -> Vec<f32> {
+        (0..6)
+            .map(|pixel| frame as f32 - pixel as f32 * 0.25)
+            .collect()
+    }
+
+    #[test]
+    fn checksums_match_their_check_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn npy_headers_are_padded_to_their_size() {
+        let header = npy_header(&[2, 3, 4]);
+        assert_eq!(header.len(), NPY_HEADER_SIZE);
+        assert_eq!(&header[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!(
+            u16::from_le_bytes([header[8], header[9]]) as usize,
+            NPY_HEADER_SIZE - 10
+        );
+
+        let dictionary = std::str::from_utf8(&header[10..]).unwrap();
+        assert!(
+            dictionary
+                .starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3, 4,), }")
+        );
+        assert!(dictionary.ends_with(" \n"));
+    }
+
+    #[test]
+    fn npy_stacks_get_their_frame_count_when_finished() {
+        let path = export_path("stack.npy");
+        let mut exporter = DiffExporter::new(&path, DiPsParameters::default()).unwrap();
+        assert_eq!(exporter.format(), DiffExportFormat::Npy);
+
+        for frame in 0..3 {
+            exporter
+                .write_frame(3, 2, &diffs(frame), Some(frame as f64 * 0.5), 1.0, None)
+                .unwrap();
+        }
+        assert!(
+            exporter
+                .write_frame(2, 3, &diffs(3), None, 1.0, None)
+                .is_err()
+        );
+        exporter.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..NPY_HEADER_SIZE], npy_header(&[3, 2, 3]));
+        let values = bytes[NPY_HEADER_SIZE..]
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(values, [diffs(0), diffs(1), diffs(2)].concat());
+
+        let sidecar = std::fs::read_to_string(path.with_extension("json")).unwrap();
+        assert!(sidecar.contains(r#""frames": 3,"#));
+        assert!(sidecar.contains(r#""timestamps": [0, 0.5, 1],"#));
+
+        std::fs::remove_file(path.with_extension("json")).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tiff_frames_decode_to_the_diffs() {
+        let path = export_path("frames.tif");
+        let mut exporter = DiffExporter::new(&path, DiPsParameters::default()).unwrap();
+        assert_eq!(exporter.format(), DiffExportFormat::Tiff);
+
+        for frame in 0..2 {
+            exporter
+                .write_frame(3, 2, &diffs(frame), None, 1.0, None)
+                .unwrap();
+        }
+        let frame_paths = exporter.tiff_files.clone();
+        exporter.finish().unwrap();
+
+        for (frame, frame_path) in frame_paths.iter().enumerate() {
+            let mut decoder = tiff::decoder::Decoder::new(File::open(frame_path).unwrap()).unwrap();
+            assert_eq!(decoder.dimensions().unwrap(), (3, 2));
+            assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::Gray(32));
+
+            let tiff::decoder::DecodingResult::F32(values) = decoder.read_image().unwrap() else {
+                panic!("Frame {frame} doesn't decode to floats");
+            };
+            assert_eq!(values, diffs(frame));
+
+            std::fs::remove_file(frame_path).unwrap();
+        }
+        std::fs::remove_file(path.with_extension("json")).unwrap();
+    }
+
+    #[test]
+    fn heatmaps_decode_to_the_colormap() {
+        let path = export_path("heatmap.png");
+        let colormap = Colormap::Viridis;
+        let table = colormap.table();
+
+        // Wide enough for more than one stored deflate block
+        let (width, height) = (300, 80);
+        let values = (0..width * height)
+            .map(|pixel| -((pixel % width) as f32) / (width - 1) as f32 * 2.0)
+            .collect::<Vec<_>>();
+        let range = write_heatmap(&path, width, height, &values, &colormap).unwrap();
+        assert_eq!(range, 2.0);
+
+        let image = image::open(&path).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (width, height));
+        for (pixel, value) in image.pixels().zip(&values) {
+            let entry = (value.abs() / range * (table.len() - 1) as f32).round() as usize;
+            assert_eq!(
+                pixel.0,
+                table[entry].map(|channel| (channel * 255.0).round() as u8)
+            );
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
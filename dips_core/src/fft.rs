@@ -0,0 +1,170 @@
+use std::f32::consts::PI;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub(crate) struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn real(re: f32) -> Self {
+        Self { re, im: 0.0 }
+    }
+
+    pub fn mul(self, other: Self) -> Self {
+        Self {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+
+    pub fn conj(self) -> Self {
+        Self {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    pub fn scale(self, factor: f32) -> Self {
+        Self {
+            re: self.re * factor,
+            im: self.im * factor,
+        }
+    }
+
+    pub fn norm(self) -> f32 {
+        self.re.hypot(self.im)
+    }
+}
+
+/// 2D FFT of row major complex values, the inverse is scaled by 1 / (width * height)
+///
+/// Sides that aren't a power of two go through [`fft_any`]
+pub(crate) fn fft_2d(
+    mut values: Vec<Complex>,
+    width: usize,
+    height: usize,
+    inverse: bool,
+) -> Vec<Complex> {
+    for row in values.chunks_exact_mut(width) {
+        fft_any(row, inverse);
+    }
+
+    let mut column = vec![Complex::default(); height];
+    for x in 0..width {
+        for y in 0..height {
+            column[y] = values[y * width + x];
+        }
+        fft_any(&mut column, inverse);
+        for y in 0..height {
+            values[y * width + x] = column[y];
+        }
+    }
+
+    if inverse {
+        let scale = 1.0 / (width * height) as f32;
+        values
+            .iter_mut()
+            .for_each(|value| *value = value.scale(scale));
+    }
+
+    values
+}
+
+/// In place unscaled FFT of any number of values
+///
+/// Powers of two go straight to [`fft`], other sizes use Bluestein's algorithm, which turns
+/// the transform into a convolution with a chirp that is done with power of two FFTs
+pub(crate) fn fft_any(values: &mut [Complex], inverse: bool) {
+    let size = values.len();
+    if size <= 1 || size.is_power_of_two() {
+        fft(values, inverse);
+        return;
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    // exp(sign * i * pi * k² / size), k² is taken modulo 2 * size so the angle stays accurate
+    let chirp = (0..size)
+        .map(|k| {
+            let angle = sign * std::f64::consts::PI * ((k * k) % (2 * size)) as f64 / size as f64;
+            let (sin, cos) = angle.sin_cos();
+            Complex {
+                re: cos as f32,
+                im: sin as f32,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let padded = (2 * size - 1).next_power_of_two();
+    let mut signal = vec![Complex::default(); padded];
+    for ((signal, value), chirp) in signal.iter_mut().zip(values.iter()).zip(chirp.iter()) {
+        *signal = value.mul(*chirp);
+    }
+
+    let mut kernel = vec![Complex::default(); padded];
+    kernel[0] = chirp[0].conj();
+    for k in 1..size {
+        kernel[k] = chirp[k].conj();
+        kernel[padded - k] = chirp[k].conj();
+    }
+
+    fft(&mut signal, false);
+    fft(&mut kernel, false);
+    for (signal, kernel) in signal.iter_mut().zip(kernel.iter()) {
+        *signal = signal.mul(*kernel);
+    }
+    fft(&mut signal, true);
+
+    let scale = 1.0 / padded as f32;
+    for ((value, signal), chirp) in values.iter_mut().zip(signal.iter()).zip(chirp.iter()) {
+        *value = signal.mul(*chirp).scale(scale);
+    }
+}
+
+/// In place unscaled radix 2 FFT of a power of two number of values
+pub(crate) fn fft(values: &mut [Complex], inverse: bool) {
+    let size = values.len();
+
+    // Bit reversed order
+    let mut j = 0;
+    for i in 1..size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut length = 2;
+    while length <= size {
+        let angle = sign * 2.0 * PI / length as f32;
+        let (sin, cos) = angle.sin_cos();
+        let root = Complex { re: cos, im: sin };
+
+        for start in (0..size).step_by(length) {
+            let mut twiddle = Complex { re: 1.0, im: 0.0 };
+            for k in 0..length / 2 {
+                let even = values[start + k];
+                let odd = values[start + k + length / 2].mul(twiddle);
+
+                values[start + k] = Complex {
+                    re: even.re + odd.re,
+                    im: even.im + odd.im,
+                };
+                values[start + k + length / 2] = Complex {
+                    re: even.re - odd.re,
+                    im: even.im - odd.im,
+                };
+                twiddle = twiddle.mul(root);
+            }
+        }
+
+        length <<= 1;
+    }
+}
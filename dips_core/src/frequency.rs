@@ -0,0 +1,752 @@
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+use crate::{
+    Colormap, MAX_ROIS, Rois,
+    export::{json_number, json_string, write_heatmap, write_npy},
+};
+
+/// Range of temporal frequencies in Hz a band power map is collected over, both ends included
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrequencyBand {
+    pub low: f32,
+    pub high: f32,
+}
+
+impl FrequencyBand {
+    /// Name the band map is written under, e.g. `band_0.5_2hz`
+    pub fn name(&self) -> String {
+        format!("band_{}_{}hz", self.low, self.high)
+    }
+}
+
+/// How the windows of frames the spectra are taken over move through the recording
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FrequencyWindow {
+    /// A set of maps for every window, starting a new window every hop frames
+    Sliding { hop: usize },
+    /// A single set of maps of the whole recording, from the spectra of half overlapping
+    /// windows averaged together
+    Whole,
+}
+
+/// Where the full spectrum is written for
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpectrumProbe {
+    /// A single pixel of the frame
+    Pixel { x: u32, y: u32 },
+    /// The mean intensity over the region of interest with the given index
+    Roi(usize),
+}
+
+/// Temporal spectrum of the intensity of every pixel
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum FrequencyAnalysis {
+    #[default]
+    Off,
+    /// Spectra over windows of the given number of frames of intensities averaged over binning
+    /// by binning pixel blocks, which keeps the frames of a window in memory affordable
+    On {
+        window: usize,
+        mode: FrequencyWindow,
+        binning: u32,
+        bands: Arc<[FrequencyBand]>,
+        probe: Option<SpectrumProbe>,
+    },
+}
+
+impl FrequencyAnalysis {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+}
+
+/// Takes the temporal spectrum of every pixel over windows of frames and turns it into maps of
+/// the dominant frequency and the power in every band
+///
+/// The intensity of every window is detrended by its mean and Hann windowed. Powers are one
+/// sided and scaled so that the bins of a spectrum add up to the mean square of the windowed
+/// signal, a sine of amplitude A has a power of A² / 2. Frequencies are in Hz from the frame
+/// rate, which is estimated from the timestamps unless it is set
+#[derive(Debug, Clone)]
+pub struct FrequencyAnalyzer {
+    analysis: FrequencyAnalysis,
+    rois: Rois,
+    frame_rate: Option<f64>,
+
+    frames: usize,
+    // First and last timestamp in seconds to estimate the frame rate from
+    timestamps: Option<(f64, f64, usize)>,
+    // Frame size and the size of the binned maps
+    dimensions: (u32, u32),
+    map_dimensions: (u32, u32),
+
+    // Binned intensities and probe signal of the frames of the current window
+    window_frames: VecDeque<Vec<f32>>,
+    probe_signal: VecDeque<f32>,
+    probe_mask: Vec<bool>,
+    // Time in seconds of the first frame of every window
+    window_starts: Vec<f64>,
+
+    // Sum of the spectra of every window for the whole recording
+    power_sum: Vec<f32>,
+    // Dominant frequency and band power maps of every window when sliding
+    sliding_maps: Vec<Vec<Vec<f32>>>,
+    // Spectrum of the probe of every window
+    probe_spectra: Vec<Vec<f32>>,
+}
+
+impl FrequencyAnalyzer {
+    pub fn new(analysis: FrequencyAnalysis, rois: Rois) -> Self {
+        Self {
+            analysis,
+            rois,
+            frame_rate: None,
+            frames: 0,
+            timestamps: None,
+            dimensions: (0, 0),
+            map_dimensions: (0, 0),
+            window_frames: VecDeque::new(),
+            probe_signal: VecDeque::new(),
+            probe_mask: Vec::new(),
+            window_starts: Vec::new(),
+            power_sum: Vec::new(),
+            sliding_maps: Vec::new(),
+            probe_spectra: Vec::new(),
+        }
+    }
+
+    /// Sets the frame rate of the stream instead of estimating it from the timestamps
+    pub fn set_frame_rate(&mut self, frame_rate: f64) {
+        if frame_rate.is_finite() && frame_rate > 0.0 {
+            self.frame_rate = Some(frame_rate);
+        }
+    }
+
+    /// Frame rate the frequencies are in Hz of, the one set or the one of the timestamps so far
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.frame_rate.or_else(|| {
+            let (first, last, count) = self.timestamps?;
+            (count > 1 && last > first).then(|| (count - 1) as f64 / (last - first))
+        })
+    }
+
+    /// Number of frames added so far
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+
+    /// Number of windows the spectra have been taken over so far
+    pub fn window_count(&self) -> usize {
+        self.window_starts.len()
+    }
+
+    /// Size of the maps
+    pub fn map_dimensions(&self) -> (u32, u32) {
+        self.map_dimensions
+    }
+
+    /// Frequency in Hz of every bin of the spectra, or in cycles per frame without a frame
+    /// rate
+    pub fn frequencies(&self) -> Vec<f32> {
+        let FrequencyAnalysis::On { window, .. } = self.analysis else {
+            return Vec::new();
+        };
+        let frame_rate = self.frame_rate().unwrap_or(1.0) as f32;
+
+        (0..=window / 2)
+            .map(|bin| bin as f32 * frame_rate / window as f32)
+            .collect()
+    }
+
+    /// Adds the intensities of an RGBA8 frame
+    pub fn push(&mut self, width: u32, height: u32, frame: &[u8], timestamp: Option<f64>) {
+        let FrequencyAnalysis::On {
+            window,
+            mode,
+            binning,
+            probe,
+            ..
+        } = self.analysis
+        else {
+            return;
+        };
+        let window = window.max(2);
+
+        if frame.len() != (width * height * 4) as usize {
+            warn!("Skipping a frame of the wrong size in the frequency analysis");
+            return;
+        }
+
+        // The windows only carry over between frames of the same size
+        if self.dimensions != (width, height) {
+            if self.dimensions != (0, 0) {
+                warn!("The frame size changed, starting the frequency analysis over");
+            }
+
+            let binning = binning.max(1);
+            self.dimensions = (width, height);
+            self.map_dimensions = ((width / binning).max(1), (height / binning).max(1));
+            self.window_frames.clear();
+            self.probe_signal.clear();
+            self.power_sum.clear();
+            self.sliding_maps.clear();
+            self.probe_spectra.clear();
+            self.window_starts.clear();
+
+            let roi_mask = match probe {
+                Some(SpectrumProbe::Roi(_)) => self.rois.mask(width, height),
+                _ => Vec::new(),
+            };
+            self.probe_mask = (0..width * height)
+                .map(|pixel| match probe {
+                    Some(SpectrumProbe::Pixel { x, y }) => pixel == y * width + x,
+                    Some(SpectrumProbe::Roi(index)) => {
+                        index < MAX_ROIS && roi_mask[pixel as usize] & 1 << index != 0
+                    }
+                    None => false,
+                })
+                .collect();
+        }
+
+        if let Some(timestamp) = timestamp.filter(|timestamp| timestamp.is_finite()) {
+            self.timestamps = Some(match self.timestamps {
+                Some((first, _, count)) => (first, timestamp, count + 1),
+                None => (timestamp, timestamp, 1),
+            });
+        }
+
+        self.window_frames
+            .push_back(self.binned_intensity(width, height, frame));
+        if probe.is_some() {
+            self.probe_signal.push_back(self.probe_intensity(frame));
+        }
+        self.frames += 1;
+
+        if self.window_frames.len() > window {
+            self.window_frames.pop_front();
+            self.probe_signal.pop_front();
+        }
+
+        let hop = match mode {
+            FrequencyWindow::Sliding { hop } => hop.max(1),
+            FrequencyWindow::Whole => (window / 2).max(1),
+        };
+        if self.window_frames.len() == window && (self.frames - window).is_multiple_of(hop) {
+            self.analyse_window(window, mode);
+        }
+    }
+
+    /// Mean intensity of every binned block of a frame
+    fn binned_intensity(&self, width: u32, height: u32, frame: &[u8]) -> Vec<f32> {
+        let (map_width, map_height) = self.map_dimensions;
+        let (bin_x, bin_y) = (width / map_width, height / map_height);
+        let scale = 1.0 / (3.0 * 255.0 * (bin_x * bin_y) as f32);
+
+        let mut binned = vec![0.0; (map_width * map_height) as usize];
+        for y in 0..map_height * bin_y {
+            let row = (y / bin_y * map_width) as usize;
+            for x in 0..map_width * bin_x {
+                let pixel = ((y * width + x) * 4) as usize;
+                binned[row + (x / bin_x) as usize] +=
+                    (frame[pixel] as u32 + frame[pixel + 1] as u32 + frame[pixel + 2] as u32)
+                        as f32;
+            }
+        }
+        binned.iter_mut().for_each(|value| *value *= scale);
+
+        binned
+    }
+
+    /// Mean intensity over the probe
+    fn probe_intensity(&self, frame: &[u8]) -> f32 {
+        let (sum, count) = frame
+            .chunks_exact(4)
+            .zip(&self.probe_mask)
+            .filter(|(_, inside)| **inside)
+            .fold((0.0, 0usize), |(sum, count), (pixel, _)| {
+                let intensity = (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) as f32;
+                (sum + intensity / (3.0 * 255.0), count + 1)
+            });
+
+        match count {
+            0 => f32::NAN,
+            count => sum / count as f32,
+        }
+    }
+
+    /// Takes the spectra of the frames of the window
+    fn analyse_window(&mut self, window: usize, mode: FrequencyWindow) {
+        let start = self.frames - window;
+        self.window_starts.push(match self.frame_rate() {
+            Some(frame_rate) => start as f64 / frame_rate,
+            None => start as f64,
+        });
+
+        let dft = Dft::new(window);
+        let bins = window / 2 + 1;
+        let pixel_count = self.window_frames[0].len();
+
+        if mode == FrequencyWindow::Whole && self.power_sum.len() != pixel_count * bins {
+            self.power_sum = vec![0.0; pixel_count * bins];
+        }
+
+        let mut signal = vec![0.0; window];
+        let mut spectra = match mode {
+            FrequencyWindow::Sliding { .. } => Vec::with_capacity(pixel_count * bins),
+            FrequencyWindow::Whole => Vec::new(),
+        };
+        for pixel in 0..pixel_count {
+            for (sample, frame) in signal.iter_mut().zip(&self.window_frames) {
+                *sample = frame[pixel];
+            }
+            let power = dft.power(&signal);
+
+            match mode {
+                FrequencyWindow::Sliding { .. } => spectra.extend(power),
+                FrequencyWindow::Whole => self.power_sum[pixel * bins..(pixel + 1) * bins]
+                    .iter_mut()
+                    .zip(power)
+                    .for_each(|(sum, power)| *sum += power),
+            }
+        }
+
+        if let FrequencyWindow::Sliding { .. } = mode {
+            let maps = self.maps(&spectra, 1.0);
+            self.sliding_maps
+                .push(maps.into_iter().map(|(_, map)| map).collect());
+        }
+
+        if self.probe_signal.len() == window {
+            let signal = self.probe_signal.iter().copied().collect::<Vec<_>>();
+            self.probe_spectra.push(dft.power(&signal));
+        }
+    }
+
+    /// Dominant frequency, its power and band power maps from the spectra of every pixel,
+    /// scaled by the given factor
+    fn maps(&self, spectra: &[f32], scale: f32) -> Vec<(String, Vec<f32>)> {
+        let FrequencyAnalysis::On {
+            window, ref bands, ..
+        } = self.analysis
+        else {
+            return Vec::new();
+        };
+        let bins = window.max(2) / 2 + 1;
+        let frequencies = self.frequencies();
+
+        let mut dominant_frequency = Vec::with_capacity(spectra.len() / bins);
+        let mut dominant_power = Vec::with_capacity(spectra.len() / bins);
+        let mut band_powers = vec![Vec::with_capacity(spectra.len() / bins); bands.len()];
+
+        for spectrum in spectra.chunks_exact(bins) {
+            // The mean is removed so the zero frequency bin is left out
+            let (bin, power) = spectrum
+                .iter()
+                .enumerate()
+                .skip(1)
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(bin, power)| (bin, *power))
+                .unwrap_or((0, 0.0));
+
+            dominant_frequency.push(match power > 0.0 {
+                true => frequencies[bin],
+                false => 0.0,
+            });
+            dominant_power.push(power * scale);
+
+            for (band, band_power) in bands.iter().zip(band_powers.iter_mut()) {
+                band_power.push(
+                    spectrum
+                        .iter()
+                        .zip(&frequencies)
+                        .filter(|(_, frequency)| (band.low..=band.high).contains(*frequency))
+                        .map(|(power, _)| power)
+                        .sum::<f32>()
+                        * scale,
+                );
+            }
+        }
+
+        [
+            (String::from("dominant_frequency"), dominant_frequency),
+            (String::from("dominant_power"), dominant_power),
+        ]
+        .into_iter()
+        .chain(bands.iter().map(|band| band.name()).zip(band_powers))
+        .collect()
+    }
+
+    /// Writes the maps named after the stem of the path, e.g. `out/run_dominant_frequency.npy`
+    ///
+    /// The whole recording gets a `(height, width)` float32 `.npy` and a heatmap `.png` of every
+    /// map, sliding windows get a `(windows, height, width)` stack of every map instead. The
+    /// spectrum of the probe goes in a `.csv` with a row for every window and frequency, and a
+    /// `.json` records the frame rate, windows and bands. Returns the paths written
+    pub fn write<P: AsRef<Path>>(&self, path: P, colormap: &Colormap) -> io::Result<Vec<PathBuf>> {
+        let FrequencyAnalysis::On {
+            window,
+            mode,
+            binning,
+            ref bands,
+            probe,
+        } = self.analysis
+        else {
+            return Ok(Vec::new());
+        };
+
+        let path = path.as_ref();
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("frequency"));
+        let (width, height) = self.map_dimensions;
+
+        if self.frame_rate().is_none() {
+            warn!("No frame rate for the frequency analysis, the frequencies are per frame");
+        }
+
+        let mut written = Vec::new();
+        let mut ranges = Vec::new();
+        match mode {
+            FrequencyWindow::Whole => {
+                let windows = self.window_starts.len().max(1) as f32;
+
+                for (name, values) in self.maps(&self.power_sum, 1.0 / windows) {
+                    let npy_path = path.with_file_name(format!("{stem}_{name}.npy"));
+                    write_npy(&npy_path, &[height as usize, width as usize], &values)?;
+                    written.push(npy_path);
+
+                    let png_path = path.with_file_name(format!("{stem}_{name}.png"));
+                    let range = write_heatmap(&png_path, width, height, &values, colormap)?;
+                    written.push(png_path);
+
+                    ranges.push(format!(
+                        "{{ \"name\": {}, \"heatmap_max\": {} }}",
+                        json_string(&name),
+                        json_number(range)
+                    ));
+                }
+            }
+            FrequencyWindow::Sliding { .. } => {
+                let names = ["dominant_frequency", "dominant_power"]
+                    .map(String::from)
+                    .into_iter()
+                    .chain(bands.iter().map(|band| band.name()));
+
+                for (index, name) in names.enumerate() {
+                    let values = self
+                        .sliding_maps
+                        .iter()
+                        .flat_map(|maps| maps[index].iter().copied())
+                        .collect::<Vec<_>>();
+
+                    let npy_path = path.with_file_name(format!("{stem}_{name}.npy"));
+                    write_npy(
+                        &npy_path,
+                        &[self.sliding_maps.len(), height as usize, width as usize],
+                        &values,
+                    )?;
+                    written.push(npy_path);
+
+                    ranges.push(format!("{{ \"name\": {} }}", json_string(&name)));
+                }
+            }
+        }
+
+        if probe.is_some() {
+            let csv_path = path.with_file_name(format!("{stem}_spectrum.csv"));
+            let mut file = BufWriter::new(File::create(&csv_path)?);
+            writeln!(file, "window_start,frequency,power")?;
+
+            let frequencies = self.frequencies();
+            let windows = self.window_starts.len().max(1) as f32;
+            let spectra = match mode {
+                // The spectra of the windows averaged like the maps
+                FrequencyWindow::Whole if !self.probe_spectra.is_empty() => {
+                    vec![
+                        (0..frequencies.len())
+                            .map(|bin| {
+                                self.probe_spectra
+                                    .iter()
+                                    .map(|power| power[bin])
+                                    .sum::<f32>()
+                                    / windows
+                            })
+                            .collect::<Vec<_>>(),
+                    ]
+                }
+                FrequencyWindow::Whole => Vec::new(),
+                FrequencyWindow::Sliding { .. } => self.probe_spectra.clone(),
+            };
+
+            for (start, spectrum) in self.window_starts.iter().zip(spectra) {
+                for (frequency, power) in frequencies.iter().zip(spectrum) {
+                    writeln!(file, "{start},{frequency},{}", json_number(power))?;
+                }
+            }
+            file.flush()?;
+            written.push(csv_path);
+        }
+
+        let sidecar_path = path.with_file_name(format!("{stem}.json"));
+        std::fs::write(
+            &sidecar_path,
+            format!(
+                r#"{{
+  "dtype": "float32",
+  "width": {width},
+  "height": {height},
+  "binning": {binning},
+  "frames": {frames},
+  "frame_rate": {frame_rate},
+  "frequency_unit": {unit},
+  "window": {window},
+  "mode": {mode},
+  "window_starts": [{starts}],
+  "bands": [{bands}],
+  "probe": {probe},
+  "colormap": {colormap},
+  "maps": [
+    {maps}
+  ]
+}}
+"#,
+                frames = self.frames,
+                frame_rate = json_number(self.frame_rate().unwrap_or(f64::NAN)),
+                unit = json_string(match self.frame_rate() {
+                    Some(_) => "hz",
+                    None => "cycles_per_frame",
+                }),
+                mode = json_string(&format!("{mode:?}")),
+                starts = self
+                    .window_starts
+                    .iter()
+                    .map(|start| json_number(*start))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                bands = bands
+                    .iter()
+                    .map(|band| format!(
+                        "{{ \"name\": {}, \"low\": {}, \"high\": {} }}",
+                        json_string(&band.name()),
+                        json_number(band.low),
+                        json_number(band.high)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                probe = match probe {
+                    Some(probe) => json_string(&format!("{probe:?}")),
+                    None => String::from("null"),
+                },
+                colormap = json_string(&format!("{colormap:?}")),
+                maps = ranges.join(",\n    "),
+            ),
+        )?;
+        written.push(sidecar_path);
+
+        Ok(written)
+    }
+}
+
+/// Discrete Fourier transform of windows of a fixed length, straight from the definition like
+/// the prototype in `test_scripts/dft.py`, so windows don't have to be a power of two
+struct Dft {
+    window: Vec<f32>,
+    cos: Vec<f32>,
+    sin: Vec<f32>,
+    // Scale from the squared magnitude of a bin to the mean square it holds
+    normalization: f32,
+}
+
+impl Dft {
+    fn new(length: usize) -> Self {
+        let window = (0..length)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / length as f32).cos())
+            .collect::<Vec<_>>();
+        let window_power = window.iter().map(|value| value * value).sum::<f32>();
+
+        let (sin, cos) = (0..length)
+            .map(|n| (2.0 * PI * n as f32 / length as f32).sin_cos())
+            .unzip();
+
+        Self {
+            window,
+            cos,
+            sin,
+            normalization: 1.0 / (length as f32 * window_power.max(f32::EPSILON)),
+        }
+    }
+
+    /// One sided power spectrum of the detrended and windowed signal
+    fn power(&self, signal: &[f32]) -> Vec<f32> {
+        let length = signal.len();
+        let mean = signal.iter().sum::<f32>() / length as f32;
+        let samples = signal
+            .iter()
+            .zip(&self.window)
+            .map(|(value, window)| (value - mean) * window)
+            .collect::<Vec<_>>();
+
+        (0..=length / 2)
+            .map(|bin| {
+                let (mut re, mut im) = (0.0, 0.0);
+                for (n, sample) in samples.iter().enumerate() {
+                    let index = bin * n % length;
+                    re += sample * self.cos[index];
+                    im -= sample * self.sin[index];
+                }
+
+                // Every bin but zero and Nyquist also stands in for its negative frequency
+                let sides = match bin == 0 || 2 * bin == length {
+                    true => 1.0,
+                    false => 2.0,
+                };
+
+                (re * re + im * im) * self.normalization * sides
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_RATE: f64 = 40.0;
+    const WINDOW: usize = 32;
+
+    /// Amplitude of the sinusoid of the first pixel
+    const AMPLITUDE: f32 = 0.2;
+
+    /// Analyzer fed with 2x1 frames, the first pixel swings at the frequency and the second
+    /// one stays the same
+    fn analyzer(frequency: f32, mode: FrequencyWindow, frames: usize) -> FrequencyAnalyzer {
+        let mut analyzer = FrequencyAnalyzer::new(
+            FrequencyAnalysis::On {
+                window: WINDOW,
+                mode,
+                binning: 1,
+                bands: [
+                    FrequencyBand {
+                        low: 3.0,
+                        high: 7.0,
+                    },
+                    FrequencyBand {
+                        low: 10.0,
+                        high: 12.0,
+                    },
+                ]
+                .into(),
+                probe: None,
+            },
+            Rois::default(),
+        );
+
+        for frame in 0..frames {
+            let time = frame as f64 / FRAME_RATE;
+            let level = 0.5 + AMPLITUDE * (2.0 * PI * frequency * time as f32).sin();
+            let level = (level * 255.0).round() as u8;
+
+            analyzer.push(
+                2,
+                1,
+                &[level, level, level, 255, 128, 128, 128, 255],
+                Some(time),
+            );
+        }
+
+        analyzer
+    }
+
+    /// Maps of the whole recording by name
+    fn whole_maps(analyzer: &FrequencyAnalyzer) -> Vec<(String, Vec<f32>)> {
+        analyzer.maps(&analyzer.power_sum, 1.0 / analyzer.window_count() as f32)
+    }
+
+    #[test]
+    fn frame_rate_comes_from_the_timestamps() {
+        let analyzer = analyzer(5.0, FrequencyWindow::Whole, WINDOW);
+        assert!((analyzer.frame_rate().unwrap() - FRAME_RATE).abs() < 1e-9);
+
+        let frequencies = analyzer.frequencies();
+        assert_eq!(frequencies.len(), WINDOW / 2 + 1);
+        assert_eq!(frequencies[4], 5.0);
+    }
+
+    #[test]
+    fn sinusoids_are_found_in_hz() {
+        // Half overlapping windows of the whole recording
+        let analyzer = analyzer(5.0, FrequencyWindow::Whole, 4 * WINDOW);
+        assert_eq!(analyzer.window_count(), 7);
+
+        let maps = whole_maps(&analyzer);
+        let names = maps
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "dominant_frequency",
+                "dominant_power",
+                "band_3_7hz",
+                "band_10_12hz"
+            ]
+        );
+
+        assert_eq!(maps[0].1, [5.0, 0.0]);
+
+        // A sine of amplitude A has a power of A² / 2, spread over the band by the window
+        let power = AMPLITUDE * AMPLITUDE / 2.0;
+        let band = &maps[2].1;
+        assert!((band[0] - power).abs() < 0.02 * power, "{band:?}");
+        assert!(maps[1].1[0] < band[0] && maps[1].1[0] > 0.5 * power);
+        assert!(maps[3].1[0] < 1e-3 * power);
+
+        for (name, map) in &maps[1..] {
+            assert_eq!(map[1], 0.0, "{name}");
+        }
+    }
+
+    #[test]
+    fn sliding_windows_follow_the_frequency() {
+        let analyzer = analyzer(5.0, FrequencyWindow::Sliding { hop: 8 }, WINDOW + 16);
+        assert_eq!(analyzer.window_count(), 3);
+        assert_eq!(analyzer.window_starts, [0.0, 0.2, 0.4]);
+        assert!(
+            analyzer
+                .sliding_maps
+                .iter()
+                .all(|maps| maps[0] == [5.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn blocks_are_binned_to_their_mean() {
+        let mut analyzer = FrequencyAnalyzer::new(
+            FrequencyAnalysis::On {
+                window: 4,
+                mode: FrequencyWindow::Whole,
+                binning: 2,
+                bands: Arc::new([]),
+                probe: None,
+            },
+            Rois::default(),
+        );
+        analyzer.push(2, 1, &[255, 255, 255, 255, 0, 0, 0, 255], None);
+
+        assert_eq!(analyzer.map_dimensions(), (1, 1));
+        assert_eq!(analyzer.window_frames[0], [0.5]);
+    }
+}
@@ -0,0 +1,480 @@
+use std::{error::Error, fmt::Display};
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+mod amplification;
+mod calibration;
+mod colormap;
+mod cpu;
+mod decimation;
+mod denoise;
+mod events;
+mod export;
+mod fft;
+mod frequency;
+mod motion;
+mod roi;
+mod scene_cut;
+mod sensitivity;
+mod summary;
+mod tracking;
+mod transfer;
+
+pub use amplification::{
+    AMPLIFICATION_WEIGHTS, Amplification, MAX_AMPLIFICATION_LEVELS, pyramid_level_dimensions,
+};
+pub use calibration::{
+    CalibrationFrame, CalibrationMaps, HotPixelMap, MIN_FLAT_FIELD, SensorCalibration,
+    SensorCalibrator,
+};
+pub use colormap::{COLORMAP_SIZE, Colormap};
+pub use cpu::{CpuBackend, frame_intensities};
+pub use decimation::{DecimatedFrame, Decimation, FrameDecimator, Retiming};
+pub use denoise::{FrequencyDenoise, FrequencyDenoiser};
+pub use events::{
+    Blob, BoundingBox, EventDetection, EventDetector, EventLog, OVERLAY_COLOR, draw_blob_boxes,
+    label_blobs,
+};
+pub use export::{DiffExportFormat, DiffExporter, RoiSeriesExporter, RoiSeriesFormat};
+pub use frequency::{
+    FrequencyAnalysis, FrequencyAnalyzer, FrequencyBand, FrequencyWindow, SpectrumProbe,
+};
+pub use motion::{MOTION_WORKING_SIZE, Motion, MotionCompensation, MotionCompensator, warp_frame};
+pub use roi::{
+    MAX_ROIS, ROI_STATS_WORDS, ROI_SUM_RANGE, ROI_SUM_SCALE, Roi, RoiShape, RoiStats, Rois,
+};
+pub use scene_cut::{SceneCutDetection, SceneCutDetector};
+pub use sensitivity::{AutoSensitivity, HISTOGRAM_BINS, SensitivityEstimator, histogram_bin};
+pub use summary::{Summary, SummaryMaps};
+pub use tracking::{BlobTracker, MotExporter, TrackMatching, TrackedBlob, Tracking, iou};
+pub use transfer::{TRANSFER_LUT_SIZE, Transfer, TransferCurve};
+
+// Constants shared with the shaders
+/// Default gain of the transfer from diffs to display values
+pub const DIFF_SCALE: f32 = 5.0;
+pub const MAX_TEMPORAL_WINDOW: usize = 64;
+pub const DEFAULT_TEMPORAL_WINDOW: usize = 4;
+pub const MAX_SPATIAL_WINDOW: u32 = 31;
+
+/// Common interface for everything that can run DiPs on a stream of frames
+///
+/// Frames are tightly packed RGBA8 data in row major order
+pub trait DiPsBackend {
+    /// Sends a frame through the backend and returns the DiPs output frame
+    /// Returns None while the baseline is still being collected
+    fn process_frame(&mut self, width: u32, height: u32, frame: &[u8]) -> Option<Vec<u8>>;
+
+    /// Drops the current baseline so that it is retaken from the next full temporal window
+    fn refresh_baseline(&mut self);
+
+    /// Signed difference between the baseline and the last output frame, one float per pixel
+    /// in row major order, before the sensitivity filter and colorizing
+    /// Returns None until there has been an output frame
+    fn raw_diff(&self) -> Option<&[f32]>;
+
+    /// Gain of the transfer the last output frame was displayed with, which the auto
+    /// sensitivity keeps picking while it is on
+    fn gain(&self) -> f32;
+
+    /// Mean, min and max raw diff inside every region of interest of the last output frame, in
+    /// the order of the regions
+    /// Returns None without regions or like raw_diff
+    fn roi_stats(&self) -> Option<&[RoiStats]>;
+
+    /// Per pixel statistics of the raw diffs of every output frame so far, kept across
+    /// baseline refreshes
+    /// Returns None while the summary is off or before the first output frame
+    fn summary(&self) -> Option<&SummaryMaps>;
+
+    /// Global motion the last frame was warped back onto the reference by, identity for the
+    /// reference itself, which is the first frame of every baseline
+    /// Returns None while the motion compensation is off
+    fn motion(&self) -> Option<Motion>;
+
+    /// Checks that frames of the given size fit in the buffers of the backend before they are
+    /// sent through it, the CPU backend takes frames of any size
+    fn check_frame_size(&self, _width: u32, _height: u32) -> Result<(), WindowError> {
+        Ok(())
+    }
+}
+
+/// Which implementation of DiPs a driver should run
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Backend {
+    #[default]
+    Gpu,
+    Cpu,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Filter {
+    Unfiltered,
+    #[default]
+    Sigmoid,
+    InverseSigmoid,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ChromaFilter {
+    #[default]
+    All,
+    Red,
+    Green,
+    Blue,
+}
+
+/// Filter applied to every frame over the spatial window before the temporal window
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum SpatialFilter {
+    #[default]
+    Median,
+    Box,
+    /// Gaussian with the given standard deviation in pixels
+    Gaussian(f32),
+    /// Edge preserving Gaussian that also weighs neighbours by how close their intensity is
+    /// to the centre pixel
+    Bilateral {
+        spatial_sigma: f32,
+        range_sigma: f32,
+    },
+}
+
+impl SpatialFilter {
+    /// Value of the SPATIAL_FILTER shader override
+    pub fn shader_id(&self) -> u32 {
+        match self {
+            Self::Median => 0,
+            Self::Box => 1,
+            Self::Gaussian(_) => 2,
+            Self::Bilateral { .. } => 3,
+        }
+    }
+
+    /// Standard deviation in pixels of the Gaussian and bilateral filters
+    pub fn spatial_sigma(&self) -> f32 {
+        match self {
+            Self::Gaussian(sigma) => sigma.max(0.1),
+            Self::Bilateral { spatial_sigma, .. } => spatial_sigma.max(0.1),
+            _ => 1.0,
+        }
+    }
+
+    /// Standard deviation in intensity of the bilateral filter
+    pub fn range_sigma(&self) -> f32 {
+        match self {
+            Self::Bilateral { range_sigma, .. } => range_sigma.max(0.001),
+            _ => 1.0,
+        }
+    }
+}
+
+/// How the spatial filter reads pixels outside of the frame
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum BorderMode {
+    /// Repeats the edge pixels
+    #[default]
+    Clamp,
+    /// Reflects the frame about the edge pixels
+    Mirror,
+}
+
+impl BorderMode {
+    /// Value of the BORDER_MODE shader override
+    pub fn shader_id(&self) -> u32 {
+        match self {
+            Self::Clamp => 0,
+            Self::Mirror => 1,
+        }
+    }
+
+    /// Maps a coordinate outside of a dimension of the given size back inside
+    pub fn map(&self, coordinate: i64, size: i64) -> i64 {
+        let coordinate = match self {
+            Self::Clamp => coordinate,
+            Self::Mirror if coordinate < 0 => -coordinate,
+            Self::Mirror if coordinate >= size => 2 * (size - 1) - coordinate,
+            Self::Mirror => coordinate,
+        };
+
+        coordinate.clamp(0, size - 1)
+    }
+}
+
+/// How the frames of the temporal window are reduced to a single intensity
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum TemporalEstimator {
+    #[default]
+    Median,
+    Mean,
+    /// Mean after dropping the given fraction of the sorted values from each end
+    TrimmedMean(f32),
+    Min,
+    Max,
+    /// Percentile between 0 and 100, linearly interpolated between the sorted values
+    Percentile(f32),
+}
+
+impl TemporalEstimator {
+    /// Value of the TEMPORAL_ESTIMATOR shader override
+    pub fn shader_id(&self) -> u32 {
+        match self {
+            Self::Median => 0,
+            Self::Mean => 1,
+            Self::TrimmedMean(_) => 2,
+            Self::Min => 3,
+            Self::Max => 4,
+            Self::Percentile(_) => 5,
+        }
+    }
+
+    /// Value of the TEMPORAL_ESTIMATOR_PARAMETER shader override
+    pub fn parameter(&self) -> f32 {
+        match self {
+            Self::TrimmedMean(fraction) => fraction.clamp(0.0, 0.49),
+            Self::Percentile(percentile) => percentile.clamp(0.0, 100.0),
+            _ => 0.0,
+        }
+    }
+}
+
+/// How the baseline that every frame is compared against evolves over time
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum BaselineMode {
+    /// The baseline is taken once and only retaken on a refresh
+    #[default]
+    Fixed,
+    /// Exponential moving average with the given time constant in frames
+    ExponentialMovingAverage(f32),
+    /// Median of the temporally filtered frames over the given number of previous frames
+    SlidingMedian(usize),
+}
+
+impl BaselineMode {
+    /// Value of the BASELINE_MODE shader override
+    pub fn shader_id(&self) -> u32 {
+        match self {
+            Self::Fixed => 0,
+            Self::ExponentialMovingAverage(_) => 1,
+            Self::SlidingMedian(_) => 2,
+        }
+    }
+
+    /// Weight of the newest frame in the exponential moving average
+    pub fn alpha(&self) -> f32 {
+        match self {
+            Self::ExponentialMovingAverage(time_constant) => {
+                1.0 - (-1.0 / time_constant.max(1.0)).exp()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Number of previous frames kept for the sliding median
+    pub fn window(&self) -> usize {
+        match self {
+            Self::SlidingMedian(frames) => (*frames).max(1),
+            _ => 1,
+        }
+    }
+}
+
+/// What every temporally filtered frame is compared against
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum DifferenceMode {
+    /// Every frame is compared against the baseline
+    #[default]
+    Baseline,
+    /// Every frame is compared against the previous frame
+    FrameToFrame,
+    /// Frame to frame changes summed up over time, leaking away with the given time
+    /// constant in frames so that slow transitions stay visible
+    AccumulatedFrameToFrame(f32),
+}
+
+impl DifferenceMode {
+    /// Value of the DIFFERENCE_MODE shader override
+    pub fn shader_id(&self) -> u32 {
+        match self {
+            Self::Baseline => 0,
+            Self::FrameToFrame => 1,
+            Self::AccumulatedFrameToFrame(_) => 2,
+        }
+    }
+
+    /// Fraction of the accumulated changes kept from one frame to the next
+    pub fn decay(&self) -> f32 {
+        match self {
+            Self::AccumulatedFrameToFrame(time_constant) => (-1.0 / time_constant.max(1.0)).exp(),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Parameters of the DiPs algorithm that are shared between every backend
+#[derive(Debug, Clone)]
+pub struct DiPsParameters {
+    pub colorize: bool,
+    pub colormap: Colormap,
+    pub spatial_window_size: u32,
+    pub spatial_filter: SpatialFilter,
+    pub border_mode: BorderMode,
+    pub sigmoid_horizontal_scalar: f32,
+    pub filter_type: Filter,
+    pub chroma_filter: ChromaFilter,
+    pub temporal_window: usize,
+    pub temporal_estimator: TemporalEstimator,
+    pub baseline_mode: BaselineMode,
+    pub difference_mode: DifferenceMode,
+    pub transfer: Transfer,
+    pub auto_sensitivity: AutoSensitivity,
+    pub rois: Rois,
+    pub summary: Summary,
+    pub motion_compensation: MotionCompensation,
+    pub denoise: FrequencyDenoise,
+    pub calibration: SensorCalibration,
+}
+
+impl Default for DiPsParameters {
+    fn default() -> Self {
+        Self {
+            colorize: true,
+            colormap: Colormap::default(),
+            spatial_window_size: 1,
+            spatial_filter: SpatialFilter::default(),
+            border_mode: BorderMode::default(),
+            sigmoid_horizontal_scalar: 5.0,
+            filter_type: Filter::default(),
+            chroma_filter: ChromaFilter::default(),
+            temporal_window: DEFAULT_TEMPORAL_WINDOW,
+            temporal_estimator: TemporalEstimator::default(),
+            baseline_mode: BaselineMode::default(),
+            difference_mode: DifferenceMode::default(),
+            transfer: Transfer::default(),
+            auto_sensitivity: AutoSensitivity::default(),
+            rois: Rois::default(),
+            summary: Summary::default(),
+            motion_compensation: MotionCompensation::default(),
+            denoise: FrequencyDenoise::default(),
+            calibration: SensorCalibration::default(),
+        }
+    }
+}
+
+impl DiPsParameters {
+    /// Raw signed diff that the transfer turned into the given display value
+    ///
+    /// The display value of a grey output pixel is `0.5 - pixel / 255`. Diffs outside of the
+    /// clamp range come back as the nearest end of it, and display values that were clipped
+    /// to the output range can't be told apart
+    pub fn diff_from_display(&self, display: f32) -> f32 {
+        let filtered = (display - self.transfer.offset) / self.transfer.gain;
+
+        let shaped = match self.filter_type {
+            Filter::Sigmoid => cpu::inv_sigmoid(filtered, self.sigmoid_horizontal_scalar),
+            Filter::InverseSigmoid => cpu::sigmoid(filtered, self.sigmoid_horizontal_scalar),
+            Filter::Unfiltered => filtered,
+        } * 2.0;
+
+        self.transfer.clamp(self.transfer.curve.invert(shaped))
+    }
+}
+
+/// Checks that the temporal window fits in the number of frames a backend can hold
+pub fn check_temporal_window(
+    temporal_window: usize,
+    max_temporal_window: usize,
+) -> Result<(), WindowError> {
+    if temporal_window == 0 || temporal_window > max_temporal_window {
+        return Err(WindowError::Temporal {
+            window: temporal_window,
+            max: max_temporal_window,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that the spatial window is odd so that it can be centred on every pixel and that it
+/// fits in the window the shaders can hold
+pub fn check_spatial_window(window_size: u32) -> Result<(), WindowError> {
+    if window_size.is_multiple_of(2) || window_size > MAX_SPATIAL_WINDOW {
+        return Err(WindowError::Spatial {
+            window: window_size,
+            max: MAX_SPATIAL_WINDOW,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that the sliding median baseline holds no more frames than the shaders can index
+///
+/// Whether a window that passes fits on a device also depends on the frame size, see
+/// [`check_baseline_buffer`]
+pub fn check_baseline_window(baseline_mode: BaselineMode) -> Result<(), WindowError> {
+    if baseline_mode.window() > MAX_TEMPORAL_WINDOW {
+        return Err(WindowError::Baseline {
+            window: baseline_mode.window(),
+            max: MAX_TEMPORAL_WINDOW,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that the sliding median baseline history of frames of the given size fits in the
+/// largest storage buffer the device can bind
+pub fn check_baseline_buffer(
+    width: u32,
+    height: u32,
+    baseline_mode: BaselineMode,
+    max_buffer_size: u64,
+) -> Result<(), WindowError> {
+    let frame_size = width as u64 * height as u64 * std::mem::size_of::<f32>() as u64;
+    let window = baseline_mode.window();
+
+    if frame_size * window as u64 > max_buffer_size {
+        return Err(WindowError::BaselineBuffer {
+            window,
+            max: (max_buffer_size / frame_size.max(1)) as usize,
+        });
+    }
+
+    Ok(())
+}
+
+// Custom Error Types
+#[derive(Debug)]
+pub enum WindowError {
+    Temporal { window: usize, max: usize },
+    Baseline { window: usize, max: usize },
+    BaselineBuffer { window: usize, max: usize },
+    Spatial { window: u32, max: u32 },
+}
+
+impl Error for WindowError {}
+
+impl Display for WindowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Temporal { window, max } => write!(
+                f,
+                "Temporal window of {window} frames is not supported, it must be between 1 and {max} frames"
+            ),
+            Self::Baseline { window, max } => write!(
+                f,
+                "Sliding median baseline of {window} frames is not supported, it can be at most {max} frames and fewer for frames too large to keep that many of on the GPU"
+            ),
+            Self::BaselineBuffer { window, max } => write!(
+                f,
+                "Sliding median baseline of {window} frames doesn't fit in a GPU buffer at this frame size, the device can keep at most {max} frames of it"
+            ),
+            Self::Spatial { window, max } => write!(
+                f,
+                "Spatial window of {window} pixels is not supported, it must be odd and at most {max} pixels"
+            ),
+        }
+    }
+}
@@ -0,0 +1,607 @@
+use std::f32::consts::PI;
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+use crate::fft::{self, Complex};
+
+/// Side of the square, power of two, greyscale images the motion is estimated on
+pub const MOTION_WORKING_SIZE: usize = 256;
+
+/// Estimated motions with a weaker correlation peak than this are treated as no motion, so
+/// flat or noisy frames aren't warped around at random
+const MIN_RESPONSE: f32 = 0.02;
+
+/// Registers every frame against the reference frame of the baseline before it is differenced
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum MotionCompensation {
+    #[default]
+    Off,
+    /// Estimates a global shift with phase correlation
+    Translation,
+    /// Also estimates a rotation and scale about the centre of the frame from the log polar
+    /// magnitude spectrum before the shift
+    Similarity,
+}
+
+impl MotionCompensation {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+}
+
+/// Global motion of a frame against the reference frame
+///
+/// A point p of the reference is found at `scale * rotate(p - centre, rotation) + centre +
+/// (dx, dy)` in the frame, in pixels with y pointing down
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Motion {
+    pub dx: f32,
+    pub dy: f32,
+    /// Rotation in radians, clockwise on screen
+    pub rotation: f32,
+    pub scale: f32,
+    /// Height of the phase correlation peak of the shift, from 0 to 1
+    pub response: f32,
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Self {
+            dx: 0.0,
+            dy: 0.0,
+            rotation: 0.0,
+            scale: 1.0,
+            response: 1.0,
+        }
+    }
+}
+
+impl Motion {
+    pub fn is_identity(&self) -> bool {
+        self.dx == 0.0 && self.dy == 0.0 && self.rotation == 0.0 && self.scale == 1.0
+    }
+
+    /// Where the point (x, y) of the reference is in the frame
+    pub fn map(&self, (x, y): (f32, f32), (centre_x, centre_y): (f32, f32)) -> (f32, f32) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let (x, y) = (x - centre_x, y - centre_y);
+
+        (
+            self.scale * (cos * x - sin * y) + centre_x + self.dx,
+            self.scale * (sin * x + cos * y) + centre_y + self.dy,
+        )
+    }
+}
+
+/// Estimates the motion of every frame against a reference and warps the frame back onto it
+///
+/// The first frame after a reset becomes the reference, the backends reset whenever their
+/// baseline is retaken so the frames are registered against the start of the baseline
+#[derive(Debug, Clone)]
+pub struct MotionCompensator {
+    mode: MotionCompensation,
+
+    dimensions: (u32, u32),
+    reference: Option<Reference>,
+    motion: Option<Motion>,
+}
+
+// Spectra of the reference frame
+#[derive(Debug, Clone)]
+struct Reference {
+    spectrum: Vec<Complex>,
+    log_polar_spectrum: Vec<Complex>,
+}
+
+impl MotionCompensator {
+    pub fn new(mode: MotionCompensation) -> Self {
+        Self {
+            mode,
+            dimensions: (0, 0),
+            reference: None,
+            motion: None,
+        }
+    }
+
+    /// Takes the next frame as the new reference
+    pub fn reset(&mut self) {
+        self.reference = None;
+    }
+
+    /// Motion of the last frame, None while the compensation is off
+    pub fn motion(&self) -> Option<Motion> {
+        self.motion
+    }
+
+    /// Estimates the motion of a frame of float intensities and returns it warped onto the
+    /// reference
+    ///
+    /// Returns None while the compensation is off, for the reference frame itself and when
+    /// there is no motion, in which case the intensities can be used as they are
+    pub fn compensate(&mut self, width: u32, height: u32, intensities: &[f32]) -> Option<Vec<f32>> {
+        if !self.mode.is_on() || intensities.len() != (width * height) as usize {
+            self.motion = None;
+            return None;
+        }
+
+        if self.dimensions != (width, height) {
+            self.dimensions = (width, height);
+            self.reference = None;
+        }
+
+        let image = working_image(width, height, intensities);
+
+        let Some(reference) = self.reference.as_ref() else {
+            let spectrum = fft_2d(&image, false);
+            let log_polar_spectrum = match self.mode {
+                MotionCompensation::Similarity => fft_2d(&log_polar(&spectrum), false),
+                _ => Vec::new(),
+            };
+
+            self.reference = Some(Reference {
+                spectrum,
+                log_polar_spectrum,
+            });
+            self.motion = Some(Motion::default());
+
+            return None;
+        };
+
+        // The frame is shrunk evenly so the centre of the frame stays the centre of rotation
+        let factor = working_factor(width, height) as f32;
+        let centre = (width as f32 * 0.5 / factor, height as f32 * 0.5 / factor);
+
+        let mut motion = match self.mode {
+            MotionCompensation::Similarity => estimate_similarity(reference, &image, centre),
+            _ => {
+                let ((dx, dy), response) =
+                    phase_correlation(&reference.spectrum, &fft_2d(&image, false));
+
+                Motion {
+                    dx,
+                    dy,
+                    response,
+                    ..Default::default()
+                }
+            }
+        };
+
+        if motion.response < MIN_RESPONSE {
+            motion = Motion {
+                response: motion.response,
+                ..Default::default()
+            };
+        }
+
+        // Back to pixels of the frame
+        motion.dx *= factor;
+        motion.dy *= factor;
+        self.motion = Some(motion);
+
+        (!motion.is_identity()).then(|| warp_frame(intensities, width, height, &motion))
+    }
+}
+
+/// Samples float intensities at the positions the motion maps every pixel of the reference to,
+/// with bilinear filtering and the edges clamped
+pub fn warp_frame(intensities: &[f32], width: u32, height: u32, motion: &Motion) -> Vec<f32> {
+    let (width, height) = (width as usize, height as usize);
+    let centre = (width as f32 * 0.5, height as f32 * 0.5);
+
+    (0..width * height)
+        .map(|index| {
+            let (x, y) = ((index % width) as f32 + 0.5, (index / width) as f32 + 0.5);
+            let (x, y) = motion.map((x, y), centre);
+
+            let [value] = sample_bilinear(width, height, (x - 0.5, y - 0.5), |offset| {
+                [intensities[offset]]
+            });
+
+            value
+        })
+        .collect()
+}
+
+/// Bilinear sample of an interleaved image at (x, y) in pixel indices, clamped to the edges
+fn sample_bilinear<const N: usize, F>(
+    width: usize,
+    height: usize,
+    (x, y): (f32, f32),
+    fetch: F,
+) -> [f32; N]
+where
+    F: Fn(usize) -> [f32; N],
+{
+    let x = x.clamp(0.0, (width - 1) as f32);
+    let y = y.clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let [a, b, c, d] =
+        [(x0, y0), (x1, y0), (x0, y1), (x1, y1)].map(|(x, y)| fetch((y * width + x) * N));
+
+    std::array::from_fn(|channel| {
+        let top = a[channel] + (b[channel] - a[channel]) * fx;
+        let bottom = c[channel] + (d[channel] - c[channel]) * fx;
+
+        top + (bottom - top) * fy
+    })
+}
+
+/// Factor the frame is shrunk by to fit the working size
+fn working_factor(width: u32, height: u32) -> usize {
+    (width.max(height) as usize)
+        .div_ceil(MOTION_WORKING_SIZE)
+        .max(1)
+}
+
+/// Box filtered, mean removed and Hann windowed intensities of a frame in the corner of a zero
+/// padded working image
+fn working_image(width: u32, height: u32, intensities: &[f32]) -> Vec<f32> {
+    let factor = working_factor(width, height);
+    let (width, height) = (width as usize, height as usize);
+    let (small_width, small_height) = (width / factor, height / factor);
+
+    let mut image = vec![0.0; MOTION_WORKING_SIZE * MOTION_WORKING_SIZE];
+    for y in 0..small_height {
+        for x in 0..small_width {
+            let mut sum = 0.0;
+            for fy in 0..factor {
+                for fx in 0..factor {
+                    sum += intensities[(y * factor + fy) * width + x * factor + fx];
+                }
+            }
+
+            image[y * MOTION_WORKING_SIZE + x] = sum / (factor * factor) as f32;
+        }
+    }
+
+    let count = (small_width * small_height).max(1) as f32;
+    let mean = image.iter().sum::<f32>() / count;
+
+    for y in 0..small_height {
+        let window_y = hann(y, small_height);
+        for x in 0..small_width {
+            image[y * MOTION_WORKING_SIZE + x] =
+                (image[y * MOTION_WORKING_SIZE + x] - mean) * window_y * hann(x, small_width);
+        }
+    }
+
+    image
+}
+
+fn hann(index: usize, size: usize) -> f32 {
+    0.5 - 0.5 * (2.0 * PI * (index as f32 + 0.5) / size as f32).cos()
+}
+
+/// Estimates rotation and scale from the log polar magnitude spectra, then the shift of the
+/// frame turned back by them
+fn estimate_similarity(reference: &Reference, image: &[f32], centre: (f32, f32)) -> Motion {
+    let spectrum = fft_2d(image, false);
+    let ((angle, log_radius), _) = phase_correlation(
+        &reference.log_polar_spectrum,
+        &fft_2d(&log_polar(&spectrum), false),
+    );
+
+    let rotation = angle * PI / MOTION_WORKING_SIZE as f32;
+    let scale = (-log_radius * log_radius_step()).exp();
+
+    // The magnitude spectrum can't tell a rotation from the same rotation plus half a turn
+    [rotation, rotation + PI]
+        .into_iter()
+        .map(|rotation| {
+            let rotation = (rotation + PI).rem_euclid(2.0 * PI) - PI;
+            let turned = Motion {
+                rotation,
+                scale,
+                ..Default::default()
+            };
+
+            // The frame turned back by the rotation and scale is the reference shifted by the
+            // rotated and scaled shift
+            let aligned = warp_working_image(image, &turned, centre);
+            let ((ux, uy), response) =
+                phase_correlation(&reference.spectrum, &fft_2d(&aligned, false));
+            let (sin, cos) = rotation.sin_cos();
+
+            Motion {
+                dx: scale * (cos * ux - sin * uy),
+                dy: scale * (sin * ux + cos * uy),
+                rotation,
+                scale,
+                response,
+            }
+        })
+        .max_by(|a, b| a.response.total_cmp(&b.response))
+        .unwrap_or_default()
+}
+
+/// Samples a working image at the positions the motion maps every pixel to
+fn warp_working_image(image: &[f32], motion: &Motion, centre: (f32, f32)) -> Vec<f32> {
+    (0..image.len())
+        .map(|index| {
+            let (x, y) = (
+                (index % MOTION_WORKING_SIZE) as f32 + 0.5,
+                (index / MOTION_WORKING_SIZE) as f32 + 0.5,
+            );
+            let (x, y) = motion.map((x, y), centre);
+
+            let [value] = sample_bilinear(
+                MOTION_WORKING_SIZE,
+                MOTION_WORKING_SIZE,
+                (x - 0.5, y - 0.5),
+                |offset| [image[offset]],
+            );
+
+            value
+        })
+        .collect()
+}
+
+/// Change of the natural log of the radius between rows of the log polar image
+fn log_radius_step() -> f32 {
+    (MOTION_WORKING_SIZE as f32 * 0.5).ln() / MOTION_WORKING_SIZE as f32
+}
+
+/// High pass filtered magnitude of a spectrum resampled to a log polar grid, with the angle
+/// from 0 to half a turn along x and the log of the radius along y
+fn log_polar(spectrum: &[Complex]) -> Vec<f32> {
+    let size = MOTION_WORKING_SIZE;
+    let half = (size / 2) as isize;
+
+    // Magnitude with the zero frequency moved to the centre, the high pass keeps the low
+    // frequencies of the image window from dominating
+    let mut magnitude = vec![0.0; size * size];
+    for v in 0..size {
+        for u in 0..size {
+            let (fu, fv) = (
+                (u as isize + half) % size as isize,
+                (v as isize + half) % size as isize,
+            );
+            let x = (u as f32 / size as f32 - 0.5) * PI;
+            let y = (v as f32 / size as f32 - 0.5) * PI;
+            let emphasis = 1.0 - x.cos() * y.cos();
+
+            magnitude[v * size + u] =
+                spectrum[fv as usize * size + fu as usize].norm() * emphasis * (2.0 - emphasis);
+        }
+    }
+
+    let step = log_radius_step();
+    let mut polar = vec![0.0; size * size];
+    for row in 0..size {
+        let radius = (row as f32 * step).exp();
+
+        for column in 0..size {
+            let angle = column as f32 * PI / size as f32;
+            let (sin, cos) = angle.sin_cos();
+
+            let [value] = sample_bilinear(
+                size,
+                size,
+                (half as f32 + radius * cos, half as f32 + radius * sin),
+                |offset| [magnitude[offset]],
+            );
+            polar[row * size + column] = value;
+        }
+    }
+
+    polar
+}
+
+/// Shift of the image of the second spectrum against the first and the height of the
+/// correlation peak, refined to sub pixels with a parabola through its neighbours
+fn phase_correlation(reference: &[Complex], spectrum: &[Complex]) -> ((f32, f32), f32) {
+    let cross_power = spectrum
+        .iter()
+        .zip(reference)
+        .map(|(a, b)| {
+            let product = a.mul(b.conj());
+            let norm = product.norm();
+
+            match norm > f32::EPSILON {
+                true => product.scale(1.0 / norm),
+                false => Complex::default(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let size = MOTION_WORKING_SIZE;
+    let correlation = fft_complex_2d(cross_power, true)
+        .iter()
+        .map(|value| value.re)
+        .collect::<Vec<_>>();
+
+    let (peak, response) = correlation
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0));
+    let (px, py) = (peak % size, peak / size);
+
+    let at = |x: usize, y: usize| correlation[(y % size) * size + x % size];
+    let refine = |before: f32, after: f32| {
+        let curvature = before - 2.0 * response + after;
+        match curvature.abs() > f32::EPSILON {
+            true => (0.5 * (before - after) / curvature).clamp(-0.5, 0.5),
+            false => 0.0,
+        }
+    };
+    let offset_x = refine(at(px + size - 1, py), at(px + 1, py));
+    let offset_y = refine(at(px, py + size - 1), at(px, py + 1));
+
+    // Shifts past half the size wrap around to negative ones
+    let signed = |position: usize, offset: f32| match position > size / 2 {
+        true => position as f32 - size as f32 + offset,
+        false => position as f32 + offset,
+    };
+
+    ((signed(px, offset_x), signed(py, offset_y)), response)
+}
+
+/// 2D FFT of a real working image
+fn fft_2d(image: &[f32], inverse: bool) -> Vec<Complex> {
+    fft_complex_2d(image.iter().copied().map(Complex::real).collect(), inverse)
+}
+
+/// 2D FFT of a working image of complex values, the inverse is scaled by 1 / size²
+fn fft_complex_2d(values: Vec<Complex>, inverse: bool) -> Vec<Complex> {
+    fft::fft_2d(values, MOTION_WORKING_SIZE, MOTION_WORKING_SIZE, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = MOTION_WORKING_SIZE as u32;
+
+    /// Gaussian blobs spread over the frame with no symmetry
+    fn pattern(x: f32, y: f32) -> f32 {
+        let mut seed = 12345u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+
+        (0..48)
+            .map(|_| {
+                let (bx, by) = (next() * SIZE as f32, next() * SIZE as f32);
+                let (sigma, weight) = (3.0 + next() * 6.0, next());
+                weight * (-((x - bx).powi(2) + (y - by).powi(2)) / (2.0 * sigma * sigma)).exp()
+            })
+            .sum()
+    }
+
+    /// Frame the pattern moved by the motion, every pixel samples the point of the reference
+    /// the motion maps onto it
+    fn moved_frame(motion: &Motion) -> Vec<f32> {
+        let centre = (SIZE as f32 * 0.5, SIZE as f32 * 0.5);
+        let (sin, cos) = motion.rotation.sin_cos();
+
+        (0..SIZE * SIZE)
+            .map(|index| {
+                let (x, y) = ((index % SIZE) as f32 + 0.5, (index / SIZE) as f32 + 0.5);
+                let (x, y) = (
+                    (x - centre.0 - motion.dx) / motion.scale,
+                    (y - centre.1 - motion.dy) / motion.scale,
+                );
+
+                pattern(cos * x + sin * y + centre.0, -sin * x + cos * y + centre.1)
+            })
+            .collect()
+    }
+
+    fn estimated_motion(mode: MotionCompensation, motion: &Motion) -> Motion {
+        let mut compensator = MotionCompensator::new(mode);
+        assert!(
+            compensator
+                .compensate(SIZE, SIZE, &moved_frame(&Motion::default()))
+                .is_none()
+        );
+
+        compensator.compensate(SIZE, SIZE, &moved_frame(motion));
+        compensator.motion().unwrap()
+    }
+
+    #[test]
+    fn circular_shifts_are_recovered() {
+        let reference = working_image(SIZE, SIZE, &moved_frame(&Motion::default()));
+        let shifted = (0..reference.len())
+            .map(|index| {
+                let (x, y) = (index % MOTION_WORKING_SIZE, index / MOTION_WORKING_SIZE);
+                let (x, y) = (
+                    (x + MOTION_WORKING_SIZE - 9) % MOTION_WORKING_SIZE,
+                    (y + 5) % MOTION_WORKING_SIZE,
+                );
+                reference[y * MOTION_WORKING_SIZE + x]
+            })
+            .collect::<Vec<_>>();
+
+        let ((dx, dy), response) =
+            phase_correlation(&fft_2d(&reference, false), &fft_2d(&shifted, false));
+        assert!(
+            (dx - 9.0).abs() < 1e-3 && (dy + 5.0).abs() < 1e-3,
+            "{dx} {dy}"
+        );
+        assert!(response > MIN_RESPONSE, "{response}");
+    }
+
+    #[test]
+    fn shifts_are_recovered_and_warped_back() {
+        let motion = Motion {
+            dx: 6.3,
+            dy: -4.6,
+            ..Default::default()
+        };
+        let estimated = estimated_motion(MotionCompensation::Translation, &motion);
+        assert!((estimated.dx - motion.dx).abs() < 0.25, "{estimated:?}");
+        assert!((estimated.dy - motion.dy).abs() < 0.25, "{estimated:?}");
+        assert_eq!((estimated.rotation, estimated.scale), (0.0, 1.0));
+
+        let warped = warp_frame(&moved_frame(&motion), SIZE, SIZE, &estimated);
+        let reference = moved_frame(&Motion::default());
+        for y in 16..SIZE as usize - 16 {
+            for x in 16..SIZE as usize - 16 {
+                let index = y * SIZE as usize + x;
+                assert!((warped[index] - reference[index]).abs() < 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn rotations_and_scales_are_recovered() {
+        let motion = Motion {
+            dx: 3.0,
+            dy: -2.0,
+            rotation: 10f32.to_radians(),
+            scale: 1.1,
+            ..Default::default()
+        };
+        let estimated = estimated_motion(MotionCompensation::Similarity, &motion);
+        assert!(
+            (estimated.rotation - motion.rotation).abs() < 1f32.to_radians(),
+            "{estimated:?}"
+        );
+        assert!(
+            (estimated.scale - motion.scale).abs() < 0.03,
+            "{estimated:?}"
+        );
+        assert!((estimated.dx - motion.dx).abs() < 1.0, "{estimated:?}");
+        assert!((estimated.dy - motion.dy).abs() < 1.0, "{estimated:?}");
+    }
+
+    #[test]
+    fn half_turns_are_told_apart() {
+        // The magnitude spectrum of a rotation by 170° is the one of a rotation by -10°
+        let motion = Motion {
+            rotation: 170f32.to_radians(),
+            ..Default::default()
+        };
+        let estimated = estimated_motion(MotionCompensation::Similarity, &motion);
+        assert!(
+            (estimated.rotation - motion.rotation).abs() < 1f32.to_radians(),
+            "{estimated:?}"
+        );
+        assert!((estimated.scale - 1.0).abs() < 0.03, "{estimated:?}");
+    }
+
+    #[test]
+    fn weak_correlations_are_no_motion() {
+        let mut compensator = MotionCompensator::new(MotionCompensation::Translation);
+        compensator.compensate(SIZE, SIZE, &moved_frame(&Motion::default()));
+
+        // A flat frame has nothing to correlate with the reference
+        let flat = vec![0.5; (SIZE * SIZE) as usize];
+        assert!(compensator.compensate(SIZE, SIZE, &flat).is_none());
+
+        let motion = compensator.motion().unwrap();
+        assert!(
+            motion.response < MIN_RESPONSE && motion.is_identity(),
+            "{motion:?}"
+        );
+    }
+}
@@ -0,0 +1,597 @@
+use std::{
+    fmt::Debug,
+    io::{self, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+/// Most regions of interest a run can have, every pixel keeps one bit per region
+pub const MAX_ROIS: usize = 32;
+
+/// Words of the statistics the GPU collects for every region: the low and high word of the
+/// fixed point sum of the biased diffs, and the order preserving bits of the min and max diff
+pub const ROI_STATS_WORDS: usize = 4;
+/// Diffs are clamped to plus or minus this range and biased by it before they are summed on
+/// the GPU, so that every term of the sum is positive
+pub const ROI_SUM_RANGE: f32 = 8.0;
+/// Fixed point scale of the diffs summed on the GPU
+pub const ROI_SUM_SCALE: f32 = 65536.0;
+
+/// Shape of a region of interest in pixels of the frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoiShape {
+    Rectangle {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Closed polygon through the given (x, y) points, pixels count when their centre is inside
+    Polygon(Vec<(f32, f32)>),
+    /// Binary mask, scaled to the frame with the nearest pixel when the sizes differ
+    Mask {
+        width: u32,
+        height: u32,
+        pixels: Vec<bool>,
+    },
+}
+
+impl RoiShape {
+    /// Mask from an 8 bit greyscale image where every non zero pixel is inside
+    pub fn mask_from_luma(width: u32, height: u32, luma: &[u8]) -> io::Result<Self> {
+        if luma.len() != (width * height) as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "A {width}x{height} mask needs {} pixels but got {}",
+                    width * height,
+                    luma.len()
+                ),
+            ));
+        }
+
+        Ok(Self::Mask {
+            width,
+            height,
+            pixels: luma.iter().map(|value| *value != 0).collect(),
+        })
+    }
+
+    /// Whether the pixel at (x, y) of a frame of the given size is inside the shape
+    pub fn contains(&self, (x, y): (u32, u32), (frame_width, frame_height): (u32, u32)) -> bool {
+        match self {
+            Self::Rectangle {
+                x: left,
+                y: top,
+                width,
+                height,
+            } => {
+                // Rectangles reaching past the largest coordinate are cut off there
+                (*left..left.saturating_add(*width)).contains(&x)
+                    && (*top..top.saturating_add(*height)).contains(&y)
+            }
+            Self::Polygon(points) => {
+                // Even odd rule on the centre of the pixel
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let mut inside = false;
+
+                for (index, &(x1, y1)) in points.iter().enumerate() {
+                    let (x0, y0) = points[(index + points.len() - 1) % points.len()];
+
+                    if (y0 > py) != (y1 > py) && px < x0 + (py - y0) / (y1 - y0) * (x1 - x0) {
+                        inside = !inside;
+                    }
+                }
+
+                inside
+            }
+            Self::Mask {
+                width,
+                height,
+                pixels,
+            } => {
+                let mx = (x as u64 * *width as u64 / frame_width.max(1) as u64) as usize;
+                let my = (y as u64 * *height as u64 / frame_height.max(1) as u64) as usize;
+
+                pixels
+                    .get(my * *width as usize + mx)
+                    .copied()
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
+
+/// Named region of interest the diffs are summarised over
+#[derive(Debug, Clone, PartialEq)]
+pub struct Roi {
+    pub name: String,
+    pub shape: RoiShape,
+}
+
+impl Roi {
+    pub fn new<S: AsRef<str>>(name: S, shape: RoiShape) -> Self {
+        Self {
+            name: String::from(name.as_ref()),
+            shape,
+        }
+    }
+
+    /// Loads regions from a text file with one region per line
+    ///
+    /// `<name> rect <x> <y> <width> <height>` for a rectangle and
+    /// `<name> polygon <x> <y> <x> <y> ...` for a polygon of at least three points, values are
+    /// separated by spaces or commas and `#` starts a comment
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Self>> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+
+        let mut rois = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let fields = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|field| !field.is_empty())
+                .collect::<Vec<_>>();
+
+            let invalid = |message: String| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Line {} of the ROIs: {message}", number + 1),
+                )
+            };
+
+            let (name, kind, values) = match fields.as_slice() {
+                [] => continue,
+                [name, kind, values @ ..] => (name, kind, values),
+                _ => return Err(invalid(String::from("expected a name and a shape"))),
+            };
+
+            let shape = match *kind {
+                "rect" => {
+                    let values = values
+                        .iter()
+                        .map(|value| value.parse::<u32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|err| invalid(err.to_string()))?;
+
+                    match values.as_slice() {
+                        [x, y, width, height] => RoiShape::Rectangle {
+                            x: *x,
+                            y: *y,
+                            width: *width,
+                            height: *height,
+                        },
+                        _ => {
+                            return Err(invalid(format!(
+                                "a rectangle needs x, y, width and height but got {} values",
+                                values.len()
+                            )));
+                        }
+                    }
+                }
+                "polygon" => {
+                    let values = values
+                        .iter()
+                        .map(|value| value.parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|err| invalid(err.to_string()))?;
+
+                    if values.len() < 6 || values.len() % 2 != 0 {
+                        return Err(invalid(format!(
+                            "a polygon needs x and y of at least three points but got {} values",
+                            values.len()
+                        )));
+                    }
+
+                    RoiShape::Polygon(
+                        values
+                            .chunks_exact(2)
+                            .map(|point| (point[0], point[1]))
+                            .collect(),
+                    )
+                }
+                kind => return Err(invalid(format!("unknown shape {kind}"))),
+            };
+
+            rois.push(Self::new(name, shape));
+        }
+
+        Ok(rois)
+    }
+}
+
+/// Regions of interest every output frame is summarised over
+#[derive(Default, Clone, PartialEq)]
+pub struct Rois {
+    pub regions: Arc<[Roi]>,
+    /// Shows the grey frame instead of the diffs outside of every region
+    pub grey_outside: bool,
+}
+
+impl Debug for Rois {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rois")
+            .field(
+                "regions",
+                &self.regions.iter().map(|roi| &roi.name).collect::<Vec<_>>(),
+            )
+            .field("grey_outside", &self.grey_outside)
+            .finish()
+    }
+}
+
+impl Rois {
+    pub fn new(regions: Vec<Roi>, grey_outside: bool) -> io::Result<Self> {
+        if regions.len() > MAX_ROIS {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} regions of interest is more than the {MAX_ROIS} supported",
+                    regions.len()
+                ),
+            ));
+        }
+
+        Ok(Self {
+            regions: regions.into(),
+            grey_outside,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// Bit mask of the regions every pixel of a frame of the given size is in, in row major
+    /// order with bit i for the region i
+    pub fn mask(&self, width: u32, height: u32) -> Vec<u32> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|pixel| {
+                self.regions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, roi)| roi.shape.contains(pixel, (width, height)))
+                    .fold(0, |mask, (index, _)| mask | 1 << index)
+            })
+            .collect()
+    }
+
+    /// Number of pixels in every region of a mask
+    pub fn pixel_counts(&self, mask: &[u32]) -> Vec<usize> {
+        (0..self.len())
+            .map(|index| mask.iter().filter(|bits| *bits & 1 << index != 0).count())
+            .collect()
+    }
+
+    /// Statistics of the diffs in every region of a mask
+    pub fn stats(&self, mask: &[u32], diffs: &[f32]) -> Vec<RoiStats> {
+        (0..self.len())
+            .map(|index| {
+                let (mut sum, mut min, mut max, mut pixels) = (0.0, f32::NAN, f32::NAN, 0);
+
+                for (_, diff) in mask
+                    .iter()
+                    .zip(diffs)
+                    .filter(|(bits, _)| *bits & 1 << index != 0)
+                {
+                    sum += *diff as f64;
+                    min = diff.min(min);
+                    max = diff.max(max);
+                    pixels += 1;
+                }
+
+                RoiStats {
+                    mean: (sum / pixels as f64) as f32,
+                    min,
+                    max,
+                    pixels,
+                }
+            })
+            .collect()
+    }
+
+    /// Contents the GPU statistics buffer is cleared to before every frame
+    pub fn gpu_stats_clear(&self) -> Vec<u32> {
+        (0..self.len().max(1))
+            .flat_map(|_| [0, 0, u32::MAX, 0])
+            .collect()
+    }
+}
+
+/// Signed diffs inside a region of interest for a single frame, NaN for empty regions
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RoiStats {
+    pub mean: f32,
+    pub min: f32,
+    pub max: f32,
+    pub pixels: usize,
+}
+
+impl RoiStats {
+    /// Statistics from the ROI_STATS_WORDS words the GPU collected for a region of the given
+    /// number of pixels
+    pub fn from_gpu(words: &[u32], pixels: usize) -> Self {
+        let sum = ((words[1] as u64) << 32 | words[0] as u64) as f64 / ROI_SUM_SCALE as f64;
+
+        if pixels == 0 {
+            return Self {
+                mean: f32::NAN,
+                min: f32::NAN,
+                max: f32::NAN,
+                pixels,
+            };
+        }
+
+        Self {
+            mean: (sum / pixels as f64 - ROI_SUM_RANGE as f64) as f32,
+            min: from_orderable_bits(words[2]),
+            max: from_orderable_bits(words[3]),
+            pixels,
+        }
+    }
+}
+
+/// Undoes the mapping of floats to unsigned integers of the same order the shaders take the
+/// atomic min and max of
+fn from_orderable_bits(bits: u32) -> f32 {
+    if bits & 0x8000_0000 != 0 {
+        f32::from_bits(bits & 0x7FFF_FFFF)
+    } else {
+        f32::from_bits(!bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the contents to a ROI file of its own in the temporary directory
+    fn roi_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("dips_roi_{}_{name}.txt", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Pixels of a frame of the given size inside the shape, as rows of `#` and `.`
+    fn drawn(shape: &RoiShape, width: u32, height: u32) -> Vec<String> {
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| match shape.contains((x, y), (width, height)) {
+                        true => '#',
+                        false => '.',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Order preserving bits of a float like the orderable_bits of the shaders
+    fn orderable_bits(value: f32) -> u32 {
+        let bits = value.to_bits();
+        match bits & 0x8000_0000 != 0 {
+            true => !bits,
+            false => bits | 0x8000_0000,
+        }
+    }
+
+    /// Words the shaders collect for a region like accumulate_roi_stats does
+    fn gpu_words(mask: &[u32], diffs: &[f32], index: usize) -> [u32; ROI_STATS_WORDS] {
+        let mut words = [0, 0, u32::MAX, 0];
+        let mut sum = 0u64;
+
+        for (_, diff) in mask
+            .iter()
+            .zip(diffs)
+            .filter(|(bits, _)| *bits & 1 << index != 0)
+        {
+            let biased = diff.clamp(-ROI_SUM_RANGE, ROI_SUM_RANGE) + ROI_SUM_RANGE;
+            sum += (biased * ROI_SUM_SCALE).round() as u64;
+            words[2] = words[2].min(orderable_bits(*diff));
+            words[3] = words[3].max(orderable_bits(*diff));
+        }
+
+        [sum as u32, (sum >> 32) as u32, words[2], words[3]]
+    }
+
+    #[test]
+    fn polygons_use_the_even_odd_rule() {
+        // A square with a square hole, drawn as one outline through a bridge to the hole
+        let frame = RoiShape::Polygon(vec![
+            (0.0, 0.0),
+            (6.0, 0.0),
+            (6.0, 6.0),
+            (0.0, 6.0),
+            (0.0, 0.0),
+            (2.0, 2.0),
+            (2.0, 4.0),
+            (4.0, 4.0),
+            (4.0, 2.0),
+            (2.0, 2.0),
+        ]);
+
+        assert_eq!(
+            drawn(&frame, 7, 7),
+            [
+                "######.", "######.", "##..##.", "##..##.", "######.", "######.", ".......",
+            ]
+        );
+
+        // Only the pixels with their centre inside of a triangle count
+        let triangle = RoiShape::Polygon(vec![(0.0, 0.0), (4.0, 0.0), (0.0, 4.0)]);
+        assert_eq!(drawn(&triangle, 4, 4), ["###.", "##..", "#...", "...."]);
+    }
+
+    #[test]
+    fn masks_are_scaled_to_the_frame() {
+        let mask = RoiShape::mask_from_luma(3, 2, &[255, 0, 1, 0, 7, 0]).unwrap();
+
+        assert_eq!(drawn(&mask, 3, 2), ["#.#", ".#."]);
+        assert_eq!(drawn(&mask, 6, 4), ["##..##", "##..##", "..##..", "..##.."]);
+        assert_eq!(drawn(&mask, 4, 2), ["##.#", "..#."]);
+
+        assert!(RoiShape::mask_from_luma(3, 2, &[0; 5]).is_err());
+    }
+
+    #[test]
+    fn rectangles_are_cut_off_at_the_largest_coordinate() {
+        let rectangle = RoiShape::Rectangle {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 1,
+        };
+        assert_eq!(drawn(&rectangle, 4, 3), ["....", ".##.", "...."]);
+
+        let saturated = RoiShape::Rectangle {
+            x: u32::MAX - 2,
+            y: u32::MAX - 1,
+            width: u32::MAX,
+            height: 8,
+        };
+        let frame = (u32::MAX, u32::MAX);
+        assert!(saturated.contains((u32::MAX - 1, u32::MAX - 1), frame));
+        assert!(!saturated.contains((u32::MAX, u32::MAX - 1), frame));
+        assert!(!saturated.contains((u32::MAX - 1, u32::MAX), frame));
+        assert!(!saturated.contains((0, 0), frame));
+    }
+
+    #[test]
+    fn regions_are_loaded_from_text() {
+        let rois = Roi::load(roi_file(
+            "valid",
+            "# name shape values\n\nleft rect 0, 0, 4, 8\ntip polygon 1 1 5 1 3 4 # triangle\n",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            rois,
+            [
+                Roi::new(
+                    "left",
+                    RoiShape::Rectangle {
+                        x: 0,
+                        y: 0,
+                        width: 4,
+                        height: 8
+                    }
+                ),
+                Roi::new(
+                    "tip",
+                    RoiShape::Polygon(vec![(1.0, 1.0), (5.0, 1.0), (3.0, 4.0)])
+                ),
+            ]
+        );
+
+        for (name, contents) in [
+            ("name_only", "left\n"),
+            ("unknown", "left circle 1 2 3\n"),
+            ("rect_values", "left rect 0 0 4\n"),
+            ("rect_negative", "left rect -1 0 4 4\n"),
+            ("polygon_points", "tip polygon 1 1 5 1\n"),
+            ("polygon_odd", "tip polygon 1 1 5 1 3 4 3\n"),
+            ("polygon_number", "tip polygon 1 1 5 one 3 4\n"),
+        ] {
+            let error = Roi::load(roi_file(name, contents)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{name}");
+            assert!(
+                error.to_string().starts_with("Line 1 of the ROIs"),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn gpu_stats_match_the_cpu_stats() {
+        let rois = Rois::new(
+            vec![
+                Roi::new(
+                    "top",
+                    RoiShape::Rectangle {
+                        x: 0,
+                        y: 0,
+                        width: 4,
+                        height: 1,
+                    },
+                ),
+                Roi::new(
+                    "right",
+                    RoiShape::Rectangle {
+                        x: 2,
+                        y: 0,
+                        width: 2,
+                        height: 3,
+                    },
+                ),
+                Roi::new(
+                    "outside",
+                    RoiShape::Rectangle {
+                        x: 8,
+                        y: 8,
+                        width: 1,
+                        height: 1,
+                    },
+                ),
+            ],
+            false,
+        )
+        .unwrap();
+
+        let mask = rois.mask(4, 3);
+        let diffs = [
+            -0.25, 0.5, -0.0, 0.125, //
+            3.0, -1.5, 0.75, -0.03125, //
+            0.0, 2.0, -4.5, 1e-3,
+        ];
+        let pixel_counts = rois.pixel_counts(&mask);
+        assert_eq!(pixel_counts, [4, 6, 0]);
+
+        let cpu_stats = rois.stats(&mask, &diffs);
+        for (index, cpu) in cpu_stats.iter().enumerate() {
+            let gpu = RoiStats::from_gpu(&gpu_words(&mask, &diffs, index), pixel_counts[index]);
+
+            assert_eq!(gpu.pixels, cpu.pixels);
+            if cpu.pixels == 0 {
+                assert!(gpu.mean.is_nan() && gpu.min.is_nan() && gpu.max.is_nan());
+                assert!(cpu.mean.is_nan() && cpu.min.is_nan() && cpu.max.is_nan());
+                continue;
+            }
+
+            assert!(
+                (gpu.mean - cpu.mean).abs() < 1.0 / ROI_SUM_SCALE,
+                "{gpu:?} {cpu:?}"
+            );
+            assert_eq!((gpu.min, gpu.max), (cpu.min, cpu.max));
+        }
+
+        assert_eq!(
+            (cpu_stats[0].min, cpu_stats[0].max),
+            (-0.25, 0.5),
+            "{cpu_stats:?}"
+        );
+        assert_eq!((cpu_stats[1].min, cpu_stats[1].max), (-4.5, 0.75));
+    }
+
+    #[test]
+    fn orderable_bits_round_trip_in_order() {
+        let values = [f32::MIN, -3.5, -1e-20, -0.0, 0.0, 1e-20, 0.5, f32::MAX];
+
+        for pair in values.windows(2) {
+            assert!(
+                orderable_bits(pair[0]) <= orderable_bits(pair[1]),
+                "{pair:?}"
+            );
+        }
+        for value in values {
+            assert_eq!(
+                from_orderable_bits(orderable_bits(value)).to_bits(),
+                value.to_bits()
+            );
+        }
+    }
+}
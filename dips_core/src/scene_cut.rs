@@ -0,0 +1,194 @@
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+/// Number of bins the intensity histograms of the histogram distance are collected in
+const INTENSITY_BINS: usize = 64;
+
+/// Watches for scene cuts and large global changes, like the camera being bumped or the lights
+/// switching, after which the baseline has to be retaken
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum SceneCutDetection {
+    /// The baseline is only refreshed by hand
+    #[default]
+    Off,
+    /// Refreshes when the mean absolute raw diff of a frame goes over the given threshold, from
+    /// 0 to 1
+    MeanAbsoluteDiff(f32),
+    /// Refreshes when the distance between the intensity histograms of a frame and the
+    /// baseline goes over the given threshold, from 0 for the same histogram to 1 for histograms
+    /// that don't overlap at all
+    HistogramDistance(f32),
+}
+
+impl SceneCutDetection {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+}
+
+/// Decides after every frame whether the baseline should be refreshed and keeps a record of the
+/// refreshes
+///
+/// Frames the backend has no diff for are collecting the baseline, the histogram distance
+/// compares against the last of those
+#[derive(Debug, Clone)]
+pub struct SceneCutDetector {
+    detection: SceneCutDetection,
+
+    // Frames checked so far
+    frame: usize,
+    baseline_histogram: Option<Vec<f32>>,
+    // Frame number and timestamp in seconds of every refresh
+    refreshes: Vec<(usize, Option<f64>)>,
+}
+
+impl SceneCutDetector {
+    pub fn new(detection: SceneCutDetection) -> Self {
+        Self {
+            detection,
+            frame: 0,
+            baseline_histogram: None,
+            refreshes: Vec::new(),
+        }
+    }
+
+    /// Frame numbers and timestamps of the refreshes so far
+    pub fn refreshes(&self) -> &[(usize, Option<f64>)] {
+        &self.refreshes
+    }
+
+    /// Checks an RGBA8 frame and the raw diff the backend returned for it, returns true when
+    /// the baseline should be refreshed
+    pub fn check(&mut self, frame: &[u8], diff: Option<&[f32]>, timestamp: Option<f64>) -> bool {
+        let frame_number = self.frame;
+        self.frame += 1;
+
+        let (measure, threshold) = match self.detection {
+            SceneCutDetection::Off => return false,
+            SceneCutDetection::MeanAbsoluteDiff(threshold) => {
+                let Some(diff) = diff.filter(|diff| !diff.is_empty()) else {
+                    return false;
+                };
+
+                let mean =
+                    diff.iter().map(|diff| diff.abs() as f64).sum::<f64>() / diff.len() as f64;
+
+                (mean as f32, threshold)
+            }
+            SceneCutDetection::HistogramDistance(threshold) => {
+                let histogram = intensity_histogram(frame);
+
+                match self.baseline_histogram.as_ref() {
+                    Some(baseline_histogram) if diff.is_some() => (
+                        histogram_distance(baseline_histogram, &histogram),
+                        threshold,
+                    ),
+                    // Still collecting the baseline, the newest frame stands in for it
+                    _ => {
+                        self.baseline_histogram = Some(histogram);
+                        return false;
+                    }
+                }
+            }
+        };
+
+        if measure <= threshold {
+            return false;
+        }
+
+        match timestamp {
+            Some(seconds) => info!(
+                "Refreshing the baseline at frame {frame_number} ({seconds:.3}s), the change of {measure} is over {threshold}"
+            ),
+            None => info!(
+                "Refreshing the baseline at frame {frame_number}, the change of {measure} is over {threshold}"
+            ),
+        }
+
+        self.refreshes.push((frame_number, timestamp));
+        self.baseline_histogram = None;
+
+        true
+    }
+}
+
+/// Normalized histogram of the mean of the red, green and blue channels of an RGBA8 frame
+fn intensity_histogram(frame: &[u8]) -> Vec<f32> {
+    let mut histogram = vec![0.0; INTENSITY_BINS];
+
+    let pixels = frame.chunks_exact(4);
+    let count = pixels.len().max(1) as f32;
+    for pixel in pixels {
+        let intensity = (pixel[0] as usize + pixel[1] as usize + pixel[2] as usize) / 3;
+        histogram[intensity * INTENSITY_BINS / 256] += 1.0;
+    }
+
+    histogram.iter_mut().for_each(|bin| *bin /= count);
+
+    histogram
+}
+
+/// Total variation distance between two normalized histograms
+fn histogram_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f32>() * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RGBA8 frame of 8 pixels, the first half at the first grey level and the rest at the second
+    fn frame(first: u8, second: u8) -> Vec<u8> {
+        [[first, first, first, 255]; 4]
+            .into_iter()
+            .chain([[second, second, second, 255]; 4])
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn mean_absolute_diff_over_the_threshold_is_a_cut() {
+        let mut detector = SceneCutDetector::new(SceneCutDetection::MeanAbsoluteDiff(0.1));
+        let frame = frame(0, 0);
+
+        // Frames collecting the baseline have no diff
+        assert!(!detector.check(&frame, None, Some(0.0)));
+        // Noise and a few changed pixels stay under the threshold
+        assert!(!detector.check(&frame, Some(&[0.05, -0.05, 0.05, -0.05]), Some(0.1)));
+        assert!(!detector.check(&frame, Some(&[0.4, 0.0, 0.0, 0.0]), Some(0.2)));
+        assert!(detector.check(&frame, Some(&[-0.2, 0.2, -0.2, 0.2]), Some(0.3)));
+
+        assert_eq!(detector.refreshes(), [(3, Some(0.3))]);
+    }
+
+    #[test]
+    fn histogram_distance_over_the_threshold_is_a_cut() {
+        let mut detector = SceneCutDetector::new(SceneCutDetection::HistogramDistance(0.3));
+        let diff = [0.0; 8];
+
+        // The last frame without a diff is the baseline
+        assert!(!detector.check(&frame(200, 200), None, None));
+        assert!(!detector.check(&frame(20, 200), None, None));
+        // A quarter of the pixels moving to another bin is a distance of 0.25
+        assert!(!detector.check(&[frame(20, 200), frame(20, 20)].concat(), Some(&diff), None));
+        // Moving the pixels within a bin doesn't change the histogram
+        assert!(!detector.check(&frame(21, 201), Some(&diff), None));
+        assert!(detector.check(&frame(100, 200), Some(&diff), None));
+
+        // A cut drops the baseline so the next frame is compared to a new one
+        assert!(!detector.check(&frame(0, 0), Some(&diff), None));
+        assert!(detector.check(&frame(255, 255), Some(&diff), None));
+
+        assert_eq!(detector.refreshes(), [(4, None), (6, None)]);
+    }
+
+    #[test]
+    fn nothing_is_a_cut_while_off() {
+        let mut detector = SceneCutDetector::new(SceneCutDetection::Off);
+
+        assert!(!detector.check(&frame(0, 0), None, None));
+        assert!(!detector.check(&frame(255, 255), Some(&[1.0; 8]), None));
+        assert!(detector.refreshes().is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+use crate::{DiPsParameters, cpu::shape_diff};
+
+/// Number of bins the histogram of the signed diffs is collected in, evenly spread over diffs
+/// from -1 to 1
+pub const HISTOGRAM_BINS: usize = 1024;
+
+/// Bin of the diff histogram a raw diff falls in, diffs outside of -1 to 1 go in the end bins
+pub fn histogram_bin(diff: f32) -> usize {
+    let position = (diff * 0.5 + 0.5) * HISTOGRAM_BINS as f32;
+
+    (position.max(0.0) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+/// Picks the gain of the transfer from the histogram of the raw diffs so that the given
+/// percentile of the diff magnitudes maps to full color
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum AutoSensitivity {
+    /// The gain of the transfer is used as it is
+    #[default]
+    Off,
+    /// The gain is picked from the diffs of the first given number of output frames and then
+    /// kept for the rest of the run
+    FirstFrames { frames: usize, percentile: f32 },
+    /// The gain follows the diffs of every frame, smoothed with the given time constant in
+    /// frames
+    Continuous { percentile: f32, time_constant: f32 },
+}
+
+impl AutoSensitivity {
+    /// Whether the gain is picked from the diffs rather than taken from the transfer, sets the
+    /// AUTO_SENSITIVITY shader override
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+
+    /// Percentile between 0 and 100 of the diff magnitudes that maps to full color
+    pub fn percentile(&self) -> f32 {
+        match self {
+            Self::FirstFrames { percentile, .. } | Self::Continuous { percentile, .. } => {
+                percentile.clamp(0.0, 100.0)
+            }
+            Self::Off => 100.0,
+        }
+    }
+}
+
+/// Turns the diff histograms the backends collect into the gain of the transfer
+#[derive(Debug, Clone)]
+pub struct SensitivityEstimator {
+    parameters: DiPsParameters,
+    gain: f32,
+
+    // Histogram summed over the frames seen so far when picking from the first frames
+    histogram: Vec<u64>,
+    frames: usize,
+}
+
+impl SensitivityEstimator {
+    pub fn new(parameters: DiPsParameters) -> Self {
+        Self {
+            gain: parameters.transfer.gain,
+            parameters,
+            histogram: vec![0; HISTOGRAM_BINS],
+            frames: 0,
+        }
+    }
+
+    /// Gain the transfer should currently use
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Whether the backend should still collect histograms
+    pub fn is_collecting(&self) -> bool {
+        match self.parameters.auto_sensitivity {
+            AutoSensitivity::Off => false,
+            AutoSensitivity::FirstFrames { frames, .. } => self.frames < frames.max(1),
+            AutoSensitivity::Continuous { .. } => true,
+        }
+    }
+
+    /// Starts over, used when the baseline is retaken
+    pub fn reset(&mut self) {
+        self.gain = self.parameters.transfer.gain;
+        self.histogram.fill(0);
+        self.frames = 0;
+    }
+
+    /// Updates the gain with the histogram of the raw diffs of an output frame and returns it
+    pub fn update(&mut self, histogram: &[u32]) -> f32 {
+        if !self.is_collecting() || histogram.len() != HISTOGRAM_BINS {
+            return self.gain;
+        }
+
+        self.frames += 1;
+
+        match self.parameters.auto_sensitivity {
+            AutoSensitivity::FirstFrames { frames, .. } => {
+                for (total, count) in self.histogram.iter_mut().zip(histogram) {
+                    *total += *count as u64;
+                }
+
+                if let Some(gain) = self.target_gain(&self.histogram) {
+                    self.gain = gain;
+                }
+
+                if self.frames == frames.max(1) {
+                    info!(
+                        "Auto sensitivity picked a gain of {} from {} frames",
+                        self.gain, self.frames
+                    );
+                }
+            }
+            AutoSensitivity::Continuous { time_constant, .. } => {
+                let histogram = histogram
+                    .iter()
+                    .map(|count| *count as u64)
+                    .collect::<Vec<_>>();
+
+                if let Some(gain) = self.target_gain(&histogram) {
+                    self.gain = if self.frames == 1 {
+                        gain
+                    } else {
+                        let alpha = 1.0 - (-1.0 / time_constant.max(1.0)).exp();
+                        self.gain + (gain - self.gain) * alpha
+                    };
+                }
+            }
+            AutoSensitivity::Off => {}
+        }
+
+        self.gain
+    }
+
+    /// Gain that maps the percentile of the diff magnitudes in the histogram to a display
+    /// value of 1, None when there is nothing to scale
+    ///
+    /// The offset of the transfer moves the diffs of both signs the same way, so the gain
+    /// leaves room for it and only the side the offset pushes towards reaches 1. An offset
+    /// of 1 or more leaves no room and the gain stays as it is
+    fn target_gain(&self, histogram: &[u64]) -> Option<f32> {
+        let magnitude =
+            percentile_magnitude(histogram, self.parameters.auto_sensitivity.percentile())?;
+        let shaped = shape_diff(magnitude, &self.parameters).abs();
+        let headroom = 1.0 - self.parameters.transfer.offset.abs();
+
+        (shaped > f32::EPSILON && headroom > 0.0).then(|| headroom / shaped)
+    }
+}
+
+/// Diff magnitude below which the given percentile of the diffs in the histogram fall,
+/// linearly interpolated within the bin it lands in
+fn percentile_magnitude(histogram: &[u64], percentile: f32) -> Option<f32> {
+    let total = histogram.iter().sum::<u64>();
+    if total == 0 {
+        return None;
+    }
+
+    // Fold the negative half onto the positive one so that bin m holds magnitudes from
+    // m to m + 1 bin widths
+    let half = HISTOGRAM_BINS / 2;
+    let bin_width = 2.0 / HISTOGRAM_BINS as f32;
+    let target = total as f64 * (percentile as f64 / 100.0);
+
+    let mut cumulative = 0.0;
+    for magnitude_bin in 0..half {
+        let count = (histogram[half + magnitude_bin] + histogram[half - 1 - magnitude_bin]) as f64;
+
+        if count > 0.0 && cumulative + count >= target {
+            let fraction = ((target - cumulative) / count).clamp(0.0, 1.0) as f32;
+            return Some((magnitude_bin as f32 + fraction) * bin_width);
+        }
+
+        cumulative += count;
+    }
+
+    Some(1.0)
+}
@@ -0,0 +1,291 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+use crate::{
+    Colormap,
+    export::{json_number, json_string, write_heatmap, write_npy},
+};
+
+/// Whether the backends keep per pixel statistics of the raw diffs over the whole run
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Summary {
+    #[default]
+    Off,
+    /// Collects the summary maps, counting the frames whose diff magnitude goes over the
+    /// threshold
+    On { threshold: f32 },
+}
+
+impl Summary {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+}
+
+/// Per pixel statistics of the raw diffs of every output frame of a run
+///
+/// The frames collected for the baseline have no diff and aren't counted. The maps are kept as
+/// running means so long recordings don't lose precision
+#[derive(Debug, Clone)]
+pub struct SummaryMaps {
+    width: u32,
+    height: u32,
+    threshold: f32,
+    frames: usize,
+
+    mean_abs: Vec<f32>,
+    max_positive: Vec<f32>,
+    max_negative: Vec<f32>,
+    // Running mean and sum of squared deviations of the signed diff
+    mean: Vec<f32>,
+    squared_deviations: Vec<f32>,
+    above_threshold: Vec<u32>,
+}
+
+impl SummaryMaps {
+    pub fn new(width: u32, height: u32, threshold: f32) -> Self {
+        let pixel_count = (width * height) as usize;
+
+        Self {
+            width,
+            height,
+            threshold,
+            frames: 0,
+            mean_abs: vec![0.0; pixel_count],
+            max_positive: vec![0.0; pixel_count],
+            max_negative: vec![0.0; pixel_count],
+            mean: vec![0.0; pixel_count],
+            squared_deviations: vec![0.0; pixel_count],
+            above_threshold: vec![0; pixel_count],
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Number of frames summarised so far
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+
+    /// Adds the raw diff of an output frame, frames of another size are skipped
+    pub fn update(&mut self, diff: &[f32]) {
+        if diff.len() != self.mean.len() {
+            warn!(
+                "Skipping a frame of {} diffs in the {}x{} summary",
+                diff.len(),
+                self.width,
+                self.height
+            );
+            return;
+        }
+
+        self.frames += 1;
+        let frames = self.frames as f32;
+
+        for (pixel, &diff) in diff.iter().enumerate() {
+            self.mean_abs[pixel] += (diff.abs() - self.mean_abs[pixel]) / frames;
+            self.max_positive[pixel] = self.max_positive[pixel].max(diff);
+            self.max_negative[pixel] = self.max_negative[pixel].min(diff);
+
+            // Welford's update of the mean and variance
+            let deviation = diff - self.mean[pixel];
+            self.mean[pixel] += deviation / frames;
+            self.squared_deviations[pixel] += deviation * (diff - self.mean[pixel]);
+
+            if diff.abs() > self.threshold {
+                self.above_threshold[pixel] += 1;
+            }
+        }
+    }
+
+    /// Mean magnitude of the diff of every pixel
+    pub fn mean_abs(&self) -> &[f32] {
+        &self.mean_abs
+    }
+
+    /// Largest positive diff of every pixel, 0 if it never went positive
+    pub fn max_positive(&self) -> &[f32] {
+        &self.max_positive
+    }
+
+    /// Most negative diff of every pixel, 0 if it never went negative
+    pub fn max_negative(&self) -> &[f32] {
+        &self.max_negative
+    }
+
+    /// Population variance of the signed diff of every pixel
+    pub fn variance(&self) -> Vec<f32> {
+        let frames = self.frames.max(1) as f32;
+
+        self.squared_deviations
+            .iter()
+            .map(|squared_deviations| squared_deviations / frames)
+            .collect()
+    }
+
+    /// Number of frames the diff magnitude of every pixel went over the threshold
+    pub fn above_threshold(&self) -> &[u32] {
+        &self.above_threshold
+    }
+
+    /// Every map by the name it is written under
+    pub fn maps(&self) -> Vec<(&'static str, Vec<f32>)> {
+        vec![
+            ("mean_abs", self.mean_abs.clone()),
+            ("max_positive", self.max_positive.clone()),
+            ("max_negative", self.max_negative.clone()),
+            ("variance", self.variance()),
+            (
+                "above_threshold",
+                self.above_threshold
+                    .iter()
+                    .map(|count| *count as f32)
+                    .collect(),
+            ),
+        ]
+    }
+
+    /// Writes every map as a `(height, width)` float32 `.npy` and a heatmap `.png` named after
+    /// the stem of the path, e.g. `out/run_mean_abs.npy`, with a `.json` of the frame count,
+    /// threshold and the range every heatmap spans
+    ///
+    /// The heatmaps run from 0 to the largest magnitude of their map through the given
+    /// colormap. Returns the paths written
+    pub fn write<P: AsRef<Path>>(&self, path: P, colormap: &Colormap) -> io::Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("summary"));
+
+        let mut written = Vec::new();
+        let mut ranges = Vec::new();
+        for (name, values) in self.maps() {
+            let npy_path = path.with_file_name(format!("{stem}_{name}.npy"));
+            write_npy(
+                &npy_path,
+                &[self.height as usize, self.width as usize],
+                &values,
+            )?;
+            written.push(npy_path);
+
+            let png_path = path.with_file_name(format!("{stem}_{name}.png"));
+            let range = write_heatmap(&png_path, self.width, self.height, &values, colormap)?;
+            written.push(png_path);
+
+            ranges.push(format!(
+                "{{ \"name\": {}, \"heatmap_max\": {} }}",
+                json_string(name),
+                json_number(range)
+            ));
+        }
+
+        let sidecar_path = path.with_file_name(format!("{stem}.json"));
+        std::fs::write(
+            &sidecar_path,
+            format!(
+                r#"{{
+  "dtype": "float32",
+  "width": {width},
+  "height": {height},
+  "frames": {frames},
+  "threshold": {threshold},
+  "colormap": {colormap},
+  "maps": [
+    {maps}
+  ]
+}}
+"#,
+                width = self.width,
+                height = self.height,
+                frames = self.frames,
+                threshold = json_number(self.threshold),
+                colormap = json_string(&format!("{colormap:?}")),
+                maps = ranges.join(",\n    "),
+            ),
+        )?;
+        written.push(sidecar_path);
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_match_a_direct_computation() {
+        // Two pixels with diffs around different means, the second one never goes positive
+        let diffs = (0..500)
+            .map(|frame| {
+                let wobble = ((frame * 37 % 101) as f32 / 100.0 - 0.5) * 0.8;
+                [0.6 + wobble, -0.5 + wobble.min(0.4)]
+            })
+            .collect::<Vec<_>>();
+
+        let mut maps = SummaryMaps::new(2, 1, 0.3);
+        for diff in &diffs {
+            maps.update(diff);
+        }
+        // Frames of another size aren't counted
+        maps.update(&[10.0; 3]);
+        assert_eq!(maps.frame_count(), diffs.len());
+
+        let variance = maps.variance();
+        for pixel in 0..2 {
+            let values = diffs
+                .iter()
+                .map(|diff| diff[pixel] as f64)
+                .collect::<Vec<_>>();
+            let count = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / count;
+            let mean_abs = values.iter().map(|value| value.abs()).sum::<f64>() / count;
+            let expected_variance = values
+                .iter()
+                .map(|value| (value - mean).powi(2))
+                .sum::<f64>()
+                / count;
+
+            assert!((maps.mean_abs()[pixel] as f64 - mean_abs).abs() < 1e-4);
+            assert!((variance[pixel] as f64 - expected_variance).abs() < 1e-4);
+            assert_eq!(
+                maps.max_positive()[pixel] as f64,
+                values.iter().copied().fold(0.0, f64::max)
+            );
+            assert_eq!(
+                maps.max_negative()[pixel] as f64,
+                values.iter().copied().fold(0.0, f64::min)
+            );
+            assert_eq!(
+                maps.above_threshold()[pixel] as usize,
+                diffs.iter().filter(|diff| diff[pixel].abs() > 0.3).count()
+            );
+        }
+        assert_eq!(maps.max_positive()[1], 0.0);
+    }
+
+    #[test]
+    fn constant_diffs_have_no_variance() {
+        let mut maps = SummaryMaps::new(1, 1, 0.5);
+        for _ in 0..1000 {
+            maps.update(&[0.25]);
+        }
+
+        assert_eq!(maps.mean_abs(), [0.25]);
+        assert_eq!(maps.variance(), [0.0]);
+        assert_eq!(maps.above_threshold(), [0]);
+    }
+}
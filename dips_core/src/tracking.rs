@@ -0,0 +1,436 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+// Logging
+#[allow(unused_imports)]
+use log::*;
+
+use crate::{Blob, BoundingBox};
+
+/// How the blobs of a frame are matched to the tracks so far
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TrackMatching {
+    /// Matches the closest blob within the given distance in pixels of where the track is
+    /// expected to be
+    Centroid { max_distance: f32 },
+    /// Matches the blob whose bounding box overlaps the expected bounding box of the track the
+    /// most, as long as the intersection over union is at least the given value
+    Iou { min_iou: f32 },
+}
+
+/// Follows the blobs of the event detection across frames
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Tracking {
+    #[default]
+    Off,
+    /// A track is born once it was matched in min_hits frames in a row and dies once it went
+    /// unmatched in more than max_missed frames in a row
+    On {
+        matching: TrackMatching,
+        min_hits: usize,
+        max_missed: usize,
+    },
+}
+
+impl Tracking {
+    pub fn is_on(&self) -> bool {
+        *self != Self::Off
+    }
+}
+
+/// Blob of a confirmed track in a single frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrackedBlob {
+    /// Persistent id of the track, counting from 1
+    pub id: u64,
+    pub frame: usize,
+    pub blob: Blob,
+}
+
+// Track that is either still waiting to be confirmed or has an id
+#[derive(Debug, Clone)]
+struct Track {
+    id: Option<u64>,
+    blob: Blob,
+    // Centroid displacement per frame between the last two matches
+    velocity: (f32, f32),
+    hits: usize,
+    missed: usize,
+    // Blobs matched before the track was confirmed
+    pending: Vec<(usize, Blob)>,
+}
+
+impl Track {
+    /// Bounding box and centroid the track is expected at after its misses so far
+    fn predicted(&self) -> (BoundingBox, (f32, f32)) {
+        let frames = (self.missed + 1) as f32;
+        let (dx, dy) = (self.velocity.0 * frames, self.velocity.1 * frames);
+
+        let bounding_box = BoundingBox {
+            x: (self.blob.bounding_box.x as f32 + dx).round().max(0.0) as u32,
+            y: (self.blob.bounding_box.y as f32 + dy).round().max(0.0) as u32,
+            ..self.blob.bounding_box
+        };
+
+        (
+            bounding_box,
+            (self.blob.centroid.0 + dx, self.blob.centroid.1 + dy),
+        )
+    }
+}
+
+/// Associates the blobs of every frame with persistent tracks
+///
+/// Matching is greedy, the best scoring track and blob pair is taken first. Tracks move on
+/// with the velocity of their last two matches while they go unmatched
+#[derive(Debug, Clone)]
+pub struct BlobTracker {
+    tracking: Tracking,
+
+    tracks: Vec<Track>,
+    next_id: u64,
+    // Confirmed blobs of the last update, including the backlog of newly born tracks
+    tracked: Vec<TrackedBlob>,
+}
+
+impl BlobTracker {
+    pub fn new(tracking: Tracking) -> Self {
+        Self {
+            tracking,
+            tracks: Vec::new(),
+            next_id: 1,
+            tracked: Vec::new(),
+        }
+    }
+
+    /// Number of tracks confirmed so far
+    pub fn track_count(&self) -> usize {
+        (self.next_id - 1) as usize
+    }
+
+    /// Confirmed blobs of the last update
+    pub fn tracked(&self) -> &[TrackedBlob] {
+        &self.tracked
+    }
+
+    /// Matches the blobs of a frame to the tracks, returns the blobs of the confirmed tracks
+    ///
+    /// A track born in this frame also returns the blobs it was matched to while it was
+    /// waiting to be confirmed, with their own frames
+    pub fn update(&mut self, frame: usize, blobs: &[Blob]) -> &[TrackedBlob] {
+        self.tracked.clear();
+
+        let Tracking::On {
+            matching,
+            min_hits,
+            max_missed,
+        } = self.tracking
+        else {
+            return &self.tracked;
+        };
+
+        // Every acceptable pair, best first
+        let mut pairs = Vec::new();
+        for (track_index, track) in self.tracks.iter().enumerate() {
+            let (bounding_box, centroid) = track.predicted();
+
+            for (blob_index, blob) in blobs.iter().enumerate() {
+                let score = match matching {
+                    TrackMatching::Centroid { max_distance } => {
+                        let distance =
+                            (centroid.0 - blob.centroid.0).hypot(centroid.1 - blob.centroid.1);
+                        if distance > max_distance {
+                            continue;
+                        }
+                        -distance
+                    }
+                    TrackMatching::Iou { min_iou } => {
+                        let iou = iou(&bounding_box, &blob.bounding_box);
+                        if iou < min_iou || iou == 0.0 {
+                            continue;
+                        }
+                        iou
+                    }
+                };
+
+                pairs.push((score, track_index, blob_index));
+            }
+        }
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut track_matches = vec![None; self.tracks.len()];
+        let mut blob_matched = vec![false; blobs.len()];
+        for (_, track_index, blob_index) in pairs {
+            if track_matches[track_index].is_none() && !blob_matched[blob_index] {
+                track_matches[track_index] = Some(blob_index);
+                blob_matched[blob_index] = true;
+            }
+        }
+
+        for (track, blob_index) in self.tracks.iter_mut().zip(track_matches) {
+            let Some(blob_index) = blob_index else {
+                track.missed += 1;
+                continue;
+            };
+            let blob = blobs[blob_index];
+
+            track.velocity = (
+                (blob.centroid.0 - track.blob.centroid.0) / (track.missed + 1) as f32,
+                (blob.centroid.1 - track.blob.centroid.1) / (track.missed + 1) as f32,
+            );
+            track.blob = blob;
+            track.hits += 1;
+            track.missed = 0;
+
+            match track.id {
+                Some(id) => self.tracked.push(TrackedBlob { id, frame, blob }),
+                None => track.pending.push((frame, blob)),
+            }
+        }
+
+        // Tentative tracks die at their first miss, confirmed ones after max_missed
+        self.tracks.retain(|track| match track.id {
+            Some(_) => track.missed <= max_missed,
+            None => track.missed == 0,
+        });
+
+        for (blob, _) in blobs
+            .iter()
+            .zip(blob_matched)
+            .filter(|(_, matched)| !matched)
+        {
+            self.tracks.push(Track {
+                id: None,
+                blob: *blob,
+                velocity: (0.0, 0.0),
+                hits: 1,
+                missed: 0,
+                pending: vec![(frame, *blob)],
+            });
+        }
+
+        for track in self.tracks.iter_mut() {
+            if track.id.is_none() && track.hits >= min_hits.max(1) {
+                let id = self.next_id;
+                self.next_id += 1;
+                track.id = Some(id);
+
+                debug!("Track {id} confirmed at frame {frame}");
+
+                self.tracked
+                    .extend(track.pending.drain(..).map(|(frame, blob)| TrackedBlob {
+                        id,
+                        frame,
+                        blob,
+                    }));
+            }
+        }
+
+        &self.tracked
+    }
+}
+
+/// Intersection over union of two bounding boxes
+pub fn iou(a: &BoundingBox, b: &BoundingBox) -> f32 {
+    let width = (a.x + a.width)
+        .min(b.x + b.width)
+        .saturating_sub(a.x.max(b.x));
+    let height = (a.y + a.height)
+        .min(b.y + b.height)
+        .saturating_sub(a.y.max(b.y));
+    let intersection = width as u64 * height as u64;
+    let union = a.width as u64 * a.height as u64 + b.width as u64 * b.height as u64 - intersection;
+
+    match union {
+        0 => 0.0,
+        union => (intersection as f64 / union as f64) as f32,
+    }
+}
+
+/// Writes the tracked blobs as a MOTChallenge results file
+///
+/// Every line is `<frame>,<id>,<bb_left>,<bb_top>,<bb_width>,<bb_height>,<conf>,-1,-1,-1` with
+/// frames counted from 1 as MOTChallenge does and a confidence of 1. Lines of tracks confirmed
+/// late come after the lines of the frame they were confirmed in, the evaluation scripts group
+/// the lines by frame themselves
+pub struct MotExporter {
+    file: BufWriter<File>,
+    lines: usize,
+}
+
+impl MotExporter {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path.as_ref())?),
+            lines: 0,
+        })
+    }
+
+    /// Number of lines written so far
+    pub fn line_count(&self) -> usize {
+        self.lines
+    }
+
+    pub fn write(&mut self, tracked: &[TrackedBlob]) -> io::Result<()> {
+        for TrackedBlob { id, frame, blob } in tracked {
+            let BoundingBox {
+                x,
+                y,
+                width,
+                height,
+            } = blob.bounding_box;
+
+            writeln!(
+                self.file,
+                "{},{id},{x},{y},{width},{height},1,-1,-1,-1",
+                frame + 1
+            )?;
+            self.lines += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 4x4 blob with its top left corner at (x, y)
+    fn blob(x: u32, y: u32) -> Blob {
+        Blob {
+            area: 16,
+            centroid: (x as f32 + 1.5, y as f32 + 1.5),
+            bounding_box: BoundingBox {
+                x,
+                y,
+                width: 4,
+                height: 4,
+            },
+            mean_diff: 0.5,
+        }
+    }
+
+    fn tracker(matching: TrackMatching, min_hits: usize, max_missed: usize) -> BlobTracker {
+        BlobTracker::new(Tracking::On {
+            matching,
+            min_hits,
+            max_missed,
+        })
+    }
+
+    /// (id, frame, x) of the tracked blobs of an update
+    fn update(tracker: &mut BlobTracker, frame: usize, blobs: &[Blob]) -> Vec<(u64, usize, u32)> {
+        tracker
+            .update(frame, blobs)
+            .iter()
+            .map(|tracked| (tracked.id, tracked.frame, tracked.blob.bounding_box.x))
+            .collect()
+    }
+
+    #[test]
+    fn tracks_keep_their_ids() {
+        let mut tracker = tracker(TrackMatching::Centroid { max_distance: 3.0 }, 2, 1);
+
+        assert!(update(&mut tracker, 0, &[blob(0, 0), blob(20, 0)]).is_empty());
+        // Born tracks hand over the blobs they were matched to while they were tentative
+        assert_eq!(
+            update(&mut tracker, 1, &[blob(22, 0), blob(2, 0)]),
+            [(1, 0, 0), (1, 1, 2), (2, 0, 20), (2, 1, 22)]
+        );
+        assert_eq!(
+            update(&mut tracker, 2, &[blob(24, 0), blob(4, 0)]),
+            [(1, 2, 4), (2, 2, 24)]
+        );
+
+        // The first track is expected to move on while it is missed
+        assert_eq!(update(&mut tracker, 3, &[blob(26, 0)]), [(2, 3, 26)]);
+        assert_eq!(
+            update(&mut tracker, 4, &[blob(8, 0), blob(28, 0)]),
+            [(1, 4, 8), (2, 4, 28)]
+        );
+        assert_eq!(tracker.track_count(), 2);
+    }
+
+    #[test]
+    fn tentative_tracks_die_at_their_first_miss() {
+        let mut tracker = tracker(TrackMatching::Centroid { max_distance: 3.0 }, 3, 5);
+
+        assert!(update(&mut tracker, 0, &[blob(0, 0)]).is_empty());
+        assert!(update(&mut tracker, 1, &[]).is_empty());
+        assert!(update(&mut tracker, 2, &[blob(0, 0)]).is_empty());
+        assert!(update(&mut tracker, 3, &[blob(0, 0)]).is_empty());
+        assert_eq!(
+            update(&mut tracker, 4, &[blob(0, 0)]),
+            [(1, 2, 0), (1, 3, 0), (1, 4, 0)]
+        );
+    }
+
+    #[test]
+    fn confirmed_tracks_die_after_max_missed() {
+        let mut tracker = tracker(TrackMatching::Iou { min_iou: 0.3 }, 1, 2);
+
+        assert_eq!(update(&mut tracker, 0, &[blob(0, 0)]), [(1, 0, 0)]);
+        assert!(update(&mut tracker, 1, &[]).is_empty());
+        assert!(update(&mut tracker, 2, &[]).is_empty());
+        assert_eq!(update(&mut tracker, 3, &[blob(1, 0)]), [(1, 3, 1)]);
+
+        for frame in 4..7 {
+            assert!(update(&mut tracker, frame, &[]).is_empty());
+        }
+        assert_eq!(update(&mut tracker, 7, &[blob(1, 0)]), [(2, 7, 1)]);
+    }
+
+    #[test]
+    fn iou_of_bounding_boxes() {
+        let (a, b) = (blob(0, 0).bounding_box, blob(2, 0).bounding_box);
+        assert_eq!(iou(&a, &a), 1.0);
+        assert_eq!(iou(&a, &b), 8.0 / 24.0);
+        assert_eq!(iou(&a, &blob(4, 4).bounding_box), 0.0);
+
+        let empty = BoundingBox {
+            width: 0,
+            height: 0,
+            ..a
+        };
+        assert_eq!(iou(&empty, &empty), 0.0);
+    }
+
+    #[test]
+    fn mot_lines_count_frames_from_one() {
+        let path =
+            std::env::temp_dir().join(format!("dips_tracking_{}_tracks.txt", std::process::id()));
+
+        let mut exporter = MotExporter::new(&path).unwrap();
+        exporter
+            .write(&[
+                TrackedBlob {
+                    id: 3,
+                    frame: 0,
+                    blob: blob(5, 6),
+                },
+                TrackedBlob {
+                    id: 4,
+                    frame: 9,
+                    blob: blob(10, 0),
+                },
+            ])
+            .unwrap();
+        assert_eq!(exporter.line_count(), 2);
+        exporter.finish().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "1,3,5,6,4,4,1,-1,-1,-1\n10,4,10,0,4,4,1,-1,-1,-1\n"
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    fmt::Debug,
+    io::{self, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::DIFF_SCALE;
+
+/// Number of entries in a transfer curve lookup table
+pub const TRANSFER_LUT_SIZE: usize = 256;
+
+/// Curve the clamped diff is shaped with before the sensitivity filter
+///
+/// Every curve is odd so that positive and negative diffs are treated alike, and the built in
+/// ones map -1, 0 and 1 onto themselves
+#[derive(Default, Clone, PartialEq)]
+pub enum TransferCurve {
+    /// Leaves the diff as it is
+    #[default]
+    Linear,
+    /// `sign(diff) * |diff|^gamma`, a gamma below 1 brings out small diffs
+    Gamma(f32),
+    /// `sign(diff) * ln(1 + |diff| / t) / ln(1 + 1 / t)` with the given linear scale t, diffs
+    /// well below t stay linear and the ones above it are compressed logarithmically
+    SymmetricLog(f32),
+    /// Output of the curve for diffs evenly spaced from -1 to 1, linearly interpolated in
+    /// between. See [`TransferCurve::piecewise`] and [`TransferCurve::load_lut`]
+    Lut(Arc<[f32; TRANSFER_LUT_SIZE]>),
+}
+
+impl Debug for TransferCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Linear => write!(f, "Linear"),
+            Self::Gamma(gamma) => write!(f, "Gamma({gamma})"),
+            Self::SymmetricLog(scale) => write!(f, "SymmetricLog({scale})"),
+            Self::Lut(_) => write!(f, "Lut"),
+        }
+    }
+}
+
+impl TransferCurve {
+    /// Piecewise linear curve through the given (diff, output) points, resampled into a LUT
+    ///
+    /// Points are sorted by their diff, diffs outside of the first and last point get their
+    /// outputs. Returns None without at least two points with different diffs
+    pub fn piecewise(points: &[(f32, f32)]) -> Option<Self> {
+        let mut points = points.to_vec();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        points.dedup_by(|a, b| a.0 == b.0);
+
+        if points.len() < 2 {
+            return None;
+        }
+
+        let table = std::array::from_fn(|entry| {
+            let diff = lut_diff(entry);
+            let upper = points
+                .iter()
+                .position(|(x, _)| *x > diff)
+                .unwrap_or(points.len() - 1)
+                .max(1);
+            let (x0, y0) = points[upper - 1];
+            let (x1, y1) = points[upper];
+            let fraction = ((diff - x0) / (x1 - x0)).clamp(0.0, 1.0);
+
+            y0 + (y1 - y0) * fraction
+        });
+
+        Some(Self::Lut(Arc::new(table)))
+    }
+
+    /// Loads a curve from a text file
+    ///
+    /// Either 256 lines of a single output for diffs evenly spaced from -1 to 1, or lines of a
+    /// diff and an output separated by spaces or commas for a piecewise linear curve. `#`
+    /// starts a comment
+    pub fn load_lut<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+
+        let mut outputs = Vec::with_capacity(TRANSFER_LUT_SIZE);
+        let mut points = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let values = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Line {} of the transfer curve: {err}", number + 1),
+                    )
+                })?;
+
+            match values.as_slice() {
+                [] => continue,
+                [output] if points.is_empty() => outputs.push(*output),
+                [diff, output] if outputs.is_empty() => points.push((*diff, *output)),
+                _ => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Line {} of the transfer curve has {} values, every line needs a single output or a diff and an output like the lines before it",
+                            number + 1,
+                            values.len()
+                        ),
+                    ));
+                }
+            }
+        }
+
+        if !points.is_empty() {
+            return Self::piecewise(&points).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    "A piecewise transfer curve needs at least two points with different diffs",
+                )
+            });
+        }
+
+        let table: [f32; TRANSFER_LUT_SIZE] = outputs.try_into().map_err(|outputs: Vec<_>| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The transfer curve has {} entries instead of {TRANSFER_LUT_SIZE}",
+                    outputs.len()
+                ),
+            )
+        })?;
+
+        Ok(Self::Lut(Arc::new(table)))
+    }
+
+    /// Value of the TRANSFER_CURVE shader override
+    pub fn shader_id(&self) -> u32 {
+        match self {
+            Self::Linear => 0,
+            Self::Gamma(_) => 1,
+            Self::SymmetricLog(_) => 2,
+            Self::Lut(_) => 3,
+        }
+    }
+
+    /// Value of the TRANSFER_PARAMETER shader override
+    pub fn parameter(&self) -> f32 {
+        match self {
+            Self::Gamma(gamma) => gamma.max(0.01),
+            Self::SymmetricLog(scale) => scale.max(0.0001),
+            _ => 1.0,
+        }
+    }
+
+    /// Shapes a diff with the curve
+    pub fn apply(&self, diff: f32) -> f32 {
+        // signum is 1 rather than 0 for a diff of 0 but every curve goes through 0 anyway
+        match self {
+            Self::Linear => diff,
+            Self::Gamma(_) => diff.signum() * diff.abs().powf(self.parameter()),
+            Self::SymmetricLog(_) => {
+                let scale = self.parameter();
+                diff.signum() * (diff.abs() / scale).ln_1p() / (1.0 / scale).ln_1p()
+            }
+            Self::Lut(table) => {
+                let position = (diff * 0.5 + 0.5).clamp(0.0, 1.0) * (TRANSFER_LUT_SIZE - 1) as f32;
+                let lower = (position.floor() as usize).min(TRANSFER_LUT_SIZE - 2);
+                let fraction = position - lower as f32;
+
+                table[lower] + (table[lower + 1] - table[lower]) * fraction
+            }
+        }
+    }
+
+    /// Diff the curve shapes into the given output
+    ///
+    /// LUTs are only invertible where they are monotonic, outputs outside of a LUT's range give
+    /// the diff at its nearest end
+    pub fn invert(&self, output: f32) -> f32 {
+        match self {
+            Self::Linear => output,
+            Self::Gamma(_) => output.signum() * output.abs().powf(1.0 / self.parameter()),
+            Self::SymmetricLog(_) => {
+                let scale = self.parameter();
+                output.signum() * scale * (output.abs() * (1.0 / scale).ln_1p()).exp_m1()
+            }
+            Self::Lut(table) => {
+                let increasing = table[TRANSFER_LUT_SIZE - 1] >= table[0];
+                let segment = table.windows(2).position(|segment| {
+                    let (low, high) = if increasing {
+                        (segment[0], segment[1])
+                    } else {
+                        (segment[1], segment[0])
+                    };
+                    (low..=high).contains(&output)
+                });
+
+                match segment {
+                    Some(lower) => {
+                        let (start, end) = (table[lower], table[lower + 1]);
+                        let fraction = if end == start {
+                            0.0
+                        } else {
+                            (output - start) / (end - start)
+                        };
+
+                        lut_diff(lower) + fraction * (lut_diff(lower + 1) - lut_diff(lower))
+                    }
+                    None if (output < table[0]) == increasing => -1.0,
+                    None => 1.0,
+                }
+            }
+        }
+    }
+
+    /// Row of texels for the lookup texture the shaders sample, only read for LUT curves
+    pub fn texture_data(&self) -> Vec<f32> {
+        match self {
+            Self::Lut(table) => table.to_vec(),
+            _ => (0..TRANSFER_LUT_SIZE)
+                .map(|entry| self.apply(lut_diff(entry)))
+                .collect(),
+        }
+    }
+}
+
+/// Diff an entry of a transfer curve lookup table stands for
+fn lut_diff(entry: usize) -> f32 {
+    entry as f32 / (TRANSFER_LUT_SIZE - 1) as f32 * 2.0 - 1.0
+}
+
+/// How the raw signed diff is turned into the value that is displayed
+///
+/// Every pixel goes through
+///
+/// 1. `clamped = clamp(diff, clamp_min, clamp_max)`
+/// 2. `shaped = curve(clamped)`
+/// 3. `filtered = filter(shaped / 2)`, the sensitivity filter, which is the identity when
+///    unfiltered
+/// 4. `display = gain * filtered + offset`
+///
+/// The display value picks the colormap entry from -1 to 1 when colorizing, and the grey level
+/// `0.5 - display` from 0 to 1 otherwise. [`crate::DiPsParameters::diff_from_display`] undoes
+/// the steps for diffs inside the clamp range
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub curve: TransferCurve,
+    pub gain: f32,
+    pub offset: f32,
+    pub clamp_min: f32,
+    pub clamp_max: f32,
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Self {
+            curve: TransferCurve::default(),
+            gain: DIFF_SCALE,
+            offset: 0.0,
+            clamp_min: -1.0,
+            clamp_max: 1.0,
+        }
+    }
+}
+
+impl Transfer {
+    /// Limits a raw diff to the clamp range
+    pub fn clamp(&self, diff: f32) -> f32 {
+        diff.max(self.clamp_min).min(self.clamp_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the contents to a curve file of its own in the temporary directory
+    fn curve_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("dips_transfer_{}_{name}.txt", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert_within(value, expected, 1e-4);
+    }
+
+    fn assert_within(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{value} != {expected}"
+        );
+    }
+
+    #[test]
+    fn piecewise_curves_go_through_their_points() {
+        // Points between the LUT entries are only met to within the slopes around them times
+        // the entry spacing
+        let tolerance = 1.6 * 2.0 / (TRANSFER_LUT_SIZE - 1) as f32;
+
+        // Unsorted with a repeated diff, only the first (0.5, _) point is kept
+        let curve =
+            TransferCurve::piecewise(&[(0.5, 0.8), (-0.5, -0.2), (0.5, 0.0), (0.0, 0.0)]).unwrap();
+
+        assert_within(curve.apply(-0.5), -0.2, tolerance);
+        assert_within(curve.apply(0.0), 0.0, tolerance);
+        assert_within(curve.apply(0.25), 0.4, tolerance);
+        assert_within(curve.apply(0.5), 0.8, tolerance);
+
+        // Diffs outside of the points get the outputs of the end points
+        assert_within(curve.apply(-1.0), -0.2, tolerance);
+        assert_within(curve.apply(1.0), 0.8, tolerance);
+
+        assert_eq!(TransferCurve::piecewise(&[(0.1, 0.2)]), None);
+        assert_eq!(TransferCurve::piecewise(&[(0.1, 0.2), (0.1, 0.4)]), None);
+    }
+
+    #[test]
+    fn curves_invert_their_outputs() {
+        let curves = [
+            TransferCurve::Linear,
+            TransferCurve::Gamma(0.5),
+            TransferCurve::SymmetricLog(0.1),
+            TransferCurve::piecewise(&[(-1.0, -1.0), (0.0, 0.0), (0.2, 0.6), (1.0, 1.0)]).unwrap(),
+            TransferCurve::piecewise(&[(-1.0, 1.0), (1.0, -1.0)]).unwrap(),
+        ];
+
+        for curve in curves {
+            for diff in [-0.9, -0.3, 0.0, 0.05, 0.2, 0.7] {
+                assert_close(curve.invert(curve.apply(diff)), diff);
+            }
+        }
+
+        // Outputs past the ends of a LUT give the diff at the nearest end
+        let curve = TransferCurve::piecewise(&[(-1.0, -0.5), (1.0, 0.5)]).unwrap();
+        assert_close(curve.invert(0.9), 1.0);
+        assert_close(curve.invert(-0.9), -1.0);
+
+        let curve = TransferCurve::piecewise(&[(-1.0, 0.5), (1.0, -0.5)]).unwrap();
+        assert_close(curve.invert(0.9), -1.0);
+        assert_close(curve.invert(-0.9), 1.0);
+    }
+
+    #[test]
+    fn curves_are_loaded_from_tables_and_points() {
+        let lines = (0..TRANSFER_LUT_SIZE)
+            .map(|entry| format!("{}", lut_diff(entry) * 0.5))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let curve =
+            TransferCurve::load_lut(curve_file("table", &format!("# half\n{lines}\n"))).unwrap();
+        assert_close(curve.apply(0.6), 0.3);
+
+        let curve = TransferCurve::load_lut(curve_file(
+            "points",
+            "-1, -1 # low end\n\n0 0\n0.5 0.9\n1 1\n",
+        ))
+        .unwrap();
+        assert_close(curve.apply(0.25), 0.45);
+        assert_close(curve.apply(0.75), 0.95);
+    }
+
+    #[test]
+    fn malformed_curves_are_rejected() {
+        for (name, contents) in [
+            ("short", "0\n".repeat(TRANSFER_LUT_SIZE - 1)),
+            ("mixed", "0\n0 0\n1 1\n".to_string()),
+            ("channels", "0 0 0\n1 1 1\n".to_string()),
+            ("number", "0 0\nhalf 1\n".to_string()),
+            ("single_point", "0.5 0.5\n0.5 1\n".to_string()),
+        ] {
+            let error = TransferCurve::load_lut(curve_file(name, &contents)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{name}");
+        }
+    }
+}
@@ -8,9 +8,13 @@ use native_dialog::FileDialog;
 use slint::SharedString;
 use std::fs;
 
-use dips::{self, ChromaFilter, DiPsFilter, DiPsProperties};
+use dips::{
+    self, ChromaFilter, Colormap, DiPsFilter, DiPsProperties, DifferenceMode, RefreshPoint,
+};
 
 const SENSITIVITY_MAX: f32 = 10.0;
+// Time constant in frames of the accumulated frame to frame difference mode
+const ACCUMULATION_TIME_CONSTANT: f32 = 30.0;
 
 fn get_thumbnail(path: &str) -> slint::Image {
     // Store a thumbnail of the input video
@@ -63,7 +67,15 @@ fn main() -> Result<(), slint::PlatformError> {
     main_window.on_find_input_path(move || get_input_path());
     main_window.on_get_thumbnail(move |path| get_thumbnail(&path.to_string()));
     main_window.on_run_dips(
-        move |path, colorize, spatial_size, sensitivity, filter_type, chroma_filter| {
+        move |path,
+              colorize,
+              colormap,
+              spatial_size,
+              sensitivity,
+              filter_type,
+              chroma_filter,
+              difference_mode,
+              refresh_points| {
             let output_path = FileDialog::new().show_save_single_file().unwrap();
 
             let output_path = match output_path {
@@ -76,11 +88,14 @@ fn main() -> Result<(), slint::PlatformError> {
                 .video_path(path.as_str())
                 .output_path(output_path)
                 .colorize(colorize)
-                .spatial_window_size(match spatial_size.as_str() {
-                    "3" => 3,
-                    "5" => 5,
-                    _ => 1,
+                .colormap(match colormap {
+                    1 => Colormap::Coolwarm,
+                    2 => Colormap::RdBu,
+                    3 => Colormap::Viridis,
+                    4 => Colormap::Magma,
+                    _ => Colormap::RedGreen,
                 })
+                .spatial_window_size(spatial_size.parse().unwrap_or(1))
                 .sensitivity(SENSITIVITY_MAX - sensitivity)
                 .filter_type(match filter_type {
                     0 => DiPsFilter::Sigmoid,
@@ -93,6 +108,23 @@ fn main() -> Result<(), slint::PlatformError> {
                     3 => ChromaFilter::Blue,
                     _ => ChromaFilter::None,
                 })
+                .difference_mode(match difference_mode {
+                    1 => DifferenceMode::FrameToFrame,
+                    2 => DifferenceMode::AccumulatedFrameToFrame(ACCUMULATION_TIME_CONSTANT),
+                    _ => DifferenceMode::Baseline,
+                })
+                .refresh_points(
+                    refresh_points
+                        .split(',')
+                        .filter(|point| !point.trim().is_empty())
+                        .filter_map(|point| match point.parse::<RefreshPoint>() {
+                            Ok(point) => Some(point),
+                            Err(err) => {
+                                warn!("Skipping {err}");
+                                None
+                            }
+                        }),
+                )
                 .build();
 
             smol::spawn(dips::perform_dips(dips_properties)).detach();
@@ -1,11 +1,61 @@
+import math
+import sys
+
 import numpy as np
-import matplotlib.pyplot as plt
-import cv2
 
-def display_fft(image_path):
+# Masks of the shifted spectrum, with the zero frequency at (rows // 2, cols // 2) like
+# np.fft.fftshift lays it out. The zero frequency is always kept, like FrequencyDenoise does
+
+def offsets(rows, cols):
+    y, x = np.ogrid[:rows, :cols]
+    return x - cols // 2, y - rows // 2
+
+def square_mask(rows, cols, r=75):
+    crow, ccol = rows // 2, cols // 2
+
+    mask = np.zeros((rows, cols), np.uint8)
+    mask[crow - r:crow + r, ccol - r:ccol + r] = 1
+    return mask
+
+def low_pass_mask(rows, cols, radius):
+    dx, dy = offsets(rows, cols)
+
+    mask = (np.hypot(dx, dy) <= radius).astype(np.uint8)
+    mask[rows // 2, cols // 2] = 1
+    return mask
+
+def band_pass_mask(rows, cols, inner, outer):
+    dx, dy = offsets(rows, cols)
+    distance = np.hypot(dx, dy)
+
+    mask = ((distance >= inner) & (distance <= outer)).astype(np.uint8)
+    mask[rows // 2, cols // 2] = 1
+    return mask
+
+def notch_mask(rows, cols, points, radius):
+    dx, dy = offsets(rows, cols)
+
+    mask = np.ones((rows, cols), np.uint8)
+    for px, py in points:
+        mask[np.hypot(dx - px, dy - py) <= radius] = 0
+        mask[np.hypot(dx + px, dy + py) <= radius] = 0
+    mask[rows // 2, cols // 2] = 1
+    return mask
+
+def reconstruct(img, mask):
+    fshift = np.fft.fftshift(np.fft.fft2(img))
+
+    # fft reverse
+    f_ishift = np.fft.ifftshift(fshift * mask)
+    return np.abs(np.fft.ifft2(f_ishift))
+
+def display_fft(image_path, mask_fn=square_mask):
+    import cv2
+    import matplotlib.pyplot as plt
+
     # Load the image in grayscale
     img = cv2.imread(image_path, cv2.IMREAD_GRAYSCALE)
-    
+
     if img is None:
         print("Error: Could not load image.")
         return
@@ -16,19 +66,8 @@ def display_fft(image_path):
     magnitude_spectrum = 20 * np.log(np.abs(fshift) + 1)  # Add 1 to avoid log(0)
 
     rows, cols = img.shape
-    crow, ccol = rows // 2, cols // 2
-
-    mask = np.zeros((rows, cols), np.uint8)
-    r = 75
-    mask[crow - r:crow + r, ccol - r:ccol + r] = 1
-
-    fshift_masked = fshift * mask
+    img_back = reconstruct(img, mask_fn(rows, cols))
 
-    # fft reverse
-    f_ishift = np.fft.ifftshift(fshift_masked)
-    img_back = np.fft.ifft2(f_ishift)
-    img_back = np.abs(img_back)
-    
     # Plot original image and its FFT
     plt.figure(figsize=(16, 6))
 
@@ -42,7 +81,7 @@ def display_fft(image_path):
     plt.imshow(magnitude_spectrum, cmap='gray')
     plt.axis('off')
 
-    
+
     plt.subplot(1, 3, 3)
     plt.title('Reconstructed FFt')
     plt.imshow(img_back, cmap='gray')
@@ -51,6 +90,49 @@ def display_fft(image_path):
     plt.tight_layout()
     plt.show()
 
-# Example usage
-image_path = 'test_files/output.png'  # Replace with your image path
-display_fft(image_path)
+# Masks of the fixture dips_core checks FrequencyDenoiser against, keep them in sync with the
+# test in dips_core/src/denoise.rs
+FIXTURE_MASKS = [
+    ("low_pass", lambda rows, cols: low_pass_mask(rows, cols, 3.0)),
+    ("band_pass", lambda rows, cols: band_pass_mask(rows, cols, 1.5, 4.0)),
+    ("notch", lambda rows, cols: notch_mask(rows, cols, [(3.0, 0.0), (1.0, 2.0)], 1.0)),
+    ("square", lambda rows, cols: square_mask(rows, cols, 2)),
+]
+
+def fixture_image(rows=8, cols=11):
+    # Stripes for the notches, a block for the low and band pass and some noise
+    seed = 1
+    img = np.zeros((rows, cols), np.uint8)
+    for y in range(rows):
+        for x in range(cols):
+            seed = (seed * 1103515245 + 12345) % 2**31
+            value = (128
+                     + 60 * math.cos(2 * math.pi * 3 * x / cols)
+                     + 30 * math.cos(2 * math.pi * (x + 2 * y) / rows)
+                     + (40 if 2 <= x < 5 and 3 <= y < 6 else 0)
+                     + seed % 21 - 10)
+            img[y, x] = min(max(round(value), 0), 255)
+    return img
+
+def write_fixture(path):
+    img = fixture_image()
+    rows, cols = img.shape
+
+    with open(path, "w") as fixture:
+        fixture.write("# Written by test_scripts/image_denoise.py --fixture\n")
+        fixture.write(f"size {cols} {rows}\n")
+        fixture.write("input\n")
+        for row in img:
+            fixture.write(" ".join(str(value) for value in row) + "\n")
+
+        for name, mask_fn in FIXTURE_MASKS:
+            fixture.write(f"{name}\n")
+            for row in reconstruct(img.astype(np.float64), mask_fn(rows, cols)):
+                fixture.write(" ".join(f"{value:.6f}" for value in row) + "\n")
+
+if len(sys.argv) == 3 and sys.argv[1] == "--fixture":
+    write_fixture(sys.argv[2])
+else:
+    # Example usage
+    image_path = 'test_files/output.png'  # Replace with your image path
+    display_fft(image_path)